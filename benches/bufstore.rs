@@ -2,7 +2,7 @@
 
 extern crate test;
 
-use orga::store::{MapStore, Read, Write};
+use orga::store::{BufStore, BufStoreMap, Empty, MapStore, Read, Write};
 use test::Bencher;
 
 #[bench]
@@ -68,3 +68,51 @@ fn bufstore_delete_8b(b: &mut Bencher) {
         i += 1;
     });
 }
+
+/// A block's worth of account updates, keyed like a map of 8-byte addresses.
+fn account_updates(n: u32) -> BufStoreMap {
+    (0..n)
+        .map(|i| {
+            (
+                i.wrapping_mul(2654435761).to_be_bytes().repeat(2),
+                Some(vec![0; 64]),
+            )
+        })
+        .collect()
+}
+
+#[bench]
+fn bufstore_flush_10k_batched(b: &mut Bencher) {
+    let updates = account_updates(10_000);
+
+    b.iter(|| {
+        let mut store = BufStore::wrap_with_map(MapStore::new(), updates.clone());
+        store.flush().unwrap();
+    });
+}
+
+/// The previous flush behavior, applying each entry with a separate write,
+/// for comparison with `bufstore_flush_10k_batched`.
+#[bench]
+fn bufstore_flush_10k_one_by_one(b: &mut Bencher) {
+    let updates = account_updates(10_000);
+
+    b.iter(|| {
+        let mut store = MapStore::new();
+        for (key, value) in updates.clone() {
+            store.put(key, value.unwrap()).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bufstore_flush_10k_into_10k_batched(b: &mut Bencher) {
+    let existing: BufStoreMap = account_updates(20_000).into_iter().step_by(2).collect();
+    let updates = account_updates(10_000);
+
+    b.iter(|| {
+        let inner = MapStore::wrap_with_map(Empty, existing.clone());
+        let mut store = BufStore::wrap_with_map(inner, updates.clone());
+        store.flush().unwrap();
+    });
+}
@@ -21,10 +21,15 @@ pub fn derive(item: TokenStream) -> TokenStream {
 
     let name = &item.ident;
     let mut generics = item.generics.clone();
-    generics.params.iter_mut().for_each(|p| {
-        if let GenericParam::Type(tp) = p {
+    generics.params.iter_mut().for_each(|p| match p {
+        GenericParam::Type(tp) => {
             tp.default.take();
         }
+        GenericParam::Const(cp) => {
+            cp.eq_token.take();
+            cp.default.take();
+        }
+        _ => {}
     });
     let where_clause = generics
         .where_clause
@@ -184,10 +184,17 @@ fn method_query_impl(tokens: &mut TokenStream2, item: &ItemImpl) {
         Noop(_) => {}
     });
 
+    // query methods may add their own bounds (e.g. `where K: Clone`), which the
+    // dispatching impl needs in order to call them
+    let method_preds = query_methods(&item)
+        .into_iter()
+        .filter_map(|method| method.sig.generics.where_clause.as_ref())
+        .flat_map(|w| w.predicates.iter())
+        .collect_vec();
     let enum_bound = quote! { #enum_ident #ty: #encode_trait + #decode_trait + ::std::fmt::Debug };
     let wher = match wher {
-        Some(w) => quote! { #w #enum_bound },
-        None => quote! { where #enum_bound },
+        Some(w) => quote! { #w #enum_bound, #( #method_preds ),* },
+        None => quote! { where #enum_bound, #( #method_preds ),* },
     };
 
     tokens.extend(quote! {
@@ -0,0 +1,300 @@
+use super::{ABCIStateMachine, Application};
+use crate::merk::MerkStore;
+use crate::{Error, Result};
+use prost::Message;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tendermint_proto::google::protobuf::Timestamp;
+use tendermint_proto::v0_34::abci::request::Value as Req;
+use tendermint_proto::v0_34::abci::response::Value as Res;
+use tendermint_proto::v0_34::abci::*;
+use tendermint_proto::v0_34::types::Header;
+
+static NEXT_HOME: AtomicUsize = AtomicUsize::new(0);
+
+/// Drives an [`ABCIStateMachine`](struct.ABCIStateMachine.html) directly,
+/// without a Tendermint process or ABCI connections, backed by a fresh
+/// `MerkStore` in a temporary directory which is removed on drop.
+///
+/// Requests go through the same `ABCIStateMachine::run` path the node uses,
+/// so buffering and commit behave as they do in production.
+pub struct InProcessHarness<A: Application> {
+    state_machine: ABCIStateMachine<A>,
+    home: PathBuf,
+    height: i64,
+}
+
+/// A block to be executed by [`InProcessHarness::run_block`].
+///
+/// The block time is part of the script rather than read from the clock, so
+/// running the same blocks twice produces the same headers.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedBlock {
+    pub time: i64,
+    pub txs: Vec<Vec<u8>>,
+}
+
+/// The consensus-critical responses produced by a single block.
+#[derive(Clone, Debug, Default)]
+pub struct BlockResponses {
+    pub deliver_txs: Vec<ResponseDeliverTx>,
+    pub end_block: ResponseEndBlock,
+    pub commit: ResponseCommit,
+}
+
+impl<A: Application> InProcessHarness<A> {
+    pub fn new(app: A) -> Self {
+        let home = std::env::temp_dir().join(format!(
+            "orga-harness-{}-{}",
+            std::process::id(),
+            NEXT_HOME.fetch_add(1, Ordering::Relaxed)
+        ));
+        let state_machine = ABCIStateMachine::new(
+            app,
+            MerkStore::new(&home),
+            false,
+            Default::default(),
+            Default::default(),
+        );
+
+        Self {
+            state_machine,
+            home,
+            height: 0,
+        }
+    }
+
+    /// The underlying state machine, for sending requests the harness doesn't
+    /// wrap.
+    pub fn state_machine(&mut self) -> &mut ABCIStateMachine<A> {
+        &mut self.state_machine
+    }
+
+    /// The height of the last block run by the harness.
+    pub fn height(&self) -> i64 {
+        self.height
+    }
+
+    pub fn init_chain(&mut self, req: RequestInitChain) -> Result<ResponseInitChain> {
+        match self.run(Req::InitChain(req))? {
+            Res::InitChain(res) => Ok(res),
+            _ => Err(Error::ABCI("Unexpected response to InitChain".into())),
+        }
+    }
+
+    pub fn check_tx(&mut self, tx: Vec<u8>) -> Result<ResponseCheckTx> {
+        let req = RequestCheckTx {
+            tx: tx.into(),
+            ..Default::default()
+        };
+        match self.run(Req::CheckTx(req))? {
+            Res::CheckTx(res) => Ok(res),
+            _ => Err(Error::ABCI("Unexpected response to CheckTx".into())),
+        }
+    }
+
+    pub fn query(&mut self, req: RequestQuery) -> Result<ResponseQuery> {
+        match self.run(Req::Query(req))? {
+            Res::Query(res) => Ok(res),
+            _ => Err(Error::ABCI("Unexpected response to Query".into())),
+        }
+    }
+
+    /// Runs `block` at the next height: BeginBlock, a DeliverTx for each tx,
+    /// EndBlock, and Commit.
+    pub fn run_block(&mut self, block: &ScriptedBlock) -> Result<BlockResponses> {
+        let height = self.height + 1;
+        let header = Header {
+            height,
+            time: Some(Timestamp {
+                seconds: block.time,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        self.run(Req::BeginBlock(RequestBeginBlock {
+            header: Some(header),
+            ..Default::default()
+        }))?;
+
+        let mut responses = BlockResponses::default();
+        for tx in block.txs.iter() {
+            let req = RequestDeliverTx {
+                tx: tx.clone().into(),
+            };
+            match self.run(Req::DeliverTx(req))? {
+                Res::DeliverTx(res) => responses.deliver_txs.push(res),
+                _ => return Err(Error::ABCI("Unexpected response to DeliverTx".into())),
+            }
+        }
+
+        match self.run(Req::EndBlock(RequestEndBlock { height }))? {
+            Res::EndBlock(res) => responses.end_block = res,
+            _ => return Err(Error::ABCI("Unexpected response to EndBlock".into())),
+        }
+        match self.run(Req::Commit(Default::default()))? {
+            Res::Commit(res) => responses.commit = res,
+            _ => return Err(Error::ABCI("Unexpected response to Commit".into())),
+        }
+
+        self.height = height;
+        Ok(responses)
+    }
+
+    fn run(&mut self, value: Req) -> Result<Res> {
+        self.state_machine.run(Request { value: Some(value) })
+    }
+}
+
+impl<A: Application> Drop for InProcessHarness<A> {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.home) {
+            log::debug!("Failed to remove harness home {:?}: {}", self.home, e);
+        }
+    }
+}
+
+/// Runs `blocks` twice, each time against a fresh app from `make_app` and an
+/// empty store, and panics unless every `ResponseDeliverTx`,
+/// `ResponseEndBlock`, and `ResponseCommit` (including the app hash) encodes
+/// to identical bytes in both runs.
+///
+/// This catches sources of non-determinism such as `HashMap` iteration order
+/// or reading the local clock before they can halt a chain. Errors returned by
+/// the app are passed through rather than compared.
+pub fn assert_deterministic<A, F>(mut make_app: F, blocks: &[ScriptedBlock]) -> Result<()>
+where
+    A: Application,
+    F: FnMut() -> A,
+{
+    let mut first = InProcessHarness::new(make_app());
+    let mut second = InProcessHarness::new(make_app());
+
+    for (i, block) in blocks.iter().enumerate() {
+        let height = i + 1;
+        let a = first.run_block(block)?;
+        let b = second.run_block(block)?;
+
+        assert_eq!(
+            a.deliver_txs.len(),
+            b.deliver_txs.len(),
+            "DeliverTx count differs at height {}",
+            height
+        );
+        for (j, (a, b)) in a.deliver_txs.iter().zip(b.deliver_txs.iter()).enumerate() {
+            assert_eq!(
+                a.encode_to_vec(),
+                b.encode_to_vec(),
+                "ResponseDeliverTx for tx {} differs at height {}:\n{:?}\n{:?}",
+                j,
+                height,
+                a,
+                b
+            );
+        }
+        assert_eq!(
+            a.end_block.encode_to_vec(),
+            b.end_block.encode_to_vec(),
+            "ResponseEndBlock differs at height {}:\n{:?}\n{:?}",
+            height,
+            a.end_block,
+            b.end_block
+        );
+        assert_eq!(
+            a.commit.encode_to_vec(),
+            b.commit.encode_to_vec(),
+            "App hash differs at height {}",
+            height
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "merk-full"))]
+mod tests {
+    use super::*;
+    use crate::abci::WrappedMerk;
+    use crate::store::{Read, Write};
+
+    /// Stores each tx under its own bytes and echoes it in the response log.
+    /// If `counter` is set, the log also includes a process-global counter, so
+    /// a second run produces different responses.
+    struct EchoApp {
+        counter: bool,
+    }
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    impl Application for EchoApp {
+        fn deliver_tx(
+            &self,
+            mut store: WrappedMerk,
+            req: RequestDeliverTx,
+        ) -> Result<ResponseDeliverTx> {
+            store.put(req.tx.to_vec(), req.tx.to_vec())?;
+            let mut log = String::from_utf8(req.tx.to_vec()).unwrap();
+            if self.counter {
+                log += &COUNTER.fetch_add(1, Ordering::Relaxed).to_string();
+            }
+            Ok(ResponseDeliverTx {
+                log,
+                ..Default::default()
+            })
+        }
+
+        fn end_block(&self, store: WrappedMerk, _req: RequestEndBlock) -> Result<ResponseEndBlock> {
+            let _ = store.get(b"a")?;
+            Ok(Default::default())
+        }
+    }
+
+    fn blocks() -> Vec<ScriptedBlock> {
+        vec![
+            ScriptedBlock {
+                time: 1,
+                txs: vec![b"a".to_vec(), b"b".to_vec()],
+            },
+            ScriptedBlock {
+                time: 2,
+                txs: vec![],
+            },
+            ScriptedBlock {
+                time: 3,
+                txs: vec![b"c".to_vec()],
+            },
+        ]
+    }
+
+    #[test]
+    fn harness_runs_blocks() -> Result<()> {
+        let mut harness = InProcessHarness::new(EchoApp { counter: false });
+        let res = harness.run_block(&blocks()[0])?;
+        assert_eq!(harness.height(), 1);
+        assert_eq!(res.deliver_txs.len(), 2);
+        assert_eq!(res.deliver_txs[1].log, "b");
+        assert!(!res.commit.data.is_empty());
+
+        let status = harness.state_machine().status();
+        assert_eq!(status.committed_height, 1);
+        assert_eq!(status.app_hash, hex::encode_upper(&res.commit.data));
+        assert_eq!(status.block_time, Some(1));
+        assert_eq!(status.key_count, Some(2));
+
+        harness.run_block(&blocks()[2])?;
+        assert_eq!(harness.state_machine().status().key_count, Some(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_app() -> Result<()> {
+        assert_deterministic(|| EchoApp { counter: false }, &blocks())
+    }
+
+    #[test]
+    #[should_panic(expected = "ResponseDeliverTx for tx 0 differs at height 1")]
+    fn nondeterministic_app() {
+        assert_deterministic(|| EchoApp { counter: true }, &blocks()).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+use crate::Result;
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+/// JSON Schema for the [`NodeStatus`] body served by the health endpoint.
+pub const NODE_STATUS_SCHEMA: &str = r#"{
+  "$schema": "http://json-schema.org/draft-07/schema#",
+  "title": "NodeStatus",
+  "type": "object",
+  "properties": {
+    "accepting_connections": { "type": "boolean" },
+    "shutting_down": { "type": "boolean" },
+    "committed_height": { "type": "integer", "minimum": 0 },
+    "app_hash": { "type": "string", "pattern": "^([0-9A-F]{2})*$" },
+    "block_time": { "type": ["integer", "null"] },
+    "key_count": { "type": ["integer", "null"], "minimum": 0 },
+    "pending_txs": { "type": "integer", "minimum": 0 },
+    "uptime_secs": { "type": "integer", "minimum": 0 }
+  },
+  "required": [
+    "accepting_connections",
+    "shutting_down",
+    "committed_height",
+    "app_hash",
+    "block_time",
+    "key_count",
+    "pending_txs",
+    "uptime_secs"
+  ],
+  "additionalProperties": false
+}"#;
+
+/// Liveness information about a running
+/// [`ABCIStateMachine`](struct.ABCIStateMachine.html), shared between the
+/// state machine and the health endpoint.
+///
+/// Updating the status is a handful of atomic stores plus one short write lock
+/// per commit, so the consensus path never waits on the health server.
+#[derive(Clone)]
+pub struct HealthStatus {
+    accepting: Arc<AtomicBool>,
+    commit: Arc<RwLock<CommitInfo>>,
+    pending_txs: Arc<AtomicU64>,
+    started: Instant,
+    shutdown_notifier: Arc<RwLock<bool>>,
+}
+
+/// Information about the last committed block.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CommitInfo {
+    pub height: u64,
+    pub app_hash: Vec<u8>,
+    pub block_time: Option<i64>,
+    pub key_count: Option<u64>,
+}
+
+/// The canonical status report for a node, served as JSON by the health
+/// endpoint. See [`NODE_STATUS_SCHEMA`] for the JSON schema.
+///
+/// - `committed_height`, `app_hash` (uppercase hex) and `block_time` (header
+///   time in unix seconds, `null` before the first commit) describe the last
+///   committed block.
+/// - `key_count` is the number of keys in the store, or `null` if the store
+///   doesn't track it (see `MerkStore::key_count`).
+/// - `pending_txs` is the number of txs accepted by `CheckTx` since the last
+///   commit, which approximates the mempool size (Tendermint may hold txs the
+///   app hasn't rechecked yet).
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeStatus {
+    pub accepting_connections: bool,
+    pub shutting_down: bool,
+    pub committed_height: u64,
+    pub app_hash: String,
+    pub block_time: Option<i64>,
+    pub key_count: Option<u64>,
+    pub pending_txs: u64,
+    pub uptime_secs: u64,
+}
+
+impl HealthStatus {
+    pub fn new(shutdown_notifier: Arc<RwLock<bool>>) -> Self {
+        Self {
+            accepting: Default::default(),
+            commit: Default::default(),
+            pending_txs: Default::default(),
+            started: Instant::now(),
+            shutdown_notifier,
+        }
+    }
+
+    pub(crate) fn set_accepting(&self, accepting: bool) {
+        self.accepting.store(accepting, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_commit(&self, commit: CommitInfo) {
+        self.pending_txs.store(0, Ordering::Relaxed);
+        if let Ok(mut info) = self.commit.write() {
+            *info = commit;
+        }
+    }
+
+    pub(crate) fn add_pending_tx(&self) {
+        self.pending_txs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> NodeStatus {
+        let commit = self
+            .commit
+            .read()
+            .map(|info| info.clone())
+            .unwrap_or_default();
+
+        NodeStatus {
+            accepting_connections: self.accepting.load(Ordering::Relaxed),
+            shutting_down: self.shutdown_notifier.read().map_or(true, |s| *s),
+            committed_height: commit.height,
+            app_hash: hex::encode_upper(commit.app_hash),
+            block_time: commit.block_time,
+            key_count: commit.key_count,
+            pending_txs: self.pending_txs.load(Ordering::Relaxed),
+            uptime_secs: self.started.elapsed().as_secs(),
+        }
+    }
+
+    /// Serves `GET /health` on `addr` from a background thread.
+    ///
+    /// Responds with `200` and a JSON [`NodeStatus`] while the node is running,
+    /// and `503` (with the same body) once shutdown has begun. Any other path
+    /// gets a `404`.
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> Result<std::thread::JoinHandle<()>> {
+        let listener = TcpListener::bind(addr)?;
+        let status = self.clone();
+
+        Ok(std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let res = stream
+                    .map_err(Into::into)
+                    .and_then(|stream| status.handle(stream));
+                if let Err(e) = res {
+                    log::debug!("Health endpoint error: {}", e);
+                }
+            }
+        }))
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+
+        let (status, body) = if path == "/health" {
+            let report = self.status();
+            let status = if report.shutting_down {
+                "503 Service Unavailable"
+            } else {
+                "200 OK"
+            };
+            (status, serde_json::to_string(&report)?)
+        } else {
+            ("404 Not Found", String::new())
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+        res
+    }
+
+    fn body(res: &str) -> serde_json::Value {
+        let body = res.split("\r\n\r\n").nth(1).unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn health_endpoint() {
+        let notifier = Arc::new(RwLock::new(false));
+        let status = HealthStatus::new(notifier.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        status.serve(addr).unwrap();
+
+        status.set_accepting(true);
+        status.add_pending_tx();
+        status.set_commit(CommitInfo {
+            height: 42,
+            app_hash: vec![0xab, 0xcd],
+            block_time: Some(1_000),
+            key_count: Some(7),
+        });
+        status.add_pending_tx();
+
+        let res = get(addr, "/health");
+        assert!(res.starts_with("HTTP/1.1 200"));
+        let json = body(&res);
+        assert_eq!(json["accepting_connections"], true);
+        assert_eq!(json["shutting_down"], false);
+        assert_eq!(json["committed_height"], 42);
+        assert_eq!(json["app_hash"], "ABCD");
+        assert_eq!(json["block_time"], 1_000);
+        assert_eq!(json["key_count"], 7);
+        assert_eq!(json["pending_txs"], 1);
+
+        assert!(get(addr, "/foo").starts_with("HTTP/1.1 404"));
+
+        *notifier.write().unwrap() = true;
+        assert!(get(addr, "/health").starts_with("HTTP/1.1 503"));
+    }
+
+    #[test]
+    fn schema_matches_status() {
+        let schema: serde_json::Value = serde_json::from_str(NODE_STATUS_SCHEMA).unwrap();
+        let mut properties: Vec<_> = schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        properties.sort();
+
+        let status = HealthStatus::new(Default::default()).status();
+        let json = serde_json::to_value(status).unwrap();
+        let mut fields: Vec<_> = json.as_object().unwrap().keys().cloned().collect();
+        fields.sort();
+
+        assert_eq!(properties, fields);
+        assert_eq!(schema["required"].as_array().unwrap().len(), fields.len());
+    }
+}
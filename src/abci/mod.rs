@@ -7,6 +7,26 @@ use crate::Result;
 mod node;
 #[cfg(feature = "abci")]
 pub use node::*;
+#[cfg(feature = "abci")]
+mod health;
+#[cfg(feature = "abci")]
+pub use health::*;
+#[cfg(feature = "abci")]
+mod tx_log;
+#[cfg(feature = "abci")]
+pub use tx_log::RejectedTxLogConfig;
+#[cfg(feature = "abci")]
+mod tx_index;
+#[cfg(feature = "abci")]
+pub use tx_index::{lookup_tx, tx_hash, IndexedTx, TX_QUERY_PATH};
+#[cfg(feature = "abci")]
+mod harness;
+#[cfg(feature = "abci")]
+pub use harness::*;
+#[cfg(feature = "abci-v0_37")]
+pub mod v0_37;
+#[cfg(feature = "abci-v0_38")]
+pub mod v0_38;
 
 pub mod prost;
 
@@ -16,18 +36,28 @@ pub use tendermint_proto::v0_34::abci as messages;
 #[cfg(feature = "abci")]
 mod server {
     use super::*;
-    use crate::merk::MerkStore;
-    use crate::store::{BufStore, BufStoreMap, MapStore, Read, Shared, Write, KV};
+    use crate::context::Context;
+    use crate::merk::{MerkStore, ReadView};
+    use crate::store::{
+        BufStore, BufStoreMap, MapStore, OpCounts, Read, Shared, StoreMetrics, Write, KV,
+    };
     use crate::Error;
     use log::info;
     use std::env;
     use std::net::ToSocketAddrs;
-    use std::sync::mpsc::{self, Receiver, SyncSender};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
     use std::sync::{Arc, RwLock};
     use tendermint_proto::v0_34::abci::request::Value as Req;
     use tendermint_proto::v0_34::abci::response::Value as Res;
     use tendermint_proto::v0_34::types::Header;
 
+    /// The number of ABCI connections accepted by
+    /// [`ABCIStateMachine::listen`](struct.ABCIStateMachine.html#method.listen)
+    /// by default, one for each connection Tendermint opens (consensus,
+    /// mempool, query, and snapshot).
+    pub const DEFAULT_ABCI_WORKERS: usize = 4;
+
     /// Top-level struct for running an ABCI application. Maintains an ABCI server,
     /// mempool, and handles committing data to the store.
     pub struct ABCIStateMachine<A: Application> {
@@ -42,6 +72,227 @@ mod server {
         header: Option<Header>,
         shutdown: Arc<RwLock<Option<Error>>>,
         shutdown_notifier: Arc<RwLock<bool>>,
+        health: HealthStatus,
+        invariant_violation: Option<String>,
+        rejected_tx_log: Option<tx_log::RejectedTxLog>,
+        tx_index: Option<tx_index::TxIndex>,
+        worker_count: usize,
+        workers: Vec<Worker>,
+        closed_sender: Sender<()>,
+        closed_receiver: Receiver<()>,
+        halt: HaltConditions,
+        halt_error: Option<Error>,
+        halted: Option<String>,
+        shutdown_handle: ShutdownHandle,
+        middleware: Vec<Box<dyn Middleware>>,
+        concurrent_queries: Option<ConcurrentQueries>,
+        store_metrics: Option<usize>,
+        tx_write_budget: Option<u64>,
+        block_write_budget: Option<u64>,
+        consensus_written: u64,
+        mempool_written: u64,
+    }
+
+    /// A handle for requesting a graceful stop of an
+    /// [`ABCIStateMachine`](struct.ABCIStateMachine.html), obtained with
+    /// `ABCIStateMachine::shutdown_handle` before calling `listen`.
+    ///
+    /// Once a shutdown is requested, `listen` finishes the block in progress (if
+    /// any) through its `Commit`, then returns `Ok(())`, while errors are still
+    /// returned as `Err`.
+    #[derive(Clone, Default)]
+    pub struct ShutdownHandle {
+        requested: Arc<AtomicBool>,
+    }
+
+    impl ShutdownHandle {
+        /// Requests that the state machine stop after the current block.
+        pub fn shutdown(&self) {
+            self.requested.store(true, Ordering::SeqCst);
+        }
+
+        /// Whether a shutdown has been requested.
+        pub fn is_requested(&self) -> bool {
+            self.requested.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Hooks run around every request handled by
+    /// [`ABCIStateMachine::run`](struct.ABCIStateMachine.html#method.run),
+    /// registered with `ABCIStateMachine::with_middleware`, e.g. for logging,
+    /// metrics, or modifying requests in tests.
+    ///
+    /// `before` hooks run in registration order and `after` hooks in reverse,
+    /// so the first middleware registered wraps all the others.
+    pub trait Middleware {
+        /// Called before the request is handled, and may modify it. Returning
+        /// an error skips the request, which fails with that error.
+        fn before(&mut self, _req: &mut Request) -> Result<()> {
+            Ok(())
+        }
+
+        /// Called with the (possibly modified) request and the result of
+        /// handling it, which may be modified.
+        fn after(&mut self, _req: &Request, _res: &mut Result<Res>) {}
+    }
+
+    /// Answers a query from a [`ReadView`](../merk/struct.ReadView.html) of the
+    /// committed state, returning `None` for queries which must instead be
+    /// answered by `Application::query`. See
+    /// `ABCIStateMachine::concurrent_queries`.
+    pub type QueryHandler =
+        Arc<dyn Fn(&ReadView, &RequestQuery) -> Option<Result<ResponseQuery>> + Send + Sync>;
+
+    /// The handler and latest committed view used to answer queries on the
+    /// connection threads.
+    #[derive(Clone)]
+    struct ConcurrentQueries {
+        handler: QueryHandler,
+        view: Arc<RwLock<ReadView>>,
+    }
+
+    impl ConcurrentQueries {
+        fn publish(&self, store: &MerkStore) {
+            *self.view.write().unwrap() = store.read_view();
+        }
+
+        /// Answers `req` if it is a query the handler accepts. The handler runs
+        /// with an isolated [`Context`](../context/struct.Context.html), so it
+        /// can't interfere with the block being processed, which only holds
+        /// the contexts added with `Context::add_inherited`, e.g. the chain ID.
+        fn answer(&self, req: &Request) -> Option<Response> {
+            let req = match req.value.as_ref() {
+                Some(Req::Query(req)) if req.path != TX_QUERY_PATH => req,
+                _ => return None,
+            };
+            let view = self.view.read().unwrap().clone();
+            let res = Context::isolated(|| (self.handler)(&view, req))?
+                .unwrap_or_else(|err| query_error(err, view.height().unwrap_or_default() as i64));
+
+            Some(Response {
+                value: Some(Res::Query(res)),
+            })
+        }
+    }
+
+    /// The number of modules listed when logging store metrics.
+    const LOGGED_MODULES: usize = 5;
+
+    /// Logs the total store operations of the block at `height` and those of
+    /// the modules with the most I/O.
+    fn log_store_metrics(height: u64) {
+        let metrics = match Context::resolve::<StoreMetrics>() {
+            Some(metrics) => metrics,
+            None => return,
+        };
+
+        let fmt = |counts: &OpCounts| {
+            let hit_rate = counts
+                .cache_hit_rate()
+                .map_or("-".to_string(), |rate| format!("{:.1}%", rate * 100.0));
+            format!(
+                "gets={} puts={} deletes={} read={}B written={}B cache_hits={}",
+                counts.gets,
+                counts.puts,
+                counts.deletes,
+                counts.bytes_read,
+                counts.bytes_written,
+                hit_rate
+            )
+        };
+
+        info!(
+            "Store metrics at height {}: {}",
+            height,
+            fmt(&metrics.total())
+        );
+        for (prefix, counts) in metrics.by_bytes().into_iter().take(LOGGED_MODULES) {
+            info!("  module {}: {}", hex::encode(prefix), fmt(counts));
+        }
+    }
+
+    /// Whether a tx failed by exceeding its write budget, in which case it is
+    /// rejected rather than the error halting the node.
+    fn is_budget_exceeded(err: &Error) -> bool {
+        matches!(err, Error::StoreErr(crate::store::Error::BudgetExceeded(_)))
+    }
+
+    fn query_error(err: Error, height: i64) -> ResponseQuery {
+        ResponseQuery {
+            code: 1,
+            log: err.to_string(),
+            info: err.to_string(),
+            codespace: "".to_string(),
+            height,
+            index: 0,
+            key: vec![].into(),
+            proof_ops: None,
+            value: vec![].into(),
+        }
+    }
+
+    /// Conditions under which an
+    /// [`ABCIStateMachine`](struct.ABCIStateMachine.html) halts, e.g. to
+    /// coordinate an upgrade. Each condition is checked when a block is
+    /// committed: once one is met, `listen` returns an error describing it
+    /// (starting with `"Reached stop "`) and every later block is rejected.
+    ///
+    /// A block past `stop_height` is also rejected after a restart, while the
+    /// time and app hash conditions only apply to blocks committed by the
+    /// running state machine.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct HaltConditions {
+        /// Halt once this height has been committed.
+        pub stop_height: Option<u64>,
+        /// Halt once a block whose header time (in seconds since the Unix
+        /// epoch) is at or after this time has been committed.
+        pub stop_time: Option<i64>,
+        /// Halt once a block with this app hash has been committed.
+        pub stop_app_hash: Option<Vec<u8>>,
+    }
+
+    impl HaltConditions {
+        /// Reads the conditions from the environment, where `ORGA_STOP_HEIGHT`
+        /// sets `stop_height`.
+        pub fn from_env() -> Result<Self> {
+            let stop_height = match env::var_os("ORGA_STOP_HEIGHT") {
+                Some(value) => Some(
+                    value
+                        .to_str()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| {
+                            Error::ABCI(format!("Invalid ORGA_STOP_HEIGHT value: {:?}", value))
+                        })?,
+                ),
+                None => None,
+            };
+
+            Ok(Self {
+                stop_height,
+                ..Default::default()
+            })
+        }
+
+        /// Returns why the state machine should halt after committing a block
+        /// with the given height, header time, and app hash, if it should.
+        pub fn check(&self, height: u64, time: Option<i64>, app_hash: &[u8]) -> Option<String> {
+            if let Some(stop_height) = self.stop_height {
+                if height >= stop_height {
+                    return Some(format!("Reached stop height ({})", stop_height));
+                }
+            }
+            if let (Some(stop_time), Some(time)) = (self.stop_time, time) {
+                if time >= stop_time {
+                    return Some(format!("Reached stop time ({})", stop_time));
+                }
+            }
+            if let Some(stop_app_hash) = self.stop_app_hash.as_ref() {
+                if app_hash == stop_app_hash.as_slice() {
+                    return Some(format!("Reached stop app hash ({})", hex::encode(app_hash)));
+                }
+            }
+            None
+        }
     }
 
     impl<A: Application> ABCIStateMachine<A> {
@@ -56,6 +307,11 @@ mod server {
             shutdown_notifier: Arc<RwLock<bool>>,
         ) -> Self {
             let (sender, receiver) = mpsc::sync_channel(0);
+            let (closed_sender, closed_receiver) = mpsc::channel();
+            let (halt, halt_error) = match HaltConditions::from_env() {
+                Ok(halt) => (halt, None),
+                Err(err) => (HaltConditions::default(), Some(err)),
+            };
             ABCIStateMachine {
                 app: Some(app),
                 store: Some(Shared::new(store)),
@@ -67,16 +323,230 @@ mod server {
                 skip_init_chain,
                 header: None,
                 shutdown,
+                health: HealthStatus::new(shutdown_notifier.clone()),
                 shutdown_notifier,
+                invariant_violation: None,
+                rejected_tx_log: None,
+                tx_index: None,
+                worker_count: DEFAULT_ABCI_WORKERS,
+                workers: vec![],
+                closed_sender,
+                closed_receiver,
+                halt,
+                halt_error,
+                halted: None,
+                shutdown_handle: ShutdownHandle::default(),
+                middleware: vec![],
+                concurrent_queries: None,
+                store_metrics: None,
+                tx_write_budget: None,
+                block_write_budget: None,
+                consensus_written: 0,
+                mempool_written: 0,
+            }
+        }
+
+        /// Returns a handle which can be used to gracefully stop `listen` from
+        /// another thread.
+        pub fn shutdown_handle(&self) -> ShutdownHandle {
+            self.shutdown_handle.clone()
+        }
+
+        /// Uses an existing [`ShutdownHandle`](struct.ShutdownHandle.html),
+        /// e.g. one created before the state machine.
+        #[must_use]
+        pub fn with_shutdown_handle(mut self, handle: ShutdownHandle) -> Self {
+            self.shutdown_handle = handle;
+            self
+        }
+
+        /// Sets the conditions under which to stop processing blocks. Defaults
+        /// to [`HaltConditions::from_env`](struct.HaltConditions.html#method.from_env).
+        #[must_use]
+        pub fn halt_conditions(mut self, halt: HaltConditions) -> Self {
+            self.halt = halt;
+            self.halt_error = None;
+            self
+        }
+
+        /// Sets the height after which to stop processing blocks, keeping the
+        /// other [`HaltConditions`](struct.HaltConditions.html).
+        #[must_use]
+        pub fn stop_height(mut self, height: Option<u64>) -> Self {
+            self.halt.stop_height = height;
+            self.halt_error = None;
+            self
+        }
+
+        /// Whether the last committed height is at or past the stop height.
+        pub fn reached_stop_height(&self) -> bool {
+            matches!(self.halt.stop_height, Some(stop_height) if self.height >= stop_height)
+        }
+
+        /// Why the state machine halted, if a halt condition was met by a
+        /// committed block.
+        pub fn halt_reason(&self) -> Option<&str> {
+            self.halted.as_deref()
+        }
+
+        /// Registers a [`Middleware`](trait.Middleware.html) to run around
+        /// every request, after any registered before it.
+        #[must_use]
+        pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+            self.middleware.push(Box::new(middleware));
+            self
+        }
+
+        /// Answers queries directly on the ABCI connection threads (or tasks,
+        /// with `listen_async`) using the app's
+        /// [`QueryHandler`](type.QueryHandler.html), so heavy query load
+        /// doesn't stall block processing. Queries are answered from a
+        /// [`ReadView`](../merk/struct.ReadView.html) published at each commit,
+        /// and don't pass through any [`Middleware`](trait.Middleware.html).
+        /// Queries the handler declines, and all queries if the app has no
+        /// handler, are still answered by the state machine. Disabled by
+        /// default.
+        #[must_use]
+        pub fn concurrent_queries(mut self) -> Self {
+            let handler = self.app.as_ref().unwrap().query_handler();
+            self.concurrent_queries = handler.map(|handler| {
+                let queries = ConcurrentQueries {
+                    handler,
+                    view: Default::default(),
+                };
+                queries.publish(&self.store.as_ref().unwrap().borrow());
+                queries
+            });
+            self
+        }
+
+        /// Sets the number of ABCI connections to accept in `listen`, each
+        /// handled by its own worker thread. Defaults to
+        /// [`DEFAULT_ABCI_WORKERS`](constant.DEFAULT_ABCI_WORKERS.html).
+        #[must_use]
+        pub fn worker_count(mut self, count: usize) -> Self {
+            self.worker_count = count;
+            self
+        }
+
+        /// Logs txs rejected by `CheckTx` or `DeliverTx` with their hash, code,
+        /// and error, subject to the rate limit in `config`. Disabled by
+        /// default.
+        #[must_use]
+        pub fn log_rejected_txs(mut self, config: RejectedTxLogConfig) -> Self {
+            self.rejected_tx_log = Some(tx_log::RejectedTxLog::new(config));
+            self
+        }
+
+        /// Indexes the results of delivered txs by hash, to be looked up with
+        /// the [`TX_QUERY_PATH`](constant.TX_QUERY_PATH.html) query. The index
+        /// is kept in the store's auxiliary data, so it does not affect the app
+        /// hash. Disabled by default.
+        #[must_use]
+        pub fn index_txs(mut self) -> Self {
+            self.tx_index = Some(Default::default());
+            self
+        }
+
+        /// Collects the [`StoreMetrics`](../store/struct.StoreMetrics.html) of
+        /// each block, grouping keys by their first `prefix_len` bytes. The
+        /// context is replaced in `BeginBlock` and the busiest modules are
+        /// logged after `Commit`. Disabled by default.
+        #[must_use]
+        pub fn store_metrics(mut self, prefix_len: usize) -> Self {
+            self.store_metrics = Some(prefix_len);
+            self
+        }
+
+        /// Limits the writes a single tx can buffer to `bytes` (counting the
+        /// key and value of each written entry). A tx which exceeds it fails
+        /// with a deterministic `BudgetExceeded` store error and its writes
+        /// are discarded. Unlimited by default.
+        #[must_use]
+        pub fn tx_write_budget(mut self, bytes: u64) -> Self {
+            self.tx_write_budget = Some(bytes);
+            self
+        }
+
+        /// Limits the writes buffered by the txs of a block (and by the txs
+        /// checked into the mempool since the last commit) to `bytes`. A tx
+        /// which would exceed it fails like one exceeding
+        /// [`tx_write_budget`](#method.tx_write_budget). The writes of each tx
+        /// count separately, even if a later tx overwrites them. Unlimited
+        /// by default.
+        #[must_use]
+        pub fn block_write_budget(mut self, bytes: u64) -> Self {
+            self.block_write_budget = Some(bytes);
+            self
+        }
+
+        /// The budget for the writes of the next tx, given the bytes already
+        /// written by the other txs of the block (or mempool).
+        fn tx_budget(&self, written: u64) -> Option<u64> {
+            let block = self
+                .block_write_budget
+                .map(|budget| budget.saturating_sub(written));
+            match (self.tx_write_budget, block) {
+                (Some(tx), Some(block)) => Some(tx.min(block)),
+                (tx, block) => tx.or(block),
             }
         }
 
+        /// Returns a handle to the state machine's liveness information, which
+        /// can be served over HTTP with
+        /// [`HealthStatus::serve`](struct.HealthStatus.html#method.serve).
+        pub fn health(&self) -> HealthStatus {
+            self.health.clone()
+        }
+
+        /// Returns the current [`NodeStatus`](struct.NodeStatus.html), the same
+        /// report served by the health endpoint.
+        pub fn status(&self) -> NodeStatus {
+            self.health.status()
+        }
+
+        /// The app and a handle to the committed store, for handling requests
+        /// which don't go through `run`.
+        pub(crate) fn app_and_store(&self) -> (&A, Shared<MerkStore>) {
+            (
+                self.app.as_ref().unwrap(),
+                self.store.as_ref().unwrap().clone(),
+            )
+        }
+
         /// Handles a single incoming ABCI request.
         ///
         /// Some messages, such as `info`, `flush`, and `echo` are automatically
         /// handled by the `ABCIStateMachine`, while others are passed to the
-        /// [`Application`](trait.Application.html).
-        pub fn run(&mut self, req: Request) -> Result<Res> {
+        /// [`Application`](trait.Application.html). The request and its
+        /// response pass through each registered
+        /// [`Middleware`](trait.Middleware.html).
+        pub fn run(&mut self, mut req: Request) -> Result<Res> {
+            if self.middleware.is_empty() {
+                return self.run_request(req);
+            }
+
+            let mut middleware = std::mem::take(&mut self.middleware);
+            let before = middleware
+                .iter_mut()
+                .try_for_each(|middleware| middleware.before(&mut req));
+            let res = match before {
+                Ok(()) => {
+                    let req_copy = req.clone();
+                    let mut res = self.run_request(req);
+                    for middleware in middleware.iter_mut().rev() {
+                        middleware.after(&req_copy, &mut res);
+                    }
+                    res
+                }
+                Err(err) => Err(err),
+            };
+            self.middleware = middleware;
+
+            res
+        }
+
+        fn run_request(&mut self, req: Request) -> Result<Res> {
             let value = match req.value {
                 None => {
                     return Err(Error::ABCI("Received empty request".into()));
@@ -115,19 +585,15 @@ mod server {
                     let store = self.store.take().unwrap();
                     let app = self.app.take().unwrap();
 
-                    let res = app
-                        .query(store.clone(), req)
-                        .unwrap_or_else(|err| ResponseQuery {
-                            code: 1,
-                            log: err.to_string(),
-                            info: err.to_string(),
-                            codespace: "".to_string(),
-                            height: self.height as i64,
-                            index: 0,
-                            key: vec![].into(),
-                            proof_ops: None,
-                            value: vec![].into(),
-                        });
+                    let res = if req.path == TX_QUERY_PATH {
+                        match self.tx_index {
+                            Some(_) => tx_index::query_tx(&store.borrow(), &req),
+                            None => Err(Error::Query("Tx indexing is disabled".into())),
+                        }
+                    } else {
+                        app.query(store.clone(), req)
+                    };
+                    let res = res.unwrap_or_else(|err| query_error(err, self.height as i64));
 
                     self.store.replace(store);
                     self.app.replace(app);
@@ -166,13 +632,11 @@ mod server {
                     Ok(Res::InitChain(res_init_chain))
                 }
                 Req::BeginBlock(req) => {
-                    if let Some(stop_height_str) = env::var_os("ORGA_STOP_HEIGHT") {
-                        let stop_height: i64 = stop_height_str
-                            .into_string()
-                            .unwrap()
-                            .parse()
-                            .expect("Invalid ORGA_STOP_HEIGHT value");
-                        if req.header.as_ref().unwrap().height > stop_height {
+                    if let Some(reason) = self.halted.as_ref() {
+                        return Err(Error::ABCI(reason.clone()));
+                    }
+                    if let Some(stop_height) = self.halt.stop_height {
+                        if req.header.as_ref().unwrap().height as u64 > stop_height {
                             return Err(Error::ABCI(format!(
                                 "Reached stop height ({})",
                                 stop_height
@@ -184,10 +648,25 @@ mod server {
                     let self_store = self.store.take().unwrap().into_inner();
                     let self_store_shared = Shared::new(self_store);
                     self.header = req.header.clone();
+                    if let Some(index) = self.tx_index.as_mut() {
+                        index.begin_block();
+                    }
+                    if let Some(prefix_len) = self.store_metrics {
+                        Context::add(StoreMetrics::new(prefix_len));
+                    }
+
+                    // a block always starts from committed state, so leftover
+                    // writes come from a block interrupted by Tendermint
+                    // disconnecting, which it will replay after reconnecting
+                    let pending = self.consensus_state.take().unwrap();
+                    if !pending.is_empty() {
+                        log::warn!("Discarding writes from an uncommitted block");
+                    }
+                    self.consensus_written = 0;
 
                     let mut store = Some(Shared::new(BufStore::wrap_with_map(
                         self_store_shared.clone(),
-                        self.consensus_state.take().unwrap(),
+                        Default::default(),
                     )));
 
                     let res_begin_block = {
@@ -209,6 +688,8 @@ mod server {
                     Ok(Res::BeginBlock(res_begin_block))
                 }
                 Req::DeliverTx(req) => {
+                    let tx_bytes = (self.rejected_tx_log.is_some() || self.tx_index.is_some())
+                        .then(|| req.tx.clone());
                     let app = self.app.take().unwrap();
                     let self_store = self.store.take().unwrap().into_inner();
                     let self_store_shared = Shared::new(self_store);
@@ -219,15 +700,30 @@ mod server {
 
                     let res_deliver_tx = {
                         let owned_store = store.take().unwrap();
-                        let flush_store = Shared::new(BufStore::wrap(owned_store.clone()));
-                        let res = app.deliver_tx(flush_store.clone(), req)?;
-                        {
-                            let mut unwrapped_fs = flush_store.into_inner();
-                            unwrapped_fs.flush()?;
-                        }
-                        let mut owned_store_inner = owned_store.into_inner();
-                        owned_store_inner.flush()?;
-                        let owned_store = Shared::new(owned_store_inner);
+                        let mut flush_store = Shared::new(BufStore::wrap(owned_store.clone()));
+                        let budget = self.tx_budget(self.consensus_written);
+                        flush_store.borrow_mut().set_budget(budget);
+                        let res = app.deliver_tx(flush_store.clone(), req);
+                        let res = match res {
+                            Err(err) if is_budget_exceeded(&err) => {
+                                let (codespace, code) = err.code();
+                                ResponseDeliverTx {
+                                    code,
+                                    codespace: codespace.to_string(),
+                                    log: err.to_string(),
+                                    ..Default::default()
+                                }
+                            }
+                            res => {
+                                let res = res?;
+                                let mut unwrapped_fs = flush_store.into_inner();
+                                self.consensus_written += unwrapped_fs.buffered_bytes();
+                                unwrapped_fs.flush()?;
+                                res
+                            }
+                        };
+                        // writes stay buffered in `consensus_state` until commit
+                        // so that CheckTx never observes a partially-executed block
                         store.replace(owned_store);
                         res
                     };
@@ -237,6 +733,15 @@ mod server {
                         .replace(store.unwrap().into_inner().into_map());
                     let self_store = self_store_shared.into_inner();
                     self.store = Some(Shared::new(self_store));
+                    if let (Some(index), Some(tx)) = (self.tx_index.as_mut(), tx_bytes.as_ref()) {
+                        let height = self.header.as_ref().map_or(0, |header| header.height);
+                        index.record(tx, height as u64, &res_deliver_tx);
+                    }
+                    if let (Some(log), Some(tx)) = (self.rejected_tx_log.as_mut(), tx_bytes) {
+                        if res_deliver_tx.code != 0 {
+                            log.log("DeliverTx", &tx, res_deliver_tx.code, &res_deliver_tx.log);
+                        }
+                    }
                     Ok(Res::DeliverTx(res_deliver_tx))
                 }
                 Req::EndBlock(req) => {
@@ -253,7 +758,15 @@ mod server {
                     let res_end_block = {
                         let owned_store = store.take().unwrap();
                         let flush_store = Shared::new(BufStore::wrap(owned_store.clone()));
-                        let res = app.end_block(flush_store.clone(), req)?;
+                        let res = match app.end_block(flush_store.clone(), req) {
+                            Err(Error::InvariantViolation(msg)) => {
+                                let msg = format!("at height {}: {}", self.height, msg);
+                                log::error!("Invariant violation {}, halting", msg);
+                                self.invariant_violation = Some(msg.clone());
+                                return Err(Error::InvariantViolation(msg));
+                            }
+                            res => res?,
+                        };
                         let mut unwrapped_fs = flush_store.into_inner();
                         unwrapped_fs.flush()?;
                         store.replace(owned_store);
@@ -268,6 +781,13 @@ mod server {
                     Ok(Res::EndBlock(res_end_block))
                 }
                 Req::Commit(_) => {
+                    if let Some(msg) = self.invariant_violation.as_ref() {
+                        return Err(Error::InvariantViolation(format!(
+                            "refusing to commit, {}",
+                            msg
+                        )));
+                    }
+
                     let self_store = self.store.take().unwrap().into_inner();
                     let mut self_store_shared = Shared::new(self_store);
                     {
@@ -278,21 +798,46 @@ mod server {
                         store.flush()?;
                     }
 
+                    if let Some(index) = self.tx_index.as_mut() {
+                        index.commit(&mut self_store_shared.borrow_mut());
+                    }
                     self_store_shared
                         .borrow_mut()
                         .commit(self.header.clone().unwrap())?;
 
                     self.mempool_state.replace(Default::default());
                     self.consensus_state.replace(Default::default());
+                    self.mempool_written = 0;
 
                     let mut res_commit = ResponseCommit::default();
                     let self_store = self_store_shared.into_inner();
 
-                    res_commit.data = self_store.root_hash()?.into();
+                    let app_hash = self_store.root_hash()?;
+                    let height = self_store.height()?;
+                    let block_time = self
+                        .header
+                        .as_ref()
+                        .and_then(|header| header.time.as_ref())
+                        .map(|time| time.seconds);
+                    self.health.set_commit(CommitInfo {
+                        height,
+                        app_hash: app_hash.clone(),
+                        block_time,
+                        key_count: self_store.key_count()?,
+                    });
+                    self.halted = self.halt.check(height, block_time, &app_hash);
+                    if let Some(queries) = self.concurrent_queries.as_ref() {
+                        queries.publish(&self_store);
+                    }
+                    if self.store_metrics.is_some() {
+                        log_store_metrics(height);
+                    }
+                    res_commit.data = app_hash.into();
                     self.store = Some(Shared::new(self_store));
                     Ok(Res::Commit(res_commit))
                 }
                 Req::CheckTx(req) => {
+                    let tx_bytes = self.rejected_tx_log.as_ref().map(|_| req.tx.clone());
                     let app = self.app.take().unwrap();
                     let self_store = self.store.take().unwrap().into_inner();
                     let self_store_shared = Shared::new(self_store);
@@ -303,11 +848,27 @@ mod server {
 
                     let res_check_tx = {
                         let owned_store = store.take().unwrap();
-                        let flush_store = Shared::new(BufStore::wrap(owned_store.clone()));
-                        let res = app.check_tx(flush_store.clone(), req)?;
-
-                        let mut unwrapped_fs = flush_store.into_inner();
-                        unwrapped_fs.flush()?;
+                        let mut flush_store = Shared::new(BufStore::wrap(owned_store.clone()));
+                        let budget = self.tx_budget(self.mempool_written);
+                        flush_store.borrow_mut().set_budget(budget);
+                        let res = match app.check_tx(flush_store.clone(), req) {
+                            Err(err) if is_budget_exceeded(&err) => {
+                                let (codespace, code) = err.code();
+                                ResponseCheckTx {
+                                    code,
+                                    codespace: codespace.to_string(),
+                                    log: err.to_string(),
+                                    ..Default::default()
+                                }
+                            }
+                            res => {
+                                let res = res?;
+                                let mut unwrapped_fs = flush_store.into_inner();
+                                self.mempool_written += unwrapped_fs.buffered_bytes();
+                                unwrapped_fs.flush()?;
+                                res
+                            }
+                        };
                         store.replace(owned_store);
                         res
                     };
@@ -316,6 +877,14 @@ mod server {
                     self.mempool_state
                         .replace(store.unwrap().into_inner().into_map());
                     self.store = Some(Shared::new(self_store_shared.into_inner()));
+                    if res_check_tx.code == 0 {
+                        self.health.add_pending_tx();
+                    }
+                    if let (Some(log), Some(tx)) = (self.rejected_tx_log.as_mut(), tx_bytes) {
+                        if res_check_tx.code != 0 {
+                            log.log("CheckTx", &tx, res_check_tx.code, &res_check_tx.log);
+                        }
+                    }
                     Ok(Res::CheckTx(res_check_tx))
                 }
                 Req::ListSnapshots(_req) => {
@@ -341,8 +910,14 @@ mod server {
                 Req::ApplySnapshotChunk(req) => {
                     let self_store = self.store.as_mut().unwrap();
                     let mut res = ResponseApplySnapshotChunk::default();
-                    match self_store.borrow_mut().apply_snapshot_chunk(req.clone()) {
-                        Ok(_) => res.result = 1, // ACCEPT
+                    let applied = self_store.borrow_mut().apply_snapshot_chunk(req.clone());
+                    match applied {
+                        Ok(_) => {
+                            res.result = 1; // ACCEPT
+                            if let Some(queries) = self.concurrent_queries.as_ref() {
+                                queries.publish(&self_store.borrow());
+                            }
+                        }
                         Err(_) => {
                             res.result = 3; // RETRY
                             res.refetch_chunks = vec![req.index];
@@ -355,31 +930,48 @@ mod server {
             }
         }
 
-        /// Creates a TCP server for the ABCI protocol and begins handling the
-        /// incoming connections.
+        /// Creates a TCP server for the ABCI protocol, accepts the configured
+        /// number of connections (see `worker_count`), and begins handling
+        /// their requests.
+        ///
+        /// When Tendermint closes a connection (e.g. when it restarts), a
+        /// replacement connection is accepted rather than shutting down. Only
+        /// protocol errors and errors from handling requests are fatal.
+        ///
+        /// When stopped through a
+        /// [`ShutdownHandle`](struct.ShutdownHandle.html), returns `Ok` with the
+        /// shutdown notifier passed to `new`, which has been set to `true`.
         pub fn listen<SA: ToSocketAddrs>(mut self, addr: SA) -> Result<Arc<RwLock<bool>>> {
-            if let Some(stop_height_str) = env::var_os("ORGA_STOP_HEIGHT") {
-                let _stop_height: u64 = stop_height_str
-                    .into_string()
-                    .unwrap()
-                    .parse()
-                    .expect("Invalid ORGA_STOP_HEIGHT value");
+            if let Some(err) = self.halt_error.take() {
+                return Err(err);
             }
 
             let server = abci2::Server::listen(addr)?;
 
-            // TODO: keep workers in struct
-            // TODO: more intelligently handle connections, e.g. handle tendermint dying/reconnecting?
-            self.create_worker(server.accept()?, self.shutdown.clone())?;
-            self.create_worker(server.accept()?, self.shutdown.clone())?;
-            self.create_worker(server.accept()?, self.shutdown.clone())?;
-            self.create_worker(server.accept()?, self.shutdown.clone())?;
+            for _ in 0..self.worker_count {
+                let worker = self.create_worker(server.accept()?, self.shutdown.clone())?;
+                self.workers.push(worker);
+            }
+            self.health.set_accepting(true);
+
+            let res = self.handle_requests(&server);
+            self.stop_workers();
+            res.map(|_| self.shutdown_notifier.clone())
+        }
 
+        fn handle_requests(&mut self, server: &abci2::Server) -> Result<()> {
+            let mut in_block = false;
             loop {
-                if let Some(e) = self.shutdown.read().unwrap().as_ref() {
-                    let mut shutdown = self.shutdown_notifier.write().unwrap();
-                    *shutdown = true;
-                    return Err(Error::ABCI(e.to_string()));
+                if let Some(res) = self.check_shutdown(in_block) {
+                    return res;
+                }
+                while self.closed_receiver.try_recv().is_ok() {
+                    log::info!("ABCI connection closed, waiting for Tendermint to reconnect");
+                    self.health.set_accepting(false);
+                    self.join_finished_workers();
+                    let worker = self.create_worker(server.accept()?, self.shutdown.clone())?;
+                    self.workers.push(worker);
+                    self.health.set_accepting(true);
                 }
                 let (req, cb) = match self
                     .receiver
@@ -392,39 +984,127 @@ mod server {
                     }
                 };
                 let is_commit = matches!(req.value, Some(Req::Commit(_)));
-                let value = match self.run(req) {
-                    Ok(val) => val,
-                    Err(e) => {
-                        let mut shutdown = self.shutdown.write().unwrap();
-                        *shutdown = Some(Error::ABCI(e.to_string()));
-                        let mut shutdown = self.shutdown_notifier.write().unwrap();
-                        *shutdown = true;
-                        return Err(e);
-                    }
-                };
-                let res = Response { value: Some(value) };
+                let res = self.handle_request(req, &mut in_block)?;
                 cb.send(res).unwrap();
 
                 if is_commit {
-                    if let Some(stop_height_str) = env::var_os("ORGA_STOP_HEIGHT") {
-                        let stop_height: u64 = stop_height_str
-                            .into_string()
-                            .unwrap()
-                            .parse()
-                            .expect("Invalid ORGA_STOP_HEIGHT value");
-                        if self.height >= stop_height {
-                            let mut shutdown = self.shutdown_notifier.write().unwrap();
-                            *shutdown = true;
-                            break Err(Error::ABCI(format!(
-                                "Reached stop height ({})",
-                                stop_height
-                            )));
+                    self.check_halted()?;
+                }
+            }
+        }
+
+        /// Like `listen`, but serves the ABCI connections as tasks on the
+        /// current tokio runtime instead of dedicated worker threads.
+        ///
+        /// Every connection Tendermint opens is accepted, so `worker_count` is
+        /// ignored. Requests are still handled one at a time, directly on the
+        /// task polling this future. Since the state machine isn't `Send`, the
+        /// future must be polled where it is created (e.g. with `block_on` or a
+        /// `LocalSet`) rather than passed to `tokio::spawn`.
+        ///
+        /// Stops under the same conditions as `listen`.
+        pub async fn listen_async<SA: tokio::net::ToSocketAddrs>(mut self, addr: SA) -> Result<()> {
+            if let Some(err) = self.halt_error.take() {
+                return Err(err);
+            }
+
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+            let mut connections = tokio::task::JoinSet::new();
+            let mut poll = tokio::time::interval(std::time::Duration::from_secs(1));
+            self.health.set_accepting(true);
+
+            let mut in_block = false;
+            let res = loop {
+                if let Some(res) = self.check_shutdown(in_block) {
+                    break res;
+                }
+                tokio::select! {
+                    conn = listener.accept() => match conn {
+                        Ok((stream, _)) => {
+                            connections.spawn(serve_connection(
+                                stream,
+                                sender.clone(),
+                                self.concurrent_queries.clone(),
+                                self.shutdown.clone(),
+                            ));
+                        }
+                        Err(e) => break Err(e.into()),
+                    },
+                    Some((req, cb)) = receiver.recv() => {
+                        let is_commit = matches!(req.value, Some(Req::Commit(_)));
+                        let res = match self.handle_request(req, &mut in_block) {
+                            Ok(res) => res,
+                            Err(e) => break Err(e),
+                        };
+                        let _ = cb.send(res);
+
+                        if is_commit {
+                            if let Err(e) = self.check_halted() {
+                                break Err(e);
+                            }
                         }
                     }
+                    _ = poll.tick() => {}
+                }
+            };
+
+            connections.shutdown().await;
+            res
+        }
+
+        /// Returns the result `listen` should stop with, if a connection or
+        /// handler failed, or if a shutdown was requested and no block is in
+        /// progress.
+        fn check_shutdown(&self, in_block: bool) -> Option<Result<()>> {
+            if let Some(e) = self.shutdown.read().unwrap().as_ref() {
+                let mut shutdown = self.shutdown_notifier.write().unwrap();
+                *shutdown = true;
+                return Some(Err(Error::ABCI(e.to_string())));
+            }
+            if !in_block && self.shutdown_handle.is_requested() {
+                log::info!("Shutdown requested, stopping ABCI server");
+                let mut shutdown = self.shutdown_notifier.write().unwrap();
+                *shutdown = true;
+                return Some(Ok(()));
+            }
+            None
+        }
+
+        /// Runs a request received from a connection, tracking whether a block
+        /// is in progress. Errors are fatal, so they also shut down the
+        /// connections.
+        fn handle_request(&mut self, req: Request, in_block: &mut bool) -> Result<Response> {
+            match req.value {
+                Some(Req::BeginBlock(_)) => *in_block = true,
+                Some(Req::Commit(_)) => *in_block = false,
+                _ => {}
+            }
+            match self.run(req) {
+                Ok(value) => Ok(Response { value: Some(value) }),
+                Err(e) => {
+                    let mut shutdown = self.shutdown.write().unwrap();
+                    *shutdown = Some(Error::ABCI(e.to_string()));
+                    let mut shutdown = self.shutdown_notifier.write().unwrap();
+                    *shutdown = true;
+                    Err(e)
                 }
             }
         }
 
+        /// Called after each commit, returning an error once a halt condition
+        /// has been met.
+        fn check_halted(&self) -> Result<()> {
+            let reason = match self.halted.as_ref() {
+                Some(reason) => reason,
+                None => return Ok(()),
+            };
+
+            let mut shutdown = self.shutdown_notifier.write().unwrap();
+            *shutdown = true;
+            Err(Error::ABCI(reason.clone()))
+        }
+
         /// Creates a new worker to handle the incoming ABCI requests for `conn`
         /// within its own threads.
         fn create_worker(
@@ -432,20 +1112,63 @@ mod server {
             conn: abci2::Connection,
             shutdown: Arc<RwLock<Option<Error>>>,
         ) -> Result<Worker> {
-            Ok(Worker::new(self.sender.clone(), conn, shutdown))
+            Ok(Worker::new(
+                self.sender.clone(),
+                conn,
+                self.concurrent_queries.clone(),
+                shutdown,
+                self.closed_sender.clone(),
+            ))
+        }
+
+        /// Joins the workers which have exited, e.g. because their connection
+        /// was closed.
+        fn join_finished_workers(&mut self) {
+            let (finished, running): (Vec<_>, Vec<_>) = self
+                .workers
+                .drain(..)
+                .partition(|worker| worker.thread.is_finished());
+            self.workers = running;
+
+            for worker in finished {
+                if worker.thread.join().is_err() {
+                    log::warn!("ABCI worker panicked");
+                }
+            }
+        }
+
+        /// Joins the workers which have exited. Workers still blocked reading
+        /// from their connection can't be interrupted, so they are detached
+        /// instead, and exit once their connection is closed or their next
+        /// request fails to send to the dropped state machine.
+        fn stop_workers(&mut self) {
+            self.join_finished_workers();
+            if !self.workers.is_empty() {
+                log::debug!(
+                    "Detaching {} ABCI workers blocked on their connections",
+                    self.workers.len()
+                );
+                self.workers.clear();
+            }
         }
     }
 
     struct Worker {
-        #[allow(dead_code)]
         thread: std::thread::JoinHandle<()>, // TODO: keep handle to connection or socket so we can close it
     }
 
     impl Worker {
+        /// Spawns a thread which forwards requests from `conn` to the state
+        /// machine, answering queries itself if `queries` is set. If the
+        /// connection is closed by Tendermint, the thread exits and notifies
+        /// `closed` so a replacement can be accepted, while other errors shut
+        /// down the state machine.
         fn new(
             req_sender: SyncSender<(Request, SyncSender<Response>)>,
             mut conn: abci2::Connection,
+            queries: Option<ConcurrentQueries>,
             shutdown: Arc<RwLock<Option<Error>>>,
+            closed: Sender<()>,
         ) -> Self {
             let thread = std::thread::spawn(move || {
                 let (res_sender, res_receiver) = mpsc::sync_channel(0);
@@ -456,26 +1179,144 @@ mod server {
                         };
                         break;
                     }
-                    let req = match conn.read() {
-                        Ok(req) => req,
+                    let res = conn.read().and_then(|req| {
+                        if let Some(res) = queries.as_ref().and_then(|q| q.answer(&req)) {
+                            conn.write(res)?;
+                            return Ok(true);
+                        }
+                        if let Err(err) = req_sender.send((req, res_sender.clone())) {
+                            log::warn!("Error sending request from worker: {}", err);
+                            return Ok(false);
+                        }
+                        let res = res_receiver.recv().unwrap();
+                        conn.write(res)?;
+                        Ok(true)
+                    });
+                    match res {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(e) if is_disconnect(&e) => {
+                            log::debug!("ABCI connection closed: {}", e);
+                            let _ = closed.send(());
+                            return;
+                        }
                         Err(e) => {
                             let mut shutdown = shutdown.write().unwrap();
                             *shutdown = Some(Error::ABCI2(e));
                             return;
                         }
-                    };
-                    if let Err(err) = req_sender.send((req, res_sender.clone())) {
-                        log::warn!("Error sending request from worker: {}", err);
-                        break;
                     }
-                    let res = res_receiver.recv().unwrap();
-                    conn.write(res).unwrap();
                 }
             });
             Worker { thread }
         }
     }
 
+    type AsyncRequest = (Request, tokio::sync::oneshot::Sender<Response>);
+
+    /// Forwards the requests read from an ABCI connection to
+    /// `ABCIStateMachine::listen_async` and writes back the responses,
+    /// answering queries itself if `queries` is set. If the connection is
+    /// closed by Tendermint the task exits quietly, while other errors shut
+    /// down the state machine.
+    async fn serve_connection(
+        stream: tokio::net::TcpStream,
+        requests: tokio::sync::mpsc::Sender<AsyncRequest>,
+        queries: Option<ConcurrentQueries>,
+        shutdown: Arc<RwLock<Option<Error>>>,
+    ) {
+        use ::prost::Message;
+        use tokio::io::AsyncWriteExt;
+
+        let (read, mut write) = stream.into_split();
+        let mut read = tokio::io::BufReader::new(read);
+        let res: std::io::Result<()> = async {
+            while let Some(req) = read_request(&mut read).await? {
+                if let Some(res) = queries.as_ref().and_then(|q| q.answer(&req)) {
+                    write
+                        .write_all(&res.encode_length_delimited_to_vec())
+                        .await?;
+                    continue;
+                }
+                let (res_sender, res_receiver) = tokio::sync::oneshot::channel();
+                if requests.send((req, res_sender)).await.is_err() {
+                    return Ok(());
+                }
+                let res = match res_receiver.await {
+                    Ok(res) => res,
+                    Err(_) => return Ok(()),
+                };
+                write
+                    .write_all(&res.encode_length_delimited_to_vec())
+                    .await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        match res {
+            Ok(()) => log::debug!("ABCI connection closed"),
+            Err(e) if is_disconnect_kind(e.kind()) => {
+                log::debug!("ABCI connection closed: {}", e)
+            }
+            Err(e) => {
+                let mut shutdown = shutdown.write().unwrap();
+                *shutdown = Some(Error::IO(e));
+            }
+        }
+    }
+
+    /// Reads a length-prefixed request, returning `None` if the connection
+    /// was closed between requests.
+    async fn read_request<R>(read: &mut R) -> std::io::Result<Option<Request>>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        use ::prost::Message;
+        use std::io::{Error as IoError, ErrorKind};
+        use tokio::io::AsyncReadExt;
+
+        let mut len = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = match read.read_u8().await {
+                Ok(byte) => byte,
+                Err(e) if shift == 0 && e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            len |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                let mut bytes = vec![0; len];
+                read.read_exact(&mut bytes).await?;
+                return Request::decode(bytes.as_slice())
+                    .map(Some)
+                    .map_err(|e| IoError::new(ErrorKind::InvalidData, e));
+            }
+        }
+
+        Err(IoError::new(
+            ErrorKind::InvalidData,
+            "Invalid ABCI message length prefix",
+        ))
+    }
+
+    fn is_disconnect_kind(kind: std::io::ErrorKind) -> bool {
+        use std::io::ErrorKind::*;
+
+        matches!(
+            kind,
+            UnexpectedEof | ConnectionReset | ConnectionAborted | BrokenPipe
+        )
+    }
+
+    /// Whether `err` means the peer closed the connection, as opposed to a
+    /// protocol error.
+    fn is_disconnect(err: &abci2::Error) -> bool {
+        match err {
+            abci2::Error::Io(err) => is_disconnect_kind(err.kind()),
+            _ => false,
+        }
+    }
+
     pub type WrappedMerk = Shared<BufStore<Shared<BufStore<Shared<MerkStore>>>>>;
     /// An interface for handling ABCI requests.
     ///
@@ -524,6 +1365,12 @@ mod server {
         fn query(&self, _store: Shared<MerkStore>, _req: RequestQuery) -> Result<ResponseQuery> {
             Ok(Default::default())
         }
+
+        /// Returns a handler for answering queries off the state machine's
+        /// thread, used if `ABCIStateMachine::concurrent_queries` is enabled.
+        fn query_handler(&self) -> Option<QueryHandler> {
+            None
+        }
     }
 
     /// Interface for persisting ABCI app state, as a supertrait of [`store::Store`](../store/trait.Store.html).
@@ -545,18 +1392,67 @@ mod server {
 
     /// A basic implementation of [`ABCIStore`](trait.ABCIStore.html) which persists
     /// data in memory (mostly for use in testing).
+    ///
+    /// Snapshots consist of a single chunk holding the entire store, which is
+    /// enough to exercise state sync without a real database.
     pub struct MemStore {
         height: u64,
         store: MapStore,
+        restoring: Option<Snapshot>,
     }
 
+    const MEM_SNAPSHOT_FORMAT: u32 = 0;
+
     impl MemStore {
         pub fn new() -> Self {
             MemStore {
                 height: 0,
                 store: MapStore::new(),
+                restoring: None,
             }
         }
+
+        /// Encodes the key/value entries in key order, each length-prefixed
+        /// with a big-endian `u64`.
+        fn encode_entries(&self) -> Result<Vec<u8>> {
+            let mut bytes = vec![];
+            let mut entry = self.store.get_next_inclusive(&[])?;
+            while let Some((key, value)) = entry {
+                bytes.extend((key.len() as u64).to_be_bytes());
+                bytes.extend(&key);
+                bytes.extend((value.len() as u64).to_be_bytes());
+                bytes.extend(&value);
+                entry = self.store.get_next(&key)?;
+            }
+
+            Ok(bytes)
+        }
+
+        fn decode_entries(mut bytes: &[u8]) -> Result<MapStore> {
+            fn take<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8]> {
+                let invalid = || Error::ABCI("Invalid snapshot chunk".into());
+                if bytes.len() < 8 {
+                    return Err(invalid());
+                }
+                let (len, rest) = bytes.split_at(8);
+                let len = u64::from_be_bytes(len.try_into().unwrap()) as usize;
+                if rest.len() < len {
+                    return Err(invalid());
+                }
+                let (value, rest) = rest.split_at(len);
+                *bytes = rest;
+                Ok(value)
+            }
+
+            let mut store = MapStore::new();
+            while !bytes.is_empty() {
+                let key = take(&mut bytes)?.to_vec();
+                let value = take(&mut bytes)?.to_vec();
+                store.put(key, value)?;
+            }
+
+            Ok(store)
+        }
     }
 
     impl Default for MemStore {
@@ -594,9 +1490,12 @@ mod server {
             Ok(self.height)
         }
 
+        /// Hashes the length-prefixed key/value entries in key order with
+        /// SHA-256, so the hash only depends on the store's contents.
         fn root_hash(&self) -> Result<Vec<u8>> {
-            // TODO: real hashing based on writes
-            Ok(vec![])
+            use sha2::{Digest, Sha256};
+
+            Ok(Sha256::digest(self.encode_entries()?).to_vec())
         }
 
         fn commit(&mut self, header: Header) -> Result<()> {
@@ -604,20 +1503,818 @@ mod server {
             Ok(())
         }
 
+        /// Returns a single snapshot of the current state, once a height has
+        /// been committed.
         fn list_snapshots(&self) -> Result<Vec<Snapshot>> {
-            Ok(Default::default())
+            if self.height == 0 {
+                return Ok(vec![]);
+            }
+
+            Ok(vec![Snapshot {
+                height: self.height,
+                format: MEM_SNAPSHOT_FORMAT,
+                chunks: 1,
+                hash: self.root_hash()?.into(),
+                metadata: Default::default(),
+            }])
         }
 
-        fn load_snapshot_chunk(&self, _req: RequestLoadSnapshotChunk) -> Result<Vec<u8>> {
-            unimplemented!()
+        fn load_snapshot_chunk(&self, req: RequestLoadSnapshotChunk) -> Result<Vec<u8>> {
+            if req.height != self.height || req.format != MEM_SNAPSHOT_FORMAT || req.chunk != 0 {
+                return Err(Error::ABCI(format!(
+                    "Snapshot chunk not found: height {}, format {}, chunk {}",
+                    req.height, req.format, req.chunk
+                )));
+            }
+
+            self.encode_entries()
         }
 
-        fn apply_snapshot_chunk(&mut self, _req: RequestApplySnapshotChunk) -> Result<()> {
-            unimplemented!()
+        fn apply_snapshot_chunk(&mut self, req: RequestApplySnapshotChunk) -> Result<()> {
+            let snapshot = self
+                .restoring
+                .as_ref()
+                .ok_or_else(|| Error::ABCI("No snapshot is being restored".into()))?;
+            if req.index != 0 {
+                return Err(Error::ABCI(format!("Unexpected chunk index {}", req.index)));
+            }
+
+            let store = Self::decode_entries(&req.chunk)?;
+            let restored = MemStore {
+                height: snapshot.height,
+                store,
+                restoring: None,
+            };
+            if restored.root_hash()? != snapshot.hash.to_vec() {
+                return Err(Error::ABCI("Snapshot chunk does not match hash".into()));
+            }
+
+            *self = restored;
+            Ok(())
         }
 
-        fn offer_snapshot(&mut self, _req: RequestOfferSnapshot) -> Result<ResponseOfferSnapshot> {
-            Ok(Default::default())
+        fn offer_snapshot(&mut self, req: RequestOfferSnapshot) -> Result<ResponseOfferSnapshot> {
+            let mut res = ResponseOfferSnapshot::default();
+            res.set_result(response_offer_snapshot::Result::Reject);
+
+            if let Some(snapshot) = req.snapshot {
+                if snapshot.format == MEM_SNAPSHOT_FORMAT && snapshot.chunks == 1 {
+                    self.restoring = Some(snapshot);
+                    res.set_result(response_offer_snapshot::Result::Accept);
+                }
+            }
+
+            Ok(res)
+        }
+    }
+
+    #[cfg(all(test, feature = "merk-full"))]
+    mod tests {
+        use super::*;
+        use crate::plugins::ChainId;
+        use tendermint_proto::google::protobuf::Timestamp;
+
+        /// Interprets txs as `put:<key>:<value>` or `get:<key>`, reporting read
+        /// values in the response log.
+        struct KvApp;
+
+        impl KvApp {
+            fn exec(store: WrappedMerk, tx: &[u8]) -> Result<String> {
+                let tx = String::from_utf8(tx.to_vec()).unwrap();
+                let parts: Vec<_> = tx.split(':').collect();
+                let mut store = store;
+                match parts.as_slice() {
+                    ["put", key, value] => {
+                        store.put(key.as_bytes().to_vec(), value.as_bytes().to_vec())?;
+                        Ok(String::new())
+                    }
+                    ["get", key] => Ok(store
+                        .get(key.as_bytes())?
+                        .map(|v| String::from_utf8(v).unwrap())
+                        .unwrap_or_default()),
+                    _ => Err(Error::App("Invalid tx".into())),
+                }
+            }
+        }
+
+        impl Application for KvApp {
+            fn deliver_tx(
+                &self,
+                store: WrappedMerk,
+                req: RequestDeliverTx,
+            ) -> Result<ResponseDeliverTx> {
+                Ok(ResponseDeliverTx {
+                    log: Self::exec(store, &req.tx)?,
+                    ..Default::default()
+                })
+            }
+
+            fn check_tx(&self, store: WrappedMerk, req: RequestCheckTx) -> Result<ResponseCheckTx> {
+                Ok(ResponseCheckTx {
+                    log: Self::exec(store, &req.tx)?,
+                    ..Default::default()
+                })
+            }
+
+            fn end_block(
+                &self,
+                store: WrappedMerk,
+                _req: RequestEndBlock,
+            ) -> Result<ResponseEndBlock> {
+                if store.get(b"corrupt")?.is_some() {
+                    return Err(Error::InvariantViolation("corrupt key present".into()));
+                }
+                Ok(Default::default())
+            }
+
+            /// Answers `/kv` queries with the latest committed value of the key
+            /// in the query data, and `/chain_id` queries with the
+            /// [`ChainId`](../plugins/struct.ChainId.html) context.
+            fn query_handler(&self) -> Option<QueryHandler> {
+                Some(Arc::new(|view: &ReadView, req: &RequestQuery| {
+                    if req.path == "/chain_id" {
+                        let res = Context::resolve::<ChainId>()
+                            .ok_or_else(|| Error::App("Chain ID context not set".into()))
+                            .map(|chain_id| ResponseQuery {
+                                value: chain_id.0.clone().into_bytes().into(),
+                                ..Default::default()
+                            });
+                        return Some(res);
+                    }
+                    if req.path != "/kv" {
+                        return None;
+                    }
+                    let res = view
+                        .read_handle(None)
+                        .ok_or_else(|| view.missing_height_error(0))
+                        .and_then(|handle| {
+                            Ok(ResponseQuery {
+                                height: handle.height() as i64,
+                                value: handle.get(&req.data)?.unwrap_or_default().into(),
+                                ..Default::default()
+                            })
+                        });
+                    Some(res)
+                }))
+            }
+        }
+
+        fn state_machine(home: &tempdir::TempDir) -> ABCIStateMachine<KvApp> {
+            ABCIStateMachine::new(
+                KvApp,
+                MerkStore::new(home.path()),
+                false,
+                Default::default(),
+                Default::default(),
+            )
+        }
+
+        fn begin_block(sm: &mut ABCIStateMachine<KvApp>, height: i64) -> Result<()> {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+            let header = Header {
+                height,
+                time: Some(Timestamp {
+                    seconds: now,
+                    nanos: 0,
+                }),
+                ..Default::default()
+            };
+            sm.run(Request {
+                value: Some(Req::BeginBlock(RequestBeginBlock {
+                    header: Some(header),
+                    ..Default::default()
+                })),
+            })?;
+            Ok(())
+        }
+
+        fn end_block_and_commit(sm: &mut ABCIStateMachine<KvApp>, height: i64) -> Result<()> {
+            sm.run(Request {
+                value: Some(Req::EndBlock(RequestEndBlock { height })),
+            })?;
+            sm.run(Request {
+                value: Some(Req::Commit(Default::default())),
+            })?;
+            Ok(())
+        }
+
+        fn deliver_tx(sm: &mut ABCIStateMachine<KvApp>, tx: &str) -> Result<String> {
+            let req = Request {
+                value: Some(Req::DeliverTx(RequestDeliverTx {
+                    tx: tx.as_bytes().to_vec().into(),
+                })),
+            };
+            match sm.run(req)? {
+                Res::DeliverTx(res) => Ok(res.log),
+                _ => unreachable!(),
+            }
+        }
+
+        fn check_tx(sm: &mut ABCIStateMachine<KvApp>, tx: &str) -> Result<String> {
+            let req = Request {
+                value: Some(Req::CheckTx(RequestCheckTx {
+                    tx: tx.as_bytes().to_vec().into(),
+                    ..Default::default()
+                })),
+            };
+            match sm.run(req)? {
+                Res::CheckTx(res) => Ok(res.log),
+                _ => unreachable!(),
+            }
+        }
+
+        #[test]
+        fn check_tx_does_not_see_uncommitted_block() -> Result<()> {
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home);
+
+            begin_block(&mut sm, 1)?;
+            deliver_tx(&mut sm, "put:a:1")?;
+            assert_eq!(deliver_tx(&mut sm, "get:a")?, "1");
+            assert_eq!(check_tx(&mut sm, "get:a")?, "");
+            end_block_and_commit(&mut sm, 1)?;
+
+            assert_eq!(check_tx(&mut sm, "get:a")?, "1");
+
+            Ok(())
+        }
+
+        #[test]
+        fn deliver_tx_does_not_see_mempool_writes() -> Result<()> {
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home);
+
+            check_tx(&mut sm, "put:b:2")?;
+            assert_eq!(check_tx(&mut sm, "get:b")?, "2");
+
+            begin_block(&mut sm, 1)?;
+            assert_eq!(deliver_tx(&mut sm, "get:b")?, "");
+            end_block_and_commit(&mut sm, 1)?;
+
+            // mempool state is reset after commit
+            assert_eq!(check_tx(&mut sm, "get:b")?, "");
+
+            Ok(())
+        }
+
+        #[test]
+        fn write_budget() -> Result<()> {
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home)
+                .tx_write_budget(8)
+                .block_write_budget(12);
+
+            begin_block(&mut sm, 1)?;
+            deliver_tx(&mut sm, "put:a:1")?;
+            let log = deliver_tx(&mut sm, "put:bbbbbbbb:1")?;
+            assert!(log.contains("budget of 8 bytes"));
+            assert_eq!(deliver_tx(&mut sm, "get:bbbbbbbb")?, "");
+
+            // the block's remaining budget limits later txs
+            deliver_tx(&mut sm, "put:c:12345")?;
+            let log = deliver_tx(&mut sm, "put:d:12345")?;
+            assert!(log.contains("budget of 4 bytes"));
+            assert_eq!(deliver_tx(&mut sm, "get:d")?, "");
+            end_block_and_commit(&mut sm, 1)?;
+
+            begin_block(&mut sm, 2)?;
+            deliver_tx(&mut sm, "put:d:12345")?;
+            assert_eq!(deliver_tx(&mut sm, "get:d")?, "12345");
+            end_block_and_commit(&mut sm, 2)?;
+
+            Ok(())
+        }
+
+        #[test]
+        fn tx_index() -> Result<()> {
+            use ::prost::Message;
+
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home).index_txs();
+            let query = |sm: &mut ABCIStateMachine<KvApp>, tx: &str| {
+                let req = Request {
+                    value: Some(Req::Query(RequestQuery {
+                        path: TX_QUERY_PATH.to_string(),
+                        data: tx_hash(tx.as_bytes()).to_vec().into(),
+                        ..Default::default()
+                    })),
+                };
+                match sm.run(req).unwrap() {
+                    Res::Query(res) => res,
+                    _ => unreachable!(),
+                }
+            };
+
+            begin_block(&mut sm, 1)?;
+            deliver_tx(&mut sm, "put:a:1")?;
+            deliver_tx(&mut sm, "get:a")?;
+            assert_eq!(query(&mut sm, "get:a").code, 1);
+            end_block_and_commit(&mut sm, 1)?;
+
+            let res = query(&mut sm, "get:a");
+            assert_eq!(res.code, 0);
+            let indexed = IndexedTx::decode(res.value.as_ref()).unwrap();
+            assert_eq!(indexed.height, 1);
+            assert_eq!(indexed.index, 1);
+            assert_eq!(indexed.result.unwrap().log, "1");
+            assert_eq!(
+                query(&mut sm, "get:b").log,
+                format!(
+                    "Query Error: Tx {} not found",
+                    hex::encode(tx_hash(b"get:b"))
+                )
+            );
+
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home);
+            begin_block(&mut sm, 1)?;
+            deliver_tx(&mut sm, "put:a:1")?;
+            end_block_and_commit(&mut sm, 1)?;
+            assert_eq!(
+                query(&mut sm, "put:a:1").log,
+                "Query Error: Tx indexing is disabled"
+            );
+
+            Ok(())
+        }
+
+        #[test]
+        fn concurrent_queries() -> Result<()> {
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home).concurrent_queries();
+            let queries = sm.concurrent_queries.clone().unwrap();
+            // answers from another thread, like a connection worker
+            let query = move |path: &str| {
+                let queries = queries.clone();
+                let req = Request {
+                    value: Some(Req::Query(RequestQuery {
+                        path: path.to_string(),
+                        data: b"a".to_vec().into(),
+                        ..Default::default()
+                    })),
+                };
+                std::thread::spawn(move || match queries.answer(&req)?.value {
+                    Some(Res::Query(res)) => Some((res.height, res.value.to_vec(), res.code)),
+                    _ => unreachable!(),
+                })
+                .join()
+                .unwrap()
+            };
+
+            assert_eq!(query("/kv").unwrap().2, 1);
+
+            begin_block(&mut sm, 1)?;
+            deliver_tx(&mut sm, "put:a:1")?;
+            end_block_and_commit(&mut sm, 1)?;
+            assert_eq!(query("/kv"), Some((1, b"1".to_vec(), 0)));
+
+            // reads the last commit while a block is in progress
+            begin_block(&mut sm, 2)?;
+            deliver_tx(&mut sm, "put:a:2")?;
+            assert_eq!(query("/kv"), Some((1, b"1".to_vec(), 0)));
+            end_block_and_commit(&mut sm, 2)?;
+            assert_eq!(query("/kv"), Some((2, b"2".to_vec(), 0)));
+
+            // other queries are left to the state machine
+            assert_eq!(query("/other"), None);
+            assert_eq!(query(TX_QUERY_PATH), None);
+
+            Ok(())
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn concurrent_queries_inherit_chain_id() -> Result<()> {
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let sm = state_machine(&home).concurrent_queries();
+            let queries = sm.concurrent_queries.clone().unwrap();
+            let query = move || {
+                let queries = queries.clone();
+                let req = Request {
+                    value: Some(Req::Query(RequestQuery {
+                        path: "/chain_id".to_string(),
+                        ..Default::default()
+                    })),
+                };
+                std::thread::spawn(move || match queries.answer(&req).unwrap().value {
+                    Some(Res::Query(res)) => (res.code, res.value.to_vec()),
+                    _ => unreachable!(),
+                })
+                .join()
+                .unwrap()
+            };
+
+            Context::add_inherited(ChainId("orga-test".to_string()));
+            assert_eq!(query(), (0, b"orga-test".to_vec()));
+
+            // contexts added with `Context::add` aren't copied
+            Context::remove::<ChainId>();
+            Context::add(ChainId("orga-test".to_string()));
+            assert_eq!(query().0, 1);
+            Context::remove::<ChainId>();
+
+            Ok(())
+        }
+
+        /// Rewrites `get` txs to `get:a`, rejects `reject:<name>` txs, and wraps
+        /// the logs of delivered txs in `<name>(...)`.
+        struct Tag(&'static str);
+
+        impl Middleware for Tag {
+            fn before(&mut self, req: &mut Request) -> Result<()> {
+                if let Some(Req::DeliverTx(req)) = req.value.as_mut() {
+                    if req.tx.as_ref() == b"get" {
+                        req.tx = b"get:a".to_vec().into();
+                    }
+                    if req.tx.as_ref() == format!("reject:{}", self.0).as_bytes() {
+                        return Err(Error::App(format!("rejected by {}", self.0)));
+                    }
+                }
+                Ok(())
+            }
+
+            fn after(&mut self, _req: &Request, res: &mut Result<Res>) {
+                if let Ok(Res::DeliverTx(res)) = res {
+                    res.log = format!("{}({})", self.0, res.log);
+                }
+            }
+        }
+
+        #[test]
+        fn middleware() -> Result<()> {
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home)
+                .with_middleware(Tag("a"))
+                .with_middleware(Tag("b"));
+
+            begin_block(&mut sm, 1)?;
+            assert_eq!(deliver_tx(&mut sm, "put:a:1")?, "a(b())");
+            assert_eq!(deliver_tx(&mut sm, "get")?, "a(b(1))");
+            let err = deliver_tx(&mut sm, "reject:b").unwrap_err();
+            assert_eq!(err.to_string(), "App Error: rejected by b");
+            end_block_and_commit(&mut sm, 1)?;
+
+            Ok(())
+        }
+
+        #[test]
+        fn invariant_violation_halts_before_commit() -> Result<()> {
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home);
+
+            begin_block(&mut sm, 1)?;
+            deliver_tx(&mut sm, "put:corrupt:1")?;
+            let err = sm
+                .run(Request {
+                    value: Some(Req::EndBlock(RequestEndBlock { height: 1 })),
+                })
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Invariant Violation: at height 1: corrupt key present"
+            );
+
+            let err = sm
+                .run(Request {
+                    value: Some(Req::Commit(Default::default())),
+                })
+                .unwrap_err();
+            assert!(matches!(err, Error::InvariantViolation(_)));
+
+            Ok(())
+        }
+
+        #[test]
+        fn stop_height() -> Result<()> {
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home).stop_height(Some(1));
+
+            begin_block(&mut sm, 1)?;
+            assert!(!sm.reached_stop_height());
+            end_block_and_commit(&mut sm, 1)?;
+            assert!(sm.reached_stop_height());
+
+            let err = begin_block(&mut sm, 2).unwrap_err();
+            assert_eq!(err.to_string(), "ABCI Error: Reached stop height (1)");
+
+            Ok(())
+        }
+
+        #[test]
+        fn halt_conditions() -> Result<()> {
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home).halt_conditions(HaltConditions {
+                stop_time: Some(i64::MAX),
+                ..Default::default()
+            });
+            begin_block(&mut sm, 1)?;
+            end_block_and_commit(&mut sm, 1)?;
+            assert_eq!(sm.halt_reason(), None);
+            let app_hash = hex::decode(sm.status().app_hash).unwrap();
+
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let mut sm = state_machine(&home).halt_conditions(HaltConditions {
+                stop_app_hash: Some(app_hash.clone()),
+                ..Default::default()
+            });
+            begin_block(&mut sm, 1)?;
+            end_block_and_commit(&mut sm, 1)?;
+            let reason = format!("Reached stop app hash ({})", hex::encode(&app_hash));
+            assert_eq!(sm.halt_reason(), Some(reason.as_str()));
+            let err = begin_block(&mut sm, 2).unwrap_err();
+            assert_eq!(err.to_string(), format!("ABCI Error: {}", reason));
+
+            let halt = HaltConditions {
+                stop_time: Some(100),
+                ..Default::default()
+            };
+            assert_eq!(halt.check(5, Some(99), &[]), None);
+            assert_eq!(halt.check(5, None, &[]), None);
+            assert_eq!(
+                halt.check(5, Some(100), &[]).as_deref(),
+                Some("Reached stop time (100)")
+            );
+
+            Ok(())
+        }
+
+        /// Sends `value` followed by a flush over a raw ABCI socket connection,
+        /// and returns the response to `value`.
+        fn socket_request(conn: &mut std::net::TcpStream, value: Req) -> Res {
+            use ::prost::Message;
+            use std::io::{Read as _, Write as _};
+
+            for value in [value, Req::Flush(Default::default())] {
+                let req = Request { value: Some(value) };
+                conn.write_all(&req.encode_length_delimited_to_vec())
+                    .unwrap();
+            }
+
+            let mut read_response = || {
+                let mut len = 0;
+                for shift in (0..).step_by(7) {
+                    let mut byte = [0];
+                    conn.read_exact(&mut byte).unwrap();
+                    len |= ((byte[0] & 0x7f) as usize) << shift;
+                    if byte[0] & 0x80 == 0 {
+                        break;
+                    }
+                }
+                let mut bytes = vec![0; len];
+                conn.read_exact(&mut bytes).unwrap();
+                Response::decode(bytes.as_slice()).unwrap().value.unwrap()
+            };
+            let res = read_response();
+            assert!(matches!(read_response(), Res::Flush(_)));
+            res
+        }
+
+        fn connect(addr: &str) -> std::net::TcpStream {
+            for _ in 0..100 {
+                if let Ok(conn) = std::net::TcpStream::connect(addr) {
+                    return conn;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            panic!("Failed to connect to {}", addr);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn reconnect_after_connection_closed() {
+            let addr = "127.0.0.1:26670";
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let shutdown: Arc<RwLock<Option<Error>>> = Default::default();
+
+            let sm_shutdown = shutdown.clone();
+            std::thread::spawn(move || {
+                let sm = ABCIStateMachine::new(
+                    KvApp,
+                    MerkStore::new(home.path()),
+                    false,
+                    sm_shutdown,
+                    Default::default(),
+                )
+                .worker_count(1);
+                let _ = sm.listen(addr);
+            });
+
+            let mut conn = connect(addr);
+            assert!(matches!(
+                socket_request(&mut conn, Req::Info(Default::default())),
+                Res::Info(_)
+            ));
+            drop(conn);
+
+            let mut conn = connect(addr);
+            match socket_request(&mut conn, Req::Info(Default::default())) {
+                Res::Info(res) => assert_eq!(res.last_block_height, 0),
+                res => panic!("Unexpected response: {:?}", res),
+            }
+            assert!(shutdown.read().unwrap().is_none());
+
+            *shutdown.write().unwrap() = Some(Error::ABCI("Test finished".into()));
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn shutdown_handle_stops_after_block() {
+            use tendermint_proto::google::protobuf::Timestamp;
+
+            let addr = "127.0.0.1:26671";
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let notifier: Arc<RwLock<bool>> = Default::default();
+
+            // the state machine isn't Send, so it is created on the server
+            // thread and only its shutdown handle is sent back
+            let (handle_sender, handle_receiver) = mpsc::channel();
+            let sm_notifier = notifier.clone();
+            let server = std::thread::spawn(move || {
+                let sm = ABCIStateMachine::new(
+                    KvApp,
+                    MerkStore::new(home.path()),
+                    false,
+                    Default::default(),
+                    sm_notifier,
+                )
+                .worker_count(1);
+                handle_sender.send(sm.shutdown_handle()).unwrap();
+                sm.listen(addr)
+            });
+            let handle = handle_receiver.recv().unwrap();
+
+            let mut conn = connect(addr);
+            let header = Header {
+                height: 1,
+                time: Some(Timestamp::default()),
+                ..Default::default()
+            };
+            socket_request(
+                &mut conn,
+                Req::BeginBlock(RequestBeginBlock {
+                    header: Some(header),
+                    ..Default::default()
+                }),
+            );
+
+            handle.shutdown();
+            std::thread::sleep(std::time::Duration::from_millis(1500));
+            assert!(!server.is_finished());
+            assert!(!*notifier.read().unwrap());
+
+            socket_request(&mut conn, Req::EndBlock(RequestEndBlock { height: 1 }));
+            socket_request(&mut conn, Req::Commit(Default::default()));
+            let stopped = server.join().unwrap().unwrap();
+            assert!(*stopped.read().unwrap());
+            assert!(*notifier.read().unwrap());
+            assert!(handle.is_requested());
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn listen_async() {
+            use tendermint_proto::google::protobuf::Timestamp;
+
+            let addr = "127.0.0.1:26672";
+            let home = tempdir::TempDir::new("orga-abci").unwrap();
+            let server = std::thread::spawn(move || {
+                let sm = ABCIStateMachine::new(
+                    KvApp,
+                    MerkStore::new(home.path()),
+                    false,
+                    Default::default(),
+                    Default::default(),
+                )
+                .stop_height(Some(1));
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap()
+                    .block_on(sm.listen_async(addr))
+            });
+
+            // connections are accepted beyond the default worker count
+            let mut conns: Vec<_> = (0..DEFAULT_ABCI_WORKERS + 1)
+                .map(|_| connect(addr))
+                .collect();
+            for conn in conns.iter_mut() {
+                match socket_request(conn, Req::Info(Default::default())) {
+                    Res::Info(res) => assert_eq!(res.last_block_height, 0),
+                    res => panic!("Unexpected response: {:?}", res),
+                }
+            }
+
+            let conn = &mut conns[0];
+            socket_request(
+                conn,
+                Req::BeginBlock(RequestBeginBlock {
+                    header: Some(Header {
+                        height: 1,
+                        time: Some(Timestamp::default()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+            );
+            socket_request(conn, Req::EndBlock(RequestEndBlock { height: 1 }));
+            match socket_request(conn, Req::Commit(Default::default())) {
+                Res::Commit(res) => assert!(!res.data.is_empty()),
+                res => panic!("Unexpected response: {:?}", res),
+            }
+
+            let err = server.join().unwrap().unwrap_err();
+            assert_eq!(err.to_string(), "ABCI Error: Reached stop height (1)");
+        }
+
+        #[test]
+        fn mem_store_root_hash() -> Result<()> {
+            let mut a = MemStore::new();
+            let empty = a.root_hash()?;
+            assert_eq!(empty.len(), 32);
+
+            a.put(vec![1], vec![10])?;
+            a.put(vec![2], vec![20])?;
+            a.put(vec![], vec![0])?;
+            let hash = a.root_hash()?;
+            assert_ne!(hash, empty);
+
+            let mut b = MemStore::new();
+            b.put(vec![2], vec![20])?;
+            b.put(vec![], vec![0])?;
+            b.put(vec![1], vec![10])?;
+            assert_eq!(b.root_hash()?, hash);
+
+            // entries are length-prefixed, so moving bytes between the key and
+            // value changes the hash
+            let mut c = MemStore::new();
+            c.put(vec![2, 20], vec![])?;
+            c.put(vec![], vec![0])?;
+            c.put(vec![1], vec![10])?;
+            assert_ne!(c.root_hash()?, hash);
+
+            b.commit(Header {
+                height: 1,
+                ..Default::default()
+            })?;
+            assert_eq!(b.height()?, 1);
+            assert_eq!(b.root_hash()?, hash);
+
+            b.delete(&[2])?;
+            assert_ne!(b.root_hash()?, hash);
+
+            Ok(())
+        }
+
+        #[test]
+        fn mem_store_snapshot() -> Result<()> {
+            let mut source = MemStore::new();
+            assert!(source.list_snapshots()?.is_empty());
+            source.put(vec![1], vec![10])?;
+            source.put(vec![2], vec![])?;
+            source.commit(Header {
+                height: 5,
+                ..Default::default()
+            })?;
+
+            let snapshots = source.list_snapshots()?;
+            assert_eq!(snapshots.len(), 1);
+            let snapshot = snapshots[0].clone();
+            assert_eq!(snapshot.height, 5);
+            assert_eq!(snapshot.chunks, 1);
+            let chunk = source.load_snapshot_chunk(RequestLoadSnapshotChunk {
+                height: 5,
+                format: snapshot.format,
+                chunk: 0,
+            })?;
+
+            let mut target = MemStore::new();
+            let apply = |target: &mut MemStore, chunk: &[u8]| {
+                target.apply_snapshot_chunk(RequestApplySnapshotChunk {
+                    index: 0,
+                    chunk: chunk.to_vec().into(),
+                    sender: String::new(),
+                })
+            };
+            assert!(apply(&mut target, &chunk).is_err());
+
+            let res = target.offer_snapshot(RequestOfferSnapshot {
+                snapshot: Some(snapshot),
+                app_hash: Default::default(),
+            })?;
+            assert_eq!(res.result(), response_offer_snapshot::Result::Accept);
+            assert!(apply(&mut target, &chunk[..chunk.len() - 1]).is_err());
+            assert!(apply(&mut target, &chunk[8..]).is_err());
+
+            apply(&mut target, &chunk)?;
+            assert_eq!(target.height()?, 5);
+            assert_eq!(target.root_hash()?, source.root_hash()?);
+            assert_eq!(target.get(&[1])?, Some(vec![10]));
+            assert_eq!(target.get(&[2])?, Some(vec![]));
+
+            Ok(())
         }
     }
 }
@@ -625,7 +2322,9 @@ mod server {
 #[cfg(feature = "abci")]
 pub use server::*;
 
-use crate::plugins::{BeginBlockCtx, EndBlockCtx, InitChainCtx};
+use crate::plugins::{
+    BeginBlockCtx, EndBlockCtx, ExtendVoteCtx, InitChainCtx, VerifyVoteExtensionCtx,
+};
 pub trait BeginBlock {
     fn begin_block(&mut self, ctx: &BeginBlockCtx) -> Result<()>;
 }
@@ -654,6 +2353,38 @@ impl<S> InitChain for S {
     }
 }
 
+/// Produces this node's vote extension for a block (ABCI 0.38 `ExtendVote`),
+/// e.g. an oracle module's price observations.
+///
+/// Runs against the last committed state, which must not be modified. The
+/// extension does not need to be deterministic, but since it is not part of
+/// the block, it only reaches the state machine if a proposer includes it in a
+/// later block.
+pub trait ExtendVote {
+    fn extend_vote(&self, ctx: &ExtendVoteCtx) -> Result<Vec<u8>>;
+}
+
+impl<S> ExtendVote for S {
+    default fn extend_vote(&self, _ctx: &ExtendVoteCtx) -> Result<Vec<u8>> {
+        Ok(vec![])
+    }
+}
+
+/// Checks another validator's vote extension (ABCI 0.38
+/// `VerifyVoteExtension`), returning `false` or an error to reject the vote.
+///
+/// Runs against the last committed state, and must be deterministic so that
+/// all honest validators accept the same extensions.
+pub trait VerifyVoteExtension {
+    fn verify_vote_extension(&self, ctx: &VerifyVoteExtensionCtx) -> Result<bool>;
+}
+
+impl<S> VerifyVoteExtension for S {
+    default fn verify_vote_extension(&self, _ctx: &VerifyVoteExtensionCtx) -> Result<bool> {
+        Ok(true)
+    }
+}
+
 pub trait AbciQuery {
     fn abci_query(&self, request: &RequestQuery) -> Result<ResponseQuery>;
 }
@@ -670,7 +2401,28 @@ impl<S> AbciQuery for S {
 }
 
 pub trait App:
-    BeginBlock + EndBlock + InitChain + State + Call + Query + Default + AbciQuery
+    BeginBlock
+    + EndBlock
+    + InitChain
+    + ExtendVote
+    + VerifyVoteExtension
+    + State
+    + Call
+    + Query
+    + Default
+    + AbciQuery
+{
+}
+impl<T> App for T where
+    T: Default
+        + BeginBlock
+        + EndBlock
+        + InitChain
+        + ExtendVote
+        + VerifyVoteExtension
+        + State
+        + Call
+        + Query
+        + AbciQuery
 {
 }
-impl<T: Default + BeginBlock + EndBlock + InitChain + State + Call + Query + AbciQuery> App for T {}
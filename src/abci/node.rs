@@ -1,18 +1,23 @@
-use super::{ABCIStateMachine, ABCIStore, AbciQuery, App, Application, WrappedMerk};
+use super::{
+    ABCIStateMachine, ABCIStore, AbciQuery, App, Application, HaltConditions, QueryHandler,
+    RejectedTxLogConfig, ShutdownHandle, WrappedMerk,
+};
 use crate::call::Call;
 use crate::context::Context;
 use crate::encoding::Decode;
+use crate::merk::ics23;
 use crate::merk::memsnapshot::MemSnapshot;
-use crate::merk::{MerkStore, ProofBuilder};
+use crate::merk::{MerkStore, ProofBuilder, Pruning, ReadHandle, ReadView, StateExport};
 use crate::migrate::Migrate;
-use crate::plugins::{ABCICall, ABCIPlugin};
+use crate::plugins::{ABCICall, ABCIPlugin, CheckTxCtx, GasMeter, ValidatorEntry};
 use crate::query::Query;
-use crate::state::State;
+use crate::state::{ExportState, State};
 use crate::store::{BackingStore, Read, Shared, Store, Write};
 use crate::tendermint::Child as TendermintChild;
 use crate::tendermint::Tendermint;
 use crate::{Error, Result};
 use home::home_dir;
+use serde::{Deserialize, Serialize};
 use std::borrow::Borrow;
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
@@ -81,6 +86,26 @@ pub struct Node<A> {
     logs: bool,
     skip_init_chain: bool,
     flags: Vec<String>,
+    health_addr: Option<String>,
+    tx_gas_limit: Option<u64>,
+    check_tx_deadline: Option<Duration>,
+    rejected_tx_log: Option<RejectedTxLogConfig>,
+    index_txs: bool,
+    concurrent_queries: bool,
+    rollback_checkpoints: bool,
+    other_chain_ids: Vec<String>,
+    store_metrics: Option<usize>,
+    tx_write_budget: Option<u64>,
+    block_write_budget: Option<u64>,
+    version: Option<Vec<u8>>,
+    abci_workers: Option<usize>,
+    halt_conditions: Option<HaltConditions>,
+    shutdown_handle: ShutdownHandle,
+    snapshot_interval: Option<u64>,
+    snapshot_keep_recent: Option<u64>,
+    retain_heights: Option<usize>,
+    pruning: Option<Pruning>,
+    state_size_prefix_len: Option<usize>,
 }
 
 impl Node<()> {
@@ -169,7 +194,7 @@ impl<A: App> Node<A> {
             }
 
             let chain_id = genesis_json["chain_id"].as_str().unwrap();
-            Context::add(crate::plugins::ChainId(chain_id.to_string()));
+            Context::add_inherited(crate::plugins::ChainId(chain_id.to_string()));
 
             std::fs::write(
                 tm_home.join("config/genesis.json"),
@@ -207,6 +232,26 @@ impl<A: App> Node<A> {
             stderr: Stdio::null(),
             logs: false,
             flags: vec![],
+            health_addr: None,
+            tx_gas_limit: None,
+            check_tx_deadline: None,
+            rejected_tx_log: None,
+            index_txs: false,
+            concurrent_queries: false,
+            rollback_checkpoints: false,
+            other_chain_ids: vec![],
+            store_metrics: None,
+            tx_write_budget: None,
+            block_write_budget: None,
+            version: None,
+            abci_workers: None,
+            halt_conditions: None,
+            shutdown_handle: ShutdownHandle::default(),
+            snapshot_interval: None,
+            snapshot_keep_recent: None,
+            retain_heights: None,
+            pruning: None,
+            state_size_prefix_len: None,
         }
     }
 
@@ -240,23 +285,82 @@ impl<A: App> Node<A> {
                 .parse()
                 .unwrap();
         let chain_id = genesis["chain_id"].as_str().unwrap();
-        Context::add(crate::plugins::ChainId(chain_id.to_string()));
+        Context::add_inherited(crate::plugins::ChainId(chain_id.to_string()));
+        if !self.other_chain_ids.is_empty() {
+            Context::add_inherited(crate::plugins::OtherChainIds(self.other_chain_ids.clone()));
+        }
         let shutdown_handler = Arc::new(RwLock::new(None));
         let shutdown_notifier = Arc::new(RwLock::new(false));
         let shutdown = shutdown_handler.clone();
         let notifier = shutdown_notifier.clone();
 
         std::thread::spawn(move || {
-            let app = InternalApp::<ABCIPlugin<A>>::new();
-            let store = MerkStore::new(self.merk_home.clone());
-            let res = ABCIStateMachine::new(
+            let mut app = InternalApp::<ABCIPlugin<A>>::new();
+            app.tx_gas_limit = self.tx_gas_limit;
+            app.check_tx_deadline = self.check_tx_deadline;
+            app.code_version = self.version.clone();
+            let mut store = MerkStore::new(self.merk_home.clone());
+            if let Some(interval) = self.snapshot_interval {
+                store = store.snapshot_interval(Some(interval));
+            }
+            if let Some(count) = self.snapshot_keep_recent {
+                store = store.snapshot_keep_recent(count);
+            }
+            if let Some(count) = self.retain_heights {
+                store = store.retain_heights(count);
+            }
+            if let Some(pruning) = self.pruning {
+                store = store.pruning(pruning);
+            }
+            if self.rollback_checkpoints {
+                store = store.rollback_checkpoint(true);
+            }
+            if let Some(prefix_len) = self.state_size_prefix_len {
+                store = store.track_state_sizes(prefix_len);
+                let tracked = store.state_sizes().unwrap().map(|(len, _)| len);
+                if tracked != Some(prefix_len) && store.height().unwrap() > 0 {
+                    log::info!("Counting state sizes, this may take a while...");
+                    store.recount_state_sizes().unwrap();
+                }
+            }
+            let mut state_machine = ABCIStateMachine::new(
                 app,
                 store,
                 self.skip_init_chain,
                 shutdown.clone(),
                 shutdown_notifier,
-            )
-            .listen(format!("127.0.0.1:{}", self.abci_port));
+            );
+            if let Some(config) = self.rejected_tx_log.clone() {
+                state_machine = state_machine.log_rejected_txs(config);
+            }
+            if self.index_txs {
+                state_machine = state_machine.index_txs();
+            }
+            if self.concurrent_queries {
+                state_machine = state_machine.concurrent_queries();
+            }
+            if let Some(prefix_len) = self.store_metrics {
+                state_machine = state_machine.store_metrics(prefix_len);
+            }
+            if let Some(bytes) = self.tx_write_budget {
+                state_machine = state_machine.tx_write_budget(bytes);
+            }
+            if let Some(bytes) = self.block_write_budget {
+                state_machine = state_machine.block_write_budget(bytes);
+            }
+            if let Some(count) = self.abci_workers {
+                state_machine = state_machine.worker_count(count);
+            }
+            if let Some(halt) = self.halt_conditions.clone() {
+                state_machine = state_machine.halt_conditions(halt);
+            }
+            state_machine = state_machine.with_shutdown_handle(self.shutdown_handle.clone());
+            if let Some(addr) = self.health_addr.as_ref() {
+                if let Err(e) = state_machine.health().serve(addr) {
+                    log::warn!("Failed to start health endpoint: {}", e);
+                }
+            }
+            let res = state_machine.listen(format!("127.0.0.1:{}", self.abci_port));
             let mut shutdown = shutdown.write().unwrap();
 
             match res {
@@ -280,7 +384,7 @@ impl<A: App> Node<A> {
 
                     std::process::exit(138);
                 }
-                Err(crate::Error::ABCI(msg)) if msg.starts_with("Reached stop height ") => {
+                Err(crate::Error::ABCI(msg)) if msg.starts_with("Reached stop ") => {
                     *shutdown = Some(crate::Error::ABCI(msg));
 
                     std::process::exit(138);
@@ -311,11 +415,20 @@ impl<A: App> Node<A> {
         self
     }
 
+    /// Sets the version of the state this binary runs, reported by the
+    /// [VERSION_QUERY_PATH] query. Also set by [Node::migrate].
+    #[must_use]
+    pub fn version(mut self, version: Vec<u8>) -> Self {
+        self.version = Some(version);
+        self
+    }
+
     // TODO: remove when we don't require compat migrations
-    pub fn migrate(self, version: Vec<u8>, compat_mode: bool, repair: bool) -> Self
+    pub fn migrate(mut self, version: Vec<u8>, compat_mode: bool, repair: bool) -> Self
     where
         ABCIPlugin<A>: Migrate,
     {
+        self.version = Some(version.clone());
         let merk_store = crate::merk::MerkStore::new(&self.merk_home);
         if let Some(store_ver) = merk_store.merk().get_aux(b"consensus_version").unwrap() {
             if store_ver == version {
@@ -330,7 +443,7 @@ impl<A: App> Node<A> {
                 .parse()
                 .unwrap();
         let chain_id = genesis["chain_id"].as_str().unwrap();
-        Context::add(crate::plugins::ChainId(chain_id.to_string()));
+        Context::add_inherited(crate::plugins::ChainId(chain_id.to_string()));
 
         log::info!("Migrating store data... (This might take a while)");
         let store = Shared::new(merk_store);
@@ -360,7 +473,7 @@ impl<A: App> Node<A> {
                 .unwrap();
 
             if repair {
-                store.into_merk().repair().unwrap();
+                store.into_merk().unwrap().repair().unwrap();
             }
         } else {
             unreachable!();
@@ -375,6 +488,74 @@ impl<A: App> Node<A> {
         self
     }
 
+    /// Reverts the app state by one height, see
+    /// [MerkStore::rollback](crate::merk::MerkStore::rollback). Run this
+    /// together with `cometbft rollback` while the node is stopped. Requires
+    /// the node to have run with
+    /// [rollback_checkpoints](Self::rollback_checkpoints). Returns the height
+    /// rolled back to.
+    pub fn rollback(&self) -> Result<u64> {
+        MerkStore::new(&self.merk_home).rollback()
+    }
+
+    /// Exports the latest committed state to `path` as a JSON
+    /// [StateExport](crate::merk::StateExport), including the readable
+    /// `app_state`, e.g. to restart or fork the chain from it. The node must
+    /// not be running.
+    pub fn export_state(&self, path: impl AsRef<Path>) -> Result<StateExport>
+    where
+        ABCIPlugin<A>: ExportState,
+    {
+        let merk_store = MerkStore::new(&self.merk_home);
+        let mut export = merk_store.export(merk_store.height()?)?;
+
+        let store = Store::new(BackingStore::Merk(Shared::new(merk_store)));
+        if let Some(bytes) = store.get(&[])? {
+            let app = ABCIPlugin::<A>::load(store, &mut bytes.as_slice())?;
+            export.app_state = Some(app.export_state()?);
+        }
+
+        std::fs::write(path, serde_json::to_vec_pretty(&export)?)?;
+        Ok(export)
+    }
+
+    /// Initializes the node's store from a state exported with
+    /// [Node::export_state], so a chain can be restarted or forked from it
+    /// without replaying its history.
+    ///
+    /// The genesis validators are replaced with the exported validator set,
+    /// and the genesis `initial_height` and `app_hash` are set to continue
+    /// from the imported state, so Tendermint skips `InitChain`. The store
+    /// must be empty.
+    pub fn init_from_export(mut self, path: impl AsRef<Path>) -> Self {
+        let export: StateExport =
+            serde_json::from_slice(&std::fs::read(path).expect("Failed to read state export"))
+                .expect("Failed to parse state export");
+        let merk_store =
+            MerkStore::import(&self.merk_home, &export).expect("Failed to import state");
+        let app_hash = merk_store.root_hash().unwrap();
+
+        let store = Store::new(BackingStore::Merk(Shared::new(merk_store)));
+        let bytes = store.get(&[]).unwrap().expect("State export is empty");
+        let app = ABCIPlugin::<A>::load(store, &mut bytes.as_slice()).unwrap();
+        let validators = app.validator_set().unwrap();
+
+        let genesis_path = self.tm_home.join("config/genesis.json");
+        let genesis_bytes = match self.genesis_bytes.take() {
+            Some(bytes) => bytes,
+            None => std::fs::read(&genesis_path).expect("Failed to read genesis.json"),
+        };
+        let mut genesis: serde_json::Value =
+            serde_json::from_slice(&genesis_bytes).expect("Failed to parse genesis");
+        carry_over_genesis(&mut genesis, export.height, &app_hash, &validators);
+        let genesis_bytes = serde_json::to_vec_pretty(&genesis).unwrap();
+        std::fs::write(genesis_path, &genesis_bytes).expect("Failed to write genesis.json");
+        self.genesis_bytes.replace(genesis_bytes);
+        self.skip_init_chain = true;
+
+        self
+    }
+
     pub fn init_from_store(self, source: impl AsRef<Path>, height: Option<u64>) -> Self {
         MerkStore::init_from(source, &self.merk_home, height).unwrap();
 
@@ -423,6 +604,197 @@ impl<A: App> Node<A> {
 
         self
     }
+
+    /// Serves an HTTP `GET /health` endpoint on the given address (e.g.
+    /// `"127.0.0.1:26660"`), reporting the node's
+    /// [`NodeStatus`](struct.NodeStatus.html) as JSON.
+    #[must_use]
+    pub fn health_endpoint<T: Into<String>>(mut self, addr: T) -> Self {
+        self.health_addr.replace(addr.into());
+
+        self
+    }
+
+    /// Limits the gas each tx may consume (see
+    /// [`GasMeter`](crate::plugins::GasMeter)). Txs which exceed the limit fail
+    /// with an out-of-gas error. Since gas is charged deterministically, this
+    /// is safe to enforce in `DeliverTx`.
+    #[must_use]
+    pub fn tx_gas_limit(mut self, limit: u64) -> Self {
+        self.tx_gas_limit.replace(limit);
+
+        self
+    }
+
+    /// Rejects txs in `CheckTx` which take longer than `deadline` of wall-clock
+    /// time to execute. Preemption is cooperative, so the deadline is only
+    /// checked when gas is charged. This is never applied in `DeliverTx`, where
+    /// it would make execution depend on the speed of each node.
+    #[must_use]
+    pub fn check_tx_deadline(mut self, deadline: Duration) -> Self {
+        self.check_tx_deadline.replace(deadline);
+
+        self
+    }
+
+    /// Logs rejected txs node-side, rate-limited according to `config`. Off by
+    /// default since logging every rejected tx is expensive.
+    #[must_use]
+    pub fn log_rejected_txs(mut self, config: RejectedTxLogConfig) -> Self {
+        self.rejected_tx_log.replace(config);
+
+        self
+    }
+
+    /// Indexes delivered tx results by hash, served by the
+    /// [TX_QUERY_PATH](super::TX_QUERY_PATH) query. Off by default.
+    #[must_use]
+    pub fn index_txs(mut self) -> Self {
+        self.index_txs = true;
+
+        self
+    }
+
+    /// Answers queries from a read-only snapshot of the last commit on the
+    /// ABCI connection threads, so query load doesn't stall block processing.
+    /// Off by default. See
+    /// [ABCIStateMachine::concurrent_queries](super::ABCIStateMachine::concurrent_queries).
+    #[must_use]
+    pub fn concurrent_queries(mut self) -> Self {
+        self.concurrent_queries = true;
+
+        self
+    }
+
+    /// Saves a checkpoint of the previous height on each commit, so the node
+    /// can be [rolled back](Self::rollback) by one height. Off by default. See
+    /// [MerkStore::rollback_checkpoint](crate::merk::MerkStore::rollback_checkpoint).
+    #[must_use]
+    pub fn rollback_checkpoints(mut self) -> Self {
+        self.rollback_checkpoints = true;
+
+        self
+    }
+
+    /// Chain ids of other networks, e.g. a testnet of the same app, whose SDK
+    /// txs are rejected as `wrong chain id` rather than `Invalid signature`.
+    /// None by default. See [OtherChainIds](crate::plugins::OtherChainIds).
+    #[must_use]
+    pub fn other_chain_ids<T: Into<String>>(
+        mut self,
+        chain_ids: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.other_chain_ids = chain_ids.into_iter().map(Into::into).collect();
+
+        self
+    }
+
+    /// Counts the store operations of each block by module, logging the
+    /// busiest modules after each commit. Keys are grouped by their first
+    /// `prefix_len` bytes. Off by default. See
+    /// [StoreMetrics](crate::store::StoreMetrics).
+    #[must_use]
+    pub fn store_metrics(mut self, prefix_len: usize) -> Self {
+        self.store_metrics.replace(prefix_len);
+
+        self
+    }
+
+    /// Limits the bytes of writes a single tx can buffer, failing txs which
+    /// exceed it. Unlimited by default. Every validator must use the same
+    /// budget, since it affects which txs succeed.
+    #[must_use]
+    pub fn tx_write_budget(mut self, bytes: u64) -> Self {
+        self.tx_write_budget.replace(bytes);
+
+        self
+    }
+
+    /// Limits the bytes of writes buffered by the txs of a block, failing
+    /// txs which would exceed it. Unlimited by default. Every validator must
+    /// use the same budget, since it affects which txs succeed.
+    #[must_use]
+    pub fn block_write_budget(mut self, bytes: u64) -> Self {
+        self.block_write_budget.replace(bytes);
+
+        self
+    }
+
+    /// Sets the number of ABCI connections the node waits for before handling
+    /// requests. Defaults to the four connections Tendermint opens.
+    #[must_use]
+    pub fn abci_workers(mut self, count: usize) -> Self {
+        self.abci_workers.replace(count);
+
+        self
+    }
+
+    /// Sets the conditions under which the node halts (exiting with code 138,
+    /// like an upgrade halt). Defaults to
+    /// [HaltConditions::from_env](super::HaltConditions::from_env).
+    #[must_use]
+    pub fn halt_conditions(mut self, halt: HaltConditions) -> Self {
+        self.halt_conditions.replace(halt);
+
+        self
+    }
+
+    /// Takes a state sync snapshot every `interval` heights, or never if
+    /// `interval` is 0. See
+    /// [MerkStore::snapshot_interval](crate::merk::MerkStore::snapshot_interval).
+    #[must_use]
+    pub fn snapshot_interval(mut self, interval: u64) -> Self {
+        self.snapshot_interval.replace(interval);
+
+        self
+    }
+
+    /// Sets how many recent state sync snapshots to keep. See
+    /// [MerkStore::snapshot_keep_recent](crate::merk::MerkStore::snapshot_keep_recent).
+    #[must_use]
+    pub fn snapshot_keep_recent(mut self, count: u64) -> Self {
+        self.snapshot_keep_recent.replace(count);
+
+        self
+    }
+
+    /// Sets how many recent heights can be queried. See
+    /// [MerkStore::retain_heights](crate::merk::MerkStore::retain_heights).
+    #[must_use]
+    pub fn retain_heights(mut self, count: usize) -> Self {
+        self.retain_heights.replace(count);
+
+        self
+    }
+
+    /// Sets how long per-height aux data such as the tx index is kept, e.g.
+    /// `Pruning::archive()` for archive nodes. Only height-scoped aux data is
+    /// pruned, synchronously during commit. See
+    /// [Pruning](crate::merk::Pruning).
+    #[must_use]
+    pub fn pruning(mut self, pruning: Pruning) -> Self {
+        self.pruning.replace(pruning);
+
+        self
+    }
+
+    /// Tracks the bytes stored under each key prefix of `prefix_len` bytes,
+    /// served by the [STATE_SIZE_QUERY_PATH] query. If the store already has
+    /// state, it is scanned once on startup to count the existing sizes. Off
+    /// by default. See
+    /// [MerkStore::state_sizes](crate::merk::MerkStore::state_sizes).
+    #[must_use]
+    pub fn track_state_sizes(mut self, prefix_len: usize) -> Self {
+        self.state_size_prefix_len.replace(prefix_len);
+
+        self
+    }
+
+    /// Returns a handle which stops the node's state machine cleanly after
+    /// the block in progress, e.g. to coordinate an upgrade.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
 }
 
 impl<A: App> InternalApp<ABCIPlugin<A>> {
@@ -485,21 +857,24 @@ impl<A: App> Application for InternalApp<ABCIPlugin<A>> {
     }
 
     fn end_block(&self, store: WrappedMerk, req: RequestEndBlock) -> Result<ResponseEndBlock> {
-        let (mut updates, events, _logs) = self.run(store, move |state| -> Result<_> {
-            state.call(req.into())?;
-            Ok((
-                state
-                    .validator_updates
-                    .take()
-                    .expect("ABCI plugin did not create validator update map"),
-                state.events.take().unwrap_or_default(),
-                state.logs.take().unwrap_or_default(),
-            ))
-        })??;
+        let (mut updates, events, _logs, consensus_param_updates) =
+            self.run(store, move |state| -> Result<_> {
+                state.call(req.into())?;
+                Ok((
+                    state
+                        .validator_updates
+                        .take()
+                        .expect("ABCI plugin did not create validator update map"),
+                    state.events.take().unwrap_or_default(),
+                    state.logs.take().unwrap_or_default(),
+                    state.consensus_param_updates.take(),
+                ))
+            })??;
 
         // Write back validator updates
         let mut res = ResponseEndBlock {
             events,
+            consensus_param_updates,
             ..Default::default()
         };
         updates.drain().for_each(|(_key, update)| {
@@ -515,9 +890,14 @@ impl<A: App> Application for InternalApp<ABCIPlugin<A>> {
     }
 
     fn deliver_tx(&self, store: WrappedMerk, req: RequestDeliverTx) -> Result<ResponseDeliverTx> {
+        let gas_meter = self.gas_meter(false);
         let run_res = self.run(store, move |state| -> Result<_> {
             let inner_call = Decode::decode(req.tx.to_vec().as_slice())?;
+            if let Some(gas_meter) = gas_meter {
+                Context::add(gas_meter);
+            }
             let res = state.call(ABCICall::DeliverTx(inner_call));
+            Context::remove::<GasMeter>();
 
             Ok((
                 res,
@@ -535,7 +915,9 @@ impl<A: App> Application for InternalApp<ABCIPlugin<A>> {
                     deliver_tx_res.events = events;
                 }
                 Err(err) => {
-                    deliver_tx_res.code = 1;
+                    let (codespace, code) = err.code();
+                    deliver_tx_res.code = code;
+                    deliver_tx_res.codespace = codespace.to_string();
                     if logs.is_empty() {
                         deliver_tx_res.log = err.to_string();
                     } else {
@@ -544,7 +926,9 @@ impl<A: App> Application for InternalApp<ABCIPlugin<A>> {
                 }
             },
             Err(err) => {
-                deliver_tx_res.code = 1;
+                let (codespace, code) = err.code();
+                deliver_tx_res.code = code;
+                deliver_tx_res.codespace = codespace.to_string();
                 deliver_tx_res.log = err.to_string();
             }
         }
@@ -553,28 +937,39 @@ impl<A: App> Application for InternalApp<ABCIPlugin<A>> {
     }
 
     fn check_tx(&self, store: WrappedMerk, req: RequestCheckTx) -> Result<ResponseCheckTx> {
+        let gas_meter = self.gas_meter(true);
         let run_res = self.run(store, move |state| -> Result<_> {
             let inner_call = Decode::decode(req.tx.to_vec().as_slice())?;
+            if let Some(gas_meter) = gas_meter {
+                Context::add(gas_meter);
+            }
+            Context::add(CheckTxCtx::from(req.r#type()));
             let res = state.call(ABCICall::CheckTx(inner_call));
+            Context::remove::<GasMeter>();
+            Context::remove::<CheckTxCtx>();
 
             Ok((
                 res,
                 state.events.take().unwrap_or_default(),
                 state.logs.take().unwrap_or_default(),
+                state.priority.take().unwrap_or_default(),
             ))
         })?;
 
         let mut check_tx_res = ResponseCheckTx::default();
 
         match run_res {
-            Ok((res, events, logs)) => match res {
+            Ok((res, events, logs, priority)) => match res {
                 Ok(()) => {
                     check_tx_res.code = 0;
                     check_tx_res.log = logs.join("\n");
                     check_tx_res.events = events;
+                    check_tx_res.priority = priority;
                 }
                 Err(err) => {
-                    check_tx_res.code = 1;
+                    let (codespace, code) = err.code();
+                    check_tx_res.code = code;
+                    check_tx_res.codespace = codespace.to_string();
                     if logs.is_empty() {
                         check_tx_res.log = err.to_string();
                     } else {
@@ -583,7 +978,9 @@ impl<A: App> Application for InternalApp<ABCIPlugin<A>> {
                 }
             },
             Err(err) => {
-                check_tx_res.code = 1;
+                let (codespace, code) = err.code();
+                check_tx_res.code = code;
+                check_tx_res.codespace = codespace.to_string();
                 check_tx_res.log = err.to_string();
             }
         }
@@ -592,6 +989,101 @@ impl<A: App> Application for InternalApp<ABCIPlugin<A>> {
     }
 
     fn query(&self, merk_store: Shared<MerkStore>, req: RequestQuery) -> Result<ResponseQuery> {
+        if req.path == VERSION_QUERY_PATH {
+            return self.version_query(&merk_store.borrow());
+        }
+        if req.path == STATE_SIZE_QUERY_PATH {
+            return state_size_query(&merk_store.borrow());
+        }
+
+        let handle = {
+            let merk_store = merk_store.borrow();
+            merk_store
+                .read_handle(query_height(&req)?)
+                .ok_or_else(|| merk_store.missing_height_error(req.height as u64))?
+        };
+
+        Self::query_handle(handle, req)
+    }
+
+    /// Answers queries from the read view, except for the version and state
+    /// size queries which read the store's auxiliary data.
+    fn query_handler(&self) -> Option<QueryHandler> {
+        Some(Arc::new(|view: &ReadView, req: &RequestQuery| {
+            if req.path == VERSION_QUERY_PATH || req.path == STATE_SIZE_QUERY_PATH {
+                return None;
+            }
+
+            let res = query_height(req).and_then(|height| {
+                let handle = view
+                    .read_handle(height)
+                    .ok_or_else(|| view.missing_height_error(req.height as u64))?;
+                Self::query_handle(handle, req.clone())
+            });
+            Some(res)
+        }))
+    }
+}
+
+/// Sets the fields of a Tendermint genesis document which let a chain continue
+/// from imported state committed at `height`.
+fn carry_over_genesis(
+    genesis: &mut serde_json::Value,
+    height: u64,
+    app_hash: &[u8],
+    validators: &[ValidatorEntry],
+) {
+    use base64::Engine;
+
+    genesis["initial_height"] = (height + 1).to_string().into();
+    genesis["app_hash"] = hex::encode_upper(app_hash).into();
+    genesis["validators"] = validators
+        .iter()
+        .map(|validator| {
+            serde_json::json!({
+                "pub_key": {
+                    "type": "tendermint/PubKeyEd25519",
+                    "value": base64::prelude::BASE64_STANDARD.encode(validator.pubkey),
+                },
+                "power": validator.power.to_string(),
+                "name": "",
+            })
+        })
+        .collect();
+}
+
+/// Answers a [STATE_SIZE_QUERY_PATH] query as of the last commit.
+fn state_size_query(store: &MerkStore) -> Result<ResponseQuery> {
+    let (prefix_len, sizes) = store
+        .state_sizes()?
+        .ok_or_else(|| Error::Query("State sizes are not tracked by this node".into()))?;
+    let sizes = StateSizes {
+        prefix_len,
+        sizes: sizes
+            .into_iter()
+            .map(|(prefix, size)| (hex::encode(prefix), size))
+            .collect(),
+    };
+
+    Ok(ResponseQuery {
+        code: 0,
+        height: store.height()?.try_into()?,
+        value: serde_json::to_vec(&sizes)?.into(),
+        ..Default::default()
+    })
+}
+
+/// The height requested by a query, where 0 means the latest height.
+fn query_height(req: &RequestQuery) -> Result<Option<u64>> {
+    Ok(match req.height {
+        0 => None,
+        height => Some(height.try_into()?),
+    })
+}
+
+impl<A: App> InternalApp<ABCIPlugin<A>> {
+    /// Answers a query from the state as of the height `handle` is pinned to.
+    fn query_handle(handle: ReadHandle, req: RequestQuery) -> Result<ResponseQuery> {
         let create_state = |store| {
             let store = Store::new(store);
             let state_bytes = store
@@ -600,20 +1092,25 @@ impl<A: App> Application for InternalApp<ABCIPlugin<A>> {
             ABCIPlugin::<A>::load(store, &mut state_bytes.as_slice())
         };
 
-        let (height, snapshot) = {
-            let merk_store_ref = merk_store.borrow();
-            if req.height == 0 {
-                merk_store_ref.mem_snapshots().last_key_value()
-            } else {
-                merk_store_ref
-                    .mem_snapshots()
-                    .get_key_value(&req.height.try_into()?)
-            }
-            .map(|(k, v)| (*k, (*v).clone()))
-            .ok_or_else(|| crate::Error::Query(format!("Cannot query for height {}", req.height)))?
-        };
+        let height = handle.height();
+
+        let mss = Shared::new(MemSnapshot::new(handle));
 
-        let mss = Shared::new(MemSnapshot::new(snapshot, merk_store));
+        if req.path == ICS23_QUERY_PATH {
+            let store = BackingStore::MemSnapshot(mss);
+            let value = store.get(&req.data)?;
+            let proof = store.create_ics23_proof(&req.data)?;
+            let proof_ops = ics23::proof_ops(&req.data, &proof, &store.root_hash());
+
+            return Ok(ResponseQuery {
+                code: 0,
+                height: height.try_into()?,
+                key: req.data,
+                value: value.unwrap_or_default().into(),
+                proof_ops: Some(proof_ops),
+                ..Default::default()
+            });
+        }
 
         if !req.path.is_empty() {
             let store = BackingStore::MemSnapshot(mss);
@@ -650,6 +1147,52 @@ impl<A: App> Application for InternalApp<ABCIPlugin<A>> {
     }
 }
 
+#[cfg(feature = "abci-v0_37")]
+impl<A: App> super::v0_37::Application for InternalApp<ABCIPlugin<A>> {}
+
+#[cfg(feature = "abci-v0_38")]
+impl<A: App> super::v0_38::Application for InternalApp<ABCIPlugin<A>> {
+    fn extend_vote(
+        &self,
+        store: Shared<MerkStore>,
+        req: super::v0_38::messages::RequestExtendVote,
+    ) -> Result<super::v0_38::messages::ResponseExtendVote> {
+        let ctx = req.into();
+        let vote_extension = self.committed_state(store, |state| {
+            crate::abci::ExtendVote::extend_vote(state, &ctx)
+        })?;
+
+        Ok(super::v0_38::messages::ResponseExtendVote { vote_extension })
+    }
+
+    fn verify_vote_extension(
+        &self,
+        store: Shared<MerkStore>,
+        req: super::v0_38::messages::RequestVerifyVoteExtension,
+    ) -> Result<super::v0_38::messages::ResponseVerifyVoteExtension> {
+        use super::v0_38::messages::VerifyStatus;
+
+        let ctx = req.into();
+        let valid = self.committed_state(store, |state| {
+            crate::abci::VerifyVoteExtension::verify_vote_extension(state, &ctx)
+        });
+        // extensions come from other validators, so an error rejects the vote
+        // rather than halting this node
+        let status = match valid {
+            Ok(true) => VerifyStatus::Accept,
+            Ok(false) => VerifyStatus::Reject,
+            Err(err) => {
+                log::debug!("Rejected vote extension: {}", err);
+                VerifyStatus::Reject
+            }
+        };
+
+        Ok(super::v0_38::messages::ResponseVerifyVoteExtension {
+            status: status as i32,
+        })
+    }
+}
+
 impl Drop for Child {
     fn drop(&mut self) {
         if let Err(e) = self.kill() {
@@ -658,13 +1201,162 @@ impl Drop for Child {
     }
 }
 
+/// The ABCI query path which returns the node's [VersionStatus] as JSON.
+///
+/// This is answered from the raw store without loading the app state, so it
+/// works even when the state can't be loaded by the running binary, e.g. in the
+/// first block after an upgrade.
+pub const VERSION_QUERY_PATH: &str = "/orga/version";
+
+/// The ABCI query path which returns the value of the raw store key in the
+/// query data along with an ICS-23 proof of it, or of its absence, against the
+/// app hash.
+///
+/// The proof is encoded as [ProofOps](tendermint_proto::v0_34::crypto::ProofOps)
+/// like those of the Cosmos SDK, so it can be checked by IBC counterparties and
+/// other standard tooling, or with
+/// [verify_proof_ops](crate::merk::ics23::verify_proof_ops).
+pub const ICS23_QUERY_PATH: &str = "/orga/ics23";
+
+/// The ABCI query path which returns the [StateSizes] tracked by the node as
+/// JSON, if enabled with [Node::track_state_sizes].
+pub const STATE_SIZE_QUERY_PATH: &str = "/orga/state_size";
+
+/// The total bytes of the keys and values stored under each key prefix,
+/// returned by the [STATE_SIZE_QUERY_PATH] query. Prefixes are hex-encoded
+/// and `prefix_len` bytes long, except for shorter keys which are counted
+/// under the whole key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StateSizes {
+    pub prefix_len: usize,
+    pub sizes: std::collections::BTreeMap<String, u64>,
+}
+
+/// The state versions known to a node, returned by the [VERSION_QUERY_PATH]
+/// query. Versions are hex-encoded.
+///
+/// - `code_version` is the version the running binary was built for (set with
+///   [Node::version] or [Node::migrate]), or `null` if it wasn't configured.
+/// - `state_version` is the version the stored state was last migrated to.
+/// - `network_version` is the version the network has activated through the
+///   upgrade module.
+/// - `pending_steps` lists the migration steps the binary would run on its
+///   next start, and `migration_pending` is true if there are any.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VersionStatus {
+    pub code_version: Option<String>,
+    pub state_version: Option<String>,
+    pub network_version: Option<String>,
+    pub migration_pending: bool,
+    pub pending_steps: Vec<String>,
+}
+
+impl VersionStatus {
+    pub fn new(code: Option<&[u8]>, state: Option<&[u8]>, network: Option<&[u8]>) -> Self {
+        let fmt = |version: Option<&[u8]>| version.map(hex::encode);
+
+        let mut pending_steps = vec![];
+        if let Some(code) = code {
+            if state != Some(code) {
+                pending_steps.push(format!(
+                    "Migrate state from version {} to {}",
+                    fmt(state).unwrap_or_else(|| "(none)".to_string()),
+                    hex::encode(code)
+                ));
+            }
+            if network != Some(code) {
+                pending_steps.push(format!(
+                    "Set network version from {} to {}",
+                    fmt(network).unwrap_or_else(|| "(none)".to_string()),
+                    hex::encode(code)
+                ));
+            }
+        }
+
+        Self {
+            code_version: fmt(code),
+            state_version: fmt(state),
+            network_version: fmt(network),
+            migration_pending: !pending_steps.is_empty(),
+            pending_steps,
+        }
+    }
+}
+
 struct InternalApp<A> {
     _app: PhantomData<A>,
+    tx_gas_limit: Option<u64>,
+    check_tx_deadline: Option<Duration>,
+    code_version: Option<Vec<u8>>,
 }
 
 impl<A: App> InternalApp<ABCIPlugin<A>> {
     pub fn new() -> Self {
-        Self { _app: PhantomData }
+        Self {
+            _app: PhantomData,
+            tx_gas_limit: None,
+            check_tx_deadline: None,
+            code_version: None,
+        }
+    }
+
+    fn version_query(&self, store: &MerkStore) -> Result<ResponseQuery> {
+        let state_version = store.merk().get_aux(b"consensus_version")?;
+        // the network version is stored with a length prefix
+        let network_version = store
+            .get(crate::upgrade::VERSION_KEY)?
+            .map(|bytes| bytes.get(1..).unwrap_or_default().to_vec());
+        let status = VersionStatus::new(
+            self.code_version.as_deref(),
+            state_version.as_deref(),
+            network_version.as_deref(),
+        );
+
+        Ok(ResponseQuery {
+            code: 0,
+            height: store.height()?.try_into()?,
+            value: serde_json::to_vec(&status)?.into(),
+            ..Default::default()
+        })
+    }
+
+    /// Loads the app state as of the last commit and runs `op` on it, without
+    /// persisting any changes.
+    #[cfg(feature = "abci-v0_38")]
+    fn committed_state<T, F: FnOnce(&ABCIPlugin<A>) -> Result<T>>(
+        &self,
+        store: Shared<MerkStore>,
+        op: F,
+    ) -> Result<T> {
+        let handle = store
+            .borrow()
+            .read_handle(None)
+            .ok_or_else(|| Error::App("No committed state".to_string()))?;
+        let store = Store::new(BackingStore::MemSnapshot(Shared::new(MemSnapshot::new(
+            handle,
+        ))));
+        let state_bytes = store
+            .get(&[])?
+            .ok_or_else(|| Error::App("Store is empty".to_string()))?;
+        let state = ABCIPlugin::<A>::load(store, &mut state_bytes.as_slice())?;
+
+        op(&state)
+    }
+
+    /// Builds the gas meter for a single tx, or `None` if calls are unmetered.
+    /// The wall-clock deadline is only applied in `CheckTx` since it is not
+    /// deterministic.
+    fn gas_meter(&self, check_tx: bool) -> Option<GasMeter> {
+        let deadline = self.check_tx_deadline.filter(|_| check_tx);
+        if self.tx_gas_limit.is_none() && deadline.is_none() {
+            return None;
+        }
+
+        let meter = GasMeter::new(self.tx_gas_limit.unwrap_or(u64::MAX));
+        Some(match deadline {
+            Some(deadline) => meter.with_deadline(deadline),
+            None => meter,
+        })
     }
 }
 
@@ -675,7 +1367,7 @@ mod tests {
         client::{wallet::Unsigned, AppClient},
         coins::Symbol,
         context::Context,
-        plugins::{ChainId, ConvertSdkTx, DefaultPlugins, PaidCall},
+        plugins::{ChainId, ConsensusParamUpdates, ConvertSdkTx, DefaultPlugins, PaidCall},
         tendermint::client::HttpClient,
     };
 
@@ -704,6 +1396,17 @@ mod tests {
         }
     }
 
+    impl crate::abci::EndBlock for App {
+        fn end_block(&mut self, _ctx: &crate::plugins::EndBlockCtx) -> Result<()> {
+            if self.count % 2 == 0 {
+                let updates = Context::resolve::<ConsensusParamUpdates>().unwrap();
+                updates.set_block_params(1_000_000, self.count as i64);
+            }
+
+            Ok(())
+        }
+    }
+
     // TODO: dedupe w/ tendermint::client tests
     pub async fn spawn_node() {
         pretty_env_logger::init();
@@ -744,6 +1447,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn carry_over_genesis() {
+        let mut genesis = serde_json::json!({
+            "chain_id": "foo",
+            "initial_height": "1",
+            "validators": [],
+        });
+        let validators = [ValidatorEntry {
+            pubkey: [1; 32],
+            power: 10,
+        }];
+        super::carry_over_genesis(&mut genesis, 100, &[0xab; 32], &validators);
+
+        assert_eq!(genesis["chain_id"], "foo");
+        assert_eq!(genesis["initial_height"], "101");
+        assert_eq!(genesis["app_hash"], "AB".repeat(32));
+        assert_eq!(
+            genesis["validators"],
+            serde_json::json!([{
+                "pub_key": {
+                    "type": "tendermint/PubKeyEd25519",
+                    "value": "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=",
+                },
+                "power": "10",
+                "name": "",
+            }])
+        );
+    }
+
+    #[test]
+    fn version_status() {
+        let status = VersionStatus::new(Some(&[2][..]), Some(&[2][..]), Some(&[2][..]));
+        assert!(!status.migration_pending);
+        assert!(status.pending_steps.is_empty());
+        assert_eq!(status.code_version.as_deref(), Some("02"));
+
+        let status = VersionStatus::new(Some(&[3][..]), Some(&[2][..]), Some(&[2][..]));
+        assert!(status.migration_pending);
+        assert_eq!(
+            status.pending_steps,
+            vec![
+                "Migrate state from version 02 to 03".to_string(),
+                "Set network version from 02 to 03".to_string(),
+            ]
+        );
+
+        // The network upgraded before this node migrated
+        let status = VersionStatus::new(Some(&[3][..]), Some(&[2][..]), Some(&[3][..]));
+        assert_eq!(status.pending_steps.len(), 1);
+
+        let status = VersionStatus::new(None, Some(&[2][..]), Some(&[3][..]));
+        assert!(!status.migration_pending);
+        assert_eq!(status.code_version, None);
+    }
+
     #[ignore]
     #[tokio::test]
     #[serial_test::serial]
@@ -761,4 +1519,94 @@ mod tests {
 
         Ok(())
     }
+
+    #[cfg(feature = "abci-v0_38")]
+    impl crate::abci::ExtendVote for App {
+        fn extend_vote(&self, _ctx: &crate::plugins::ExtendVoteCtx) -> Result<Vec<u8>> {
+            Ok(self.count.to_be_bytes().to_vec())
+        }
+    }
+
+    #[cfg(feature = "abci-v0_38")]
+    impl crate::abci::VerifyVoteExtension for App {
+        fn verify_vote_extension(
+            &self,
+            ctx: &crate::plugins::VerifyVoteExtensionCtx,
+        ) -> Result<bool> {
+            Ok(ctx.vote_extension == self.count.to_be_bytes())
+        }
+    }
+
+    #[cfg(all(feature = "abci-v0_38", feature = "merk-full"))]
+    #[test]
+    fn vote_extensions() {
+        use crate::abci::v0_38::{messages::*, ABCIStateMachine};
+        use tendermint_proto::google::protobuf::Timestamp;
+
+        let home = tempdir::TempDir::new("orga-vote-extensions").unwrap();
+        let mut sm = ABCIStateMachine::new(
+            InternalApp::<ABCIPlugin<App>>::new(),
+            MerkStore::new(home.path()),
+            false,
+            Default::default(),
+            Default::default(),
+        );
+        let mut run = |value| sm.run(Request { value: Some(value) }).unwrap();
+
+        run(request::Value::InitChain(Default::default()));
+        run(request::Value::FinalizeBlock(RequestFinalizeBlock {
+            height: 1,
+            time: Some(Timestamp::default()),
+            ..Default::default()
+        }));
+        run(request::Value::Commit(Default::default()));
+
+        let vote_extension = match run(request::Value::ExtendVote(RequestExtendVote {
+            height: 2,
+            ..Default::default()
+        })) {
+            response::Value::ExtendVote(res) => res.vote_extension,
+            res => panic!("Unexpected response: {:?}", res),
+        };
+        assert_eq!(vote_extension, 1u32.to_be_bytes());
+
+        let mut verify = |vote_extension| match run(request::Value::VerifyVoteExtension(
+            RequestVerifyVoteExtension {
+                height: 2,
+                vote_extension,
+                ..Default::default()
+            },
+        )) {
+            response::Value::VerifyVoteExtension(res) => res.status,
+            res => panic!("Unexpected response: {:?}", res),
+        };
+        assert_eq!(verify(vote_extension), VerifyStatus::Accept as i32);
+        assert_eq!(verify(vec![1, 2, 3]), VerifyStatus::Reject as i32);
+    }
+
+    #[cfg(feature = "merk-full")]
+    #[test]
+    fn consensus_param_updates() -> Result<()> {
+        use crate::abci::{InProcessHarness, ScriptedBlock};
+        use tendermint_proto::v0_34::abci::BlockParams;
+
+        let mut harness = InProcessHarness::new(InternalApp::<ABCIPlugin<App>>::new());
+        harness.init_chain(Default::default())?;
+
+        let res = harness.run_block(&ScriptedBlock::default())?;
+        assert!(res.end_block.consensus_param_updates.is_none());
+
+        let res = harness.run_block(&ScriptedBlock::default())?;
+        let params = res.end_block.consensus_param_updates.unwrap();
+        assert_eq!(
+            params.block,
+            Some(BlockParams {
+                max_bytes: 1_000_000,
+                max_gas: 2,
+            })
+        );
+        assert!(params.evidence.is_none());
+
+        Ok(())
+    }
 }
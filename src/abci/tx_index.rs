@@ -0,0 +1,93 @@
+use crate::merk::MerkStore;
+use crate::Result;
+use prost::Message;
+use sha2::{Digest, Sha256};
+use tendermint_proto::v0_34::abci::{RequestQuery, ResponseDeliverTx, ResponseQuery};
+
+/// The query path for looking up an indexed tx result, with the 32-byte tx
+/// hash as the query data. The response value is an encoded
+/// [`IndexedTx`](struct.IndexedTx.html).
+pub const TX_QUERY_PATH: &str = "/orga/tx";
+
+/// Prefix of the auxiliary keys holding indexed tx results, followed by the
+/// tx hash.
+const TX_INDEX_PREFIX: &[u8] = b"tx/";
+
+/// The result of a delivered tx, as recorded by the tx index.
+#[derive(Clone, PartialEq, Message)]
+pub struct IndexedTx {
+    #[prost(uint64, tag = "1")]
+    pub height: u64,
+    #[prost(uint32, tag = "2")]
+    pub index: u32,
+    #[prost(message, optional, tag = "3")]
+    pub result: Option<ResponseDeliverTx>,
+}
+
+/// Returns the hash txs are indexed by, the same SHA-256 hash Tendermint uses.
+pub fn tx_hash(tx: &[u8]) -> [u8; 32] {
+    Sha256::digest(tx).into()
+}
+
+/// Records the results of the txs in the current block, which are written to
+/// the store's auxiliary data along with the block's commit. Being auxiliary,
+/// the index does not affect the app hash, so nodes can enable it
+/// independently. Entries are removed once their height is pruned, see
+/// [Pruning](crate::merk::Pruning).
+#[derive(Default)]
+pub(crate) struct TxIndex {
+    pending: Vec<([u8; 32], IndexedTx)>,
+}
+
+impl TxIndex {
+    /// Drops the results recorded for a block which was never committed.
+    pub fn begin_block(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn record(&mut self, tx: &[u8], height: u64, result: &ResponseDeliverTx) {
+        let index = self.pending.len() as u32;
+        self.pending.push((
+            tx_hash(tx),
+            IndexedTx {
+                height,
+                index,
+                result: Some(result.clone()),
+            },
+        ));
+    }
+
+    /// Queues the recorded results to be written by the next commit of
+    /// `store`.
+    pub fn commit(&mut self, store: &mut MerkStore) {
+        for (hash, indexed) in self.pending.drain(..) {
+            store.put_height_aux(index_key(&hash), indexed.encode_to_vec());
+        }
+    }
+}
+
+/// Looks up the result of the tx with the given hash as of the last commit.
+pub fn lookup_tx(store: &MerkStore, hash: &[u8]) -> Result<Option<IndexedTx>> {
+    store
+        .get_aux(&index_key(hash))?
+        .map(|bytes| IndexedTx::decode(bytes.as_slice()))
+        .transpose()
+        .map_err(|err| crate::Error::Store(format!("Invalid tx index entry: {}", err)))
+}
+
+fn index_key(hash: &[u8]) -> Vec<u8> {
+    [TX_INDEX_PREFIX, hash].concat()
+}
+
+/// Handles a [`TX_QUERY_PATH`](constant.TX_QUERY_PATH.html) query.
+pub(crate) fn query_tx(store: &MerkStore, req: &RequestQuery) -> Result<ResponseQuery> {
+    let indexed = lookup_tx(store, &req.data)?
+        .ok_or_else(|| crate::Error::Query(format!("Tx {} not found", hex::encode(&req.data))))?;
+
+    Ok(ResponseQuery {
+        height: indexed.height as i64,
+        key: req.data.clone(),
+        value: indexed.encode_to_vec().into(),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,102 @@
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+
+/// Configuration for logging txs rejected in `CheckTx` or `DeliverTx`.
+///
+/// Logging every rejected tx is expensive and an easy way for an attacker to
+/// flood the node's logs, so at most `max_per_window` rejections are logged per
+/// `window`. The number of suppressed entries is reported once the window
+/// rolls over.
+#[derive(Clone, Debug)]
+pub struct RejectedTxLogConfig {
+    pub level: log::Level,
+    pub max_per_window: u32,
+    pub window: Duration,
+}
+
+impl Default for RejectedTxLogConfig {
+    fn default() -> Self {
+        Self {
+            level: log::Level::Info,
+            max_per_window: 10,
+            window: Duration::from_secs(1),
+        }
+    }
+}
+
+pub(crate) struct RejectedTxLog {
+    config: RejectedTxLogConfig,
+    window_start: Instant,
+    logged: u32,
+    suppressed: u64,
+}
+
+impl RejectedTxLog {
+    pub fn new(config: RejectedTxLogConfig) -> Self {
+        Self {
+            config,
+            window_start: Instant::now(),
+            logged: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Logs a rejected tx, returning `false` if it was suppressed by the rate
+    /// limit.
+    pub fn log(&mut self, kind: &str, tx: &[u8], code: u32, reason: &str) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= self.config.window {
+            if self.suppressed > 0 {
+                log::log!(
+                    self.config.level,
+                    "Suppressed logging of {} rejected txs",
+                    self.suppressed
+                );
+            }
+            self.window_start = now;
+            self.logged = 0;
+            self.suppressed = 0;
+        }
+
+        if self.logged >= self.config.max_per_window {
+            self.suppressed += 1;
+            return false;
+        }
+        self.logged += 1;
+
+        let hash = Sha256::digest(tx);
+        log::log!(
+            self.config.level,
+            "{} rejected tx {} (code {}): {}",
+            kind,
+            hex::encode_upper(hash),
+            code,
+            reason
+        );
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit() {
+        let mut log = RejectedTxLog::new(RejectedTxLogConfig {
+            level: log::Level::Debug,
+            max_per_window: 2,
+            window: Duration::from_millis(50),
+        });
+
+        assert!(log.log("CheckTx", b"a", 1, "bad"));
+        assert!(log.log("CheckTx", b"b", 1, "bad"));
+        assert!(!log.log("CheckTx", b"c", 1, "bad"));
+        assert_eq!(log.suppressed, 1);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(log.log("DeliverTx", b"d", 1, "bad"));
+        assert_eq!(log.suppressed, 0);
+    }
+}
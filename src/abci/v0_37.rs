@@ -0,0 +1,217 @@
+//! Support for Tendermint 0.37 (ABCI++), enabled with the `abci-v0_37`
+//! feature.
+//!
+//! ABCI 0.37 keeps the block execution messages of 0.34 (`BeginBlock`,
+//! `DeliverTx`, `EndBlock`, `Commit`) and adds `PrepareProposal` and
+//! `ProcessProposal`. The messages shared by both versions are wire-compatible,
+//! so [`ABCIStateMachine`] converts them to their 0.34 equivalents and runs
+//! them through the regular [`super::ABCIStateMachine`], and only the proposal
+//! messages are handled here.
+//!
+//! Fields which 0.37 removed (e.g. `ResponseCheckTx::priority`) are dropped
+//! from responses, and event attributes must be valid UTF-8 since 0.37 encodes
+//! them as strings.
+
+use super::Application as BaseApplication;
+use crate::merk::MerkStore;
+use crate::store::Shared;
+use crate::{Error, Result};
+use prost::Message;
+use std::sync::{Arc, RwLock};
+use tendermint_proto::v0_34::abci as v0_34;
+
+pub use tendermint_proto::v0_37::abci as messages;
+
+use messages::request::Value as Req;
+use messages::response::Value as Res;
+use messages::response_process_proposal::ProposalStatus;
+use messages::*;
+
+/// An interface for handling the ABCI 0.37 proposal requests, in addition to
+/// the requests of [`super::Application`].
+///
+/// The proposal methods receive the last committed state, since the proposed
+/// block has not been executed yet. By default, `PrepareProposal` proposes
+/// the mempool's txs in order (up to `max_tx_bytes`) and `ProcessProposal`
+/// accepts every proposal, which matches the behavior of ABCI 0.34.
+pub trait Application: BaseApplication {
+    fn prepare_proposal(
+        &self,
+        _store: Shared<MerkStore>,
+        req: RequestPrepareProposal,
+    ) -> Result<ResponsePrepareProposal> {
+        let mut total_bytes = 0;
+        let txs = req
+            .txs
+            .into_iter()
+            .take_while(|tx| {
+                total_bytes += tx.len() as i64;
+                total_bytes <= req.max_tx_bytes
+            })
+            .collect();
+
+        Ok(ResponsePrepareProposal { txs })
+    }
+
+    fn process_proposal(
+        &self,
+        _store: Shared<MerkStore>,
+        _req: RequestProcessProposal,
+    ) -> Result<ResponseProcessProposal> {
+        Ok(ResponseProcessProposal {
+            status: ProposalStatus::Accept as i32,
+        })
+    }
+}
+
+/// Runs an ABCI 0.37 application, wrapping a [`super::ABCIStateMachine`]
+/// which handles everything except the proposal requests.
+pub struct ABCIStateMachine<A: Application> {
+    inner: super::ABCIStateMachine<A>,
+}
+
+impl<A: Application> ABCIStateMachine<A> {
+    /// See [`super::ABCIStateMachine::new`].
+    pub fn new(
+        app: A,
+        store: MerkStore,
+        skip_init_chain: bool,
+        shutdown: Arc<RwLock<Option<Error>>>,
+        shutdown_notifier: Arc<RwLock<bool>>,
+    ) -> Self {
+        super::ABCIStateMachine::new(app, store, skip_init_chain, shutdown, shutdown_notifier)
+            .into()
+    }
+
+    /// The wrapped 0.34 state machine, e.g. for configuring it or reading its
+    /// status.
+    pub fn inner(&mut self) -> &mut super::ABCIStateMachine<A> {
+        &mut self.inner
+    }
+
+    /// Handles a single incoming ABCI 0.37 request.
+    pub fn run(&mut self, req: Request) -> Result<Res> {
+        let value = req
+            .value
+            .ok_or_else(|| Error::ABCI("Received empty request".into()))?;
+
+        match value {
+            Req::PrepareProposal(req) => {
+                let (app, store) = self.inner.app_and_store();
+                Ok(Res::PrepareProposal(app.prepare_proposal(store, req)?))
+            }
+            Req::ProcessProposal(req) => {
+                let (app, store) = self.inner.app_and_store();
+                Ok(Res::ProcessProposal(app.process_proposal(store, req)?))
+            }
+            value => {
+                let req: v0_34::Request = convert(&Request { value: Some(value) })?;
+                let value = self.inner.run(req)?;
+                let res: Response = convert(&v0_34::Response { value: Some(value) })?;
+                res.value
+                    .ok_or_else(|| Error::ABCI("Response has no ABCI 0.37 equivalent".into()))
+            }
+        }
+    }
+}
+
+impl<A: Application> From<super::ABCIStateMachine<A>> for ABCIStateMachine<A> {
+    fn from(inner: super::ABCIStateMachine<A>) -> Self {
+        Self { inner }
+    }
+}
+
+/// Converts between the 0.34 and 0.37 versions of a message by re-encoding
+/// it, relying on the messages being wire-compatible.
+fn convert<T: Message, U: Message + Default>(msg: &T) -> Result<U> {
+    U::decode(msg.encode_to_vec().as_slice())
+        .map_err(|e| Error::ABCI(format!("Cannot convert ABCI message: {}", e)))
+}
+
+#[cfg(all(test, feature = "merk-full"))]
+mod tests {
+    use super::*;
+    use crate::abci::WrappedMerk;
+    use crate::store::Write;
+
+    struct TestApp;
+
+    impl BaseApplication for TestApp {
+        fn deliver_tx(
+            &self,
+            mut store: WrappedMerk,
+            req: v0_34::RequestDeliverTx,
+        ) -> Result<v0_34::ResponseDeliverTx> {
+            store.put(req.tx.to_vec(), vec![1])?;
+            Ok(v0_34::ResponseDeliverTx {
+                log: "delivered".to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    impl Application for TestApp {}
+
+    fn run(sm: &mut ABCIStateMachine<TestApp>, value: Req) -> Res {
+        sm.run(Request { value: Some(value) }).unwrap()
+    }
+
+    #[test]
+    fn v0_37_block() {
+        let home = tempdir::TempDir::new("orga-v0_37").unwrap();
+        let mut sm = ABCIStateMachine::new(
+            TestApp,
+            MerkStore::new(home.path()),
+            false,
+            Default::default(),
+            Default::default(),
+        );
+
+        run(&mut sm, Req::InitChain(Default::default()));
+
+        let txs: Vec<prost::bytes::Bytes> =
+            vec![vec![1; 10].into(), vec![2; 10].into(), vec![3; 10].into()];
+        match run(
+            &mut sm,
+            Req::PrepareProposal(RequestPrepareProposal {
+                max_tx_bytes: 25,
+                txs: txs.clone(),
+                ..Default::default()
+            }),
+        ) {
+            Res::PrepareProposal(res) => assert_eq!(res.txs, txs[..2].to_vec()),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        match run(&mut sm, Req::ProcessProposal(Default::default())) {
+            Res::ProcessProposal(res) => assert_eq!(res.status, ProposalStatus::Accept as i32),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        run(
+            &mut sm,
+            Req::BeginBlock(RequestBeginBlock {
+                header: Some(tendermint_proto::v0_37::types::Header {
+                    height: 1,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+        );
+        match run(
+            &mut sm,
+            Req::DeliverTx(RequestDeliverTx { tx: txs[0].clone() }),
+        ) {
+            Res::DeliverTx(res) => assert_eq!(res.log, "delivered"),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+        run(&mut sm, Req::EndBlock(RequestEndBlock { height: 1 }));
+        match run(&mut sm, Req::Commit(Default::default())) {
+            Res::Commit(res) => assert!(!res.data.is_empty()),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        assert_eq!(sm.inner().status().committed_height, 1);
+        assert_eq!(sm.inner().status().key_count, Some(1));
+    }
+}
@@ -0,0 +1,546 @@
+//! Support for CometBFT 0.38 (ABCI 2.0), enabled with the `abci-v0_38`
+//! feature.
+//!
+//! ABCI 0.38 replaces `BeginBlock`, `DeliverTx` and `EndBlock` with a single
+//! `FinalizeBlock` message, and adds `ExtendVote` and `VerifyVoteExtension`.
+//! [`ABCIStateMachine`] splits each `FinalizeBlock` back into the 0.34 block
+//! execution messages and runs them through the regular
+//! [`super::ABCIStateMachine`] (via [`super::v0_37::ABCIStateMachine`] for the
+//! proposal messages), so apps keep implementing the existing
+//! `BeginBlock`/`DeliverTx`/`EndBlock` handlers.
+//!
+//! `FinalizeBlock` must return the block's app hash, so the block is committed
+//! to the store while handling it, and the later `Commit` request only reports
+//! the retain height. If the node stops between the two, CometBFT replays the
+//! saved `FinalizeBlock` responses on restart.
+//!
+//! The `tendermint-proto` version we depend on predates 0.38, so the messages
+//! which 0.38 added or changed are defined in [`messages`]. The rest are
+//! wire-compatible with their 0.37 equivalents and reuse those types.
+
+use super::v0_37;
+use crate::merk::MerkStore;
+use crate::store::Shared;
+use crate::{Error, Result};
+use prost::Message;
+use std::sync::{Arc, RwLock};
+use tendermint_proto::v0_37::abci as v0_37_messages;
+
+use messages::request::Value as Req;
+use messages::response::Value as Res;
+use messages::*;
+use v0_37_messages::request::Value as Req37;
+use v0_37_messages::response::Value as Res37;
+
+/// The ABCI 0.38 messages.
+pub mod messages {
+    use tendermint_proto::google::protobuf::Timestamp;
+    use tendermint_proto::v0_37::abci as v0_37;
+    use tendermint_proto::v0_37::types::ConsensusParams;
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Request {
+        #[prost(
+            oneof = "request::Value",
+            tags = "1, 2, 3, 5, 6, 8, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20"
+        )]
+        pub value: Option<request::Value>,
+    }
+
+    pub mod request {
+        use super::*;
+
+        #[derive(Clone, PartialEq, ::prost::Oneof)]
+        pub enum Value {
+            #[prost(message, tag = "1")]
+            Echo(v0_37::RequestEcho),
+            #[prost(message, tag = "2")]
+            Flush(v0_37::RequestFlush),
+            #[prost(message, tag = "3")]
+            Info(v0_37::RequestInfo),
+            #[prost(message, tag = "5")]
+            InitChain(v0_37::RequestInitChain),
+            #[prost(message, tag = "6")]
+            Query(v0_37::RequestQuery),
+            #[prost(message, tag = "8")]
+            CheckTx(v0_37::RequestCheckTx),
+            #[prost(message, tag = "11")]
+            Commit(v0_37::RequestCommit),
+            #[prost(message, tag = "12")]
+            ListSnapshots(v0_37::RequestListSnapshots),
+            #[prost(message, tag = "13")]
+            OfferSnapshot(v0_37::RequestOfferSnapshot),
+            #[prost(message, tag = "14")]
+            LoadSnapshotChunk(v0_37::RequestLoadSnapshotChunk),
+            #[prost(message, tag = "15")]
+            ApplySnapshotChunk(v0_37::RequestApplySnapshotChunk),
+            #[prost(message, tag = "16")]
+            PrepareProposal(v0_37::RequestPrepareProposal),
+            #[prost(message, tag = "17")]
+            ProcessProposal(v0_37::RequestProcessProposal),
+            #[prost(message, tag = "18")]
+            ExtendVote(RequestExtendVote),
+            #[prost(message, tag = "19")]
+            VerifyVoteExtension(RequestVerifyVoteExtension),
+            #[prost(message, tag = "20")]
+            FinalizeBlock(RequestFinalizeBlock),
+        }
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Response {
+        #[prost(
+            oneof = "response::Value",
+            tags = "1, 2, 3, 4, 6, 7, 9, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21"
+        )]
+        pub value: Option<response::Value>,
+    }
+
+    pub mod response {
+        use super::*;
+
+        #[derive(Clone, PartialEq, ::prost::Oneof)]
+        pub enum Value {
+            #[prost(message, tag = "1")]
+            Exception(v0_37::ResponseException),
+            #[prost(message, tag = "2")]
+            Echo(v0_37::ResponseEcho),
+            #[prost(message, tag = "3")]
+            Flush(v0_37::ResponseFlush),
+            #[prost(message, tag = "4")]
+            Info(v0_37::ResponseInfo),
+            #[prost(message, tag = "6")]
+            InitChain(v0_37::ResponseInitChain),
+            #[prost(message, tag = "7")]
+            Query(v0_37::ResponseQuery),
+            #[prost(message, tag = "9")]
+            CheckTx(v0_37::ResponseCheckTx),
+            #[prost(message, tag = "12")]
+            Commit(ResponseCommit),
+            #[prost(message, tag = "13")]
+            ListSnapshots(v0_37::ResponseListSnapshots),
+            #[prost(message, tag = "14")]
+            OfferSnapshot(v0_37::ResponseOfferSnapshot),
+            #[prost(message, tag = "15")]
+            LoadSnapshotChunk(v0_37::ResponseLoadSnapshotChunk),
+            #[prost(message, tag = "16")]
+            ApplySnapshotChunk(v0_37::ResponseApplySnapshotChunk),
+            #[prost(message, tag = "17")]
+            PrepareProposal(v0_37::ResponsePrepareProposal),
+            #[prost(message, tag = "18")]
+            ProcessProposal(v0_37::ResponseProcessProposal),
+            #[prost(message, tag = "19")]
+            ExtendVote(ResponseExtendVote),
+            #[prost(message, tag = "20")]
+            VerifyVoteExtension(ResponseVerifyVoteExtension),
+            #[prost(message, tag = "21")]
+            FinalizeBlock(ResponseFinalizeBlock),
+        }
+    }
+
+    /// Commit no longer returns the app hash, which is returned by
+    /// `FinalizeBlock` instead.
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ResponseCommit {
+        #[prost(int64, tag = "3")]
+        pub retain_height: i64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct RequestFinalizeBlock {
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub txs: Vec<Vec<u8>>,
+        #[prost(message, optional, tag = "2")]
+        pub decided_last_commit: Option<CommitInfo>,
+        #[prost(message, repeated, tag = "3")]
+        pub misbehavior: Vec<v0_37::Misbehavior>,
+        #[prost(bytes = "vec", tag = "4")]
+        pub hash: Vec<u8>,
+        #[prost(int64, tag = "5")]
+        pub height: i64,
+        #[prost(message, optional, tag = "6")]
+        pub time: Option<Timestamp>,
+        #[prost(bytes = "vec", tag = "7")]
+        pub next_validators_hash: Vec<u8>,
+        #[prost(bytes = "vec", tag = "8")]
+        pub proposer_address: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ResponseFinalizeBlock {
+        #[prost(message, repeated, tag = "1")]
+        pub events: Vec<v0_37::Event>,
+        #[prost(message, repeated, tag = "2")]
+        pub tx_results: Vec<ExecTxResult>,
+        #[prost(message, repeated, tag = "3")]
+        pub validator_updates: Vec<v0_37::ValidatorUpdate>,
+        #[prost(message, optional, tag = "4")]
+        pub consensus_param_updates: Option<ConsensusParams>,
+        #[prost(bytes = "vec", tag = "5")]
+        pub app_hash: Vec<u8>,
+    }
+
+    /// The result of executing a tx in `FinalizeBlock`, equivalent to
+    /// `ResponseDeliverTx`.
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ExecTxResult {
+        #[prost(uint32, tag = "1")]
+        pub code: u32,
+        #[prost(bytes = "vec", tag = "2")]
+        pub data: Vec<u8>,
+        #[prost(string, tag = "3")]
+        pub log: String,
+        #[prost(string, tag = "4")]
+        pub info: String,
+        #[prost(int64, tag = "5")]
+        pub gas_wanted: i64,
+        #[prost(int64, tag = "6")]
+        pub gas_used: i64,
+        #[prost(message, repeated, tag = "7")]
+        pub events: Vec<v0_37::Event>,
+        #[prost(string, tag = "8")]
+        pub codespace: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct CommitInfo {
+        #[prost(int32, tag = "1")]
+        pub round: i32,
+        #[prost(message, repeated, tag = "2")]
+        pub votes: Vec<VoteInfo>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct VoteInfo {
+        #[prost(message, optional, tag = "1")]
+        pub validator: Option<v0_37::Validator>,
+        /// A `tendermint_proto::v0_37::types::BlockIdFlag`.
+        #[prost(int32, tag = "3")]
+        pub block_id_flag: i32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct RequestExtendVote {
+        #[prost(bytes = "vec", tag = "1")]
+        pub hash: Vec<u8>,
+        #[prost(int64, tag = "2")]
+        pub height: i64,
+        #[prost(message, optional, tag = "3")]
+        pub time: Option<Timestamp>,
+        #[prost(bytes = "vec", repeated, tag = "4")]
+        pub txs: Vec<Vec<u8>>,
+        #[prost(message, optional, tag = "5")]
+        pub proposed_last_commit: Option<CommitInfo>,
+        #[prost(message, repeated, tag = "6")]
+        pub misbehavior: Vec<v0_37::Misbehavior>,
+        #[prost(bytes = "vec", tag = "7")]
+        pub next_validators_hash: Vec<u8>,
+        #[prost(bytes = "vec", tag = "8")]
+        pub proposer_address: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ResponseExtendVote {
+        #[prost(bytes = "vec", tag = "1")]
+        pub vote_extension: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct RequestVerifyVoteExtension {
+        #[prost(bytes = "vec", tag = "1")]
+        pub hash: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub validator_address: Vec<u8>,
+        #[prost(int64, tag = "3")]
+        pub height: i64,
+        #[prost(bytes = "vec", tag = "4")]
+        pub vote_extension: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ResponseVerifyVoteExtension {
+        #[prost(enumeration = "VerifyStatus", tag = "1")]
+        pub status: i32,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum VerifyStatus {
+        Unknown = 0,
+        Accept = 1,
+        Reject = 2,
+    }
+}
+
+/// An interface for handling the ABCI 0.38 vote extension requests, in
+/// addition to the requests of [`super::v0_37::Application`].
+///
+/// Like the proposal methods, these receive the last committed state. By
+/// default, no vote extensions are added and every extension is accepted.
+pub trait Application: v0_37::Application {
+    fn extend_vote(
+        &self,
+        _store: Shared<MerkStore>,
+        _req: RequestExtendVote,
+    ) -> Result<ResponseExtendVote> {
+        Ok(Default::default())
+    }
+
+    fn verify_vote_extension(
+        &self,
+        _store: Shared<MerkStore>,
+        _req: RequestVerifyVoteExtension,
+    ) -> Result<ResponseVerifyVoteExtension> {
+        Ok(ResponseVerifyVoteExtension {
+            status: VerifyStatus::Accept as i32,
+        })
+    }
+}
+
+/// Runs an ABCI 0.38 application, wrapping a
+/// [`super::v0_37::ABCIStateMachine`] which handles everything except the
+/// vote extension and `FinalizeBlock` requests.
+pub struct ABCIStateMachine<A: Application> {
+    inner: v0_37::ABCIStateMachine<A>,
+    retain_height: i64,
+}
+
+impl<A: Application> ABCIStateMachine<A> {
+    /// See [`super::ABCIStateMachine::new`].
+    pub fn new(
+        app: A,
+        store: MerkStore,
+        skip_init_chain: bool,
+        shutdown: Arc<RwLock<Option<Error>>>,
+        shutdown_notifier: Arc<RwLock<bool>>,
+    ) -> Self {
+        super::ABCIStateMachine::new(app, store, skip_init_chain, shutdown, shutdown_notifier)
+            .into()
+    }
+
+    /// The wrapped 0.34 state machine, e.g. for configuring it or reading its
+    /// status.
+    pub fn inner(&mut self) -> &mut super::ABCIStateMachine<A> {
+        self.inner.inner()
+    }
+
+    /// Handles a single incoming ABCI 0.38 request.
+    pub fn run(&mut self, req: Request) -> Result<Res> {
+        let value = req
+            .value
+            .ok_or_else(|| Error::ABCI("Received empty request".into()))?;
+
+        match value {
+            Req::FinalizeBlock(req) => Ok(Res::FinalizeBlock(self.finalize_block(req)?)),
+            Req::Commit(_) => Ok(Res::Commit(ResponseCommit {
+                retain_height: self.retain_height,
+            })),
+            Req::ExtendVote(req) => {
+                let (app, store) = self.inner().app_and_store();
+                Ok(Res::ExtendVote(app.extend_vote(store, req)?))
+            }
+            Req::VerifyVoteExtension(req) => {
+                let (app, store) = self.inner().app_and_store();
+                Ok(Res::VerifyVoteExtension(
+                    app.verify_vote_extension(store, req)?,
+                ))
+            }
+            value => {
+                let req: v0_37_messages::Request = convert(&Request { value: Some(value) })?;
+                let value = self.inner.run(req)?;
+                let res: Response = convert(&v0_37_messages::Response { value: Some(value) })?;
+                res.value
+                    .ok_or_else(|| Error::ABCI("Response has no ABCI 0.38 equivalent".into()))
+            }
+        }
+    }
+
+    /// Executes and commits a block by running it through the inner state
+    /// machine as `BeginBlock`, a `DeliverTx` for each tx, `EndBlock` and
+    /// `Commit`.
+    fn finalize_block(&mut self, req: RequestFinalizeBlock) -> Result<ResponseFinalizeBlock> {
+        use tendermint_proto::v0_37::types::{BlockIdFlag, Header};
+
+        let last_commit_info = req
+            .decided_last_commit
+            .map(|commit| v0_37_messages::CommitInfo {
+                round: commit.round,
+                votes: commit
+                    .votes
+                    .into_iter()
+                    .map(|vote| v0_37_messages::VoteInfo {
+                        validator: vote.validator,
+                        signed_last_block: vote.block_id_flag == BlockIdFlag::Commit as i32,
+                    })
+                    .collect(),
+            });
+        let header = Header {
+            height: req.height,
+            time: req.time,
+            next_validators_hash: req.next_validators_hash.into(),
+            proposer_address: req.proposer_address.into(),
+            ..Default::default()
+        };
+
+        let mut res = ResponseFinalizeBlock::default();
+
+        let begin_block = self.run_inner(Req37::BeginBlock(v0_37_messages::RequestBeginBlock {
+            hash: req.hash.into(),
+            header: Some(header),
+            last_commit_info,
+            byzantine_validators: req.misbehavior,
+        }))?;
+        match begin_block {
+            Res37::BeginBlock(begin_block) => res.events.extend(begin_block.events),
+            _ => return Err(unexpected_response("BeginBlock")),
+        }
+
+        for tx in req.txs {
+            let deliver_tx =
+                self.run_inner(Req37::DeliverTx(v0_37_messages::RequestDeliverTx {
+                    tx: tx.into(),
+                }))?;
+            match deliver_tx {
+                Res37::DeliverTx(deliver_tx) => res.tx_results.push(convert(&deliver_tx)?),
+                _ => return Err(unexpected_response("DeliverTx")),
+            }
+        }
+
+        let end_block = self.run_inner(Req37::EndBlock(v0_37_messages::RequestEndBlock {
+            height: req.height,
+        }))?;
+        match end_block {
+            Res37::EndBlock(end_block) => {
+                res.events.extend(end_block.events);
+                res.validator_updates = end_block.validator_updates;
+                res.consensus_param_updates = end_block.consensus_param_updates;
+            }
+            _ => return Err(unexpected_response("EndBlock")),
+        }
+
+        match self.run_inner(Req37::Commit(Default::default()))? {
+            Res37::Commit(commit) => {
+                res.app_hash = commit.data.to_vec();
+                self.retain_height = commit.retain_height;
+            }
+            _ => return Err(unexpected_response("Commit")),
+        }
+
+        Ok(res)
+    }
+
+    fn run_inner(&mut self, value: Req37) -> Result<Res37> {
+        self.inner
+            .run(v0_37_messages::Request { value: Some(value) })
+    }
+}
+
+impl<A: Application> From<super::ABCIStateMachine<A>> for ABCIStateMachine<A> {
+    fn from(inner: super::ABCIStateMachine<A>) -> Self {
+        Self {
+            inner: inner.into(),
+            retain_height: 0,
+        }
+    }
+}
+
+fn unexpected_response(req: &str) -> Error {
+    Error::ABCI(format!("Unexpected response to {}", req))
+}
+
+/// Converts between versions of a message by re-encoding it, relying on the
+/// messages being wire-compatible.
+fn convert<T: Message, U: Message + Default>(msg: &T) -> Result<U> {
+    U::decode(msg.encode_to_vec().as_slice())
+        .map_err(|e| Error::ABCI(format!("Cannot convert ABCI message: {}", e)))
+}
+
+#[cfg(all(test, feature = "merk-full"))]
+mod tests {
+    use super::*;
+    use crate::abci::{Application as BaseApplication, WrappedMerk};
+    use crate::store::Write;
+    use tendermint_proto::google::protobuf::Timestamp;
+    use tendermint_proto::v0_34::abci as v0_34;
+
+    struct TestApp;
+
+    impl BaseApplication for TestApp {
+        fn deliver_tx(
+            &self,
+            mut store: WrappedMerk,
+            req: v0_34::RequestDeliverTx,
+        ) -> Result<v0_34::ResponseDeliverTx> {
+            store.put(req.tx.to_vec(), vec![1])?;
+            Ok(v0_34::ResponseDeliverTx {
+                log: "delivered".to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    impl v0_37::Application for TestApp {}
+
+    impl Application for TestApp {}
+
+    fn run(sm: &mut ABCIStateMachine<TestApp>, value: Req) -> Res {
+        sm.run(Request { value: Some(value) }).unwrap()
+    }
+
+    #[test]
+    fn v0_38_block() {
+        let home = tempdir::TempDir::new("orga-v0_38").unwrap();
+        let mut sm = ABCIStateMachine::new(
+            TestApp,
+            MerkStore::new(home.path()),
+            false,
+            Default::default(),
+            Default::default(),
+        );
+
+        run(&mut sm, Req::InitChain(Default::default()));
+
+        match run(&mut sm, Req::ExtendVote(Default::default())) {
+            Res::ExtendVote(res) => assert!(res.vote_extension.is_empty()),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+        match run(&mut sm, Req::VerifyVoteExtension(Default::default())) {
+            Res::VerifyVoteExtension(res) => {
+                assert_eq!(res.status, VerifyStatus::Accept as i32)
+            }
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        let app_hash = match run(
+            &mut sm,
+            Req::FinalizeBlock(RequestFinalizeBlock {
+                txs: vec![vec![1; 10], vec![2; 10]],
+                height: 1,
+                time: Some(Timestamp::default()),
+                ..Default::default()
+            }),
+        ) {
+            Res::FinalizeBlock(res) => {
+                assert_eq!(res.tx_results.len(), 2);
+                assert_eq!(res.tx_results[1].log, "delivered");
+                assert!(!res.app_hash.is_empty());
+                res.app_hash
+            }
+            res => panic!("Unexpected response: {:?}", res),
+        };
+        assert_eq!(sm.inner().status().committed_height, 1);
+
+        match run(&mut sm, Req::Commit(Default::default())) {
+            Res::Commit(res) => assert_eq!(res.retain_height, 0),
+            res => panic!("Unexpected response: {:?}", res),
+        }
+        match run(&mut sm, Req::Info(Default::default())) {
+            Res::Info(res) => {
+                assert_eq!(res.last_block_height, 1);
+                assert_eq!(res.last_block_app_hash.to_vec(), app_hash);
+            }
+            res => panic!("Unexpected response: {:?}", res),
+        }
+        assert_eq!(sm.inner().status().key_count, Some(2));
+    }
+}
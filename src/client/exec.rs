@@ -31,6 +31,16 @@ pub trait Transport<T: Query + Call>: Send + Sync {
     async fn query(&self, query: T::Query) -> Result<Store>;
 
     async fn call(&self, call: T::Call) -> Result<()>;
+
+    /// Queries the state as of the block at `height`. Returns an error if the
+    /// transport can't read historical state, or if the node no longer retains
+    /// that height.
+    async fn query_at(&self, _query: T::Query, height: u64) -> Result<Store> {
+        Err(Error::Client(format!(
+            "Transport does not support querying at height {}",
+            height
+        )))
+    }
 }
 
 impl<T: Transport<U>, U: Query + Call> Transport<U> for &mut T {
@@ -41,12 +51,46 @@ impl<T: Transport<U>, U: Query + Call> Transport<U> for &mut T {
     async fn call(&self, call: <U as Call>::Call) -> Result<()> {
         (**self).call(call).await
     }
+
+    async fn query_at(&self, query: <U as Query>::Query, height: u64) -> Result<Store> {
+        (**self).query_at(query, height).await
+    }
 }
 
 // TODO: remove need for ABCIPlugin wrapping at this level, and App bound
 pub async fn execute<T, U>(
     store: Store,
     client: &impl Transport<ABCIPlugin<QueryPlugin<T>>>,
+    query_fn: impl FnMut(ABCIPlugin<QueryPlugin<T>>) -> Result<U>,
+) -> Result<(U, Store)>
+where
+    T: App + State + Query + Call + Describe,
+    T::Query: Send + Sync,
+    T::Call: Send + Sync,
+{
+    execute_inner(store, client, None, query_fn).await
+}
+
+/// Like [execute], but every query made by the transport reads the state as of
+/// `height`.
+pub async fn execute_at<T, U>(
+    store: Store,
+    client: &impl Transport<ABCIPlugin<QueryPlugin<T>>>,
+    height: u64,
+    query_fn: impl FnMut(ABCIPlugin<QueryPlugin<T>>) -> Result<U>,
+) -> Result<(U, Store)>
+where
+    T: App + State + Query + Call + Describe,
+    T::Query: Send + Sync,
+    T::Call: Send + Sync,
+{
+    execute_inner(store, client, Some(height), query_fn).await
+}
+
+async fn execute_inner<T, U>(
+    store: Store,
+    client: &impl Transport<ABCIPlugin<QueryPlugin<T>>>,
+    height: Option<u64>,
     mut query_fn: impl FnMut(ABCIPlugin<QueryPlugin<T>>) -> Result<U>,
 ) -> Result<(U, Store)>
 where
@@ -73,7 +117,10 @@ where
         }
         queries.insert(query_bytes);
 
-        let res = client.query(query).await?;
+        let res = match height {
+            Some(height) => client.query_at(query, height).await?,
+            None => client.query(query).await?,
+        };
 
         store = join_store(store, res)?;
     }
@@ -1,4 +1,4 @@
-use std::{any::Any, collections::BTreeMap, marker::PhantomData, sync::Mutex};
+use std::{collections::BTreeMap, marker::PhantomData, sync::Mutex};
 
 use crate::{
     abci::App,
@@ -39,21 +39,19 @@ impl<T: App + State + Query + Call> SyncTransport<ABCIPlugin<QueryPlugin<T>>>
         let query_bytes = query.encode()?;
         self.queries.lock().unwrap().push(query_bytes);
 
-        let store = Store::new(BackingStore::Other(Shared::new(Box::new(ReadLog::new(
-            self.store.clone(),
-        )))));
+        let store = Store::new(BackingStore::other(ReadLog::new(self.store.clone())));
 
         let root_bytes = store.get(&[])?.unwrap_or_default();
         let app = ABCIPlugin::<QueryPlugin<T>>::load(store.clone(), &mut root_bytes.as_slice())?;
         app.query(query)?;
         drop(app);
 
-        let mut log = if let BackingStore::Other(b) = store.into_backing_store().into_inner() {
-            let b = b.into_inner() as Box<dyn Any>;
-            b.downcast::<ReadLog<Store>>().unwrap().reads().clone()
-        } else {
-            unreachable!()
-        };
+        let mut log = store
+            .into_backing_store()
+            .into_inner()
+            .into_other_as::<ReadLog<Store>>()?
+            .reads()
+            .clone();
 
         // TODO: move to PartialMapStore associated function
         let mut out = BTreeMap::new();
@@ -1,4 +1,5 @@
 use crate::call::Call;
+use crate::coins::{Accounts, Address, Amount};
 use crate::describe::Describe;
 use crate::encoding::{Decode, Encode};
 
@@ -169,6 +170,46 @@ where
         self.query_with_store(Store::default(), op).await
     }
 
+    /// Runs `op` against the state as of the block at `height`, with every
+    /// read verified against that height's proof.
+    ///
+    /// Nodes only retain a limited number of recent heights (see
+    /// `MerkStore::retain_heights`), so this returns an error for heights which have
+    /// been pruned.
+    pub async fn query_at<U2, F2: FnMut(U) -> Result<U2>>(
+        &self,
+        height: u64,
+        mut op: F2,
+    ) -> Result<U2> {
+        let (res, _) = exec::execute_at(Store::default(), &self.transport, height, |app| {
+            let inner = app
+                .inner
+                .inner
+                .into_inner()
+                .inner
+                .inner
+                .inner
+                .inner
+                .inner
+                .inner;
+            op((self.sub)(inner))
+        })
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns the proven balance of `address` in the [Accounts] returned by
+    /// `accounts`, as of the block at `height`.
+    pub async fn balance_at<S: crate::coins::Symbol>(
+        &self,
+        address: Address,
+        height: u64,
+        accounts: impl Fn(&U) -> &Accounts<S>,
+    ) -> Result<Amount> {
+        self.query_at(height, |app| accounts(&app).balance(address))
+            .await
+    }
+
     async fn query_with_store<U2, F2: FnMut(U) -> Result<U2>>(
         &self,
         store: Store,
@@ -3,9 +3,9 @@ use std::path::Path;
 use secp256k1::SecretKey;
 
 use crate::{
-    coins::Address,
+    coins::{Address, BECH32_PREFIX},
     plugins::{SigType, SignerCall},
-    Result,
+    Error, Result,
 };
 
 pub trait Wallet: Clone + Send + Sync {
@@ -36,11 +36,46 @@ impl Wallet for Unsigned {
     }
 }
 
+/// The chain-specific parameters used to derive keys from a mnemonic and to
+/// encode their addresses.
+///
+/// The default matches this crate's chain: the Cosmos coin type (118) and the
+/// [BECH32_PREFIX] address prefix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DerivationConfig {
+    /// The BIP-44 coin type.
+    pub coin_type: u32,
+    /// The bech32 human-readable prefix for addresses.
+    pub hrp: String,
+}
+
+impl DerivationConfig {
+    pub fn new(coin_type: u32, hrp: impl Into<String>) -> Self {
+        Self {
+            coin_type,
+            hrp: hrp.into(),
+        }
+    }
+
+    /// The BIP-44 derivation path for the key at `index` of the first account,
+    /// e.g. `m/44'/118'/0'/0/0`.
+    pub fn path(&self, index: u32) -> String {
+        format!("m/44'/{}'/0'/0/{}", self.coin_type, index)
+    }
+}
+
+impl Default for DerivationConfig {
+    fn default() -> Self {
+        Self::new(118, BECH32_PREFIX)
+    }
+}
+
 /// A wallet that derives a private key from a seed - intended to be used in
-/// tests.
+/// tests - or from a BIP-39 mnemonic.
 #[derive(Clone, Debug)]
 pub struct DerivedKey {
     privkey: secp256k1::SecretKey,
+    config: DerivationConfig,
 }
 
 impl DerivedKey {
@@ -52,11 +87,37 @@ impl DerivedKey {
 
         let privkey = secp256k1::SecretKey::from_slice(&hash)?;
 
-        Ok(Self { privkey })
+        Ok(Self::from_secret_key(privkey))
     }
 
     pub fn from_secret_key(privkey: SecretKey) -> Self {
-        Self { privkey }
+        Self {
+            privkey,
+            config: Default::default(),
+        }
+    }
+
+    /// Derives the key at `index` from an English BIP-39 mnemonic (with no
+    /// passphrase), using the coin type from `config`. The wallet's bech32
+    /// address is encoded with the prefix from `config`.
+    pub fn from_mnemonic(phrase: &str, index: u32, config: DerivationConfig) -> Result<Self> {
+        use bip32::{DerivationPath, Language, Mnemonic, XPrv};
+
+        let mnemonic = Mnemonic::new(phrase, Language::English)
+            .map_err(|e| Error::Client(format!("Invalid mnemonic: {}", e)))?;
+        let path: DerivationPath = config
+            .path(index)
+            .parse()
+            .map_err(|e| Error::Client(format!("Invalid derivation path: {}", e)))?;
+        let xprv = XPrv::derive_from_path(mnemonic.to_seed(""), &path)
+            .map_err(|e| Error::Client(format!("Key derivation failed: {}", e)))?;
+        let privkey = SecretKey::from_slice(&xprv.to_bytes())?;
+
+        Ok(Self { privkey, config })
+    }
+
+    pub fn config(&self) -> &DerivationConfig {
+        &self.config
     }
 
     pub fn address_for(seed: &[u8]) -> Result<Address> {
@@ -75,6 +136,13 @@ impl DerivedKey {
     pub fn address(&self) -> Address {
         Address::from_pubkey(self.pubkey().serialize())
     }
+
+    /// The wallet's address, bech32-encoded with the configured prefix.
+    pub fn bech32_address(&self) -> Result<String> {
+        self.address()
+            .to_bech32(&self.config.hrp)
+            .map_err(|e| Error::Client(format!("Invalid address prefix: {}", e)))
+    }
 }
 
 impl Wallet for DerivedKey {
@@ -148,3 +216,41 @@ impl Wallet for SimpleWallet {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn derive_from_mnemonic() -> Result<()> {
+        let cosmos = DerivedKey::from_mnemonic(MNEMONIC, 0, DerivationConfig::new(118, "cosmos"))?;
+        assert_eq!(
+            hex::encode(cosmos.privkey().secret_bytes()),
+            "c4a48e2fce1481cd3294b4490f6678090ea98d3d0e5cd984558ab0968741b104"
+        );
+        assert_eq!(
+            cosmos.bech32_address()?,
+            "cosmos19rl4cm2hmr8afy4kldpxz3fka4jguq0auqdal4"
+        );
+
+        let default = DerivedKey::from_mnemonic(MNEMONIC, 0, Default::default())?;
+        assert_eq!(default.address(), cosmos.address());
+        assert_eq!(
+            default.bech32_address()?,
+            "oraibtc19rl4cm2hmr8afy4kldpxz3fka4jguq0a9ets0e"
+        );
+        assert_eq!(default.bech32_address()?, default.address().to_string());
+
+        let other = DerivedKey::from_mnemonic(MNEMONIC, 0, DerivationConfig::new(60, "cosmos"))?;
+        assert_eq!(
+            other.bech32_address()?,
+            "cosmos1gsvdpdxec8hsu57lhxg5xem7refr233zu34d65"
+        );
+
+        assert!(DerivedKey::from_mnemonic("abandon abandon", 0, Default::default()).is_err());
+
+        Ok(())
+    }
+}
@@ -1,17 +1,50 @@
-use crate::coins::{Address, Amount, Coin, Give, Symbol, Take};
+use crate::coins::{distribute, Address, Amount, Coin, Give, PublicKey, Symbol, Take};
 use crate::collections::map::Iter as MapIter;
-use crate::collections::Map;
+use crate::collections::{Map, Set};
 use crate::context::GetContext;
 use crate::orga;
 use crate::plugins::Paid;
-use crate::plugins::Signer;
+use crate::plugins::StateImport;
+use crate::plugins::{Signer, SignerPubKey};
 use crate::{Error, Result};
+use std::ops::Bound;
 
+/// The maximum number of outputs in an [Accounts::transfer_multi] call.
+pub const MAX_TRANSFER_OUTPUTS: usize = 100;
+/// The maximum number of entries returned by [Accounts::balances_paginated].
+pub const MAX_BALANCES_PAGE: u32 = 100;
+
+/// Balances of a single [Symbol], keyed by account id.
+///
+/// An account id is the address derived from the key which first controlled
+/// the account. Control can later be moved to a different key with
+/// [Accounts::rotate_key] without changing the account id, so balances never
+/// have to move and other accounts can keep sending to the same address.
+///
+/// Key rotation is scoped to a single `Accounts<S>` instance: it moves control
+/// of the balances of one symbol and nothing else. The [Signer] context (and
+/// anything keyed on it, such as nonces, staking, and the `Accounts` of other
+/// symbols) still reports the address of the key that signed the tx, so the
+/// old key keeps control everywhere outside this instance. Every address
+/// passed to this module is translated to an account id by
+/// [Accounts::account_of].
+///
+/// The key indirection was added by appending `pub_keys` and `key_accounts`.
+/// Maps don't write any bytes of their own and new fields get new substore
+/// prefixes, so state written before the change loads without a migration,
+/// and every existing account is treated as controlled by the key its id was
+/// derived from. `registered_pub_keys` was appended the same way.
 #[orga]
 pub struct Accounts<S: Symbol> {
     transfers_allowed: bool,
-    transfer_exceptions: Map<Address, ()>,
+    transfer_exceptions: Set<Address>,
     accounts: Map<Address, Coin<S>>,
+    /// The current key of each account whose key has been rotated.
+    pub_keys: Map<Address, PublicKey>,
+    /// The address of each rotated-in key, mapped to the account it controls.
+    key_accounts: Map<Address, Address>,
+    /// Keys registered with [Accounts::store_pubkey], keyed by their address.
+    registered_pub_keys: Map<Address, PublicKey>,
 }
 
 #[orga]
@@ -22,17 +55,165 @@ impl<S: Symbol> Accounts<S> {
 
     #[call]
     pub fn transfer(&mut self, to: Address, amount: Amount) -> Result<()> {
-        let signer = self.signer()?;
-        if !self.transfers_allowed && !self.transfer_exceptions.contains_key(signer)? {
-            return Err(Error::Coins("Transfers are currently disabled".into()));
-        }
+        self.check_transfers_allowed()?;
         let taken_coins = self.take_own_coins(amount)?;
+        let to = self.account_of(to)?;
         let mut receiver = self.accounts.entry(to)?.or_insert_default()?;
         receiver.give(taken_coins)?;
 
         Ok(())
     }
 
+    /// Transfers to each of `outputs` in a single call. The total is taken
+    /// from the signer's account before any recipient is credited, so either
+    /// every transfer is made or the call fails without changing any balance.
+    #[call]
+    pub fn transfer_multi(&mut self, outputs: Vec<(Address, Amount)>) -> Result<()> {
+        if outputs.is_empty() {
+            return Err(Error::Coins(
+                "Transfer must have at least one output".into(),
+            ));
+        }
+        if outputs.len() > MAX_TRANSFER_OUTPUTS {
+            return Err(Error::Coins(format!(
+                "Transfer has {} outputs, the maximum is {}",
+                outputs.len(),
+                MAX_TRANSFER_OUTPUTS
+            )));
+        }
+        self.check_transfers_allowed()?;
+
+        let total = outputs
+            .iter()
+            .try_fold(Amount::from(0), |total, (_, amount)| {
+                total.checked_add(*amount)
+            })?;
+        let mut taken_coins = self.take_own_coins(total)?;
+
+        for (to, amount) in outputs {
+            let coins = taken_coins.take(amount)?;
+            let to = self.account_of(to)?;
+            let mut receiver = self.accounts.entry(to)?.or_insert_default()?;
+            receiver.give(coins)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_transfers_allowed(&mut self) -> Result<()> {
+        let signer = self.signer()?;
+        if !self.transfers_allowed && !self.transfer_exceptions.contains(signer)? {
+            return Err(Error::Coins("Transfers are currently disabled".into()));
+        }
+
+        Ok(())
+    }
+
+    /// Moves control of the signer's account in this instance to
+    /// `new_pubkey`. The account id (and so its balance) stays the same, and
+    /// the previous key can no longer spend it. See [Accounts] for why this
+    /// doesn't affect other modules.
+    ///
+    /// `signature` proves that the owner of `new_pubkey` agreed to control the
+    /// account: it must be a signature by `new_pubkey` over the SHA-256 hash
+    /// of [rotate_key_sign_bytes], which binds the account id and its current
+    /// key.
+    ///
+    /// Rotating back to the key the account id was derived from removes the
+    /// indirection. The new key must not already control or own an account.
+    #[call]
+    pub fn rotate_key(&mut self, new_pubkey: PublicKey, signature: [u8; 64]) -> Result<()> {
+        let account = self.signer()?;
+        let new_key = new_pubkey.address()?;
+        let old_key = match self.pub_keys.get(account)? {
+            Some(pubkey) => pubkey.address()?,
+            None => account,
+        };
+        verify_rotation(account, old_key, &new_pubkey, &signature)?;
+
+        if new_key == old_key {
+            return Err(Error::Coins("Key already controls this account".into()));
+        }
+        if new_key != account
+            && (self.key_accounts.contains_key(new_key)?
+                || self.pub_keys.contains_key(new_key)?
+                || self.accounts.contains_key(new_key)?)
+        {
+            return Err(Error::Coins("Key already controls an account".into()));
+        }
+
+        if old_key != account {
+            self.key_accounts.remove(old_key)?;
+        }
+        if new_key == account {
+            self.pub_keys.remove(account)?;
+        } else {
+            self.key_accounts.insert(new_key, account)?;
+            self.pub_keys.insert(account, new_pubkey)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the id of the account `address` refers to: the account it
+    /// controls if it is the address of a rotated-in key, otherwise `address`
+    /// itself.
+    ///
+    /// Funds sent to a rotated-in key's address are credited to the account it
+    /// controls, since nothing could ever spend them otherwise.
+    #[query]
+    pub fn account_of(&self, address: Address) -> Result<Address> {
+        Ok(self
+            .key_accounts
+            .get(address)?
+            .map_or(address, |account| *account))
+    }
+
+    /// Returns the current key of `account`, if it has been rotated away from
+    /// the key its id was derived from.
+    #[query]
+    pub fn pub_key(&self, account: Address) -> Result<Option<PublicKey>> {
+        Ok(self.pub_keys.get(account)?.map(|pubkey| *pubkey))
+    }
+
+    /// Registers the public key which signed this call, so it can be looked up
+    /// by its address with [Accounts::pubkey].
+    ///
+    /// Registering the same key again is a no-op, but a different key can
+    /// never replace one already stored for an address.
+    #[call]
+    pub fn store_pubkey(&mut self) -> Result<()> {
+        let key = self
+            .context::<Signer>()
+            .ok_or_else(|| Error::Signer("No Signer context available".into()))?
+            .signer
+            .ok_or_else(|| Error::Coins("Unauthorized account action".into()))?;
+        let pubkey = self
+            .context::<SignerPubKey>()
+            .ok_or_else(|| Error::Signer("No public key available for signer".into()))?
+            .0;
+        if pubkey.address().ok() != Some(key) {
+            return Err(Error::Signer(
+                "Public key does not match signer address".into(),
+            ));
+        }
+
+        match self.registered_pub_keys.get(key)? {
+            Some(stored) if *stored == pubkey => Ok(()),
+            Some(_) => Err(Error::Coins(
+                "A different public key is already stored for this address".into(),
+            )),
+            None => self.registered_pub_keys.insert(key, pubkey),
+        }
+    }
+
+    /// Returns the public key registered for `address` with
+    /// [Accounts::store_pubkey], if any.
+    #[query]
+    pub fn pubkey(&self, address: Address) -> Result<Option<PublicKey>> {
+        Ok(self.registered_pub_keys.get(address)?.map(|pubkey| *pubkey))
+    }
+
     #[call]
     pub fn take_as_funding(&mut self, amount: Amount) -> Result<()> {
         let taken_coins = self.take_own_coins(amount)?;
@@ -57,10 +238,19 @@ impl<S: Symbol> Accounts<S> {
     }
 
     fn signer(&mut self) -> Result<Address> {
-        self.context::<Signer>()
+        let key = self
+            .context::<Signer>()
             .ok_or_else(|| Error::Signer("No Signer context available".into()))?
             .signer
-            .ok_or_else(|| Error::Coins("Unauthorized account action".into()))
+            .ok_or_else(|| Error::Coins("Unauthorized account action".into()))?;
+
+        // the key an account id was derived from loses control of it once
+        // rotated away
+        if self.pub_keys.contains_key(key)? {
+            return Err(Error::Coins("Account key has been rotated".into()));
+        }
+
+        self.account_of(key)
     }
 
     #[call]
@@ -97,15 +287,37 @@ impl<S: Symbol> Accounts<S> {
 
     #[query]
     pub fn balance(&self, address: Address) -> Result<Amount> {
-        match self.accounts.get(address)? {
+        match self.accounts.get(self.account_of(address)?)? {
             Some(coin) => Ok(coin.amount),
             None => Ok(0.into()),
         }
     }
 
+    /// Returns up to `limit` (at most [MAX_BALANCES_PAGE]) balances in address
+    /// order, starting after `start_after`, or from the first account if it
+    /// is `None`. Pass the last returned address to get the next page.
+    #[query]
+    pub fn balances_paginated(
+        &self,
+        start_after: Option<Address>,
+        limit: u32,
+    ) -> Result<Vec<(Address, Amount)>> {
+        let start = start_after.map_or(Bound::Unbounded, Bound::Excluded);
+        let limit = limit.min(MAX_BALANCES_PAGE) as usize;
+
+        self.accounts
+            .range((start, Bound::Unbounded))?
+            .take(limit)
+            .map(|entry| {
+                let (address, coin) = entry?;
+                Ok((*address, coin.amount))
+            })
+            .collect()
+    }
+
     #[query]
     pub fn exists(&self, address: Address) -> Result<bool> {
-        Ok(self.accounts.get(address)?.is_some())
+        Ok(self.accounts.get(self.account_of(address)?)?.is_some())
     }
 
     pub fn allow_transfers(&mut self, enabled: bool) {
@@ -113,18 +325,300 @@ impl<S: Symbol> Accounts<S> {
     }
 
     pub fn add_transfer_exception(&mut self, address: Address) -> Result<()> {
-        self.transfer_exceptions.insert(address, ())
+        self.transfer_exceptions.insert(address)?;
+
+        Ok(())
     }
 
     pub fn deposit(&mut self, address: Address, coins: Coin<S>) -> Result<()> {
+        let address = self.account_of(address)?;
         let mut account = self.accounts.entry(address)?.or_insert_default()?;
         account.give(coins)?;
 
         Ok(())
     }
 
+    /// Deposits `coins` into the accounts in `weights`, split in proportion to
+    /// their weights (see [distribute]). Nothing is lost to rounding.
+    pub fn deposit_distributed(
+        &mut self,
+        coins: Coin<S>,
+        weights: &[(Address, u64)],
+    ) -> Result<()> {
+        for (address, amount) in distribute(coins.amount, weights)? {
+            self.deposit(address, Coin::mint(amount))?;
+        }
+        coins.burn();
+
+        Ok(())
+    }
+
+    /// Mints the balances for this symbol from a [StateImport], e.g. when
+    /// forking a chain. Meant to be called from the app's `InitChain`
+    /// alongside the nonce import done by `NoncePlugin`.
+    pub fn import_balances(&mut self, import: &StateImport) -> Result<()> {
+        if let Some(balances) = import.balances.get(S::NAME) {
+            for (address, amount) in balances {
+                self.deposit(*address, Coin::mint(*amount))?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn withdraw(&mut self, address: Address, amount: Amount) -> Result<Coin<S>> {
+        let address = self.account_of(address)?;
         let mut account = self.accounts.entry(address)?.or_insert_default()?;
         account.take(amount)
     }
 }
+
+/// The bytes a key signs (hashed with SHA-256) to agree to control `account`
+/// in place of `current_key`, for [Accounts::rotate_key].
+pub fn rotate_key_sign_bytes(account: Address, current_key: Address) -> Vec<u8> {
+    [
+        b"orga/rotate_key".as_slice(),
+        account.bytes().as_slice(),
+        current_key.bytes().as_slice(),
+    ]
+    .concat()
+}
+
+fn verify_rotation(
+    account: Address,
+    current_key: Address,
+    new_pubkey: &PublicKey,
+    signature: &[u8; 64],
+) -> Result<()> {
+    use secp256k1::hashes::sha256;
+    use secp256k1::{ecdsa::Signature, Message, Secp256k1};
+
+    let pubkey = secp256k1::PublicKey::from_slice(new_pubkey.as_bytes())?;
+    let bytes = rotate_key_sign_bytes(account, current_key);
+    let msg = Message::from_hashed_data::<sha256::Hash>(&bytes);
+    let signature = Signature::from_compact(signature)?;
+    Secp256k1::verification_only()
+        .verify_ecdsa(&msg, &signature, &pubkey)
+        .map_err(|_| Error::Coins("Invalid signature from new key".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use serial_test::serial;
+
+    #[orga]
+    #[derive(Clone, Debug)]
+    struct Simp;
+    impl Symbol for Simp {
+        const INDEX: u8 = 7;
+        const NAME: &'static str = "SIMP";
+    }
+
+    fn pubkey(n: u8) -> PublicKey {
+        let secp = secp256k1::Secp256k1::new();
+        let privkey = secp256k1::SecretKey::from_slice(&[n; 32]).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&secp, &privkey);
+        PublicKey::secp256k1(pubkey.serialize()).unwrap()
+    }
+
+    fn sign_as(key: Address) {
+        Context::add(Signer { signer: Some(key) });
+    }
+
+    /// Signs the rotation of `account` away from `current_key` with the key
+    /// `pubkey(n)`.
+    fn rotation_sig(n: u8, account: Address, current_key: Address) -> [u8; 64] {
+        use secp256k1::hashes::sha256;
+
+        let secp = secp256k1::Secp256k1::new();
+        let privkey = secp256k1::SecretKey::from_slice(&[n; 32]).unwrap();
+        let bytes = rotate_key_sign_bytes(account, current_key);
+        let msg = secp256k1::Message::from_hashed_data::<sha256::Hash>(&bytes);
+        secp.sign_ecdsa(&msg, &privkey).serialize_compact()
+    }
+
+    #[test]
+    #[serial]
+    fn rotate_key() -> Result<()> {
+        let (key_a, key_b, key_c) = (pubkey(1), pubkey(2), pubkey(3));
+        let (a, b, c) = (key_a.address()?, key_b.address()?, key_c.address()?);
+        let bob = Address::from_pubkey([9; 33]);
+
+        let mut accounts: Accounts<Simp> = Default::default();
+        accounts.allow_transfers(true);
+        accounts.deposit(a, 100.into())?;
+        accounts.deposit(bob, 10.into())?;
+
+        sign_as(a);
+        accounts.rotate_key(key_b, rotation_sig(2, a, a))?;
+        assert_eq!(accounts.account_of(b)?, a);
+        assert_eq!(accounts.pub_key(a)?, Some(key_b));
+        accounts
+            .transfer(bob, 1.into())
+            .expect_err("Old key should not control the account");
+
+        sign_as(b);
+        accounts.transfer(bob, 30.into())?;
+        assert_eq!(accounts.balance(a)?, 70.into());
+        assert_eq!(accounts.balance(b)?, 70.into());
+
+        sign_as(bob);
+        accounts.transfer(b, 5.into())?;
+        assert_eq!(accounts.balance(a)?, 75.into());
+        assert_eq!(accounts.withdraw(b, 5.into())?.amount, 5.into());
+        assert_eq!(accounts.balance(a)?, 70.into());
+
+        sign_as(b);
+        accounts.rotate_key(key_c, rotation_sig(3, a, b))?;
+        assert_eq!(accounts.account_of(b)?, b);
+        assert_eq!(accounts.account_of(c)?, a);
+
+        sign_as(c);
+        accounts.rotate_key(key_a, rotation_sig(1, a, c))?;
+        assert_eq!(accounts.account_of(a)?, a);
+        assert_eq!(accounts.account_of(c)?, c);
+        assert_eq!(accounts.pub_key(a)?, None);
+        Context::remove::<Signer>();
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn rotate_key_rejected() -> Result<()> {
+        let (key_a, key_b, key_c) = (pubkey(1), pubkey(2), pubkey(3));
+        let (a, b, c) = (key_a.address()?, key_b.address()?, key_c.address()?);
+        let bob = Address::from_pubkey([9; 33]);
+
+        let mut accounts: Accounts<Simp> = Default::default();
+        accounts.deposit(a, 100.into())?;
+
+        sign_as(a);
+        accounts
+            .rotate_key(key_b, rotation_sig(3, a, a))
+            .expect_err("Should require a signature from the new key");
+        accounts
+            .rotate_key(key_a, rotation_sig(1, a, a))
+            .expect_err("Should not rotate to the current key");
+        accounts.rotate_key(key_b, rotation_sig(2, a, a))?;
+
+        sign_as(bob);
+        accounts
+            .rotate_key(key_b, rotation_sig(2, bob, bob))
+            .expect_err("Should not take over a rotated-in key");
+
+        sign_as(b);
+        accounts.rotate_key(key_c, rotation_sig(3, a, b))?;
+        sign_as(c);
+        accounts
+            .rotate_key(key_b, rotation_sig(2, a, a))
+            .expect_err("Should not accept a signature for a previous key");
+        assert_eq!(accounts.account_of(c)?, a);
+        Context::remove::<Signer>();
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn store_pubkey() -> Result<()> {
+        let (key_a, key_b) = (pubkey(1), pubkey(2));
+        let (a, b) = (key_a.address()?, key_b.address()?);
+
+        let mut accounts: Accounts<Simp> = Default::default();
+        sign_as(a);
+        Context::remove::<SignerPubKey>();
+        accounts
+            .store_pubkey()
+            .expect_err("Should require a public key in context");
+
+        Context::add(SignerPubKey(key_b));
+        accounts
+            .store_pubkey()
+            .expect_err("Should reject a key not matching the signer");
+
+        Context::add(SignerPubKey(key_a));
+        accounts.store_pubkey()?;
+        accounts.store_pubkey()?;
+        assert_eq!(accounts.pubkey(a)?, Some(key_a));
+        assert_eq!(accounts.pubkey(b)?, None);
+        assert_eq!(accounts.account_of(a)?, a);
+
+        accounts.registered_pub_keys.insert(b, key_a)?;
+        sign_as(b);
+        Context::add(SignerPubKey(key_b));
+        accounts
+            .store_pubkey()
+            .expect_err("Should not overwrite a different key");
+        assert_eq!(accounts.pubkey(b)?, Some(key_a));
+
+        Context::remove::<Signer>();
+        Context::remove::<SignerPubKey>();
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn transfer_multi() -> Result<()> {
+        let alice = Address::from_pubkey([1; 33]);
+        let bob = Address::from_pubkey([2; 33]);
+        let carol = Address::from_pubkey([3; 33]);
+
+        let mut accounts: Accounts<Simp> = Default::default();
+        accounts.deposit(alice, 100.into())?;
+
+        sign_as(alice);
+        accounts
+            .transfer_multi(vec![(bob, 10.into())])
+            .expect_err("Transfers should be disabled");
+        accounts.allow_transfers(true);
+
+        accounts.transfer_multi(vec![(bob, 10.into()), (carol, 20.into()), (bob, 5.into())])?;
+        assert_eq!(accounts.balance(alice)?, 55.into());
+        assert_eq!(accounts.balance(bob)?, 15.into());
+        assert_eq!(accounts.balance(carol)?, 20.into());
+
+        accounts
+            .transfer_multi(vec![(bob, 50.into()), (carol, 50.into())])
+            .expect_err("Total exceeds balance");
+        accounts
+            .transfer_multi(vec![])
+            .expect_err("Should require an output");
+        accounts
+            .transfer_multi(vec![(bob, 0.into()); MAX_TRANSFER_OUTPUTS + 1])
+            .expect_err("Should limit the number of outputs");
+        assert_eq!(accounts.balance(alice)?, 55.into());
+        assert_eq!(accounts.balance(bob)?, 15.into());
+        assert_eq!(accounts.balance(carol)?, 20.into());
+        Context::remove::<Signer>();
+
+        Ok(())
+    }
+
+    #[test]
+    fn balances_paginated() -> Result<()> {
+        let mut accounts: Accounts<Simp> = Default::default();
+        let mut expected = vec![];
+        for i in 1..=5u8 {
+            let address = Address::from_pubkey([i; 33]);
+            accounts.deposit(address, (i as u64).into())?;
+            expected.push((address, Amount::from(i as u64)));
+        }
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let first = accounts.balances_paginated(None, 2)?;
+        assert_eq!(first, expected[..2]);
+        let second = accounts.balances_paginated(Some(first[1].0), 2)?;
+        assert_eq!(second, expected[2..4]);
+        let last = accounts.balances_paginated(Some(second[1].0), 2)?;
+        assert_eq!(last, expected[4..]);
+        assert!(accounts.balances_paginated(Some(last[0].0), 2)?.is_empty());
+
+        assert_eq!(accounts.balances_paginated(None, u32::MAX)?, expected);
+
+        Ok(())
+    }
+}
@@ -23,10 +23,44 @@ impl Ord for Amount {
 
 impl Eq for Amount {}
 
+/// Arithmetic helpers for amounts.
+///
+/// Any math whose result is written to state (balances, supply, fees, rewards)
+/// is consensus-critical and must use the checked variants (or the operator
+/// impls, which are also checked and return a `MathResult`), so an overflow
+/// fails the call rather than wrapping. The saturating variants are only
+/// suitable for values which are not persisted, e.g. display or estimates.
 impl Amount {
     pub fn new(value: u64) -> Self {
         Amount { value }
     }
+
+    /// Adds `other`, returning `Error::Overflow` if the sum exceeds `u64::MAX`.
+    pub fn checked_add(self, other: Amount) -> Result<Amount> {
+        self.value
+            .checked_add(other.value)
+            .map(Amount::new)
+            .ok_or(Error::Overflow)
+    }
+
+    /// Subtracts `other`, returning `Error::Overflow` if `other` is greater
+    /// than `self`.
+    pub fn checked_sub(self, other: Amount) -> Result<Amount> {
+        self.value
+            .checked_sub(other.value)
+            .map(Amount::new)
+            .ok_or(Error::Overflow)
+    }
+
+    /// Adds `other`, clamping the result at `u64::MAX`.
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount::new(self.value.saturating_add(other.value))
+    }
+
+    /// Subtracts `other`, clamping the result at zero.
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount::new(self.value.saturating_sub(other.value))
+    }
 }
 
 impl From<u64> for Amount {
@@ -48,3 +82,28 @@ impl TryFrom<Result<Amount>> for Amount {
         value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_math() {
+        let max = Amount::new(u64::MAX);
+        assert_eq!(Amount::new(1).checked_add(2.into()).unwrap(), 3.into());
+        assert!(matches!(max.checked_add(1.into()), Err(Error::Overflow)));
+        assert_eq!(Amount::new(3).checked_sub(2.into()).unwrap(), 1.into());
+        assert!(matches!(
+            Amount::new(1).checked_sub(2.into()),
+            Err(Error::Overflow)
+        ));
+    }
+
+    #[test]
+    fn saturating_math() {
+        let max = Amount::new(u64::MAX);
+        assert_eq!(max.saturating_add(1.into()), max);
+        assert_eq!(Amount::new(1).saturating_sub(2.into()), 0.into());
+        assert_eq!(Amount::new(1).saturating_add(2.into()), 3.into());
+    }
+}
@@ -73,7 +73,7 @@ impl<S: Symbol> Take<S> for Coin<S> {
         if amount > self.amount {
             return Err(Error::Coins("Insufficient funds".into()));
         }
-        self.amount = (self.amount - amount)?;
+        self.amount = self.amount.checked_sub(amount)?;
 
         Ok(Coin::mint(amount))
     }
@@ -81,7 +81,7 @@ impl<S: Symbol> Take<S> for Coin<S> {
 
 impl<S: Symbol> Give<Self> for Coin<S> {
     fn give(&mut self, value: Coin<S>) -> Result<()> {
-        self.amount = (self.amount + value.amount)?;
+        self.amount = self.amount.checked_add(value.amount)?;
 
         Ok(())
     }
@@ -98,3 +98,31 @@ impl<S: Symbol> From<u64> for Coin<S> {
         Self::mint(amount)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[orga]
+    #[derive(Clone, Debug)]
+    struct Simp;
+    impl Symbol for Simp {
+        const INDEX: u8 = 0;
+        const NAME: &'static str = "SIMP";
+    }
+
+    #[test]
+    fn give_overflow() {
+        let mut coin = Coin::<Simp>::mint(u64::MAX);
+        assert!(matches!(coin.give(Coin::mint(1)), Err(Error::Overflow)));
+        assert_eq!(coin.amount, u64::MAX.into());
+    }
+
+    #[test]
+    fn take_underflow() {
+        let mut coin = Coin::<Simp>::mint(5);
+        assert!(coin.take(6).is_err());
+        assert_eq!(coin.take(5).unwrap().amount, 5.into());
+        assert_eq!(coin.amount, 0.into());
+    }
+}
@@ -0,0 +1,144 @@
+use super::{Address, Amount};
+use crate::{Error, Result};
+
+/// Splits `total` between addresses in proportion to their weights, using the
+/// largest-remainder method so the amounts always sum to exactly `total`.
+///
+/// Each address first gets the floor of its proportional share. The units
+/// lost to rounding are then given out one each to the addresses with the
+/// largest fractional remainders, with ties going to the lowest address.
+///
+/// The result is sorted by address, and weights listed more than once for the
+/// same address are combined, so the output depends only on the set of
+/// weights and not on their order. Addresses with zero weight are included
+/// with a zero amount.
+///
+/// Returns an error if `total` is non-zero and all weights are zero.
+pub fn distribute(total: Amount, weights: &[(Address, u64)]) -> Result<Vec<(Address, Amount)>> {
+    let mut weights: Vec<(Address, u128)> = weights
+        .iter()
+        .map(|(address, weight)| (*address, *weight as u128))
+        .collect();
+    weights.sort_by_key(|(address, _)| *address);
+    weights.dedup_by(|(address, weight), (prev_address, prev_weight)| {
+        if address == prev_address {
+            *prev_weight += *weight;
+            true
+        } else {
+            false
+        }
+    });
+
+    let total: u64 = total.into();
+    let total_weight: u128 = weights.iter().map(|(_, weight)| weight).sum();
+    if total_weight == 0 {
+        if total == 0 {
+            return Ok(weights.into_iter().map(|(a, _)| (a, 0.into())).collect());
+        }
+        return Err(Error::Coins(
+            "Cannot distribute to a total weight of zero".into(),
+        ));
+    }
+
+    let mut shares = Vec::with_capacity(weights.len());
+    let mut remainders = Vec::with_capacity(weights.len());
+    let mut distributed = 0;
+    for (i, (address, weight)) in weights.iter().enumerate() {
+        let product = total as u128 * weight;
+        let share = (product / total_weight) as u64;
+        distributed += share;
+        shares.push((*address, share));
+        remainders.push((product % total_weight, i));
+    }
+
+    // Weights are sorted by address, so a stable sort by descending remainder
+    // breaks ties by address
+    remainders.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let leftover = (total - distributed) as usize;
+    for (_, i) in remainders.into_iter().take(leftover) {
+        shares[i].1 += 1;
+    }
+
+    Ok(shares
+        .into_iter()
+        .map(|(address, share)| (address, share.into()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(n: u8) -> Address {
+        [n; Address::LENGTH].into()
+    }
+
+    fn sum(shares: &[(Address, Amount)]) -> u64 {
+        shares.iter().map(|(_, amount)| u64::from(*amount)).sum()
+    }
+
+    #[test]
+    fn largest_remainder() -> Result<()> {
+        let shares = distribute(100.into(), &[(addr(1), 1), (addr(2), 1), (addr(3), 1)])?;
+        assert_eq!(
+            shares,
+            vec![
+                (addr(1), 34.into()),
+                (addr(2), 33.into()),
+                (addr(3), 33.into()),
+            ]
+        );
+
+        // 10 * 2/7 = 2.857, 10 * 5/7 = 7.143
+        let shares = distribute(10.into(), &[(addr(1), 5), (addr(2), 2)])?;
+        assert_eq!(shares, vec![(addr(1), 7.into()), (addr(2), 3.into())]);
+
+        let shares = distribute(10.into(), &[(addr(1), 0), (addr(2), 3)])?;
+        assert_eq!(shares, vec![(addr(1), 0.into()), (addr(2), 10.into())]);
+
+        assert!(distribute(10.into(), &[(addr(1), 0)]).is_err());
+        assert!(distribute(10.into(), &[]).is_err());
+        assert_eq!(distribute(0.into(), &[])?, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sums_to_total() -> Result<()> {
+        let weights: Vec<_> = (0..50)
+            .map(|n| (addr(n), (n as u64 * 7919) % 101 + 1))
+            .collect();
+        for total in [0, 1, 49, 50, 51, 9_999, 1_000_003, u64::MAX] {
+            let shares = distribute(total.into(), &weights)?;
+            assert_eq!(shares.len(), weights.len());
+            assert_eq!(sum(&shares), total);
+        }
+
+        let shares = distribute(u64::MAX.into(), &[(addr(1), u64::MAX), (addr(2), 1)])?;
+        assert_eq!(sum(&shares), u64::MAX);
+
+        Ok(())
+    }
+
+    #[test]
+    fn independent_of_order() -> Result<()> {
+        let weights: Vec<_> = (0..20).map(|n| (addr(n), 3 + n as u64 % 4)).collect();
+        let expected = distribute(1_001.into(), &weights)?;
+
+        let mut reversed = weights.clone();
+        reversed.reverse();
+        assert_eq!(distribute(1_001.into(), &reversed)?, expected);
+
+        let mut rotated = weights.clone();
+        rotated.rotate_left(7);
+        assert_eq!(distribute(1_001.into(), &rotated)?, expected);
+
+        // Duplicate entries are combined
+        let mut split = weights;
+        split[0].1 -= 1;
+        split.push((addr(0), 1));
+        assert_eq!(distribute(1_001.into(), &split)?, expected);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use crate::coins::{Address, Amount, Symbol};
+use crate::collections::Map;
+use crate::context::GetContext;
+use crate::orga;
+use crate::plugins::{Paid, Signer};
+use crate::{Error, Result};
+
+/// Identifier for a denomination tracked by [DynAccounts].
+///
+/// Ids `0..FIRST_DYN_DENOM` are reserved for statically-typed [Symbol]s, where
+/// the id is the symbol's `INDEX`, so balances for a compile-time symbol and
+/// its runtime counterpart never collide. Ids at or above `FIRST_DYN_DENOM`
+/// are allocated sequentially by [DynAccounts::register_denom] and are never
+/// reused.
+pub type DenomId = u32;
+
+/// The first denom id handed out by [DynAccounts::register_denom].
+pub const FIRST_DYN_DENOM: DenomId = 1 << 8;
+
+/// Returns the reserved denom id for the statically-typed symbol `S`.
+pub fn denom_id<S: Symbol>() -> DenomId {
+    S::INDEX as DenomId
+}
+
+/// A multi-denomination account ledger where denoms are identified by a
+/// runtime [DenomId] rather than a [Symbol] type parameter.
+///
+/// This is useful for tokens which are created after genesis (e.g. bridged or
+/// user-issued assets), where a new Rust type can't be introduced without an
+/// upgrade.
+#[orga]
+pub struct DynAccounts {
+    next_denom_id: DenomId,
+    balances: Map<(DenomId, Address), Amount>,
+}
+
+#[orga]
+impl DynAccounts {
+    /// Allocates a new denom id. Ids are assigned sequentially starting at
+    /// `FIRST_DYN_DENOM`.
+    pub fn register_denom(&mut self) -> Result<DenomId> {
+        let id = self.next_denom_id.max(FIRST_DYN_DENOM);
+        self.next_denom_id = id
+            .checked_add(1)
+            .ok_or_else(|| Error::Coins("Denom ids exhausted".into()))?;
+
+        Ok(id)
+    }
+
+    /// Returns whether `denom` refers to a reserved static symbol id or to a
+    /// denom which has already been allocated.
+    #[query]
+    pub fn denom_exists(&self, denom: DenomId) -> Result<bool> {
+        Ok(denom < FIRST_DYN_DENOM || denom < self.next_denom_id)
+    }
+
+    #[call]
+    pub fn transfer(&mut self, denom: DenomId, to: Address, amount: Amount) -> Result<()> {
+        let signer = self.signer()?;
+        self.withdraw(denom, signer, amount)?;
+        self.deposit(denom, to, amount)
+    }
+
+    #[query]
+    pub fn balance(&self, denom: DenomId, address: Address) -> Result<Amount> {
+        Ok(self
+            .balances
+            .get((denom, address))?
+            .map(|amount| *amount)
+            .unwrap_or_default())
+    }
+
+    pub fn deposit(&mut self, denom: DenomId, address: Address, amount: Amount) -> Result<()> {
+        self.check_denom(denom)?;
+
+        let mut balance = self.balances.entry((denom, address))?.or_insert_default()?;
+        *balance = balance.checked_add(amount)?;
+
+        Ok(())
+    }
+
+    pub fn withdraw(&mut self, denom: DenomId, address: Address, amount: Amount) -> Result<()> {
+        self.check_denom(denom)?;
+
+        let mut balance = self
+            .balances
+            .get_mut((denom, address))?
+            .ok_or_else(|| Error::Coins("Insufficient funds".into()))?;
+        if *balance < amount {
+            return Err(Error::Coins("Insufficient funds".into()));
+        }
+        *balance = balance.checked_sub(amount)?;
+
+        Ok(())
+    }
+
+    /// Moves `amount` of `denom` from the signer's balance into the [Paid]
+    /// context, to fund a paid call.
+    #[call]
+    pub fn take_as_funding(&mut self, denom: DenomId, amount: Amount) -> Result<()> {
+        let funding_denom = funding_denom(denom)?;
+        let signer = self.signer()?;
+        self.withdraw(denom, signer, amount)?;
+
+        self.paid()?.give_denom(amount, funding_denom)
+    }
+
+    /// Moves `amount` of `denom` from the [Paid] context into the signer's
+    /// balance.
+    #[call]
+    pub fn give_from_funding(&mut self, denom: DenomId, amount: Amount) -> Result<()> {
+        let funding_denom = funding_denom(denom)?;
+        let signer = self.signer()?;
+        self.paid()?.take_denom(amount, funding_denom)?;
+
+        self.deposit(denom, signer, amount)
+    }
+
+    /// Moves all remaining funds of every denom from the [Paid] context into
+    /// the signer's balances.
+    #[call]
+    pub fn give_from_funding_all(&mut self) -> Result<()> {
+        let signer = self.signer()?;
+        let paid = self.paid()?;
+        let balances = paid.balances();
+        for (denom, amount) in balances.iter() {
+            paid.take_denom(*amount, *denom)?;
+        }
+
+        for (denom, amount) in balances {
+            self.deposit(denom as DenomId, signer, amount)?;
+        }
+
+        Ok(())
+    }
+
+    fn paid(&mut self) -> Result<&mut Paid> {
+        self.context::<Paid>()
+            .ok_or_else(|| Error::Coins("No Paid context found".into()))
+    }
+
+    fn check_denom(&self, denom: DenomId) -> Result<()> {
+        if !self.denom_exists(denom)? {
+            return Err(Error::Coins(format!("Unknown denom {}", denom)));
+        }
+
+        Ok(())
+    }
+
+    fn signer(&mut self) -> Result<Address> {
+        self.context::<Signer>()
+            .ok_or_else(|| Error::Signer("No Signer context available".into()))?
+            .signer
+            .ok_or_else(|| Error::Coins("Unauthorized account action".into()))
+    }
+}
+
+/// The [Paid] context tracks funds by `u8` denom, so only denoms with ids
+/// below 256 can be used to fund calls.
+fn funding_denom(denom: DenomId) -> Result<u8> {
+    denom
+        .try_into()
+        .map_err(|_| Error::Coins(format!("Denom {} cannot be used for funding", denom)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use serial_test::serial;
+
+    #[orga]
+    #[derive(Clone, Debug)]
+    struct Simp;
+    impl Symbol for Simp {
+        const INDEX: u8 = 7;
+        const NAME: &'static str = "SIMP";
+    }
+
+    #[test]
+    #[serial]
+    fn dyn_transfer() -> Result<()> {
+        let alice = Address::from_pubkey([1; 33]);
+        let bob = Address::from_pubkey([2; 33]);
+        let mut accounts = DynAccounts::default();
+
+        let foo = accounts.register_denom()?;
+        let bar = accounts.register_denom()?;
+        assert_eq!(foo, FIRST_DYN_DENOM);
+        assert_eq!(bar, FIRST_DYN_DENOM + 1);
+        assert!(!accounts.denom_exists(bar + 1)?);
+
+        accounts.deposit(foo, alice, 100.into())?;
+        accounts.deposit(denom_id::<Simp>(), alice, 5.into())?;
+        accounts
+            .deposit(bar + 1, alice, 1.into())
+            .expect_err("Should not deposit unknown denom");
+
+        Context::add(Signer {
+            signer: Some(alice),
+        });
+        accounts.transfer(foo, bob, 30.into())?;
+        accounts
+            .transfer(bar, bob, 1.into())
+            .expect_err("Should not transfer denom with no balance");
+        Context::remove::<Signer>();
+
+        assert_eq!(accounts.balance(foo, alice)?, 70.into());
+        assert_eq!(accounts.balance(foo, bob)?, 30.into());
+        assert_eq!(accounts.balance(bar, bob)?, 0.into());
+        assert_eq!(accounts.balance(denom_id::<Simp>(), alice)?, 5.into());
+
+        Ok(())
+    }
+
+    #[orga]
+    #[derive(Clone, Debug)]
+    struct Other;
+    impl Symbol for Other {
+        const INDEX: u8 = 8;
+        const NAME: &'static str = "OTHER";
+    }
+
+    #[test]
+    #[serial]
+    fn multi_denom_funding() -> Result<()> {
+        let alice = Address::from_pubkey([1; 33]);
+        let bob = Address::from_pubkey([2; 33]);
+        let (simp, other) = (denom_id::<Simp>(), denom_id::<Other>());
+        let mut accounts = DynAccounts::default();
+        accounts.deposit(simp, alice, 100.into())?;
+        accounts.deposit(other, alice, 50.into())?;
+
+        Context::add(Paid::default());
+        Context::add(Signer {
+            signer: Some(alice),
+        });
+        accounts.take_as_funding(simp, 40.into())?;
+        accounts.take_as_funding(other, 20.into())?;
+        {
+            let paid = Context::resolve::<Paid>().unwrap();
+            assert_eq!(paid.balance::<Simp>()?, 40.into());
+            assert_eq!(paid.balance::<Other>()?, 20.into());
+        }
+
+        Context::add(Signer { signer: Some(bob) });
+        accounts.give_from_funding(simp, 15.into())?;
+        accounts.give_from_funding(other, 5.into())?;
+        accounts
+            .give_from_funding(other, 16.into())
+            .expect_err("Should not withdraw more than was funded");
+        assert_eq!(accounts.balance(simp, bob)?, 15.into());
+        assert_eq!(accounts.balance(other, bob)?, 5.into());
+
+        accounts.give_from_funding_all()?;
+        assert_eq!(accounts.balance(simp, bob)?, 40.into());
+        assert_eq!(accounts.balance(other, bob)?, 20.into());
+        assert!(Context::resolve::<Paid>().unwrap().balances().is_empty());
+        Context::resolve::<Paid>().unwrap().check_spent()?;
+
+        let dyn_denom = accounts.register_denom()?;
+        accounts
+            .take_as_funding(dyn_denom, 0.into())
+            .expect_err("Dynamic denoms cannot fund calls");
+
+        Context::remove::<Signer>();
+        Context::remove::<Paid>();
+
+        Ok(())
+    }
+}
@@ -34,6 +34,12 @@ pub use staking::*;
 pub mod accounts;
 pub use accounts::*;
 
+pub mod dyn_accounts;
+pub use dyn_accounts::*;
+
+pub mod pubkey;
+pub use pubkey::PublicKey;
+
 pub mod adjust;
 pub use adjust::*;
 
@@ -46,6 +52,9 @@ pub use decimal::Decimal;
 pub mod math;
 pub use math::*;
 
+pub mod distribute;
+pub use distribute::*;
+
 pub mod faucet;
 pub use faucet::*;
 
@@ -106,6 +115,12 @@ impl Address {
     pub fn is_null(&self) -> bool {
         *self == Self::NULL
     }
+
+    /// Encodes the address as bech32 with the given human-readable prefix,
+    /// for use with chains other than the one described by [BECH32_PREFIX].
+    pub fn to_bech32(&self, hrp: &str) -> std::result::Result<String, bech32::Error> {
+        bech32::encode(hrp, self.bytes.to_base32(), Variant::Bech32)
+    }
 }
 
 impl Display for Address {
@@ -0,0 +1,198 @@
+use super::Address;
+use crate::describe::{Builder, Describe, Descriptor};
+use crate::encoding::{Decode, Encode, Terminated};
+use crate::migrate::Migrate;
+use crate::state::State;
+use crate::store::Store;
+use crate::{Error, Result};
+use serde::Serialize;
+
+const SECP256K1_TAG: u8 = 0;
+const ED25519_TAG: u8 = 1;
+
+/// An account public key, tagged with its key type.
+///
+/// Encoded as a one-byte type tag followed by the key bytes: `0` for a
+/// secp256k1 key in 33-byte SEC1 compressed form, or `1` for a 32-byte ed25519
+/// key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PublicKey {
+    Secp256k1([u8; 33]),
+    Ed25519([u8; 32]),
+}
+
+impl PublicKey {
+    /// Creates a secp256k1 key, checking that `bytes` is a valid compressed
+    /// curve point.
+    pub fn secp256k1(bytes: [u8; 33]) -> Result<Self> {
+        secp256k1::PublicKey::from_slice(&bytes)?;
+        Ok(PublicKey::Secp256k1(bytes))
+    }
+
+    /// Creates an ed25519 key, checking that `bytes` is a valid curve point.
+    pub fn ed25519(bytes: [u8; 32]) -> Result<Self> {
+        ed25519_dalek::PublicKey::from_bytes(&bytes)?;
+        Ok(PublicKey::Ed25519(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            PublicKey::Secp256k1(bytes) => bytes,
+            PublicKey::Ed25519(bytes) => bytes,
+        }
+    }
+
+    /// Returns the address derived from this key, matching
+    /// [Address::from_pubkey]. Only secp256k1 keys can control accounts.
+    pub fn address(&self) -> Result<Address> {
+        match self {
+            PublicKey::Secp256k1(bytes) => Ok(Address::from_pubkey(*bytes)),
+            PublicKey::Ed25519(_) => Err(Error::Signer(
+                "Addresses can only be derived from secp256k1 keys".into(),
+            )),
+        }
+    }
+}
+
+impl Encode for PublicKey {
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        let tag = match self {
+            PublicKey::Secp256k1(_) => SECP256K1_TAG,
+            PublicKey::Ed25519(_) => ED25519_TAG,
+        };
+        dest.write_all(&[tag])?;
+        dest.write_all(self.as_bytes())?;
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> ed::Result<usize> {
+        Ok(1 + self.as_bytes().len())
+    }
+}
+
+impl Decode for PublicKey {
+    fn decode<R: std::io::Read>(mut input: R) -> ed::Result<Self> {
+        let mut tag = [0];
+        input.read_exact(&mut tag)?;
+
+        match tag[0] {
+            SECP256K1_TAG => {
+                let mut bytes = [0; 33];
+                input.read_exact(&mut bytes)?;
+                PublicKey::secp256k1(bytes).map_err(|_| ed::Error::UnexpectedByte(bytes[0]))
+            }
+            ED25519_TAG => {
+                let mut bytes = [0; 32];
+                input.read_exact(&mut bytes)?;
+                PublicKey::ed25519(bytes).map_err(|_| ed::Error::UnexpectedByte(bytes[0]))
+            }
+            tag => Err(ed::Error::UnexpectedByte(tag)),
+        }
+    }
+}
+
+impl Terminated for PublicKey {}
+
+/// Serializes as `{"type": <"secp256k1" | "ed25519">, "key": <hex bytes>}`.
+impl Serialize for PublicKey {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let ty = match self {
+            PublicKey::Secp256k1(_) => "secp256k1",
+            PublicKey::Ed25519(_) => "ed25519",
+        };
+        let mut state = serializer.serialize_struct("PublicKey", 2)?;
+        state.serialize_field("type", ty)?;
+        state.serialize_field("key", &hex::encode(self.as_bytes()))?;
+        state.end()
+    }
+}
+
+impl State for PublicKey {
+    fn attach(&mut self, _store: Store) -> Result<()> {
+        Ok(())
+    }
+
+    fn flush<W: std::io::Write>(self, out: &mut W) -> Result<()> {
+        self.encode_into(out)?;
+        Ok(())
+    }
+
+    fn load(_store: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self::decode(bytes)?)
+    }
+}
+
+impl Migrate for PublicKey {
+    fn migrate(_src: Store, _dest: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self::decode(bytes)?)
+    }
+}
+
+impl Describe for PublicKey {
+    fn describe() -> Descriptor {
+        Builder::new::<Self>().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::Map;
+
+    fn secp_key() -> PublicKey {
+        let secp = secp256k1::Secp256k1::new();
+        let privkey = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&secp, &privkey);
+        PublicKey::secp256k1(pubkey.serialize()).unwrap()
+    }
+
+    fn ed_key() -> PublicKey {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[2; 32]).unwrap();
+        let pubkey: ed25519_dalek::PublicKey = (&secret).into();
+        PublicKey::ed25519(pubkey.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn encoding_round_trip() {
+        for key in [secp_key(), ed_key()] {
+            let bytes = key.encode().unwrap();
+            assert_eq!(bytes.len(), key.encoding_length().unwrap());
+            assert_eq!(PublicKey::decode(bytes.as_slice()).unwrap(), key);
+
+            let maybe_key = Some(key).encode().unwrap();
+            assert_eq!(
+                Option::<PublicKey>::decode(maybe_key.as_slice()).unwrap(),
+                Some(key)
+            );
+        }
+
+        assert!(PublicKey::decode([2, 0].as_slice()).is_err());
+        assert!(PublicKey::decode([0; 34].as_slice()).is_err());
+    }
+
+    #[test]
+    fn store_round_trip() -> Result<()> {
+        let store = Store::with_map_store();
+        let mut map: Map<Address, PublicKey> = Map::default();
+        map.attach(store.clone())?;
+
+        let secp = secp_key();
+        let ed = ed_key();
+        map.insert(secp.address()?, secp)?;
+        map.insert(Address::NULL, ed)?;
+
+        let mut bytes = vec![];
+        map.flush(&mut bytes)?;
+
+        let map: Map<Address, PublicKey> = Map::load(store, &mut bytes.as_slice())?;
+        assert_eq!(*map.get(secp.address()?)?.unwrap(), secp);
+        assert_eq!(*map.get(Address::NULL)?.unwrap(), ed);
+
+        Ok(())
+    }
+}
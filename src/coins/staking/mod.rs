@@ -6,14 +6,15 @@ use crate::context::GetContext;
 use crate::encoding::{Decode, Encode};
 use crate::migrate::{Migrate, MigrateFrom};
 use crate::orga;
-use crate::plugins::{BeginBlockCtx, EndBlockCtx, Validators};
+use crate::plugins::{
+    BeginBlockCtx, EndBlockCtx, Misbehavior, MisbehaviorKind, Slashable, Validators,
+};
 use crate::plugins::{Paid, Signer, Time};
 use crate::state::State;
 use crate::{Error, Result};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::convert::TryInto;
-use tendermint_proto::v0_34::abci::EvidenceType;
 
 mod delegator;
 pub use delegator::*;
@@ -161,40 +162,29 @@ impl<S: Symbol> BeginBlock for Staking<S> {
             }
         }
 
-        for evidence in &ctx.byzantine_validators {
-            match &evidence.validator {
-                Some(validator) => {
-                    let hash: [u8; 20] = validator.address.to_vec().try_into().map_err(|_| {
-                        Error::Coins("Invalid pubkey length from Tendermint".into())
-                    })?;
-                    match self.address_for_tm_hash.get(hash)? {
-                        Some(address) => {
-                            let address = *address;
-                            match evidence.r#type() {
-                                EvidenceType::DuplicateVote => {
-                                    self.punish_double_sign(address.into())?;
-                                }
-                                EvidenceType::LightClientAttack => {
-                                    self.punish_light_client_attack(address.into())?;
-                                }
-                                _ => {}
-                            };
-                        }
-                        None => {
-                            return Err(Error::Coins(
-                                "Invalid pubkey length from Tendermint".into(),
-                            ));
-                        }
-                    }
-                }
-                None => {}
-            }
-        }
+        ctx.punish_misbehavior(self)?;
 
         Ok(())
     }
 }
 
+impl<S: Symbol> Slashable for Staking<S> {
+    fn punish(&mut self, misbehavior: &Misbehavior) -> Result<()> {
+        let address = match self
+            .address_for_tm_hash
+            .get(misbehavior.validator_address)?
+        {
+            Some(address) => *address,
+            None => return Err(Error::Coins("Invalid pubkey length from Tendermint".into())),
+        };
+
+        match misbehavior.kind {
+            MisbehaviorKind::DuplicateVote => self.punish_double_sign(address.into()),
+            MisbehaviorKind::LightClientAttack => self.punish_light_client_attack(address.into()),
+        }
+    }
+}
+
 #[orga]
 impl<S: Symbol> Staking<S> {
     pub fn delegate(
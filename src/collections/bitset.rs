@@ -0,0 +1,209 @@
+use super::map::Map;
+use crate::orga;
+use crate::state::State;
+use crate::store::Store;
+use crate::Result;
+
+const WORD_BITS: u64 = u64::BITS as u64;
+
+/// A set of bit flags keyed by index, e.g. the blocks a validator missed in
+/// its signing window.
+///
+/// Flags are packed into 64-bit words stored in a [Map] keyed by word index,
+/// so each store entry holds 64 flags, and words with no flags set are not
+/// stored at all. The number of set flags is kept alongside the words.
+#[orga]
+pub struct Bitset {
+    count: u64,
+    words: Map<u64, u64>,
+}
+
+impl Bitset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_store(store: Store) -> Result<Self> {
+        let mut bitset = Self::default();
+        bitset.attach(store)?;
+
+        Ok(bitset)
+    }
+}
+
+impl std::fmt::Debug for Bitset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Bitset")
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+#[orga]
+impl Bitset {
+    /// The number of flags which are set.
+    #[query]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[query]
+    pub fn get(&self, index: u64) -> Result<bool> {
+        let word = self.word(index / WORD_BITS)?;
+        Ok(word & bit(index) != 0)
+    }
+
+    /// Counts the flags set in `start..end`, reading one store entry per 64
+    /// indexes, e.g. the blocks a validator missed in the last `n` heights.
+    #[query]
+    pub fn count_range(&self, start: u64, end: u64) -> Result<u64> {
+        if start >= end {
+            return Ok(0);
+        }
+
+        let first = start / WORD_BITS;
+        let last = (end - 1) / WORD_BITS;
+        let mut count = 0;
+        for entry in self.words.range(first..=last)? {
+            let (index, word) = entry?;
+            let mut word = *word;
+            if *index == first {
+                word &= u64::MAX << (start % WORD_BITS);
+            }
+            if *index == last {
+                word &= u64::MAX >> (WORD_BITS - 1 - (end - 1) % WORD_BITS);
+            }
+            count += word.count_ones() as u64;
+        }
+
+        Ok(count)
+    }
+}
+
+impl Bitset {
+    /// Sets or clears the flag at `index`, returning its previous value.
+    pub fn set(&mut self, index: u64, value: bool) -> Result<bool> {
+        let word_index = index / WORD_BITS;
+        let word = self.word(word_index)?;
+        let prev = word & bit(index) != 0;
+        if prev == value {
+            return Ok(prev);
+        }
+
+        let word = word ^ bit(index);
+        if value {
+            self.count += 1;
+        } else {
+            self.count -= 1;
+        }
+
+        if word == 0 {
+            self.words.remove(word_index)?;
+        } else {
+            self.words.insert(word_index, word)?;
+        }
+
+        Ok(prev)
+    }
+
+    fn word(&self, word_index: u64) -> Result<u64> {
+        Ok(self.words.get(word_index)?.map_or(0, |word| *word))
+    }
+}
+
+fn bit(index: u64) -> u64 {
+    1 << (index % WORD_BITS)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Bitset;
+    use crate::state::State;
+    use crate::store::{MapStore, Shared, Store};
+    use crate::Result;
+
+    fn setup() -> (Store, Bitset) {
+        let store = mapstore();
+        let bits = Bitset::with_store(store.clone()).unwrap();
+        (store, bits)
+    }
+
+    fn mapstore() -> Store {
+        Store::new(Shared::new(MapStore::new()).into())
+    }
+
+    fn set_all(bits: &mut Bitset, indexes: &[u64]) -> Result<()> {
+        for index in indexes {
+            bits.set(*index, true)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn set_and_get() -> Result<()> {
+        let (_, mut bits) = setup();
+        assert!(!bits.set(63, true)?);
+        assert!(!bits.set(64, true)?);
+
+        assert!(bits.get(63)?);
+        assert!(bits.get(64)?);
+        assert!(!bits.get(62)?);
+        assert_eq!(bits.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_existing() -> Result<()> {
+        let (_, mut bits) = setup();
+        assert!(!bits.set(5, true)?);
+        assert!(bits.set(5, true)?);
+        assert_eq!(bits.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn clear() -> Result<()> {
+        let (_, mut bits) = setup();
+        set_all(&mut bits, &[5, 130])?;
+
+        assert!(bits.set(130, false)?);
+        assert!(!bits.set(131, false)?);
+        assert!(!bits.get(130)?);
+        assert_eq!(bits.count(), 1);
+        assert_eq!(bits.count_range(128, 192)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn count_range() -> Result<()> {
+        let (_, mut bits) = setup();
+        set_all(&mut bits, &[0, 5, 63, 64, 130, 1000])?;
+
+        assert_eq!(bits.count_range(0, 1001)?, 6);
+        assert_eq!(bits.count_range(5, 64)?, 2);
+        assert_eq!(bits.count_range(6, 131)?, 3);
+        assert_eq!(bits.count_range(64, 64)?, 0);
+        assert_eq!(bits.count_range(10, 5)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_load() -> Result<()> {
+        let (store, mut bits) = setup();
+        set_all(&mut bits, &[5, 1000])?;
+
+        let mut bytes = vec![];
+        bits.flush(&mut bytes)?;
+        let bits = Bitset::load(store, &mut bytes.as_slice())?;
+
+        assert_eq!(bits.count(), 2);
+        assert!(bits.get(1000)?);
+        assert_eq!(bits.count_range(0, 1001)?, 2);
+
+        Ok(())
+    }
+}
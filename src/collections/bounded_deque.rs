@@ -0,0 +1,292 @@
+use serde::Serialize;
+use std::iter::Rev;
+
+use super::deque::{Deque, Iter};
+use super::map::{ChildMut, ReadOnly, Ref};
+use crate::call::FieldCall;
+use crate::describe::Describe;
+use crate::migrate::Migrate;
+use crate::orga;
+use crate::query::FieldQuery;
+use crate::state::State;
+use crate::store::Store;
+use crate::{Error, Result};
+
+/// A [Deque] which holds at most `MAX_LEN` values, e.g. a window of recent
+/// block hashes.
+///
+/// When full, [push_back](Self::push_back) evicts the value at the front to
+/// make room, while [try_push_back](Self::try_push_back) rejects the push.
+/// The store layout is the same as `Deque<T>`.
+#[derive(FieldQuery, FieldCall)]
+pub struct BoundedDeque<T, const MAX_LEN: u64> {
+    deque: Deque<T>,
+}
+
+impl<T, const MAX_LEN: u64> BoundedDeque<T, MAX_LEN> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T: State, const MAX_LEN: u64> BoundedDeque<T, MAX_LEN> {
+    pub fn with_store(store: Store) -> Result<Self> {
+        Ok(Self {
+            deque: Deque::with_store(store)?,
+        })
+    }
+}
+
+impl<T, const MAX_LEN: u64> Default for BoundedDeque<T, MAX_LEN> {
+    fn default() -> Self {
+        BoundedDeque {
+            deque: Deque::default(),
+        }
+    }
+}
+
+impl<T, const MAX_LEN: u64> std::fmt::Debug for BoundedDeque<T, MAX_LEN> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BoundedDeque")
+            .field("deque", &self.deque)
+            .finish()
+    }
+}
+
+impl<T: Serialize + State, const MAX_LEN: u64> Serialize for BoundedDeque<T, MAX_LEN> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        self.deque.serialize(serializer)
+    }
+}
+
+impl<T: State, const MAX_LEN: u64> State for BoundedDeque<T, MAX_LEN> {
+    fn attach(&mut self, store: Store) -> Result<()> {
+        self.deque.attach(store)
+    }
+
+    fn flush<W: std::io::Write>(self, out: &mut W) -> Result<()> {
+        self.deque.flush(out)
+    }
+
+    fn load(store: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            deque: Deque::load(store, bytes)?,
+        })
+    }
+}
+
+impl<T: State + Describe, const MAX_LEN: u64> Describe for BoundedDeque<T, MAX_LEN> {
+    fn describe() -> crate::describe::Descriptor {
+        use crate::describe::Builder;
+        Builder::new::<Self>()
+            .dynamic_child::<u64, T>(|mut query_bytes| {
+                query_bytes.extend_from_slice(&[129]);
+                query_bytes
+            })
+            .build()
+    }
+}
+
+impl<T: Migrate, const MAX_LEN: u64> Migrate for BoundedDeque<T, MAX_LEN> {
+    fn migrate(src: Store, dest: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            deque: Deque::migrate(src, dest, bytes)?,
+        })
+    }
+}
+
+#[orga]
+impl<T: State, const MAX_LEN: u64> BoundedDeque<T, MAX_LEN> {
+    #[query]
+    pub fn len(&self) -> u64 {
+        self.deque.len()
+    }
+
+    #[query]
+    pub fn get_raw(&self, key: u64) -> Result<Option<Ref<T>>> {
+        self.deque.get_raw(key)
+    }
+
+    #[query]
+    pub fn is_empty(&self) -> bool {
+        self.deque.is_empty()
+    }
+
+    #[query]
+    pub fn is_full(&self) -> bool {
+        self.deque.len() >= MAX_LEN
+    }
+
+    #[query]
+    pub fn get(&self, index: u64) -> Result<Option<Ref<T>>> {
+        self.deque.get(index)
+    }
+
+    #[query]
+    pub fn front(&self) -> Result<Option<Ref<T>>> {
+        self.deque.front()
+    }
+
+    #[query]
+    pub fn back(&self) -> Result<Option<Ref<T>>> {
+        self.deque.back()
+    }
+}
+
+impl<T: State, const MAX_LEN: u64> BoundedDeque<T, MAX_LEN> {
+    /// Pushes a value to the back, first evicting the value at the front if
+    /// the deque is full. Returns the evicted value, if any.
+    pub fn push_back(&mut self, value: T) -> Result<Option<ReadOnly<T>>> {
+        if MAX_LEN == 0 {
+            return Ok(Some(ReadOnly::new(value)));
+        }
+
+        let evicted = if self.is_full() {
+            self.deque.pop_front()?
+        } else {
+            None
+        };
+        self.deque.push_back(value)?;
+
+        Ok(evicted)
+    }
+
+    /// Pushes a value to the back, or returns an error if the deque is full.
+    pub fn try_push_back(&mut self, value: T) -> Result<()> {
+        if self.is_full() {
+            return Err(Error::App(format!(
+                "Deque is full (max length {})",
+                MAX_LEN
+            )));
+        }
+
+        self.deque.push_back(value)
+    }
+
+    pub fn pop_front(&mut self) -> Result<Option<ReadOnly<T>>> {
+        self.deque.pop_front()
+    }
+
+    pub fn pop_back(&mut self) -> Result<Option<ReadOnly<T>>> {
+        self.deque.pop_back()
+    }
+
+    pub fn get_mut(&mut self, index: u64) -> Result<Option<ChildMut<u64, T>>> {
+        self.deque.get_mut(index)
+    }
+
+    pub fn iter(&self) -> Result<Iter<T>> {
+        self.deque.iter()
+    }
+
+    pub fn iter_rev(&self) -> Result<Rev<Iter<T>>> {
+        self.deque.iter_rev()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BoundedDeque;
+    use crate::state::State;
+    use crate::store::{MapStore, Shared, Store};
+    use crate::Result;
+
+    fn setup() -> (Store, BoundedDeque<u32, 2>) {
+        let store = mapstore();
+        let deque = BoundedDeque::with_store(store.clone()).unwrap();
+        (store, deque)
+    }
+
+    fn mapstore() -> Store {
+        Store::new(Shared::new(MapStore::new()).into())
+    }
+
+    #[test]
+    fn push_back_until_full() -> Result<()> {
+        let (_, mut deque) = setup();
+        assert!(deque.push_back(1)?.is_none());
+        assert!(!deque.is_full());
+        assert!(deque.push_back(2)?.is_none());
+        assert!(deque.is_full());
+        assert_eq!(deque.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn push_back_evicts_front() -> Result<()> {
+        let (_, mut deque) = setup();
+        deque.push_back(1)?;
+        deque.push_back(2)?;
+
+        assert_eq!(*deque.push_back(3)?.unwrap(), 1);
+        assert_eq!(deque.len(), 2);
+        assert_eq!(*deque.front()?.unwrap(), 2);
+        assert_eq!(*deque.back()?.unwrap(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn try_push_back_rejects_when_full() -> Result<()> {
+        let (_, mut deque) = setup();
+        deque.try_push_back(1)?;
+        deque.try_push_back(2)?;
+
+        assert!(deque.try_push_back(3).is_err());
+        assert_eq!(deque.len(), 2);
+        assert_eq!(*deque.back()?.unwrap(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn zero_capacity() -> Result<()> {
+        let mut deque: BoundedDeque<u32, 0> = BoundedDeque::with_store(mapstore())?;
+        assert_eq!(*deque.push_back(1)?.unwrap(), 1);
+        assert!(deque.is_empty());
+        assert!(deque.try_push_back(1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_load() -> Result<()> {
+        let (store, mut deque) = setup();
+        deque.push_back(1)?;
+        deque.push_back(2)?;
+
+        let mut bytes = vec![];
+        deque.flush(&mut bytes)?;
+        let mut deque: BoundedDeque<u32, 2> = BoundedDeque::load(store, &mut bytes.as_slice())?;
+
+        assert!(deque.is_full());
+        assert_eq!(*deque.push_back(3)?.unwrap(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn describe_resolves_get_raw() -> Result<()> {
+        use super::BoundedDequeMethodQuery;
+        use crate::describe::Describe;
+        use crate::encoding::{Decode, Encode};
+        use crate::query::{Item, Query};
+        use std::any::TypeId;
+
+        type Deque = BoundedDeque<u32, 4>;
+        let key = 9u64.encode()?;
+        let query_bytes =
+            Deque::describe().resolve_by_type_id(TypeId::of::<u32>(), &key, vec![], vec![])?;
+        let query = <Deque as Query>::Query::decode(query_bytes.as_slice())?;
+        assert!(matches!(
+            query,
+            Item::Method(BoundedDequeMethodQuery::GetRaw(9, _))
+        ));
+
+        Ok(())
+    }
+}
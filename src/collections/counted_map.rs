@@ -0,0 +1,321 @@
+use serde::Serialize;
+use std::iter::Rev;
+use std::ops::RangeBounds;
+
+use super::map::{ChildMut, Iter, Map, ReadOnly, Ref};
+use crate::describe::Describe;
+use crate::encoding::{Decode, Encode, Terminated};
+use crate::migrate::Migrate;
+use crate::orga;
+use crate::query::FieldQuery;
+use crate::state::State;
+use crate::store::Store;
+use crate::Result;
+
+/// A [Map] which keeps count of its entries, so [len](Self::len) and
+/// [is_empty](Self::is_empty) don't have to scan the store.
+///
+/// The count is encoded in the map's own bytes (in its parent's entry), so
+/// changing the type of an existing `Map` field to `CountedMap` requires a
+/// migration. Keeping the count costs a read of the key on each insert and
+/// remove, which is why plain `Map` doesn't do it.
+#[derive(FieldQuery, Encode, Decode)]
+pub struct CountedMap<K, V> {
+    len: u64,
+    map: Map<K, V>,
+}
+
+impl<K, V> Describe for CountedMap<K, V>
+where
+    K: Encode + Terminated + Clone + 'static + Describe,
+    V: State + Describe,
+{
+    fn describe() -> crate::describe::Descriptor {
+        use crate::describe::Builder;
+        Builder::new::<Self>()
+            .dynamic_child::<K, V>(|mut query_bytes| {
+                // `get`, the fourth query method
+                query_bytes.extend_from_slice(&[131]);
+                query_bytes
+            })
+            .build()
+    }
+}
+
+impl<K, V> CountedMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K: Encode + Decode + Terminated + 'static, V: State> CountedMap<K, V> {
+    pub fn with_store(store: Store) -> Result<Self> {
+        Ok(Self {
+            len: 0,
+            map: Map::with_store(store)?,
+        })
+    }
+}
+
+impl<K, V> Default for CountedMap<K, V> {
+    fn default() -> Self {
+        CountedMap {
+            len: 0,
+            map: Map::default(),
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for CountedMap<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CountedMap")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<K, V> Serialize for CountedMap<K, V>
+where
+    K: Serialize + Encode + Decode + Terminated + Clone + 'static,
+    V: Serialize + State,
+{
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        self.map.serialize(serializer)
+    }
+}
+
+// not derived, so entries are stored directly under the collection's prefix,
+// as they are for a plain `Map`
+impl<K, V> State for CountedMap<K, V>
+where
+    K: Encode + Terminated + 'static,
+    V: State,
+{
+    fn attach(&mut self, store: Store) -> Result<()> {
+        self.map.attach(store)
+    }
+
+    fn flush<W: std::io::Write>(self, out: &mut W) -> Result<()> {
+        self.len.flush(out)?;
+        self.map.flush(out)
+    }
+
+    fn load(store: Store, bytes: &mut &[u8]) -> Result<Self> {
+        let mut value = Self {
+            len: u64::load(store.clone(), bytes)?,
+            map: Map::load(store.clone(), bytes)?,
+        };
+
+        value.attach(store)?;
+
+        Ok(value)
+    }
+}
+
+impl<K, V> Migrate for CountedMap<K, V>
+where
+    K: Encode + Decode + State + Terminated + Clone + Send + Sync + Migrate,
+    V: State + Migrate,
+{
+    fn migrate(src: Store, dest: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            len: u64::migrate(Store::default(), Store::default(), bytes)?,
+            map: Map::migrate(src, dest, bytes)?,
+        })
+    }
+}
+
+#[orga]
+impl<K, V> CountedMap<K, V>
+where
+    K: Encode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+{
+    #[query]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[query]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[query]
+    pub fn contains_key(&self, key: K) -> Result<bool> {
+        self.map.contains_key(key)
+    }
+
+    #[query]
+    pub fn get(&self, key: K) -> Result<Option<Ref<V>>> {
+        self.map.get(key)
+    }
+}
+
+impl<K, V> CountedMap<K, V>
+where
+    K: Encode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+{
+    /// Inserts a value at the given key, replacing any existing value.
+    pub fn insert(&mut self, key: K, value: V) -> Result<()> {
+        if !self.map.contains_key(key.clone())? {
+            self.len += 1;
+        }
+
+        self.map.insert(key, value)
+    }
+
+    /// Removes the value at the given key, if any.
+    pub fn remove(&mut self, key: K) -> Result<Option<ReadOnly<V>>> {
+        let removed = self.map.remove(key)?;
+        if removed.is_some() {
+            self.len -= 1;
+        }
+
+        Ok(removed)
+    }
+
+    /// Gets a mutable reference to the value at the given key.
+    ///
+    /// Values must be removed with [remove](Self::remove) rather than
+    /// [ChildMut::remove], which would leave the count out of date.
+    pub fn get_mut(&mut self, key: K) -> Result<Option<ChildMut<K, V>>> {
+        self.map.get_mut(key)
+    }
+}
+
+impl<'a, K, V> CountedMap<K, V>
+where
+    K: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+{
+    pub fn iter(&'a self) -> Result<Iter<'a, K, V>> {
+        self.map.iter()
+    }
+
+    pub fn range<B: RangeBounds<K>>(&'a self, range: B) -> Result<Iter<'a, K, V>> {
+        self.map.range(range)
+    }
+
+    pub fn iter_rev(&'a self) -> Result<Rev<Iter<'a, K, V>>> {
+        self.map.iter_rev()
+    }
+
+    pub fn range_rev<B: RangeBounds<K>>(&'a self, range: B) -> Result<Rev<Iter<'a, K, V>>> {
+        self.map.range_rev(range)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CountedMap;
+    use crate::state::State;
+    use crate::store::{MapStore, Shared, Store};
+    use crate::Result;
+
+    fn setup() -> (Store, CountedMap<u32, u32>) {
+        let store = mapstore();
+        let map = CountedMap::with_store(store.clone()).unwrap();
+        (store, map)
+    }
+
+    fn mapstore() -> Store {
+        Store::new(Shared::new(MapStore::new()).into())
+    }
+
+    #[test]
+    fn empty() {
+        let (_, map) = setup();
+        assert!(map.is_empty());
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn insert_counts_new_keys() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 10)?;
+        map.insert(2, 20)?;
+        map.insert(2, 21)?;
+
+        assert_eq!(map.len(), 2);
+        assert!(!map.is_empty());
+        assert_eq!(*map.get(2)?.unwrap(), 21);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 10)?;
+        map.insert(2, 20)?;
+
+        assert!(map.remove(3)?.is_none());
+        assert_eq!(map.len(), 2);
+        assert_eq!(*map.remove(1)?.unwrap(), 10);
+        assert_eq!(map.len(), 1);
+        assert!(map.remove(1)?.is_none());
+        assert_eq!(map.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn len_persists() -> Result<()> {
+        let (store, mut map) = setup();
+        map.insert(1, 10)?;
+        map.insert(2, 20)?;
+
+        let mut bytes = vec![];
+        map.flush(&mut bytes)?;
+        let map: CountedMap<u32, u32> = CountedMap::load(store, &mut bytes.as_slice())?;
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(*map.get(1)?.unwrap(), 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn iter() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(3, 30)?;
+        map.insert(2, 20)?;
+
+        let keys = map
+            .iter()?
+            .map(|entry| entry.map(|(key, _)| *key))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(keys, vec![2, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn describe_resolves_get() -> Result<()> {
+        use super::CountedMapMethodQuery;
+        use crate::describe::Describe;
+        use crate::encoding::{Decode, Encode};
+        use crate::query::{Item, Query};
+        use std::any::TypeId;
+
+        let key = 7u32.encode()?;
+        let query_bytes = CountedMap::<u32, u64>::describe().resolve_by_type_id(
+            TypeId::of::<u64>(),
+            key.as_slice(),
+            vec![],
+            vec![],
+        )?;
+        let query = <CountedMap<u32, u64> as Query>::Query::decode(query_bytes.as_slice())?;
+        assert!(matches!(
+            query,
+            Item::Method(CountedMapMethodQuery::Get(7, _))
+        ));
+
+        Ok(())
+    }
+}
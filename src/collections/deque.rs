@@ -1,4 +1,5 @@
 use serde::Serialize;
+use std::iter::Rev;
 
 use super::map::{ChildMut, Map, ReadOnly, Ref};
 use crate::call::Call;
@@ -241,6 +242,12 @@ impl<'a, T: State> Deque<T> {
             map_iter: self.map.iter()?,
         })
     }
+
+    /// Iterates from back to front, e.g. to read the latest `n` items pushed
+    /// with `push_back` without reading the rest of the deque.
+    pub fn iter_rev(&'a self) -> Result<Rev<Iter<'a, T>>> {
+        Ok(self.iter()?.rev())
+    }
 }
 
 impl<T: State> Deque<T> {
@@ -681,4 +688,28 @@ mod test {
         let mut iter = deque.iter().unwrap();
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn deque_iter_rev() {
+        let store = Store::with_map_store();
+        let mut deque: Deque<u32> = Deque::with_store(store.clone()).unwrap();
+        for i in 0..5 {
+            deque.push_back(i).unwrap();
+        }
+        deque.push_front(9).unwrap();
+        let mut bytes = vec![];
+        deque.flush(&mut bytes).unwrap();
+
+        let mut deque: Deque<u32> = Deque::load(store, &mut bytes.as_slice()).unwrap();
+        deque.push_back(5).unwrap();
+        let latest: Vec<u32> = deque
+            .iter_rev()
+            .unwrap()
+            .take(3)
+            .map(|value| *value.unwrap())
+            .collect();
+        assert_eq!(latest, vec![5, 4, 3]);
+        assert_eq!(deque.iter_rev().unwrap().count(), 7);
+        assert_eq!(*deque.iter_rev().unwrap().last().unwrap().unwrap(), 9);
+    }
 }
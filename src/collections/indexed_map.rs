@@ -0,0 +1,338 @@
+use serde::Serialize;
+use std::marker::PhantomData;
+
+use super::map::{Iter as MapIter, Map, ReadOnly, Ref};
+use super::set::{Iter as SetIter, Set};
+use crate::call::FieldCall;
+use crate::describe::Describe;
+use crate::encoding::{Decode, Encode, Terminated};
+use crate::migrate::Migrate;
+use crate::orga;
+use crate::query::FieldQuery;
+use crate::state::State;
+use crate::store::Store;
+use crate::Result;
+
+/// Derives the secondary index key of an entry in an [IndexedMap].
+pub trait Index<K, V>: 'static {
+    /// The key entries are ordered by in the index, e.g. a validator's voting
+    /// power.
+    type Key: Encode + Decode + Terminated + Clone + Send + Sync + 'static;
+
+    /// Returns the index key of the entry with the given key and value.
+    fn index_key(key: &K, value: &V) -> Self::Key;
+}
+
+/// A [Map] which also keeps its entries ordered by a secondary index `I`, so
+/// entries can be iterated in order of e.g. balance or voting power without
+/// scanning the whole map.
+///
+/// The index is stored as a set of `(I::Key, K)` pairs, which is updated on
+/// every [insert](Self::insert), [update](Self::update) and
+/// [remove](Self::remove). Values can only be mutated through `update`, so
+/// the index never goes out of date.
+#[derive(FieldQuery, FieldCall)]
+pub struct IndexedMap<K, V, I: Index<K, V>> {
+    map: Map<K, V>,
+    index: Set<(I::Key, K)>,
+    _marker: PhantomData<I>,
+}
+
+impl<K, V, I: Index<K, V>> IndexedMap<K, V, I> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K, V, I: Index<K, V>> Default for IndexedMap<K, V, I> {
+    fn default() -> Self {
+        IndexedMap {
+            map: Map::default(),
+            index: Set::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V, I: Index<K, V>> std::fmt::Debug for IndexedMap<K, V, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IndexedMap").finish()
+    }
+}
+
+impl<K, V, I: Index<K, V>> Terminated for IndexedMap<K, V, I> {}
+
+impl<K, V, I> IndexedMap<K, V, I>
+where
+    K: Encode + Decode + Terminated + 'static,
+    V: State,
+    I: Index<K, V>,
+{
+    pub fn with_store(store: Store) -> Result<Self> {
+        let mut map = Self::default();
+        map.attach(store)?;
+
+        Ok(map)
+    }
+}
+
+impl<K, V, I> State for IndexedMap<K, V, I>
+where
+    K: Encode + Terminated + 'static,
+    V: State,
+    I: Index<K, V>,
+{
+    fn attach(&mut self, store: Store) -> Result<()> {
+        self.map.attach(store.sub(&[0]))?;
+        self.index.attach(store.sub(&[1]))
+    }
+
+    fn flush<W: std::io::Write>(self, out: &mut W) -> Result<()> {
+        self.map.flush(out)?;
+        self.index.flush(out)
+    }
+
+    fn load(store: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            map: Map::load(store.sub(&[0]), bytes)?,
+            index: Set::load(store.sub(&[1]), bytes)?,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<K, V, I> Describe for IndexedMap<K, V, I>
+where
+    K: Encode + Decode + Terminated + Clone + Describe + 'static,
+    V: State + Describe,
+    I: Index<K, V>,
+    I::Key: Describe,
+{
+    fn describe() -> crate::describe::Descriptor {
+        use crate::describe::Builder;
+        Builder::new::<Self>()
+            .named_child::<Map<K, V>>("map", &[0])
+            .named_child::<Set<(I::Key, K)>>("index", &[1])
+            .build()
+    }
+}
+
+impl<K, V, I> Migrate for IndexedMap<K, V, I>
+where
+    K: Encode + Decode + State + Terminated + Clone + Send + Sync + Migrate,
+    V: State + Migrate,
+    I: Index<K, V>,
+    I::Key: State + Migrate,
+{
+    fn migrate(src: Store, dest: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            map: Map::migrate(src.sub(&[0]), dest.sub(&[0]), bytes)?,
+            index: Set::migrate(src.sub(&[1]), dest.sub(&[1]), bytes)?,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<K, V, I> Serialize for IndexedMap<K, V, I>
+where
+    K: Serialize + Encode + Decode + Terminated + Clone + 'static,
+    V: Serialize + State,
+    I: Index<K, V>,
+{
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        self.map.serialize(serializer)
+    }
+}
+
+#[orga]
+impl<K, V, I> IndexedMap<K, V, I>
+where
+    K: Encode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+    I: Index<K, V>,
+{
+    #[query]
+    pub fn contains_key(&self, key: K) -> Result<bool> {
+        self.map.contains_key(key)
+    }
+
+    #[query]
+    pub fn get(&self, key: K) -> Result<Option<Ref<V>>> {
+        self.map.get(key)
+    }
+}
+
+impl<K, V, I> IndexedMap<K, V, I>
+where
+    K: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+    I: Index<K, V>,
+{
+    /// Inserts a value at the given key, replacing any existing value and its
+    /// index entry.
+    pub fn insert(&mut self, key: K, value: V) -> Result<()> {
+        self.remove(key.clone())?;
+        self.index
+            .insert((I::index_key(&key, &value), key.clone()))?;
+        self.map.insert(key, value)
+    }
+
+    /// Removes the value at the given key and its index entry, if any.
+    pub fn remove(&mut self, key: K) -> Result<Option<ReadOnly<V>>> {
+        let removed = self.map.remove(key.clone())?;
+        if let Some(value) = removed.as_ref() {
+            self.index.remove((I::index_key(&key, value), key))?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Mutates the value at the given key with `f`, moving its index entry if
+    /// its index key changed. Returns `false` if the key has no value.
+    pub fn update<F>(&mut self, key: K, f: F) -> Result<bool>
+    where
+        F: FnOnce(&mut V) -> Result<()>,
+    {
+        let mut child = match self.map.get_mut(key.clone())? {
+            Some(child) => child,
+            None => return Ok(false),
+        };
+
+        let old_index_key = I::index_key(&key, &child);
+        f(&mut child)?;
+        let new_index_key = I::index_key(&key, &child);
+
+        if old_index_key.encode()? != new_index_key.encode()? {
+            self.index.remove((old_index_key, key.clone()))?;
+            self.index.insert((new_index_key, key))?;
+        }
+
+        Ok(true)
+    }
+
+    /// Iterates over the entries in order of their keys.
+    pub fn iter(&self) -> Result<MapIter<K, V>> {
+        self.map.iter()
+    }
+
+    /// Iterates over the `(index key, key)` pairs of the entries in ascending
+    /// order of index key. Use `.rev()` for descending order, e.g. to get the
+    /// validators with the most voting power.
+    pub fn iter_index(&self) -> Result<SetIter<(I::Key, K)>> {
+        self.index.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Index, IndexedMap};
+    use crate::state::State;
+    use crate::store::{MapStore, Shared, Store};
+    use crate::Result;
+
+    struct ByValue;
+
+    impl Index<u32, u64> for ByValue {
+        type Key = u64;
+
+        fn index_key(_key: &u32, value: &u64) -> u64 {
+            *value
+        }
+    }
+
+    fn setup() -> (Store, IndexedMap<u32, u64, ByValue>) {
+        let store = mapstore();
+        let map = IndexedMap::with_store(store.clone()).unwrap();
+        (store, map)
+    }
+
+    fn mapstore() -> Store {
+        Store::new(Shared::new(MapStore::new()).into())
+    }
+
+    fn by_value(map: &IndexedMap<u32, u64, ByValue>) -> Result<Vec<(u64, u32)>> {
+        map.iter_index()?.rev().collect()
+    }
+
+    #[test]
+    fn insert() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 100)?;
+        map.insert(2, 300)?;
+        map.insert(3, 200)?;
+
+        assert_eq!(*map.get(2)?.unwrap(), 300);
+        assert_eq!(by_value(&map)?, vec![(300, 2), (200, 3), (100, 1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn insert_replaces_index_entry() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 100)?;
+        map.insert(1, 400)?;
+
+        assert_eq!(by_value(&map)?, vec![(400, 1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_moves_index_entry() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 100)?;
+        map.insert(2, 200)?;
+
+        assert!(map.update(1, |value| {
+            *value = 500;
+            Ok(())
+        })?);
+        assert_eq!(*map.get(1)?.unwrap(), 500);
+        assert_eq!(by_value(&map)?, vec![(500, 1), (200, 2)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_missing() -> Result<()> {
+        let (_, mut map) = setup();
+        assert!(!map.update(4, |_| Ok(()))?);
+        assert!(by_value(&map)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 100)?;
+        map.insert(2, 300)?;
+
+        assert_eq!(*map.remove(2)?.unwrap(), 300);
+        assert!(map.remove(2)?.is_none());
+        assert!(!map.contains_key(2)?);
+        assert_eq!(by_value(&map)?, vec![(100, 1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_load() -> Result<()> {
+        let (store, mut map) = setup();
+        map.insert(1, 100)?;
+        map.insert(2, 300)?;
+
+        let mut bytes = vec![];
+        map.flush(&mut bytes)?;
+        let map: IndexedMap<u32, u64, ByValue> = IndexedMap::load(store, &mut bytes.as_slice())?;
+
+        assert_eq!(*map.get(1)?.unwrap(), 100);
+        assert_eq!(by_value(&map)?, vec![(300, 2), (100, 1)]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+use serde::Serialize;
+use std::ops::RangeBounds;
+
+use super::map::{Iter as MapIter, Map, Ref};
+use crate::call::FieldCall;
+use crate::describe::Describe;
+use crate::migrate::Migrate;
+use crate::orga;
+use crate::query::FieldQuery;
+use crate::state::State;
+use crate::store::Store;
+use crate::{Error, Result};
+
+/// An append-only list of values, e.g. event history, checkpoint records or
+/// light client headers.
+///
+/// Values are stored in a [Map] keyed by their index, starting at 0, so each
+/// value keeps the same index (and store key) for as long as it exists. Unlike
+/// [Deque](super::Deque), values can't be removed or modified once pushed.
+#[derive(FieldQuery, FieldCall)]
+pub struct Log<T> {
+    len: u64,
+    map: Map<u64, T>,
+}
+
+impl<T> Log<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T: State> Log<T> {
+    pub fn with_store(store: Store) -> Result<Self> {
+        Ok(Self {
+            len: 0,
+            map: Map::with_store(store)?,
+        })
+    }
+}
+
+impl<T> Default for Log<T> {
+    fn default() -> Self {
+        Log {
+            len: 0,
+            map: Map::default(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Log<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Log").field("len", &self.len).finish()
+    }
+}
+
+impl<T: Serialize + State> Serialize for Log<T> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeSeq};
+        let mut seq = serializer.serialize_seq(None)?;
+        for entry in self.iter().map_err(Error::custom)? {
+            let (_, value) = entry.map_err(Error::custom)?;
+            seq.serialize_element(&*value)?;
+        }
+        seq.end()
+    }
+}
+
+impl<T: State> State for Log<T> {
+    fn attach(&mut self, store: Store) -> Result<()> {
+        self.map.attach(store)
+    }
+
+    fn flush<W: std::io::Write>(self, out: &mut W) -> Result<()> {
+        self.len.flush(out)?;
+        self.map.flush(out)
+    }
+
+    fn load(store: Store, bytes: &mut &[u8]) -> Result<Self> {
+        let mut value = Self {
+            len: u64::load(store.clone(), bytes)?,
+            map: Map::load(store.clone(), bytes)?,
+        };
+
+        value.attach(store)?;
+
+        Ok(value)
+    }
+}
+
+impl<T> Describe for Log<T>
+where
+    T: State + Describe,
+{
+    fn describe() -> crate::describe::Descriptor {
+        use crate::describe::Builder;
+        Builder::new::<Self>()
+            .dynamic_child::<u64, T>(|mut query_bytes| {
+                // `get`, the third query method, since entries are keyed by index
+                query_bytes.extend_from_slice(&[130]);
+                query_bytes
+            })
+            .build()
+    }
+}
+
+impl<T: Migrate> Migrate for Log<T> {
+    fn migrate(src: Store, dest: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            len: u64::migrate(Store::default(), Store::default(), bytes)?,
+            map: Map::migrate(src, dest, bytes)?,
+        })
+    }
+}
+
+#[orga]
+impl<T: State> Log<T> {
+    #[query]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[query]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[query]
+    pub fn get(&self, index: u64) -> Result<Option<Ref<T>>> {
+        self.map.get(index)
+    }
+
+    #[query]
+    pub fn last(&self) -> Result<Option<Ref<T>>> {
+        match self.len {
+            0 => Ok(None),
+            len => self.map.get(len - 1),
+        }
+    }
+}
+
+impl<T: State> Log<T> {
+    /// Appends a value, returning its index.
+    pub fn push(&mut self, value: T) -> Result<u64> {
+        let index = self.len;
+        self.len = index.checked_add(1).ok_or(Error::Overflow)?;
+        self.map.insert(index, value)?;
+
+        Ok(index)
+    }
+
+    /// Iterates over the values in the order they were pushed, along with
+    /// their indexes.
+    pub fn iter(&self) -> Result<MapIter<u64, T>> {
+        self.map.iter()
+    }
+
+    /// Iterates over the values with indexes in `range`, e.g. `log.range(n..)`
+    /// to read everything pushed after the first `n` values. Reverse the
+    /// iterator to read the latest values first.
+    pub fn range<B: RangeBounds<u64>>(&self, range: B) -> Result<MapIter<u64, T>> {
+        self.map.range(range)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Log;
+    use crate::state::State;
+    use crate::store::{MapStore, Shared, Store};
+    use crate::Result;
+
+    fn setup() -> (Store, Log<u32>) {
+        let store = mapstore();
+        let log = Log::with_store(store.clone()).unwrap();
+        (store, log)
+    }
+
+    fn mapstore() -> Store {
+        Store::new(Shared::new(MapStore::new()).into())
+    }
+
+    #[test]
+    fn empty() -> Result<()> {
+        let (_, log) = setup();
+        assert!(log.is_empty());
+        assert_eq!(log.len(), 0);
+        assert!(log.get(0)?.is_none());
+        assert!(log.last()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn push_get() -> Result<()> {
+        let (_, mut log) = setup();
+        assert_eq!(log.push(10)?, 0);
+        assert_eq!(log.push(11)?, 1);
+
+        assert_eq!(log.len(), 2);
+        assert_eq!(*log.get(0)?.unwrap(), 10);
+        assert_eq!(*log.get(1)?.unwrap(), 11);
+        assert!(log.get(2)?.is_none());
+        assert_eq!(*log.last()?.unwrap(), 11);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_load() -> Result<()> {
+        let (store, mut log) = setup();
+        log.push(10)?;
+        log.push(11)?;
+
+        let mut bytes = vec![];
+        log.flush(&mut bytes)?;
+        let mut log: Log<u32> = Log::load(store, &mut bytes.as_slice())?;
+
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.push(12)?, 2);
+        assert_eq!(*log.last()?.unwrap(), 12);
+
+        Ok(())
+    }
+
+    #[test]
+    fn range_rev() -> Result<()> {
+        let (_, mut log) = setup();
+        log.push(10)?;
+        log.push(11)?;
+        log.push(12)?;
+
+        let latest: Vec<(u64, u32)> = log
+            .range(1..)?
+            .rev()
+            .map(|entry| entry.map(|(i, v)| (*i, *v)))
+            .collect::<Result<_>>()?;
+        assert_eq!(latest, vec![(2, 12), (1, 11)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn describe_resolves_get() -> Result<()> {
+        use super::LogMethodQuery;
+        use crate::describe::Describe;
+        use crate::encoding::{Decode, Encode};
+        use crate::query::{Item, Query};
+        use std::any::TypeId;
+
+        let key = 4u64.encode()?;
+        let query_bytes =
+            Log::<u32>::describe().resolve_by_type_id(TypeId::of::<u32>(), &key, vec![], vec![])?;
+        let query = <Log<u32> as Query>::Query::decode(query_bytes.as_slice())?;
+        assert!(matches!(query, Item::Method(LogMethodQuery::Get(4, _))));
+
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
+use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::collections::btree_map::Entry::{Occupied, Vacant};
 use std::collections::{btree_map, BTreeMap};
-use std::iter::Peekable;
+use std::iter::{Peekable, Rev};
 use std::marker::PhantomData;
 use std::ops::{Bound, Deref, DerefMut, RangeBounds};
 
@@ -79,6 +80,12 @@ impl<K: Encode> Ord for MapKey<K> {
 
 impl<K> Eq for MapKey<K> {}
 
+impl<K> Borrow<[u8]> for MapKey<K> {
+    fn borrow(&self) -> &[u8] {
+        self.inner_bytes.as_slice()
+    }
+}
+
 /// A map collection which stores data in a backing key/value store.
 ///
 /// Keys are encoded into bytes and values are stored at the resulting key, with
@@ -116,7 +123,7 @@ where
 
     fn flush<W: std::io::Write>(mut self, _out: &mut W) -> Result<()> {
         while let Some((key, maybe_value)) = self.children.pop_first() {
-            Self::apply_change(&mut self.store, key.inner.encode()?, maybe_value)?;
+            Self::apply_change(&mut self.store, key.inner_bytes, maybe_value)?;
         }
 
         Ok(())
@@ -145,6 +152,17 @@ impl<K, V> Default for Map<K, V> {
     }
 }
 
+/// The maximum number of entries returned by [Map::paginate].
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+/// A page of entries returned by [Map::paginate].
+pub struct Page<'a, K, V> {
+    pub entries: Vec<(Ref<'a, K>, Ref<'a, V>)>,
+    /// The cursor to pass as `start_after` to get the next page, or `None` if
+    /// this is the last page.
+    pub next: Option<K>,
+}
+
 #[orga]
 impl<K, V> Map<K, V>
 where
@@ -220,6 +238,54 @@ where
             self.get_from_store(&map_key.inner)?.map(Ref::Owned)
         })
     }
+
+    /// Returns up to `limit` (at most [MAX_PAGE_SIZE]) entries in ascending
+    /// key order, starting after `start_after`, or from the first entry if it
+    /// is `None`.
+    ///
+    /// Clients can page through a large map by passing the returned
+    /// [Page::next] cursor back in, so no single query has to scan it all.
+    #[query]
+    pub fn paginate(&self, start_after: Option<K>, limit: u32) -> Result<Page<K, V>>
+    where
+        K: Decode + Clone,
+    {
+        let start = start_after.map_or(Bound::Unbounded, Bound::Excluded);
+        let limit = limit.min(MAX_PAGE_SIZE) as usize;
+
+        let mut iter = self.range((start, Bound::Unbounded))?;
+        let entries = iter.by_ref().take(limit).collect::<Result<Vec<_>>>()?;
+        let has_more = iter.next().transpose()?.is_some();
+        let next = match entries.last() {
+            Some((key, _)) if has_more => Some((**key).clone()),
+            _ => None,
+        };
+
+        Ok(Page { entries, next })
+    }
+}
+
+impl<K, V> Map<K, V>
+where
+    K: Encode + Terminated + Send + Sync + 'static,
+    V: State,
+{
+    /// Inserts every key/value pair in `entries`, e.g. balances at genesis or
+    /// for an airdrop.
+    ///
+    /// As with `insert`, the values are staged in memory, then written to the
+    /// store in ascending key order when the map is flushed, which is the
+    /// cheapest order for the store to apply them in.
+    pub fn extend<I>(&mut self, entries: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        for (key, value) in entries {
+            self.insert(key, value)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<K: Serialize, V: Serialize> Serialize for Map<K, V>
@@ -401,24 +467,68 @@ where
     K: Encode + Decode + Terminated + Clone + 'static,
     V: State,
 {
+    /// Iterates over all entries in ascending key order, including writes
+    /// not yet flushed to the store.
     pub fn iter(&'a self) -> Result<Iter<'a, K, V>> {
         self.range(..)
     }
 
+    /// Iterates over all keys in ascending order, without loading the values
+    /// of entries read from the store.
+    pub fn keys(&'a self) -> Result<Keys<'a, K, V>> {
+        Ok(Keys { iter: self.iter()? })
+    }
+
+    /// Iterates over all values in ascending key order, without decoding the
+    /// keys of entries read from the store.
+    pub fn values(&'a self) -> Result<Values<'a, K, V>> {
+        Ok(Values { iter: self.iter()? })
+    }
+
+    /// Iterates over the entries whose keys are within `range`, in ascending
+    /// order of their encoded keys. Bounds may be inclusive or exclusive, e.g.
+    /// `map.range(..(timestamp, 0))` with tuple keys scans every entry keyed
+    /// before `timestamp`. The iterator can be reversed to scan in descending
+    /// order.
+    ///
+    /// Only the entries within the range are read from the store.
     pub fn range<B: RangeBounds<K>>(&'a self, range: B) -> Result<Iter<'a, K, V>> {
-        let map_start = range
-            .start_bound()
-            .map(|inner| MapKey::<K>::new(inner.clone()).unwrap());
-        let map_end = range
-            .end_bound()
-            .map(|inner| MapKey::<K>::new(inner.clone()).unwrap());
-        let map_iter = self.children.range((map_start, map_end)).peekable();
-
-        let encoded_range = (
+        self.range_encoded((
             encode_bound(range.start_bound())?,
             encode_bound(range.end_bound())?,
-        );
-        let store_iter = StoreNextIter::new(&self.store, encoded_range)?;
+        ))
+    }
+
+    /// Iterates over the entries whose keys start with the encoding of
+    /// `prefix`, e.g. every `(Address, Denom)` entry of one address in a map
+    /// keyed by `(Address, Denom)`.
+    ///
+    /// Tuple keys are encoded as the concatenation of their components, so the
+    /// entries of a composite key are grouped by their leading components and
+    /// any leading components (`a` or `(a, b)` for a key of type `(A, B, C)`)
+    /// can be used as a prefix. The prefix must be `Terminated` so that it
+    /// can't also match keys with a different, longer first component.
+    pub fn iter_prefix<P: Encode + Terminated>(&'a self, prefix: P) -> Result<Iter<'a, K, V>> {
+        let start = prefix.encode()?;
+        let end = match prefix_end(start.clone()) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        };
+
+        self.range_encoded((Bound::Included(start), end))
+    }
+
+    fn range_encoded(&'a self, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Result<Iter<'a, K, V>> {
+        // children are ordered by their encoded keys, so they can be ranged
+        // over by bytes (see the `Borrow<[u8]>` impl of `MapKey`)
+        let map_iter = self
+            .children
+            .range::<[u8], _>((
+                range.0.as_ref().map(Vec::as_slice),
+                range.1.as_ref().map(Vec::as_slice),
+            ))
+            .peekable();
+        let store_iter = StoreNextIter::new(&self.store, range)?;
 
         Ok(Iter {
             parent: self,
@@ -426,6 +536,109 @@ where
             store_iter,
         })
     }
+
+    /// Iterates over all entries in descending key order, reading backwards
+    /// from the end of the map.
+    pub fn iter_rev(&'a self) -> Result<Rev<Iter<'a, K, V>>> {
+        Ok(self.iter()?.rev())
+    }
+
+    /// Like [Map::range], but in descending key order, e.g. to read the
+    /// highest `n` entries below a key.
+    pub fn range_rev<B: RangeBounds<K>>(&'a self, range: B) -> Result<Rev<Iter<'a, K, V>>> {
+        Ok(self.range(range)?.rev())
+    }
+}
+
+impl<K, V> Map<K, V>
+where
+    K: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+{
+    /// Removes all entries, including writes not yet flushed.
+    ///
+    /// Entries are deleted from the store immediately rather than on flush,
+    /// without loading their values.
+    pub fn clear(&mut self) -> Result<()> {
+        self.children.clear();
+        // the entry at the map's own prefix belongs to its parent
+        self.store
+            .remove_range((Bound::Excluded(vec![]), Bound::Unbounded))
+    }
+
+    /// Returns an iterator which removes the entries in ascending key order,
+    /// yielding each one as it is removed. Entries which are not iterated over
+    /// are kept.
+    pub fn drain(&mut self) -> Drain<K, V> {
+        Drain {
+            map: self,
+            last_key: None,
+        }
+    }
+
+    /// Removes every entry for which `f` returns `false`, e.g. to prune
+    /// matured unbondings in `begin_block`. Entries are visited once, in
+    /// ascending key order.
+    pub fn retain<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&K, &V) -> Result<bool>,
+    {
+        let mut to_remove = vec![];
+        for entry in self.iter()? {
+            let (key, value) = entry?;
+            if !f(&key, &value)? {
+                to_remove.push((*key).clone());
+            }
+        }
+
+        for key in to_remove {
+            self.remove(key)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An iterator which removes and yields the entries of a [Map], created by
+/// [Map::drain].
+pub struct Drain<'a, K, V> {
+    map: &'a mut Map<K, V>,
+    last_key: Option<K>,
+}
+
+impl<'a, K, V> Drain<'a, K, V>
+where
+    K: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+{
+    fn next_entry(&mut self) -> Result<Option<(K, ReadOnly<V>)>> {
+        // start after the last removed key so removed entries aren't rescanned
+        let start = self
+            .last_key
+            .take()
+            .map_or(Bound::Unbounded, Bound::Excluded);
+        let key = match self.map.range((start, Bound::Unbounded))?.next() {
+            Some(entry) => (*entry?.0).clone(),
+            None => return Ok(None),
+        };
+
+        let value = self.map.remove(key.clone())?;
+        self.last_key = Some(key.clone());
+
+        Ok(value.map(|value| (key, value)))
+    }
+}
+
+impl<'a, K, V> Iterator for Drain<'a, K, V>
+where
+    K: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+{
+    type Item = Result<(K, ReadOnly<V>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
 }
 
 fn encode_bound<K: Encode>(bound: Bound<&K>) -> Result<Bound<Vec<u8>>> {
@@ -504,6 +717,19 @@ where
     V: State,
 {
     fn iter_merge_next(&mut self, forward: bool) -> Result<Option<(Ref<'a, K>, Ref<'a, V>)>> {
+        Ok(match self.next_raw(forward)? {
+            Some(RawEntry::Child(key, value)) => Some((Ref::Borrowed(key), Ref::Borrowed(value))),
+            Some(RawEntry::Stored((key, value))) => Some((
+                Ref::Owned(Self::decode_key(&key)?),
+                Ref::Owned(self.load_value(&key, &value)?),
+            )),
+            None => None,
+        })
+    }
+
+    /// Merges the next entry from the in-memory children and the store,
+    /// without decoding the entries read from the store.
+    fn next_raw(&mut self, forward: bool) -> Result<Option<RawEntry<'a, K, V>>> {
         loop {
             let (map_entry, backing_entry) = if forward {
                 (self.map_iter.peek().cloned(), self.store_iter.peek())
@@ -534,9 +760,7 @@ where
                 (Some(_), None) => {
                     match map_next().unwrap() {
                         // map value has not been deleted, emit value
-                        (key, Some(value)) => {
-                            Some((Ref::Borrowed(&key.inner), Ref::Borrowed(value)))
-                        }
+                        (key, Some(value)) => Some(RawEntry::Child(&key.inner, value)),
 
                         // map value is a delete, go to the next entry
                         (_, None) => continue,
@@ -550,19 +774,7 @@ where
                         .transpose()?
                         .expect("Peek ensures this arm is unreachable");
 
-                    let mut key_bytes = entry.0.as_slice();
-                    let key = Decode::decode(&mut key_bytes)?;
-                    debug_assert!(key_bytes.is_empty(), "Key had leftover bytes after decode");
-
-                    let mut value_bytes = entry.1.as_slice();
-                    let value =
-                        V::load(self.parent.store.sub(entry.0.as_slice()), &mut value_bytes)?;
-                    debug_assert!(
-                        value_bytes.is_empty(),
-                        "Value had leftover bytes after decode"
-                    );
-
-                    Some((Ref::Owned(key), Ref::Owned(value)))
+                    Some(RawEntry::Stored(entry))
                 }
 
                 // merge values from both iterators
@@ -575,15 +787,6 @@ where
                         Ok((ref key, _)) => key,
                     };
 
-                    let mut key_bytes = backing_key.as_slice();
-                    let key = Decode::decode(&mut key_bytes)?;
-                    debug_assert!(
-                        key_bytes.is_empty(),
-                        "Key had leftover bytes after decode: key={} leftover={}",
-                        hex::encode(backing_key),
-                        hex::encode(key_bytes),
-                    );
-
                     // so compare backing_key with map_key.inner_bytes
                     let key_cmp = map_key.inner_bytes.cmp(backing_key);
 
@@ -592,16 +795,7 @@ where
                         || (!forward && key_cmp == Ordering::Less)
                     {
                         let entry = store_next().unwrap()?;
-
-                        let mut value_bytes = entry.1.as_slice();
-                        let value =
-                            V::load(self.parent.store.sub(entry.0.as_slice()), &mut value_bytes)?;
-                        debug_assert!(
-                            value_bytes.is_empty(),
-                            "Value had leftover bytes after decode"
-                        );
-
-                        return Ok(Some((Ref::Owned(key), Ref::Owned(value))));
+                        return Ok(Some(RawEntry::Stored(entry)));
                     }
 
                     // map_key == backing_key, map entry shadows backing entry
@@ -611,9 +805,7 @@ where
 
                     // map_key is before or at backing_key
                     match map_next().unwrap() {
-                        (key, Some(value)) => {
-                            Some((Ref::Borrowed(&key.inner), Ref::Borrowed(value)))
-                        }
+                        (key, Some(value)) => Some(RawEntry::Child(&key.inner, value)),
 
                         // map entry deleted in in-memory map, skip
                         (_, None) => continue,
@@ -623,6 +815,30 @@ where
         }
     }
 
+    fn decode_key(key_bytes: &[u8]) -> Result<K> {
+        let mut remaining = key_bytes;
+        let key = Decode::decode(&mut remaining)?;
+        debug_assert!(
+            remaining.is_empty(),
+            "Key had leftover bytes after decode: key={} leftover={}",
+            hex::encode(key_bytes),
+            hex::encode(remaining),
+        );
+
+        Ok(key)
+    }
+
+    fn load_value(&self, key_bytes: &[u8], value_bytes: &[u8]) -> Result<V> {
+        let mut remaining = value_bytes;
+        let value = V::load(self.parent.store.sub(key_bytes), &mut remaining)?;
+        debug_assert!(
+            remaining.is_empty(),
+            "Value had leftover bytes after decode"
+        );
+
+        Ok(value)
+    }
+
     fn peek_map_back(&mut self) -> Option<(&'a MapKey<K>, &'a Option<V>)> {
         self.map_iter.next_back().map(|back_entry| {
             let maybe_front_entry = self.map_iter.next();
@@ -664,6 +880,107 @@ where
     }
 }
 
+/// An entry yielded by [Iter::next_raw], either a child retained in memory or
+/// the raw key and value bytes read from the store.
+enum RawEntry<'a, K, V> {
+    Child(&'a K, &'a V),
+    Stored(KV),
+}
+
+/// An iterator over the keys of a [Map], created by [Map::keys]. Values read
+/// from the store are not loaded.
+pub struct Keys<'a, K, V>
+where
+    K: Decode + Encode + Terminated + 'static,
+    V: State,
+{
+    iter: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Keys<'a, K, V>
+where
+    K: Decode + Encode + Terminated + 'static,
+    V: State,
+{
+    fn next_key(&mut self, forward: bool) -> Result<Option<Ref<'a, K>>> {
+        Ok(match self.iter.next_raw(forward)? {
+            Some(RawEntry::Child(key, _)) => Some(Ref::Borrowed(key)),
+            Some(RawEntry::Stored((key, _))) => Some(Ref::Owned(Iter::<K, V>::decode_key(&key)?)),
+            None => None,
+        })
+    }
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V>
+where
+    K: Decode + Encode + Terminated,
+    V: State,
+{
+    type Item = Result<Ref<'a, K>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_key(true).transpose()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Keys<'a, K, V>
+where
+    K: Decode + Encode + Terminated,
+    V: State,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_key(false).transpose()
+    }
+}
+
+/// An iterator over the values of a [Map], created by [Map::values]. Keys
+/// read from the store are not decoded.
+pub struct Values<'a, K, V>
+where
+    K: Decode + Encode + Terminated + 'static,
+    V: State,
+{
+    iter: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Values<'a, K, V>
+where
+    K: Decode + Encode + Terminated + 'static,
+    V: State,
+{
+    fn next_value(&mut self, forward: bool) -> Result<Option<Ref<'a, V>>> {
+        Ok(match self.iter.next_raw(forward)? {
+            Some(RawEntry::Child(_, value)) => Some(Ref::Borrowed(value)),
+            Some(RawEntry::Stored((key, value))) => {
+                Some(Ref::Owned(self.iter.load_value(&key, &value)?))
+            }
+            None => None,
+        })
+    }
+}
+
+impl<'a, K, V> Iterator for Values<'a, K, V>
+where
+    K: Decode + Encode + Terminated,
+    V: State,
+{
+    type Item = Result<Ref<'a, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_value(true).transpose()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Values<'a, K, V>
+where
+    K: Decode + Encode + Terminated,
+    V: State,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_value(false).transpose()
+    }
+}
+
 struct StoreNextIter<'a, S: Default + Read, K: Decode> {
     store: &'a S,
     next_key: Bound<Vec<u8>>,
@@ -690,6 +1007,19 @@ fn increment_bytes(mut bytes: Vec<u8>) -> Vec<u8> {
     bytes
 }
 
+/// Returns the smallest key which is greater than every key starting with
+/// `prefix`, or `None` if there is no such key.
+fn prefix_end(mut prefix: Vec<u8>) -> Option<Vec<u8>> {
+    while let Some(byte) = prefix.pop() {
+        if byte < 255 {
+            prefix.push(byte + 1);
+            return Some(prefix);
+        }
+    }
+
+    None
+}
+
 fn decrement_bytes(mut bytes: Vec<u8>) -> Option<Vec<u8>> {
     for byte in bytes.iter_mut().rev() {
         if *byte == 0 {
@@ -710,9 +1040,19 @@ fn decrement_bytes(mut bytes: Vec<u8>) -> Option<Vec<u8>> {
 
 impl<'a, S: Default + Read, K: Decode> StoreNextIter<'a, S, K> {
     pub fn new<B: RangeBounds<Vec<u8>>>(store: &'a S, range: B) -> Result<Self> {
+        // the entries of a value's children come right after its key, so an
+        // excluded start key must skip past them too
+        let next_key = match encode_bound(range.start_bound())? {
+            Bound::Excluded(key) => match prefix_end(key.clone()) {
+                Some(end) => Bound::Included(end),
+                None => Bound::Excluded(key),
+            },
+            bound => bound,
+        };
+
         Ok(StoreNextIter {
             store,
-            next_key: encode_bound(range.start_bound())?,
+            next_key,
             end_key: encode_bound(range.end_bound())?,
             _phantom: PhantomData,
         })
@@ -1020,6 +1360,34 @@ where
         child.deref_mut();
         Ok(child)
     }
+
+    /// Like `or_insert`, but only calls `make_value` to create the value if
+    /// the `Entry` is empty.
+    pub fn or_insert_with<F>(self, make_value: F) -> Result<ChildMut<'a, K, V>>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Vacant { .. } => self.or_insert(make_value()),
+            Entry::Occupied { child } => Ok(child),
+        }
+    }
+
+    /// Calls `f` with a mutable reference to the existing value if the
+    /// `Entry` is not empty, then returns the `Entry` so it can be chained
+    /// with e.g. `or_insert`.
+    pub fn and_modify<F>(self, f: F) -> Result<Self>
+    where
+        F: FnOnce(&mut V) -> Result<()>,
+    {
+        Ok(match self {
+            Entry::Occupied { mut child } => {
+                f(&mut child)?;
+                Entry::Occupied { child }
+            }
+            vacant => vacant,
+        })
+    }
 }
 
 impl<K: Encode + Decode + Terminated + 'static, V: State> Map<K, V> {
@@ -1193,6 +1561,33 @@ mod tests {
         assert_eq!(store.get(&enc(11)).unwrap().unwrap(), enc(u32::default()));
     }
 
+    #[test]
+    fn or_insert_with_and_modify() {
+        let (store, mut map) = setup();
+
+        let mut calls = 0;
+        for _ in 0..3 {
+            map.entry(18)
+                .unwrap()
+                .and_modify(|value| {
+                    *value += 1;
+                    Ok(())
+                })
+                .unwrap()
+                .or_insert_with(|| {
+                    calls += 1;
+                    10
+                })
+                .unwrap();
+        }
+        assert_eq!(calls, 1);
+        assert_eq!(*map.get(18).unwrap().unwrap(), 12);
+
+        let mut buf = vec![];
+        map.flush(&mut buf).unwrap();
+        assert_eq!(store.get(&enc(18)).unwrap().unwrap(), enc(12));
+    }
+
     #[test]
     fn remove() {
         let (store, mut map) = setup();
@@ -1964,6 +2359,237 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn map_range_excluded_start_with_children() {
+        let store = mapstore();
+        let mut edit_map: Map<u32, Map<u32, u32>> = Default::default();
+        edit_map.attach(store.clone()).unwrap();
+        for i in 1..=3 {
+            let mut sub_map = edit_map.entry(i).unwrap().or_insert_default().unwrap();
+            sub_map.insert(i * 10, i).unwrap();
+        }
+        let mut buf = vec![];
+        edit_map.flush(&mut buf).unwrap();
+
+        let read_map: Map<u32, Map<u32, u32>> = Map::with_store(store).unwrap();
+        let keys = |iter: Iter<u32, Map<u32, u32>>| -> Vec<u32> {
+            iter.map(|entry| *entry.unwrap().0).collect()
+        };
+        let after_1 = (Bound::Excluded(1), Bound::Unbounded);
+
+        assert_eq!(keys(read_map.range(after_1).unwrap()), vec![2, 3]);
+        assert_eq!(keys(read_map.range(after_1).unwrap().rev()), vec![3, 2]);
+        assert_eq!(
+            keys(
+                read_map
+                    .range((Bound::Excluded(2), Bound::Excluded(3)))
+                    .unwrap()
+            ),
+            Vec::<u32>::new()
+        );
+        assert_eq!(keys(read_map.range(..3).unwrap().rev()), vec![2, 1]);
+    }
+
+    #[test]
+    fn map_iter_rev() {
+        let (store, mut edit_map) = setup();
+        for i in 1..=5 {
+            edit_map.insert(i, i * 2).unwrap();
+        }
+        let mut buf = vec![];
+        edit_map.flush(&mut buf).unwrap();
+
+        let mut read_map: Map<u32, u32> = Map::with_store(store).unwrap();
+        read_map.insert(6, 12).unwrap();
+        read_map.remove(5).unwrap();
+
+        let top: Vec<(u32, u32)> = read_map
+            .iter_rev()
+            .unwrap()
+            .take(2)
+            .map(|entry| entry.map(|(k, v)| (*k, *v)).unwrap())
+            .collect();
+        assert_eq!(top, vec![(6, 12), (4, 8)]);
+
+        let below_4: Vec<u32> = read_map
+            .range_rev(..4)
+            .unwrap()
+            .map(|entry| *entry.unwrap().0)
+            .collect();
+        assert_eq!(below_4, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn map_iter_prefix() {
+        let store = mapstore();
+        let mut edit_map: Map<(u32, u32), u32> = Map::with_store(store.clone()).unwrap();
+        for (a, b) in [(0, 9), (1, 1), (1, 2), (2, 0), (u32::MAX, 1)] {
+            edit_map.insert((a, b), a + b).unwrap();
+        }
+        let mut buf = vec![];
+        edit_map.flush(&mut buf).unwrap();
+
+        let mut map: Map<(u32, u32), u32> = Map::with_store(store).unwrap();
+        map.insert((1, 0), 1).unwrap();
+        map.remove((1, 2)).unwrap();
+
+        let keys = |prefix: u32| -> Vec<(u32, u32)> {
+            map.iter_prefix(prefix)
+                .unwrap()
+                .map(|entry| *entry.unwrap().0)
+                .collect()
+        };
+        assert_eq!(keys(1), vec![(1, 0), (1, 1)]);
+        assert_eq!(keys(u32::MAX), vec![(u32::MAX, 1)]);
+        assert_eq!(keys(3), vec![]);
+
+        let last: Vec<(u32, u32)> = map
+            .iter_prefix((1u32, 1u32))
+            .unwrap()
+            .map(|entry| *entry.unwrap().0)
+            .collect();
+        assert_eq!(last, vec![(1, 1)]);
+    }
+
+    #[test]
+    fn map_keys_values() {
+        let (store, mut edit_map) = setup();
+        for i in 1..=3 {
+            edit_map.insert(i, i * 2).unwrap();
+        }
+        let mut buf = vec![];
+        edit_map.flush(&mut buf).unwrap();
+
+        let mut map: Map<u32, u32> = Map::with_store(store).unwrap();
+        map.insert(2, 5).unwrap();
+        map.insert(4, 8).unwrap();
+        map.remove(1).unwrap();
+
+        let keys: Vec<u32> = map.keys().unwrap().map(|k| *k.unwrap()).collect();
+        assert_eq!(keys, vec![2, 3, 4]);
+        let values: Vec<u32> = map.values().unwrap().map(|v| *v.unwrap()).collect();
+        assert_eq!(values, vec![5, 6, 8]);
+        let last_key = map.keys().unwrap().next_back().unwrap().unwrap();
+        assert_eq!(*last_key, 4);
+    }
+
+    #[test]
+    fn map_extend() {
+        let (store, mut map) = setup();
+        map.insert(2, 0).unwrap();
+        map.extend((1..=4).rev().map(|i| (i, i * 3))).unwrap();
+        assert_eq!(*map.get(2).unwrap().unwrap(), 6);
+
+        let mut buf = vec![];
+        map.flush(&mut buf).unwrap();
+
+        let entries: Vec<_> = store.range(..).collect::<Result<_>>().unwrap();
+        let expected: Vec<_> = (1..=4).map(|i| (enc(i), enc(i * 3))).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn map_retain() {
+        let (store, mut edit_map) = setup();
+        for i in 1..=6 {
+            edit_map.insert(i, i * 2).unwrap();
+        }
+        let mut buf = vec![];
+        edit_map.flush(&mut buf).unwrap();
+
+        let mut map: Map<u32, u32> = Map::with_store(store).unwrap();
+        map.insert(7, 14).unwrap();
+        map.retain(|k, v| Ok(k % 2 == 1 && *v != 10)).unwrap();
+
+        let keys: Vec<u32> = map.keys().unwrap().map(|k| *k.unwrap()).collect();
+        assert_eq!(keys, vec![1, 3, 7]);
+    }
+
+    #[test]
+    fn map_paginate() {
+        let (_store, mut map) = setup();
+        for i in 1..=5 {
+            map.insert(i, i * 2).unwrap();
+        }
+
+        let mut pages = vec![];
+        let mut cursor = None;
+        loop {
+            let page = map.paginate(cursor, 2).unwrap();
+            pages.push(
+                page.entries
+                    .iter()
+                    .map(|(k, v)| (**k, **v))
+                    .collect::<Vec<_>>(),
+            );
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(
+            pages,
+            vec![vec![(1, 2), (2, 4)], vec![(3, 6), (4, 8)], vec![(5, 10)]]
+        );
+
+        let page = map.paginate(Some(3), 2).unwrap();
+        assert_eq!(page.entries.len(), 2);
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn map_clear() {
+        let mut store = mapstore();
+        store.put(vec![5], vec![1]).unwrap();
+        let mut map: Map<u32, u32> = Map::with_store(store.sub(&[5])).unwrap();
+        for i in 1..=3 {
+            map.insert(i, i).unwrap();
+        }
+        let mut buf = vec![];
+        map.flush(&mut buf).unwrap();
+
+        let mut map: Map<u32, u32> = Map::with_store(store.sub(&[5])).unwrap();
+        map.insert(4, 4).unwrap();
+        map.clear().unwrap();
+        assert!(map.iter().unwrap().next().is_none());
+        map.insert(5, 5).unwrap();
+        map.flush(&mut buf).unwrap();
+
+        let entries: Vec<_> = store.range(..).collect::<Result<_>>().unwrap();
+        assert_eq!(
+            entries,
+            vec![(vec![5], vec![1]), (vec![5, 0, 0, 0, 5], vec![0, 0, 0, 5])]
+        );
+    }
+
+    #[test]
+    fn map_drain() {
+        let (store, mut edit_map) = setup();
+        for i in 1..=4 {
+            edit_map.insert(i, i * 2).unwrap();
+        }
+        let mut buf = vec![];
+        edit_map.flush(&mut buf).unwrap();
+
+        let mut map: Map<u32, u32> = Map::with_store(store).unwrap();
+        map.insert(5, 10).unwrap();
+        map.remove(2).unwrap();
+
+        let drained: Vec<(u32, u32)> = map
+            .drain()
+            .take(3)
+            .map(|entry| entry.map(|(k, v)| (k, *v)).unwrap())
+            .collect();
+        assert_eq!(drained, vec![(1, 2), (3, 6), (4, 8)]);
+
+        let rest: Vec<(u32, u32)> = map
+            .drain()
+            .map(|entry| entry.map(|(k, v)| (k, *v)).unwrap())
+            .collect();
+        assert_eq!(rest, vec![(5, 10)]);
+        assert!(map.iter().unwrap().next().is_none());
+    }
+
     #[test]
     fn map_range_empty() {
         let (_store, map) = setup();
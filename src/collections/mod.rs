@@ -3,13 +3,31 @@ use crate::state::State;
 
 pub use crate::macros::{Entry, Next};
 
+pub mod bitset;
+pub mod bounded_deque;
+pub mod counted_map;
 pub mod deque;
 pub mod entry_map;
+pub mod indexed_map;
+pub mod log;
 pub mod map;
-
+pub mod priority_queue;
+pub mod set;
+pub mod top_n;
+pub mod ttl_map;
+
+pub use bitset::Bitset;
+pub use bounded_deque::BoundedDeque;
+pub use counted_map::CountedMap;
 pub use deque::Deque;
 pub use entry_map::EntryMap;
+pub use indexed_map::{Index, IndexedMap};
+pub use log::Log;
 pub use map::Map;
+pub use priority_queue::PriorityQueue;
+pub use set::Set;
+pub use top_n::TopN;
+pub use ttl_map::TtlMap;
 
 pub use map::{ChildMut, Ref};
 
@@ -0,0 +1,317 @@
+use serde::Serialize;
+
+use super::map::{Iter as MapIter, Map, ReadOnly, Ref};
+use crate::call::FieldCall;
+use crate::describe::Describe;
+use crate::encoding::{Decode, Encode, Terminated};
+use crate::migrate::Migrate;
+use crate::orga;
+use crate::query::FieldQuery;
+use crate::state::State;
+use crate::store::Store;
+use crate::Result;
+
+/// A queue of values ordered by priority, lowest first, e.g. unbondings keyed
+/// by the time they mature.
+///
+/// Values are stored in a [Map] keyed by `(priority, sequence)`, where the
+/// sequence number is incremented on each push, so values with equal
+/// priorities are popped in the order they were pushed. Priorities are
+/// compared by their encodings, so e.g. big-endian unsigned integers order as
+/// expected.
+#[derive(FieldQuery, FieldCall)]
+pub struct PriorityQueue<P, T> {
+    len: u64,
+    next_seq: u64,
+    map: Map<(P, u64), T>,
+}
+
+impl<P, T> PriorityQueue<P, T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<P, T> Default for PriorityQueue<P, T> {
+    fn default() -> Self {
+        PriorityQueue {
+            len: 0,
+            next_seq: 0,
+            map: Map::default(),
+        }
+    }
+}
+
+impl<P, T> std::fmt::Debug for PriorityQueue<P, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PriorityQueue")
+            .field("len", &self.len)
+            .field("next_seq", &self.next_seq)
+            .finish()
+    }
+}
+
+impl<P, T> Terminated for PriorityQueue<P, T> {}
+
+impl<P, T> PriorityQueue<P, T>
+where
+    P: Encode + Decode + Terminated + 'static,
+    T: State,
+{
+    pub fn with_store(store: Store) -> Result<Self> {
+        Ok(Self {
+            len: 0,
+            next_seq: 0,
+            map: Map::with_store(store)?,
+        })
+    }
+}
+
+impl<P, T> State for PriorityQueue<P, T>
+where
+    P: Encode + Terminated + 'static,
+    T: State,
+{
+    fn attach(&mut self, store: Store) -> Result<()> {
+        self.map.attach(store)
+    }
+
+    fn flush<W: std::io::Write>(self, out: &mut W) -> Result<()> {
+        self.len.flush(out)?;
+        self.next_seq.flush(out)?;
+        self.map.flush(out)
+    }
+
+    fn load(store: Store, bytes: &mut &[u8]) -> Result<Self> {
+        let mut value = Self {
+            len: u64::load(store.clone(), bytes)?,
+            next_seq: u64::load(store.clone(), bytes)?,
+            map: Map::load(store.clone(), bytes)?,
+        };
+
+        value.attach(store)?;
+
+        Ok(value)
+    }
+}
+
+impl<P, T> Describe for PriorityQueue<P, T>
+where
+    P: Encode + Decode + Terminated + Clone + Describe + 'static,
+    T: State + Describe,
+{
+    fn describe() -> crate::describe::Descriptor {
+        use crate::describe::Builder;
+        Builder::new::<Self>()
+            .dynamic_child::<(P, u64), T>(|mut query_bytes| {
+                query_bytes.extend_from_slice(&[129]);
+                query_bytes
+            })
+            .build()
+    }
+}
+
+impl<P, T> Migrate for PriorityQueue<P, T>
+where
+    P: Encode + Decode + State + Terminated + Clone + Send + Sync + Migrate,
+    T: State + Migrate,
+{
+    fn migrate(src: Store, dest: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            len: u64::migrate(Store::default(), Store::default(), bytes)?,
+            next_seq: u64::migrate(Store::default(), Store::default(), bytes)?,
+            map: Map::migrate(src, dest, bytes)?,
+        })
+    }
+}
+
+impl<P, T> Serialize for PriorityQueue<P, T>
+where
+    P: Serialize + Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    T: Serialize + State,
+{
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        self.map.serialize(serializer)
+    }
+}
+
+#[orga]
+impl<P, T> PriorityQueue<P, T>
+where
+    P: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    T: State,
+{
+    #[query]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Gets the value stored under a `(priority, sequence)` key.
+    #[query]
+    pub fn get_raw(&self, key: (P, u64)) -> Result<Option<Ref<T>>> {
+        self.map.get(key)
+    }
+
+    #[query]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the value with the lowest priority, along with its priority,
+    /// without removing it.
+    #[query]
+    pub fn peek_min(&self) -> Result<Option<(P, Ref<T>)>> {
+        Ok(match self.map.iter()?.next().transpose()? {
+            Some((key, value)) => Some((key.0.clone(), value)),
+            None => None,
+        })
+    }
+}
+
+impl<P, T> PriorityQueue<P, T>
+where
+    P: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    T: State,
+{
+    /// Adds a value with the given priority.
+    pub fn push(&mut self, priority: P, value: T) -> Result<()> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.len += 1;
+        self.map.insert((priority, seq), value)
+    }
+
+    /// Removes and returns the value with the lowest priority, along with its
+    /// priority. Of values with equal priorities, the first pushed is popped
+    /// first.
+    pub fn pop_min(&mut self) -> Result<Option<(P, ReadOnly<T>)>> {
+        let key = match self.map.iter()?.next().transpose()? {
+            Some((key, _)) => key.clone(),
+            None => return Ok(None),
+        };
+
+        let value = self.map.remove(key.clone())?;
+        self.len -= 1;
+
+        Ok(value.map(|value| (key.0, value)))
+    }
+
+    /// Iterates over the values in the order they would be popped. Keys are
+    /// `(priority, sequence number)` pairs.
+    pub fn iter(&self) -> Result<MapIter<(P, u64), T>> {
+        self.map.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PriorityQueue;
+    use crate::state::State;
+    use crate::store::{MapStore, Shared, Store};
+    use crate::Result;
+
+    fn setup() -> (Store, PriorityQueue<u64, u32>) {
+        let store = mapstore();
+        let queue = PriorityQueue::with_store(store.clone()).unwrap();
+        (store, queue)
+    }
+
+    fn mapstore() -> Store {
+        Store::new(Shared::new(MapStore::new()).into())
+    }
+
+    fn pop_all(queue: &mut PriorityQueue<u64, u32>) -> Result<Vec<(u64, u32)>> {
+        std::iter::from_fn(|| queue.pop_min().transpose())
+            .map(|entry| entry.map(|(priority, value)| (priority, *value)))
+            .collect()
+    }
+
+    #[test]
+    fn empty() -> Result<()> {
+        let (_, mut queue) = setup();
+        assert!(queue.is_empty());
+        assert!(queue.peek_min()?.is_none());
+        assert!(queue.pop_min()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn peek_min() -> Result<()> {
+        let (_, mut queue) = setup();
+        queue.push(20, 1)?;
+        queue.push(10, 2)?;
+
+        let (priority, value) = queue.peek_min()?.unwrap();
+        assert_eq!((priority, *value), (10, 2));
+        assert_eq!(queue.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pops_lowest_priority_first() -> Result<()> {
+        let (_, mut queue) = setup();
+        queue.push(20, 1)?;
+        queue.push(10, 2)?;
+        queue.push(15, 3)?;
+
+        assert_eq!(pop_all(&mut queue)?, vec![(10, 2), (15, 3), (20, 1)]);
+        assert!(queue.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn equal_priorities_pop_in_push_order() -> Result<()> {
+        let (_, mut queue) = setup();
+        queue.push(20, 1)?;
+        queue.push(20, 2)?;
+        queue.push(20, 3)?;
+
+        assert_eq!(pop_all(&mut queue)?, vec![(20, 1), (20, 2), (20, 3)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_load() -> Result<()> {
+        let (store, mut queue) = setup();
+        queue.push(20, 1)?;
+        queue.push(10, 2)?;
+
+        let mut bytes = vec![];
+        queue.flush(&mut bytes)?;
+        let mut queue: PriorityQueue<u64, u32> = PriorityQueue::load(store, &mut bytes.as_slice())?;
+
+        assert_eq!(queue.len(), 2);
+        queue.push(20, 3)?;
+        assert_eq!(pop_all(&mut queue)?, vec![(10, 2), (20, 1), (20, 3)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn describe_resolves_get_raw() -> Result<()> {
+        use super::PriorityQueueMethodQuery;
+        use crate::describe::Describe;
+        use crate::encoding::{Decode, Encode};
+        use crate::query::{Item, Query};
+        use std::any::TypeId;
+
+        type Queue = PriorityQueue<u64, u32>;
+        let key = (20u64, 1u64).encode()?;
+        let query_bytes =
+            Queue::describe().resolve_by_type_id(TypeId::of::<u32>(), &key, vec![], vec![])?;
+        let query = <Queue as Query>::Query::decode(query_bytes.as_slice())?;
+        assert!(matches!(
+            query,
+            Item::Method(PriorityQueueMethodQuery::GetRaw((20, 1), _))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use serde::Serialize;
+use std::ops::RangeBounds;
+
+use super::map::{Iter as MapIter, Map};
+use crate::call::FieldCall;
+use crate::describe::Describe;
+use crate::encoding::{Decode, Encode, Terminated};
+use crate::migrate::Migrate;
+use crate::orga;
+use crate::query::FieldQuery;
+use crate::state::State;
+use crate::store::Store;
+use crate::Result;
+
+/// A set of keys, backed by a [Map] with empty values.
+///
+/// The store layout is the same as `Map<K, ()>`, so a `Map<K, ()>` field can
+/// be changed to a `Set<K>` without migrating its state.
+#[derive(FieldQuery, FieldCall)]
+pub struct Set<K> {
+    map: Map<K, ()>,
+}
+
+impl<K> Set<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K: Encode + Decode + Terminated + 'static> Set<K> {
+    pub fn with_store(store: Store) -> Result<Self> {
+        Ok(Self {
+            map: Map::with_store(store)?,
+        })
+    }
+}
+
+impl<K> Default for Set<K> {
+    fn default() -> Self {
+        Set {
+            map: Map::default(),
+        }
+    }
+}
+
+impl<K> std::fmt::Debug for Set<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Set").finish()
+    }
+}
+
+impl<K> Terminated for Set<K> {}
+
+impl<K> State for Set<K>
+where
+    K: Encode + Terminated + 'static,
+{
+    fn attach(&mut self, store: Store) -> Result<()> {
+        self.map.attach(store)
+    }
+
+    fn flush<W: std::io::Write>(self, out: &mut W) -> Result<()> {
+        self.map.flush(out)
+    }
+
+    fn load(store: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            map: Map::load(store, bytes)?,
+        })
+    }
+}
+
+impl<K> Describe for Set<K>
+where
+    K: Encode + Terminated + Clone + 'static,
+{
+    fn describe() -> crate::describe::Descriptor {
+        use crate::describe::Builder;
+        // members have no value to query, so a set is described as a leaf
+        Builder::new::<Self>().build()
+    }
+}
+
+impl<K> Migrate for Set<K>
+where
+    K: Encode + Decode + State + Terminated + Clone + Send + Sync + Migrate,
+{
+    fn migrate(src: Store, dest: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            map: Map::migrate(src, dest, bytes)?,
+        })
+    }
+}
+
+impl<K> Serialize for Set<K>
+where
+    K: Serialize + Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeSeq};
+        let mut seq = serializer.serialize_seq(None)?;
+        for key in self.iter().map_err(Error::custom)? {
+            let key = key.map_err(Error::custom)?;
+            seq.serialize_element(&key)?;
+        }
+        seq.end()
+    }
+}
+
+#[orga]
+impl<K> Set<K>
+where
+    K: Encode + Terminated + Clone + Send + Sync + 'static,
+{
+    #[query]
+    pub fn contains(&self, key: K) -> Result<bool> {
+        self.map.contains_key(key)
+    }
+
+    /// Adds the key to the set, returning `true` if it was not already
+    /// present.
+    pub fn insert(&mut self, key: K) -> Result<bool> {
+        if self.map.contains_key(key.clone())? {
+            return Ok(false);
+        }
+
+        self.map.insert(key, ())?;
+        Ok(true)
+    }
+
+    /// Removes the key from the set, returning `true` if it was present.
+    pub fn remove(&mut self, key: K) -> Result<bool> {
+        Ok(self.map.remove(key)?.is_some())
+    }
+}
+
+impl<'a, K> Set<K>
+where
+    K: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    /// Iterates over the keys in the set, in ascending order of their
+    /// encodings.
+    pub fn iter(&'a self) -> Result<Iter<'a, K>> {
+        Ok(Iter {
+            map_iter: self.map.iter()?,
+        })
+    }
+
+    /// Iterates over the keys in the given range.
+    pub fn range<B: RangeBounds<K>>(&'a self, range: B) -> Result<Iter<'a, K>> {
+        Ok(Iter {
+            map_iter: self.map.range(range)?,
+        })
+    }
+}
+
+/// An iterator over the keys of a [Set].
+pub struct Iter<'a, K>
+where
+    K: Decode + Encode + Terminated,
+{
+    map_iter: MapIter<'a, K, ()>,
+}
+
+impl<'a, K> Iterator for Iter<'a, K>
+where
+    K: Decode + Encode + Terminated + Clone,
+{
+    type Item = Result<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.map_iter
+            .next()
+            .map(|entry| entry.map(|(key, _)| key.clone()))
+    }
+}
+
+impl<'a, K> DoubleEndedIterator for Iter<'a, K>
+where
+    K: Decode + Encode + Terminated + Clone,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.map_iter
+            .next_back()
+            .map(|entry| entry.map(|(key, _)| key.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Set;
+    use crate::state::State;
+    use crate::store::{MapStore, Shared, Store};
+    use crate::Result;
+
+    fn setup() -> (Store, Set<u32>) {
+        let store = mapstore();
+        let set = Set::with_store(store.clone()).unwrap();
+        (store, set)
+    }
+
+    fn mapstore() -> Store {
+        Store::new(Shared::new(MapStore::new()).into())
+    }
+
+    #[test]
+    fn insert() -> Result<()> {
+        let (_, mut set) = setup();
+        assert!(set.insert(3)?);
+        assert!(!set.insert(3)?);
+        assert!(set.contains(3)?);
+        assert!(!set.contains(2)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove() -> Result<()> {
+        let (_, mut set) = setup();
+        set.insert(1)?;
+
+        assert!(set.remove(1)?);
+        assert!(!set.remove(1)?);
+        assert!(!set.contains(1)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_load() -> Result<()> {
+        let (store, mut set) = setup();
+        set.insert(1)?;
+
+        let mut bytes = vec![];
+        set.flush(&mut bytes)?;
+        assert!(bytes.is_empty());
+        let set: Set<u32> = Set::load(store, &mut bytes.as_slice())?;
+
+        assert!(set.contains(1)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn iter() -> Result<()> {
+        let (_, mut set) = setup();
+        set.insert(3)?;
+        set.insert(2)?;
+
+        assert_eq!(set.iter()?.collect::<Result<Vec<_>>>()?, vec![2, 3]);
+        assert_eq!(set.iter()?.rev().collect::<Result<Vec<_>>>()?, vec![3, 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn range() -> Result<()> {
+        let (_, mut set) = setup();
+        set.insert(1)?;
+        set.insert(3)?;
+
+        assert_eq!(set.range(2..)?.collect::<Result<Vec<_>>>()?, vec![3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn describe_leaf() {
+        use crate::describe::{Children, Describe};
+
+        let desc = Set::<u32>::describe();
+        assert!(matches!(desc.children(), Children::None));
+    }
+}
@@ -0,0 +1,317 @@
+use serde::Serialize;
+
+use super::indexed_map::{Index, IndexedMap};
+use super::map::{Map, ReadOnly, Ref};
+use super::set::Set;
+use crate::call::FieldCall;
+use crate::describe::Describe;
+use crate::encoding::{Decode, Encode, Terminated};
+use crate::migrate::Migrate;
+use crate::orga;
+use crate::query::FieldQuery;
+use crate::state::State;
+use crate::store::Store;
+use crate::Result;
+
+/// Indexes the entries of a [TopN] by their weight.
+pub struct ByWeight;
+
+impl<K, W> Index<K, W> for ByWeight
+where
+    W: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    type Key = W;
+
+    fn index_key(_key: &K, weight: &W) -> W {
+        weight.clone()
+    }
+}
+
+/// Weighted candidates, of which the `capacity` with the largest weights can
+/// be read without scanning the rest, e.g. selecting the bonded validator set
+/// by voting power in `end_block`.
+///
+/// Candidates are kept in an [IndexedMap] indexed by weight, so setting a
+/// weight only touches the candidate's own entries, and reading the top
+/// entries only reads `capacity` entries of the index. Weights are compared
+/// by their encodings (so e.g. unsigned integers order as expected), and
+/// candidates with equal weights are ordered by key, largest first.
+#[derive(FieldQuery, FieldCall)]
+pub struct TopN<K, W>
+where
+    W: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    capacity: u64,
+    entries: IndexedMap<K, W, ByWeight>,
+}
+
+impl<K, W> TopN<K, W>
+where
+    W: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            entries: IndexedMap::default(),
+        }
+    }
+}
+
+impl<K, W> TopN<K, W>
+where
+    K: Encode + Decode + Terminated + 'static,
+    W: State + Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    pub fn with_store(store: Store, capacity: u64) -> Result<Self> {
+        Ok(Self {
+            capacity,
+            entries: IndexedMap::with_store(store)?,
+        })
+    }
+}
+
+impl<K, W> Default for TopN<K, W>
+where
+    W: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl<K, W> std::fmt::Debug for TopN<K, W>
+where
+    W: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TopN")
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl<K, W> Serialize for TopN<K, W>
+where
+    K: Serialize + Encode + Decode + Terminated + Clone + 'static,
+    W: Serialize + State + Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        self.entries.serialize(serializer)
+    }
+}
+
+impl<K, W> Terminated for TopN<K, W> where
+    W: Encode + Decode + Terminated + Clone + Send + Sync + 'static
+{
+}
+
+impl<K, W> State for TopN<K, W>
+where
+    K: Encode + Terminated + 'static,
+    W: State + Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    fn attach(&mut self, store: Store) -> Result<()> {
+        self.entries.attach(store)
+    }
+
+    fn flush<Wr: std::io::Write>(self, out: &mut Wr) -> Result<()> {
+        self.capacity.flush(out)?;
+        self.entries.flush(out)
+    }
+
+    fn load(store: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            capacity: u64::load(store.clone(), bytes)?,
+            entries: IndexedMap::load(store, bytes)?,
+        })
+    }
+}
+
+impl<K, W> Describe for TopN<K, W>
+where
+    K: Encode + Decode + Terminated + Clone + Describe + 'static,
+    W: State + Describe + Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    fn describe() -> crate::describe::Descriptor {
+        use crate::describe::Builder;
+        Builder::new::<Self>()
+            .named_child::<Map<K, W>>("weights", &[0])
+            .named_child::<Set<(W, K)>>("index", &[1])
+            .build()
+    }
+}
+
+impl<K, W> Migrate for TopN<K, W>
+where
+    K: Encode + Decode + State + Terminated + Clone + Send + Sync + Migrate,
+    W: State + Migrate + Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    fn migrate(src: Store, dest: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            capacity: u64::migrate(Store::default(), Store::default(), bytes)?,
+            entries: IndexedMap::migrate(src, dest, bytes)?,
+        })
+    }
+}
+
+#[orga]
+impl<K, W> TopN<K, W>
+where
+    K: Encode + Terminated + Clone + Send + Sync + 'static,
+    W: State + Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    #[query]
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    #[query]
+    pub fn weight(&self, key: K) -> Result<Option<Ref<W>>> {
+        self.entries.get(key)
+    }
+}
+
+impl<K, W> TopN<K, W>
+where
+    K: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    W: State + Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+{
+    /// Sets the number of candidates returned by [top](Self::top).
+    pub fn set_capacity(&mut self, capacity: u64) {
+        self.capacity = capacity;
+    }
+
+    /// Adds a candidate or updates its weight.
+    pub fn set_weight(&mut self, key: K, weight: W) -> Result<()> {
+        self.entries.insert(key, weight)
+    }
+
+    /// Removes a candidate, returning its weight if it existed.
+    pub fn remove(&mut self, key: K) -> Result<Option<ReadOnly<W>>> {
+        self.entries.remove(key)
+    }
+
+    /// Returns the `capacity` candidates with the largest weights, largest
+    /// first, reading only their entries of the index.
+    pub fn top(&self) -> Result<Vec<(K, W)>> {
+        self.entries
+            .iter_index()?
+            .rev()
+            .take(self.capacity as usize)
+            .map(|entry| entry.map(|(weight, key)| (key, weight)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TopN;
+    use crate::state::State;
+    use crate::store::{MapStore, Shared, Store};
+    use crate::Result;
+
+    fn setup() -> (Store, TopN<u32, u64>) {
+        let store = mapstore();
+        let top = TopN::with_store(store.clone(), 2).unwrap();
+        (store, top)
+    }
+
+    fn mapstore() -> Store {
+        Store::new(Shared::new(MapStore::new()).into())
+    }
+
+    #[test]
+    fn empty() -> Result<()> {
+        let (_, top) = setup();
+        assert!(top.top()?.is_empty());
+        assert!(top.weight(1)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn top() -> Result<()> {
+        let (_, mut top) = setup();
+        top.set_weight(1, 100)?;
+        top.set_weight(2, 300)?;
+        top.set_weight(3, 200)?;
+
+        assert_eq!(top.top()?, vec![(2, 300), (3, 200)]);
+        assert_eq!(*top.weight(1)?.unwrap(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn equal_weights_order_by_key() -> Result<()> {
+        let (_, mut top) = setup();
+        top.set_weight(1, 100)?;
+        top.set_weight(3, 100)?;
+        top.set_weight(2, 100)?;
+
+        assert_eq!(top.top()?, vec![(3, 100), (2, 100)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_weight_updates() -> Result<()> {
+        let (_, mut top) = setup();
+        top.set_weight(1, 100)?;
+        top.set_weight(2, 300)?;
+        top.set_weight(3, 200)?;
+        top.set_weight(2, 50)?;
+
+        assert_eq!(top.top()?, vec![(3, 200), (1, 100)]);
+        assert_eq!(*top.weight(2)?.unwrap(), 50);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove() -> Result<()> {
+        let (_, mut top) = setup();
+        top.set_weight(1, 100)?;
+        top.set_weight(2, 300)?;
+
+        assert_eq!(*top.remove(2)?.unwrap(), 300);
+        assert!(top.remove(2)?.is_none());
+        assert_eq!(top.top()?, vec![(1, 100)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_capacity() -> Result<()> {
+        let (_, mut top) = setup();
+        top.set_weight(1, 100)?;
+        top.set_weight(2, 300)?;
+        top.set_weight(3, 200)?;
+
+        top.set_capacity(3);
+        assert_eq!(top.top()?, vec![(2, 300), (3, 200), (1, 100)]);
+        top.set_capacity(0);
+        assert!(top.top()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_load() -> Result<()> {
+        let (store, mut top) = setup();
+        top.set_weight(1, 100)?;
+        top.set_weight(2, 300)?;
+
+        let mut bytes = vec![];
+        top.flush(&mut bytes)?;
+        let top: TopN<u32, u64> = TopN::load(store, &mut bytes.as_slice())?;
+
+        assert_eq!(top.capacity(), 2);
+        assert_eq!(top.top()?, vec![(2, 300), (1, 100)]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+use serde::Serialize;
+
+use super::map::{Iter as MapIter, Map, ReadOnly, Ref};
+use super::set::Set;
+use crate::call::FieldCall;
+use crate::describe::Describe;
+use crate::encoding::{Decode, Encode, Terminated};
+use crate::migrate::Migrate;
+use crate::orga;
+use crate::plugins::BeginBlockCtx;
+use crate::query::FieldQuery;
+use crate::state::State;
+use crate::store::Store;
+use crate::Result;
+
+/// A [Map] whose entries expire at a given block height, e.g. pending swaps
+/// or session keys which would otherwise be left in state forever.
+///
+/// Expired entries are removed by [purge](Self::purge), which should be called
+/// from `begin_block`. Until then they can still be read, so reads stay
+/// deterministic within a block.
+#[derive(FieldQuery, FieldCall)]
+pub struct TtlMap<K, V> {
+    values: Map<K, V>,
+    expirations: Map<K, u64>,
+    queue: Set<(u64, K)>,
+}
+
+impl<K, V> TtlMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K, V> Default for TtlMap<K, V> {
+    fn default() -> Self {
+        TtlMap {
+            values: Map::default(),
+            expirations: Map::default(),
+            queue: Set::default(),
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for TtlMap<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TtlMap").finish()
+    }
+}
+
+impl<K, V> Terminated for TtlMap<K, V> {}
+
+impl<K, V> TtlMap<K, V>
+where
+    K: Encode + Decode + Terminated + 'static,
+    V: State,
+{
+    pub fn with_store(store: Store) -> Result<Self> {
+        let mut map = Self::default();
+        map.attach(store)?;
+
+        Ok(map)
+    }
+}
+
+impl<K, V> State for TtlMap<K, V>
+where
+    K: Encode + Terminated + 'static,
+    V: State,
+{
+    fn attach(&mut self, store: Store) -> Result<()> {
+        self.values.attach(store.sub(&[0]))?;
+        self.expirations.attach(store.sub(&[1]))?;
+        self.queue.attach(store.sub(&[2]))
+    }
+
+    fn flush<W: std::io::Write>(self, out: &mut W) -> Result<()> {
+        self.values.flush(out)?;
+        self.expirations.flush(out)?;
+        self.queue.flush(out)
+    }
+
+    fn load(store: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            values: Map::load(store.sub(&[0]), bytes)?,
+            expirations: Map::load(store.sub(&[1]), bytes)?,
+            queue: Set::load(store.sub(&[2]), bytes)?,
+        })
+    }
+}
+
+impl<K, V> Describe for TtlMap<K, V>
+where
+    K: Encode + Decode + Terminated + Clone + Describe + 'static,
+    V: State + Describe,
+{
+    fn describe() -> crate::describe::Descriptor {
+        use crate::describe::Builder;
+        Builder::new::<Self>()
+            .named_child::<Map<K, V>>("values", &[0])
+            .named_child::<Map<K, u64>>("expirations", &[1])
+            .named_child::<Set<(u64, K)>>("queue", &[2])
+            .build()
+    }
+}
+
+impl<K, V> Migrate for TtlMap<K, V>
+where
+    K: Encode + Decode + State + Terminated + Clone + Send + Sync + Migrate,
+    V: State + Migrate,
+{
+    fn migrate(src: Store, dest: Store, bytes: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            values: Map::migrate(src.sub(&[0]), dest.sub(&[0]), bytes)?,
+            expirations: Map::migrate(src.sub(&[1]), dest.sub(&[1]), bytes)?,
+            queue: Set::migrate(src.sub(&[2]), dest.sub(&[2]), bytes)?,
+        })
+    }
+}
+
+impl<K, V> Serialize for TtlMap<K, V>
+where
+    K: Serialize + Encode + Decode + Terminated + Clone + 'static,
+    V: Serialize + State,
+{
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        self.values.serialize(serializer)
+    }
+}
+
+#[orga]
+impl<K, V> TtlMap<K, V>
+where
+    K: Encode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+{
+    #[query]
+    pub fn contains_key(&self, key: K) -> Result<bool> {
+        self.values.contains_key(key)
+    }
+
+    #[query]
+    pub fn get(&self, key: K) -> Result<Option<Ref<V>>> {
+        self.values.get(key)
+    }
+
+    /// The height at which the entry for the given key expires, if any.
+    #[query]
+    pub fn expiration(&self, key: K) -> Result<Option<u64>> {
+        Ok(self.expirations.get(key)?.map(|height| *height))
+    }
+}
+
+impl<K, V> TtlMap<K, V>
+where
+    K: Encode + Decode + Terminated + Clone + Send + Sync + 'static,
+    V: State,
+{
+    /// Inserts a value which will be removed by the first [purge](Self::purge)
+    /// at or after height `expires_at`, replacing any existing value and its
+    /// expiration.
+    pub fn insert(&mut self, key: K, value: V, expires_at: u64) -> Result<()> {
+        self.remove(key.clone())?;
+        self.queue.insert((expires_at, key.clone()))?;
+        self.expirations.insert(key.clone(), expires_at)?;
+        self.values.insert(key, value)
+    }
+
+    /// Removes the value at the given key before it expires, if any.
+    pub fn remove(&mut self, key: K) -> Result<Option<ReadOnly<V>>> {
+        if let Some(expires_at) = self.expirations.remove(key.clone())? {
+            self.queue.remove((*expires_at, key.clone()))?;
+        }
+
+        self.values.remove(key)
+    }
+
+    /// Removes the entries which have expired as of the block being begun,
+    /// returning how many were removed.
+    pub fn purge(&mut self, ctx: &BeginBlockCtx) -> Result<u64> {
+        self.purge_expired(ctx.height)
+    }
+
+    /// Removes the entries which expire at or before `height`, returning how
+    /// many were removed.
+    pub fn purge_expired(&mut self, height: u64) -> Result<u64> {
+        let mut purged = 0;
+        loop {
+            let (expires_at, key) = match self.queue.iter()?.next().transpose()? {
+                Some(entry) => entry,
+                None => break,
+            };
+            if expires_at > height {
+                break;
+            }
+
+            self.remove(key)?;
+            purged += 1;
+        }
+
+        Ok(purged)
+    }
+
+    /// Iterates over the entries in order of their keys, including expired
+    /// entries which have not been purged yet.
+    pub fn iter(&self) -> Result<MapIter<K, V>> {
+        self.values.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TtlMap;
+    use crate::state::State;
+    use crate::store::{MapStore, Shared, Store};
+    use crate::Result;
+
+    fn setup() -> (Store, TtlMap<u32, u32>) {
+        let store = mapstore();
+        let map = TtlMap::with_store(store.clone()).unwrap();
+        (store, map)
+    }
+
+    fn mapstore() -> Store {
+        Store::new(Shared::new(MapStore::new()).into())
+    }
+
+    #[test]
+    fn insert() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 10, 5)?;
+
+        assert!(map.contains_key(1)?);
+        assert_eq!(*map.get(1)?.unwrap(), 10);
+        assert_eq!(map.expiration(1)?, Some(5));
+        assert_eq!(map.expiration(2)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn insert_replaces_expiration() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 10, 3)?;
+        map.insert(1, 11, 8)?;
+
+        assert_eq!(map.expiration(1)?, Some(8));
+        assert_eq!(map.purge_expired(5)?, 0);
+        assert_eq!(*map.get(1)?.unwrap(), 11);
+
+        Ok(())
+    }
+
+    #[test]
+    fn purge_expired() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 10, 5)?;
+        map.insert(2, 20, 3)?;
+        map.insert(3, 30, 8)?;
+
+        assert_eq!(map.purge_expired(2)?, 0);
+        assert_eq!(map.purge_expired(5)?, 2);
+        assert!(!map.contains_key(1)?);
+        assert!(!map.contains_key(2)?);
+        assert_eq!(map.expiration(1)?, None);
+        assert_eq!(*map.get(3)?.unwrap(), 30);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove() -> Result<()> {
+        let (_, mut map) = setup();
+        map.insert(1, 10, 5)?;
+
+        assert_eq!(*map.remove(1)?.unwrap(), 10);
+        assert!(map.remove(1)?.is_none());
+        assert_eq!(map.expiration(1)?, None);
+        assert_eq!(map.purge_expired(u64::MAX)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_load() -> Result<()> {
+        let (store, mut map) = setup();
+        map.insert(1, 10, 5)?;
+
+        let mut bytes = vec![];
+        map.flush(&mut bytes)?;
+        let mut map: TtlMap<u32, u32> = TtlMap::load(store, &mut bytes.as_slice())?;
+
+        assert_eq!(*map.get(1)?.unwrap(), 10);
+        assert_eq!(map.purge_expired(5)?, 1);
+
+        Ok(())
+    }
+}
@@ -1,45 +1,133 @@
 use crate::state::State;
 use std::any::TypeId;
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::mem::{transmute, ManuallyDrop};
+use std::mem::ManuallyDrop;
 use std::sync::LazyLock;
 use std::sync::Mutex;
 
-type ContextMap = ManuallyDrop<HashMap<TypeId, Box<()>>>;
-static CONTEXT_MAP: LazyLock<Mutex<ContextMap>> =
+type ContextMap = HashMap<TypeId, Entry>;
+static CONTEXT_MAP: LazyLock<Mutex<ManuallyDrop<ContextMap>>> =
     LazyLock::new(|| Mutex::new(ManuallyDrop::new(HashMap::new())));
 
+thread_local! {
+    /// The context used instead of the global one while running
+    /// [Context::isolated] on this thread.
+    static ISOLATED: RefCell<Option<ContextMap>> = const { RefCell::new(None) };
+}
+
+/// A type-erased context, along with the function which drops it as its
+/// original type, and for inherited contexts the function which clones it
+/// into an isolated context.
+struct Entry {
+    ctx: ManuallyDrop<Box<()>>,
+    drop: unsafe fn(Box<()>),
+    inherit: Option<unsafe fn(&Entry) -> Entry>,
+}
+
+unsafe fn drop_ctx<T>(ctx: Box<()>) {
+    drop(Box::from_raw(Box::into_raw(ctx) as *mut T));
+}
+
+unsafe fn inherit_ctx<T: Clone + Send + Sync + 'static>(entry: &Entry) -> Entry {
+    let ctx = &*(&**entry.ctx as *const () as *const T);
+    Entry::inherited(ctx.clone())
+}
+
+impl Entry {
+    fn new<T: 'static>(ctx: T) -> Self {
+        let raw = Box::into_raw(Box::new(ctx)) as *mut ();
+        Self {
+            ctx: ManuallyDrop::new(unsafe { Box::from_raw(raw) }),
+            drop: drop_ctx::<T>,
+            inherit: None,
+        }
+    }
+
+    fn inherited<T: Clone + Send + Sync + 'static>(ctx: T) -> Self {
+        let mut entry = Self::new(ctx);
+        entry.inherit = Some(inherit_ctx::<T>);
+        entry
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(ManuallyDrop::take(&mut self.ctx)) }
+    }
+}
+
+/// Runs `op` on the current thread's isolated context if there is one,
+/// otherwise on the global context.
+fn with_map<R>(op: impl FnOnce(&mut ContextMap) -> R) -> R {
+    ISOLATED.with(|isolated| match isolated.borrow_mut().as_mut() {
+        Some(map) => op(map),
+        None => op(&mut CONTEXT_MAP.lock().unwrap()),
+    })
+}
+
 pub struct Context<I> {
     _inner: I,
 }
 
 impl Context<()> {
     pub fn add<T: 'static>(ctx: T) {
-        let mut context_store = CONTEXT_MAP.lock().unwrap();
-        let id = TypeId::of::<T>();
-        let boxed_ctx = Box::new(ctx);
-        let raw = unsafe { transmute::<_, Box<()>>(boxed_ctx) };
-        let replaced = context_store.insert(id, raw);
-        if let Some(replaced) = replaced {
-            unsafe { transmute::<_, Box<T>>(replaced) };
-        }
+        Self::insert::<T>(Entry::new(ctx));
+    }
+
+    /// Adds a context which is also copied into every [Context::isolated]
+    /// context created while it is set, for values such as the
+    /// [ChainId](crate::plugins::ChainId) which queries answered on other
+    /// threads still need.
+    pub fn add_inherited<T: Clone + Send + Sync + 'static>(ctx: T) {
+        Self::insert::<T>(Entry::inherited(ctx));
+    }
+
+    fn insert<T: 'static>(entry: Entry) {
+        let replaced = with_map(|map| map.insert(TypeId::of::<T>(), entry));
+        // dropped outside the lock, in case its destructor uses the context
+        drop(replaced);
     }
 
     pub fn resolve<'a, T: 'static>() -> Option<&'a mut T> {
-        let mut context_store = CONTEXT_MAP.lock().unwrap();
-        let id = TypeId::of::<T>();
-        let boxed_ctx = context_store.get_mut(&id);
-        match boxed_ctx {
-            Some(ctx) => unsafe { Some(transmute::<_, &'a mut Box<T>>(ctx)) },
-            None => None,
-        }
+        with_map(|map| {
+            map.get_mut(&TypeId::of::<T>())
+                .map(|entry| unsafe { &mut *(&mut **entry.ctx as *mut () as *mut T) })
+        })
     }
 
     pub fn remove<T: 'static>() {
-        let mut context_store = CONTEXT_MAP.lock().unwrap();
-        if let Some(replaced) = context_store.remove(&TypeId::of::<T>()) {
-            unsafe { transmute::<_, Box<T>>(replaced) };
+        let removed = with_map(|map| map.remove(&TypeId::of::<T>()));
+        drop(removed);
+    }
+
+    /// Runs `op` with a separate context for the current thread, which is
+    /// dropped when `op` returns. Code run this way can't observe or modify
+    /// the global context, e.g. a query answered on another thread won't
+    /// charge gas to the tx being delivered. The isolated context starts with
+    /// copies of the contexts added with [Context::add_inherited], and is
+    /// otherwise empty.
+    pub fn isolated<R>(op: impl FnOnce() -> R) -> R {
+        struct Restore(Option<ContextMap>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let outer = self.0.take();
+                let isolated = ISOLATED.with(|isolated| isolated.replace(outer));
+                drop(isolated);
+            }
         }
+
+        let inherited: ContextMap = with_map(|map| {
+            map.iter()
+                .filter_map(|(type_id, entry)| {
+                    let inherit = entry.inherit?;
+                    Some((*type_id, unsafe { inherit(entry) }))
+                })
+                .collect()
+        });
+        let outer = ISOLATED.with(|isolated| isolated.replace(Some(inherited)));
+        let _restore = Restore(outer);
+        op()
     }
 }
 
@@ -99,4 +187,48 @@ mod tests {
         let resolved_e = Context::resolve::<ContextD<Vec<i32>>>().unwrap();
         assert_eq!(resolved_e.inner, vec![1, 2, 3, 4]);
     }
+
+    #[test]
+    fn isolated() {
+        struct ContextE(u32);
+
+        Context::add(ContextE(1));
+        let inner = Context::isolated(|| {
+            assert!(Context::resolve::<ContextE>().is_none());
+            Context::add(ContextE(2));
+            Context::resolve::<ContextE>().unwrap().0
+        });
+        assert_eq!(inner, 2);
+        assert_eq!(Context::resolve::<ContextE>().unwrap().0, 1);
+
+        std::thread::spawn(|| Context::isolated(|| Context::remove::<ContextE>()))
+            .join()
+            .unwrap();
+        assert_eq!(Context::resolve::<ContextE>().unwrap().0, 1);
+        Context::remove::<ContextE>();
+    }
+
+    #[test]
+    fn isolated_inherits() {
+        #[derive(Clone)]
+        struct ContextF(u32);
+
+        Context::add_inherited(ContextF(1));
+        let inner = std::thread::spawn(|| {
+            Context::isolated(|| {
+                let ctx = Context::resolve::<ContextF>().unwrap();
+                ctx.0 += 1;
+                ctx.0
+            })
+        })
+        .join()
+        .unwrap();
+        assert_eq!(inner, 2);
+        assert_eq!(Context::resolve::<ContextF>().unwrap().0, 1);
+
+        Context::remove::<ContextF>();
+        assert!(Context::isolated(
+            || Context::resolve::<ContextF>().is_none()
+        ));
+    }
 }
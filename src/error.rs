@@ -15,6 +15,14 @@ pub enum Error {
     Call(String),
     #[error("Client Error: {0}")]
     Client(String),
+    /// An app-defined error which is reported in tx results with the given
+    /// `(codespace, code)` pair, see [Error::code].
+    #[error("{message}")]
+    Coded {
+        codespace: &'static str,
+        code: u32,
+        message: String,
+    },
     #[error("Coins Error: {0}")]
     Coins(String),
     #[error(transparent)]
@@ -34,6 +42,10 @@ pub enum Error {
     IbcContext(#[from] ibc::core::ContextError),
     #[error("Invalid ID")]
     InvalidID,
+    /// Returned from `EndBlock` when the app detects corrupted state. The node
+    /// refuses to commit the block and halts rather than persisting it.
+    #[error("Invariant Violation: {0}")]
+    InvariantViolation(String),
     #[error(transparent)]
     IO(#[from] std::io::Error),
     #[cfg(feature = "merk-verify")]
@@ -43,6 +55,8 @@ pub enum Error {
     Migrate(String),
     #[error("Nonce Error: {0}")]
     Nonce(String),
+    #[error("Out of Gas: {0}")]
+    OutOfGas(String),
     #[error("Overflow Error")]
     Overflow,
     #[error("Parse Int Error: {0}")]
@@ -81,5 +95,99 @@ pub enum Error {
     Unknown,
 }
 
+/// The codespace reported in tx results for errors raised by orga itself.
+pub const CODESPACE: &str = "orga";
+
+impl Error {
+    /// Creates an app-defined error which is reported in tx results as
+    /// `(codespace, code)`. Apps should keep their codes stable across
+    /// releases, and `code` must not be 0 (which signals success).
+    pub fn coded(codespace: &'static str, code: u32, message: impl Into<String>) -> Self {
+        Error::Coded {
+            codespace,
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Returns the `(codespace, code)` pair reported in `ResponseDeliverTx`
+    /// and `ResponseCheckTx` when a tx fails with this error.
+    ///
+    /// The codes of orga's own variants are stable and do not depend on which
+    /// features are enabled, so clients can match on them rather than parsing
+    /// the log. Code 1 is reserved for unknown errors.
+    pub fn code(&self) -> (&'static str, u32) {
+        let code = match self {
+            Error::Coded {
+                codespace, code, ..
+            } => return (codespace, *code),
+            Error::Unknown => 1,
+            #[cfg(feature = "abci")]
+            Error::ABCI(_) => 2,
+            #[cfg(feature = "abci")]
+            Error::ABCI2(_) => 3,
+            Error::App(_) => 4,
+            Error::Call(_) => 5,
+            Error::Client(_) => 6,
+            Error::Coins(_) => 7,
+            Error::Dalek(_) => 8,
+            Error::Decimal(_) => 9,
+            Error::DivideByZero => 10,
+            Error::Downcast(_) => 11,
+            Error::Ed(_) => 12,
+            Error::Ibc(_) => 13,
+            #[cfg(feature = "ibc")]
+            Error::IbcContext(_) => 14,
+            Error::InvalidID => 15,
+            Error::InvariantViolation(_) => 16,
+            Error::IO(_) => 17,
+            #[cfg(feature = "merk-verify")]
+            Error::Merk(_) => 18,
+            Error::Migrate(_) => 19,
+            Error::Nonce(_) => 20,
+            Error::OutOfGas(_) => 21,
+            Error::Overflow => 22,
+            Error::ParseInt(_) => 23,
+            Error::Poison(_) => 24,
+            Error::Tendermint(_) => 25,
+            #[cfg(feature = "abci")]
+            Error::TendermintRPC(_) => 26,
+            Error::TryFromInt(_) => 27,
+            #[cfg(feature = "merk-full")]
+            Error::RocksDB(_) => 28,
+            Error::Secp256k1(_) => 29,
+            Error::SerdeJson(_) => 30,
+            Error::Signer(_) => 31,
+            Error::Store(_) => 32,
+            Error::StoreErr(_) => 33,
+            Error::State(_) => 34,
+            Error::Test(_) => 35,
+            Error::Query(_) => 36,
+            Error::Upgrade(_) => 37,
+        };
+
+        (CODESPACE, code)
+    }
+}
+
 /// A result type bound to the standard orga error type.
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        assert_eq!(
+            Error::Coins("Insufficient funds".into()).code(),
+            ("orga", 7)
+        );
+        assert_eq!(Error::Nonce("Nonce is too low".into()).code(), ("orga", 20));
+        assert_eq!(Error::Unknown.code(), ("orga", 1));
+
+        let err = Error::coded("bank", 3, "Account is frozen");
+        assert_eq!(err.code(), ("bank", 3));
+        assert_eq!(err.to_string(), "Account is frozen");
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{Error, Result};
+use merk::{rocksdb::DBRawIterator, tree::Tree};
+use serde::{Deserialize, Serialize};
+
+/// A dump of a [`MerkStore`](super::MerkStore)'s state as of a height, created
+/// with [`MerkStore::export`](super::MerkStore::export) and serialized as JSON,
+/// e.g. to restart a chain from its latest state or to fork it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StateExport {
+    /// The height the state was exported at.
+    pub height: u64,
+    /// The hex-encoded app hash of the state.
+    pub app_hash: String,
+    /// A readable view of the app state (see
+    /// [`ExportState`](crate::state::ExportState)), for inspection and
+    /// tooling. It is ignored when importing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_state: Option<serde_json::Value>,
+    /// Every key and value in the tree, hex-encoded and sorted by key, from
+    /// which the state can be reproduced exactly.
+    pub entries: Vec<(String, String)>,
+}
+
+impl StateExport {
+    pub(crate) fn new(height: u64, merk_root: &[u8], entries: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        Self {
+            height,
+            app_hash: hex::encode(super::app_hash(merk_root)),
+            app_state: None,
+            entries: entries
+                .into_iter()
+                .map(|(key, value)| (hex::encode(key), hex::encode(value)))
+                .collect(),
+        }
+    }
+}
+
+/// Reads every key and value of a tree with a raw iterator over its nodes.
+pub(crate) fn read_entries(mut iter: DBRawIterator) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut entries = vec![];
+    iter.seek_to_first();
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        let tree = Tree::decode(vec![], iter.value().unwrap());
+        entries.push((key.to_vec(), tree.value().to_vec()));
+        iter.next();
+    }
+    iter.status()?;
+
+    Ok(entries)
+}
+
+/// Decodes a hex-encoded entry of a [StateExport].
+pub(crate) fn decode_entry(key: &str, value: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+    let decode = |hex_str| {
+        hex::decode(hex_str)
+            .map_err(|err| Error::Store(format!("Invalid state export entry: {}", err)))
+    };
+    Ok((decode(key)?, decode(value)?))
+}
@@ -1,11 +1,22 @@
+#[cfg(feature = "merk-full")]
 use super::MerkStore;
 use crate::{Error, Result};
 use ics23::{
-    commitment_proof::Proof, CommitmentProof, ExistenceProof, HashOp, InnerOp, InnerSpec, LeafOp,
-    LengthOp, NonExistenceProof, ProofSpec,
+    commitment_proof::Proof, CommitmentProof, ExistenceProof, HashOp, HostFunctionsManager,
+    InnerSpec, LeafOp, LengthOp, ProofSpec,
 };
+#[cfg(feature = "merk-full")]
+use ics23::{InnerOp, NonExistenceProof};
+#[cfg(feature = "merk-full")]
 use merk::tree::{Fetch, RefWalker, Tree};
+use prost::Message;
+use tendermint_proto::v0_34::crypto::{ProofOp, ProofOps};
 
+/// The key the Merk root hash is committed under in the app hash, see
+/// [app_hash](super::app_hash).
+pub const APP_HASH_KEY: &[u8] = b"ibc";
+
+#[cfg(feature = "merk-full")]
 pub fn create_ics23_proof<S>(
     maybe_root: Option<RefWalker<'_, S>>,
     key: &[u8],
@@ -23,25 +34,135 @@ where
     Ok(CommitmentProof { proof: Some(proof) })
 }
 
+/// Creates the proof that the app hash commits to `merk_root`, the outer layer
+/// of the proofs returned by [proof_ops].
+pub fn app_hash_proof(merk_root: &[u8]) -> CommitmentProof {
+    CommitmentProof {
+        proof: Some(Proof::Exist(ExistenceProof {
+            key: APP_HASH_KEY.to_vec(),
+            value: merk_root.to_vec(),
+            leaf: Some(app_hash_leaf_op()),
+            path: vec![],
+        })),
+    }
+}
+
+/// Encodes a proof of `key` created by [create_ics23_proof] along with the
+/// proof of the Merk root hash in the app hash, ordered from the innermost
+/// layer like the proofs of the Cosmos SDK's multistore.
+pub fn proof_ops(key: &[u8], proof: &CommitmentProof, merk_root: &[u8]) -> ProofOps {
+    ProofOps {
+        ops: vec![
+            ProofOp {
+                r#type: "".to_string(),
+                key: key.to_vec(),
+                data: proof.encode_to_vec(),
+            },
+            ProofOp {
+                r#type: "".to_string(),
+                key: APP_HASH_KEY.to_vec(),
+                data: app_hash_proof(merk_root).encode_to_vec(),
+            },
+        ],
+    }
+}
+
+/// Verifies proofs created by [proof_ops] against an app hash, returning the
+/// proven value of `key`, or `None` if the proofs show it is absent.
+pub fn verify_proof_ops(ops: &ProofOps, app_hash: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>> {
+    let invalid =
+        |msg: &str| Error::Merk(merk::Error::Proof(format!("Invalid ICS 23 proof: {}", msg)));
+
+    let (inner, outer) = match ops.ops.as_slice() {
+        [inner, outer] => (inner, outer),
+        _ => return Err(invalid("expected 2 proof ops")),
+    };
+    if inner.key != key || outer.key != APP_HASH_KEY {
+        return Err(invalid("unexpected proof op key"));
+    }
+    let decode = |op: &ProofOp| {
+        CommitmentProof::decode(op.data.as_slice()).map_err(|_| invalid("failed to decode proof"))
+    };
+    let (inner, outer) = (decode(inner)?, decode(outer)?);
+
+    // the root hash is only trusted once the outer proof ties it to the app
+    // hash
+    let existence = match &inner.proof {
+        Some(Proof::Exist(proof)) => Some(proof),
+        Some(Proof::Nonexist(proof)) => proof.left.as_ref().or(proof.right.as_ref()),
+        _ => None,
+    }
+    .ok_or_else(|| invalid("missing existence proof"))?;
+    let merk_root = ics23::calculate_existence_root::<HostFunctionsManager>(existence)
+        .map_err(|err| invalid(&err.to_string()))?;
+    if !ics23::verify_membership::<HostFunctionsManager>(
+        &outer,
+        &app_hash_spec(),
+        &app_hash.to_vec(),
+        APP_HASH_KEY,
+        &merk_root,
+    ) {
+        return Err(invalid("root hash does not match app hash"));
+    }
+
+    let spec = proof_spec();
+    match &inner.proof {
+        Some(Proof::Exist(proof)) => {
+            if !ics23::verify_membership::<HostFunctionsManager>(
+                &inner,
+                &spec,
+                &merk_root,
+                key,
+                &proof.value,
+            ) {
+                return Err(invalid("existence proof does not match root hash"));
+            }
+            Ok(Some(proof.value.clone()))
+        }
+        _ => {
+            if !ics23::verify_non_membership::<HostFunctionsManager>(&inner, &spec, &merk_root, key)
+            {
+                return Err(invalid("non-existence proof does not match root hash"));
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// The spec of proofs of keys in a Merk tree.
+pub fn proof_spec() -> ProofSpec {
+    ProofSpec {
+        leaf_spec: Some(leaf_op()),
+        inner_spec: Some(InnerSpec {
+            child_order: vec![0, 1, 2],
+            child_size: 32,
+            empty_child: vec![0; 32],
+            hash: HashOp::Sha512256.into(),
+            max_prefix_length: 1,
+            min_prefix_length: 1,
+        }),
+        max_depth: 0,
+        min_depth: 0,
+        prehash_key_before_comparison: false,
+    }
+}
+
+/// The spec of the proof created by [app_hash_proof].
+pub fn app_hash_spec() -> ProofSpec {
+    ProofSpec {
+        leaf_spec: Some(app_hash_leaf_op()),
+        ..proof_spec()
+    }
+}
+
+#[cfg(feature = "merk-full")]
 impl MerkStore {
     pub fn ics23_spec() -> ProofSpec {
-        ProofSpec {
-            leaf_spec: Some(leaf_op()),
-            inner_spec: Some(InnerSpec {
-                child_order: vec![0, 1, 2],
-                child_size: 32,
-                empty_child: vec![0; 32],
-                hash: HashOp::Sha512256.into(),
-                max_prefix_length: 1,
-                min_prefix_length: 1,
-            }),
-            max_depth: 0,
-            min_depth: 0,
-            prehash_key_before_comparison: false,
-        }
+        proof_spec()
     }
 }
 
+#[cfg(feature = "merk-full")]
 fn create_proof<S>(
     mut node: RefWalker<'_, S>,
     key: &[u8],
@@ -98,12 +219,14 @@ where
     Ok(Proof::Nonexist(proof))
 }
 
+#[cfg(feature = "merk-full")]
 enum Branch {
     Left,
     Right,
     KV,
 }
 
+#[cfg(feature = "merk-full")]
 fn inner_op<S>(node: &RefWalker<'_, S>, branch: Branch) -> InnerOp
 where
     S: Fetch + Sized + Clone + Send,
@@ -128,6 +251,16 @@ where
     }
 }
 
+fn app_hash_leaf_op() -> LeafOp {
+    LeafOp {
+        hash: HashOp::Sha512256.into(),
+        length: LengthOp::NoPrefix.into(),
+        prefix: vec![],
+        prehash_key: HashOp::NoHash.into(),
+        prehash_value: HashOp::NoHash.into(),
+    }
+}
+
 fn leaf_op() -> LeafOp {
     LeafOp {
         hash: HashOp::Sha512256.into(),
@@ -138,12 +271,12 @@ fn leaf_op() -> LeafOp {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "merk-full"))]
 mod tests {
     use ics23::HostFunctionsManager;
 
-    use crate::merk::ics23::create_ics23_proof;
-    use crate::merk::MerkStore;
+    use crate::merk::ics23::{create_ics23_proof, proof_ops, verify_proof_ops};
+    use crate::merk::{app_hash, MerkStore};
     use crate::store::Write;
 
     #[test]
@@ -208,4 +341,30 @@ mod tests {
             b"foo2",
         ));
     }
+
+    #[test]
+    fn verify_app_hash() {
+        let path = "/tmp/ics23-proof-test3";
+        let mut store = MerkStore::new(path);
+
+        store.put(b"foo".to_vec(), b"1".to_vec()).unwrap();
+        store.put(b"bar".to_vec(), b"2".to_vec()).unwrap();
+        store.put(b"baz".to_vec(), b"3".to_vec()).unwrap();
+        store.write(vec![]).unwrap();
+
+        let proof = store
+            .merk()
+            .walk(|w| create_ics23_proof(w, b"bar").unwrap());
+        let root_hash = store.merk().root_hash().to_vec();
+
+        drop(store);
+        merk::Merk::destroy(merk::Merk::open(path).unwrap()).unwrap();
+
+        let ops = proof_ops(b"bar", &proof, &root_hash);
+        let value = verify_proof_ops(&ops, &app_hash(&root_hash), b"bar").unwrap();
+        assert_eq!(value, Some(b"2".to_vec()));
+
+        assert!(verify_proof_ops(&ops, &app_hash(&[0; 32]), b"bar").is_err());
+        assert!(verify_proof_ops(&ops, &app_hash(&root_hash), b"baz").is_err());
+    }
 }
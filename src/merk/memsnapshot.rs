@@ -1,36 +1,161 @@
-use crate::{
-    store::{Read, Shared},
-    Result,
-};
+use crate::{store::Read, Error, Result};
+use merk::rocksdb::DB;
+use merk::snapshot::StaticSnapshot;
+use merk::Merk;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::Duration;
 
-use super::MerkStore;
+/// A cheap, cloneable, read-only view of a
+/// [`MerkStore`](super::MerkStore) as of a committed height.
+///
+/// Handles are created with
+/// [`MerkStore::read_handle`](super::MerkStore::read_handle) and may be sent to
+/// other threads. Reads go straight to a RocksDB snapshot taken at commit time,
+/// so they see a stable state while new blocks are committed, and never wait
+/// on (or block) the committing thread.
+///
+/// The store retains a version for each of the last
+/// [`MerkStore::retained_heights`](super::MerkStore::retained_heights) heights
+/// (by default [`MEM_SNAPSHOT_LIMIT`](super::store::MEM_SNAPSHOT_LIMIT)). A version
+/// stays readable for as long as any handle to it is alive, even after the
+/// store has pruned it, and its snapshot is released as soon as it is both
+/// pruned and no longer pinned by a handle. Replacing or handing off the
+/// database ([`MerkStore::into_merk`](super::MerkStore::into_merk), rolling
+/// back or restoring from state sync) waits for outstanding handles to be
+/// dropped and fails if they are held too long. Dropping the store does not
+/// wait: the database stays open until the last handle is dropped.
+#[derive(Clone)]
+pub struct ReadHandle {
+    version: Arc<Version>,
+}
 
-pub struct MemSnapshot {
-    snapshot: merk::snapshot::StaticSnapshot,
-    merk_store: Shared<MerkStore>,
+struct Version {
+    height: u64,
+    snapshot: Option<StaticSnapshot>,
+    db: *const DB,
+    readers: Arc<Readers>,
 }
 
-impl MemSnapshot {
-    pub fn new(
-        mut snapshot: merk::snapshot::StaticSnapshot,
-        merk_store: Shared<MerkStore>,
+// SAFETY: RocksDB snapshots and databases may be read from any thread,
+// concurrently with writes. The database is boxed by the store so it does not
+// move, and is not closed while `readers` counts a live version.
+unsafe impl Send for Version {}
+unsafe impl Sync for Version {}
+
+impl ReadHandle {
+    pub(crate) fn new(
+        height: u64,
+        snapshot: StaticSnapshot,
+        db: &DB,
+        readers: &Arc<Readers>,
     ) -> Self {
-        snapshot.should_drop = true;
+        readers.acquire();
         Self {
-            snapshot,
-            merk_store,
+            version: Arc::new(Version {
+                height,
+                snapshot: Some(snapshot),
+                db,
+                readers: readers.clone(),
+            }),
+        }
+    }
+
+    /// The committed height this handle reads from.
+    pub fn height(&self) -> u64 {
+        self.version.height
+    }
+
+    /// Returns a handle to the same version which does not keep it alive.
+    pub fn downgrade(&self) -> WeakReadHandle {
+        WeakReadHandle {
+            version: Arc::downgrade(&self.version),
         }
     }
 
     pub fn use_snapshot<R, F: FnOnce(&merk::Snapshot) -> R>(&self, f: F) -> R {
-        let store = self.merk_store.borrow();
-        let db = store.merk().db();
-        let ss = unsafe { self.snapshot.with_db(db) };
+        let db = unsafe { &*self.version.db };
+        let snapshot = self.version.snapshot.as_ref().unwrap();
+        let ss = unsafe { snapshot.with_db(db) };
         f(&ss)
     }
 }
 
-impl Read for MemSnapshot {
+impl Drop for Version {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            unsafe { snapshot.drop(&*self.db) };
+        }
+        self.readers.release();
+    }
+}
+
+/// Counts the live versions of a [`MerkStore`](super::MerkStore), so the
+/// store knows when its database can be closed.
+#[derive(Default)]
+pub(crate) struct Readers {
+    state: Mutex<ReadersState>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct ReadersState {
+    count: usize,
+    closed: Option<Box<Merk>>,
+}
+
+impl Readers {
+    fn acquire(&self) {
+        self.state.lock().unwrap().count += 1;
+    }
+
+    fn release(&self) {
+        let closed = {
+            let mut state = self.state.lock().unwrap();
+            state.count -= 1;
+            if state.count == 0 {
+                state.closed.take()
+            } else {
+                None
+            }
+        };
+        self.released.notify_all();
+        drop(closed);
+    }
+
+    /// Waits for every version to be dropped, failing if any are still alive
+    /// after `timeout`.
+    pub(crate) fn wait(&self, timeout: Duration) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .released
+            .wait_timeout_while(state, timeout, |state| state.count > 0)
+            .unwrap();
+        if state.count > 0 {
+            return Err(Error::Store(format!(
+                "Timed out waiting for {} read handle(s) to be dropped",
+                state.count
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Closes the database once every version has been dropped, without
+    /// waiting: if versions are still alive, the last one to be dropped
+    /// closes it.
+    pub(crate) fn close(&self, merk: Box<Merk>) {
+        let mut state = self.state.lock().unwrap();
+        if state.count > 0 {
+            state.closed = Some(merk);
+        } else {
+            drop(state);
+            drop(merk);
+        }
+    }
+}
+
+impl Read for ReadHandle {
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         Ok(self.use_snapshot(|ss| ss.get(key))?)
     }
@@ -49,3 +174,257 @@ impl Read for MemSnapshot {
         })
     }
 }
+
+/// A [ReadHandle] which does not pin its version, created with
+/// [ReadHandle::downgrade].
+#[derive(Clone)]
+pub struct WeakReadHandle {
+    version: Weak<Version>,
+}
+
+impl WeakReadHandle {
+    /// Returns a [ReadHandle] to the version, unless it has been released.
+    pub fn upgrade(&self) -> Option<ReadHandle> {
+        self.version.upgrade().map(|version| ReadHandle { version })
+    }
+}
+
+/// The heights retained by a [`MerkStore`](super::MerkStore) as of a commit,
+/// created with [`MerkStore::read_view`](super::MerkStore::read_view).
+///
+/// Unlike the store, a view can be shared with other threads, e.g. to answer
+/// queries without waiting for the thread processing blocks. It does not pin
+/// the heights it lists: once the store prunes a height or is closed, the view
+/// treats it as pruned, so an outdated view never delays the store.
+#[derive(Clone, Default)]
+pub struct ReadView {
+    handles: BTreeMap<u64, WeakReadHandle>,
+    retained_heights: usize,
+}
+
+impl ReadView {
+    pub(crate) fn new<'a>(
+        handles: impl Iterator<Item = &'a ReadHandle>,
+        retained_heights: usize,
+    ) -> Self {
+        Self {
+            handles: handles
+                .map(|handle| (handle.height(), handle.downgrade()))
+                .collect(),
+            retained_heights,
+        }
+    }
+
+    /// The latest height in the view, if any.
+    pub fn height(&self) -> Option<u64> {
+        self.handles.keys().next_back().copied()
+    }
+
+    /// Returns a [ReadHandle] pinned to the given height, or to the latest
+    /// height if `height` is `None`, like
+    /// [`MerkStore::read_handle`](super::MerkStore::read_handle).
+    pub fn read_handle(&self, height: Option<u64>) -> Option<ReadHandle> {
+        match height {
+            Some(height) => self.handles.get(&height),
+            None => self.handles.values().next_back(),
+        }
+        .and_then(WeakReadHandle::upgrade)
+    }
+
+    /// Describes why `height` can't be read, like
+    /// [`MerkStore::missing_height_error`](super::MerkStore::missing_height_error).
+    pub fn missing_height_error(&self, height: u64) -> Error {
+        missing_height_error(height, self.height(), self.retained_heights)
+    }
+}
+
+pub(crate) fn missing_height_error(height: u64, latest: Option<u64>, retained: usize) -> Error {
+    Error::Query(match latest {
+        Some(latest) if height <= latest => format!(
+            "Cannot query for height {}: pruned (only the last {} heights are retained, latest is {})",
+            height, retained, latest
+        ),
+        _ => format!("Cannot query for height {}: not yet committed", height),
+    })
+}
+
+pub struct MemSnapshot {
+    handle: ReadHandle,
+}
+
+impl MemSnapshot {
+    pub fn new(handle: ReadHandle) -> Self {
+        Self { handle }
+    }
+
+    pub fn handle(&self) -> &ReadHandle {
+        &self.handle
+    }
+
+    pub fn use_snapshot<R, F: FnOnce(&merk::Snapshot) -> R>(&self, f: F) -> R {
+        self.handle.use_snapshot(f)
+    }
+}
+
+impl Read for MemSnapshot {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.handle.get(key)
+    }
+
+    fn get_next(&self, key: &[u8]) -> Result<Option<crate::store::KV>> {
+        self.handle.get_next(key)
+    }
+
+    fn get_prev(&self, key: Option<&[u8]>) -> Result<Option<crate::store::KV>> {
+        self.handle.get_prev(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abci::ABCIStore;
+    use crate::merk::MerkStore;
+    use crate::store::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use tempdir::TempDir;
+    use tendermint_proto::google::protobuf::Timestamp;
+    use tendermint_proto::v0_34::types::Header;
+
+    fn commit(store: &mut MerkStore, height: u64) {
+        for key in [b"a", b"b", b"c"] {
+            store
+                .put(key.to_vec(), height.to_be_bytes().to_vec())
+                .unwrap();
+        }
+        store
+            .commit(Header {
+                height: height as i64,
+                time: Some(Timestamp::default()),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn pinned_reads_during_commits() {
+        let home = TempDir::new("orga-read-handle").unwrap();
+        let mut store = MerkStore::new(home.path());
+        commit(&mut store, 1);
+        let pinned = store.read_handle(Some(1)).unwrap();
+
+        let (send, recv) = mpsc::channel::<ReadHandle>();
+        let recv = Arc::new(Mutex::new(recv));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let recv = recv.clone();
+                thread::spawn(move || {
+                    let mut reads = 0;
+                    loop {
+                        let handle = match recv.lock().unwrap().recv() {
+                            Ok(handle) => handle,
+                            Err(_) => return reads,
+                        };
+                        let expected = handle.height().to_be_bytes().to_vec();
+                        for _ in 0..20 {
+                            for key in [b"a", b"b", b"c"] {
+                                assert_eq!(handle.get(key).unwrap(), Some(expected.clone()));
+                            }
+                            assert_eq!(
+                                handle.get_next(b"a").unwrap(),
+                                Some((b"b".to_vec(), expected.clone()))
+                            );
+                            reads += 1;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for height in 2..=100 {
+            commit(&mut store, height);
+            for _ in 0..4 {
+                send.send(store.read_handle(None).unwrap()).unwrap();
+            }
+        }
+        drop(send);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+
+        assert!(store.read_handle(Some(1)).is_none());
+        assert_eq!(store.read_handle(None).unwrap().height(), 100);
+        assert_eq!(pinned.height(), 1);
+        assert_eq!(pinned.get(b"c").unwrap(), Some(1u64.to_be_bytes().to_vec()));
+
+        let released = Arc::new(AtomicBool::new(false));
+        let reader = thread::spawn({
+            let released = released.clone();
+            move || {
+                thread::sleep(Duration::from_millis(50));
+                assert_eq!(pinned.get(b"a").unwrap(), Some(1u64.to_be_bytes().to_vec()));
+                released.store(true, Ordering::SeqCst);
+                drop(pinned);
+            }
+        });
+        // dropping the store does not wait for the reader, which can still
+        // read from the database until it drops its handle
+        drop(store);
+        assert!(!released.load(Ordering::SeqCst));
+        reader.join().unwrap();
+        assert!(released.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn readers_wait_timeout() {
+        let readers = Arc::new(Readers::default());
+        readers.acquire();
+        assert_eq!(
+            readers
+                .wait(Duration::from_millis(10))
+                .unwrap_err()
+                .to_string(),
+            "Store Error: Timed out waiting for 1 read handle(s) to be dropped"
+        );
+
+        let releaser = thread::spawn({
+            let readers = readers.clone();
+            move || {
+                thread::sleep(Duration::from_millis(20));
+                readers.release();
+            }
+        });
+        readers.wait(Duration::from_secs(10)).unwrap();
+        releaser.join().unwrap();
+    }
+
+    #[test]
+    fn read_view() {
+        let home = TempDir::new("orga-read-view").unwrap();
+        let mut store = MerkStore::new(home.path()).retain_heights(2);
+        commit(&mut store, 1);
+        commit(&mut store, 2);
+
+        let view = store.read_view();
+        assert_eq!(view.height(), Some(2));
+        let handle = view.read_handle(Some(1)).unwrap();
+        assert_eq!(handle.get(b"a").unwrap(), Some(1u64.to_be_bytes().to_vec()));
+        drop(handle);
+
+        // the view does not keep height 1 alive once the store prunes it
+        commit(&mut store, 3);
+        assert!(view.read_handle(Some(1)).is_none());
+        assert_eq!(view.read_handle(None).unwrap().height(), 2);
+        assert_eq!(
+            view.missing_height_error(3).to_string(),
+            "Query Error: Cannot query for height 3: not yet committed"
+        );
+
+        // nor does it block closing the store
+        drop(store);
+        assert!(view.read_handle(None).is_none());
+    }
+}
@@ -1,5 +1,7 @@
 mod client;
 #[cfg(feature = "merk-full")]
+mod export;
+#[cfg(feature = "merk-verify")]
 pub mod ics23;
 #[cfg(feature = "merk-full")]
 pub mod memsnapshot;
@@ -13,10 +15,25 @@ pub mod snapshot;
 pub mod store;
 
 pub use client::Client;
+#[cfg(feature = "merk-full")]
+pub use export::StateExport;
+#[cfg(feature = "merk-full")]
+pub use memsnapshot::{ReadHandle, ReadView};
 pub use merk;
 #[cfg(feature = "merk-full")]
 pub use proofbuilder::ProofBuilder;
 #[cfg(feature = "merk-verify")]
 pub use proofstore::ProofStore;
 #[cfg(feature = "merk-full")]
-pub use store::MerkStore;
+pub use store::{MerkStore, Pruning};
+
+/// Computes the app hash committed to Tendermint for a Merk root hash.
+pub fn app_hash(merk_root: &[u8]) -> Vec<u8> {
+    use sha2::{Digest, Sha512_256};
+
+    let mut hasher = Sha512_256::new();
+    hasher.update(b"ibc");
+    hasher.update(merk_root);
+
+    hasher.finalize().to_vec()
+}
@@ -1,4 +1,5 @@
 use std::cell::RefCell;
+use std::ops::{Bound, RangeBounds};
 use std::rc::Rc;
 
 use super::memsnapshot::MemSnapshot;
@@ -47,6 +48,46 @@ impl<T: Prove> ProofBuilder<T> {
     }
 }
 
+impl<T: Read> ProofBuilder<T> {
+    /// Reads up to `limit` entries with keys in `bounds`, e.g. a page of a
+    /// `Map`, and records them as a single range rather than one range per
+    /// entry, so the proof built by `build` covers the result with one range
+    /// proof.
+    ///
+    /// The recorded range ends at the last entry returned. Since a Merk range
+    /// proof includes the neighbours of its bounds, a verifier can also check
+    /// whether there are more entries in `bounds` after the last one.
+    pub fn read_range<B: RangeBounds<Vec<u8>>>(
+        &self,
+        bounds: B,
+        limit: usize,
+    ) -> Result<Vec<store::KV>> {
+        let (start, mut next) = match bounds.start_bound() {
+            Bound::Included(key) => (key.clone(), self.store.get_next_inclusive(key)?),
+            Bound::Excluded(key) => (key.clone(), self.store.get_next(key)?),
+            Bound::Unbounded => (vec![], self.store.get_next_inclusive(&[])?),
+        };
+
+        let mut entries = vec![];
+        let mut last_key = start.clone();
+        while entries.len() < limit {
+            let (key, value) = match next {
+                Some(entry) if bounds.contains(&entry.0) => entry,
+                _ => break,
+            };
+            next = self.store.get_next(&key)?;
+            last_key = key.clone();
+            entries.push((key, value));
+        }
+
+        self.query
+            .borrow_mut()
+            .insert_range_inclusive(start..=last_key);
+
+        Ok(entries)
+    }
+}
+
 impl<T: Read> store::Read for ProofBuilder<T> {
     /// Gets the value from the underlying store, recording the key to be
     /// included in the proof when `build` is called.
@@ -121,6 +162,7 @@ impl Prove for MemSnapshot {
 
 #[cfg(test)]
 mod tests {
+    use super::super::proofstore::ProofStore;
     use super::super::*;
     use super::MerkStore;
     use super::*;
@@ -214,4 +256,42 @@ mod tests {
         let _res = iter.next().unwrap().unwrap();
         //assert!(res.is_none());
     }
+
+    #[test]
+    fn read_range() {
+        let mut store = Shared::new(temp_merk_store());
+        for i in 1..=6 {
+            store.put(vec![i], vec![i * 10]).unwrap();
+        }
+        store.borrow_mut().write(vec![]).unwrap();
+
+        let builder = ProofBuilder::new(store.clone());
+        let entries = builder.read_range(vec![2]..vec![9], 3).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                (vec![2], vec![20]),
+                (vec![3], vec![30]),
+                (vec![4], vec![40])
+            ]
+        );
+        // the next page starts after the last key of the previous one
+        let rest = builder
+            .read_range((Bound::Excluded(vec![4]), Bound::Unbounded), 10)
+            .unwrap();
+        assert_eq!(rest, vec![(vec![5], vec![50]), (vec![6], vec![60])]);
+
+        let (proof, _) = builder.build().unwrap();
+        let root_hash = store.borrow().merk().root_hash();
+        let proof_store = ProofStore(verify(proof.as_slice(), root_hash).unwrap());
+
+        let mut key = vec![2];
+        let mut verified = vec![(key.clone(), proof_store.get(&key).unwrap().unwrap())];
+        while let Some((next_key, value)) = proof_store.get_next(&key).unwrap() {
+            key = next_key.clone();
+            verified.push((next_key, value));
+        }
+        assert_eq!(verified.len(), 5);
+        assert_eq!(verified[4], (vec![6], vec![60]));
+    }
 }
@@ -5,6 +5,7 @@ use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::thread::JoinHandle;
 use tendermint_proto::v0_34::abci::{RequestLoadSnapshotChunk, Snapshot as AbciSnapshot};
 
 use super::store::{FIRST_SNAPSHOT_HEIGHT, SNAPSHOT_INTERVAL};
@@ -14,22 +15,39 @@ pub struct Snapshot {
     pub(crate) checkpoint: Rc<RefCell<Merk>>,
     length: u32,
     hash: Hash,
+    chunk_hashes: Vec<Hash>,
 }
 
+/// A checkpoint with its chunk count, root hash, and chunk hashes, which can be
+/// computed on another thread before being turned into a [Snapshot].
+type Prepared = (Merk, u32, Hash, Vec<Hash>);
+
 impl Snapshot {
     fn new(checkpoint: Merk) -> Result<Self> {
-        let length = {
-            let chunks = checkpoint.chunks()?;
-            chunks.len() as u32
+        Ok(Self::from_prepared(Self::prepare(checkpoint)?))
+    }
+
+    fn prepare(checkpoint: Merk) -> Result<Prepared> {
+        let chunk_hashes = {
+            let mut chunks = checkpoint.chunks()?;
+            (0..chunks.len())
+                .map(|index| -> Result<Hash> { Ok(chunk_hash(&chunks.chunk(index)?)) })
+                .collect::<Result<Vec<_>>>()?
         };
+        let length = chunk_hashes.len() as u32;
 
         let hash = checkpoint.root_hash();
 
-        Ok(Self {
+        Ok((checkpoint, length, hash, chunk_hashes))
+    }
+
+    fn from_prepared((checkpoint, length, hash, chunk_hashes): Prepared) -> Self {
+        Self {
             checkpoint: Rc::new(RefCell::new(checkpoint)),
             length,
             hash,
-        })
+            chunk_hashes,
+        }
     }
 
     fn chunk(&self, index: usize) -> Result<Vec<u8>> {
@@ -60,6 +78,38 @@ impl Read for Snapshot {
     }
 }
 
+/// The hash of a snapshot chunk, listed in order in the metadata of the
+/// snapshots served to peers so each chunk can be checked as it arrives.
+pub fn chunk_hash(chunk: &[u8]) -> Hash {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(chunk).into()
+}
+
+/// Checks that the metadata of a snapshot offered by a peer declares a hash
+/// for each of its chunks.
+pub(crate) fn has_chunk_hashes(snapshot: &AbciSnapshot) -> bool {
+    snapshot.metadata.len() == snapshot.chunks as usize * 32
+}
+
+/// Checks chunk `index` of `snapshot` against the hash declared for it in the
+/// snapshot's metadata.
+pub(crate) fn verify_chunk(snapshot: &AbciSnapshot, index: u32, chunk: &[u8]) -> Result<()> {
+    let start = index as usize * 32;
+    let expected = snapshot
+        .metadata
+        .get(start..start + 32)
+        .ok_or_else(|| crate::Error::Store(format!("No hash declared for chunk {}", index)))?;
+    if chunk_hash(chunk) != expected {
+        return Err(crate::Error::Store(format!(
+            "Chunk {} does not match its declared hash",
+            index
+        )));
+    }
+
+    Ok(())
+}
+
 pub enum SnapshotFilter {
     Interval {
         interval: u64,
@@ -90,7 +140,7 @@ impl SnapshotFilter {
     pub fn should_keep(&self, ss_height: u64, cur_height: u64) -> bool {
         match self {
             SnapshotFilter::Interval { interval, limit } => {
-                ss_height % interval == 0 && cur_height - ss_height < interval * limit
+                ss_height % interval == 0 && cur_height.saturating_sub(ss_height) < interval * limit
             }
             SnapshotFilter::SpecificHeight { height, keep_until } => {
                 ss_height == *height && keep_until.map_or(true, |n| cur_height < n)
@@ -102,7 +152,10 @@ impl SnapshotFilter {
 #[derive(Default)]
 pub struct Snapshots {
     snapshots: BTreeMap<u64, Snapshot>,
+    /// Snapshots still being prepared on a background thread, by height.
+    pending: BTreeMap<u64, JoinHandle<Result<Prepared>>>,
     filters: Vec<SnapshotFilter>,
+    interval: Option<u64>,
     path: PathBuf,
 }
 
@@ -114,7 +167,9 @@ impl Snapshots {
 
         Ok(Self {
             snapshots: BTreeMap::new(),
+            pending: BTreeMap::new(),
             filters: vec![],
+            interval: None,
             path: path.to_path_buf(),
         })
     }
@@ -144,6 +199,27 @@ impl Snapshots {
         self
     }
 
+    /// Takes a snapshot every `interval` heights (if set) and at
+    /// [FIRST_SNAPSHOT_HEIGHT], keeping the last `keep_recent` interval
+    /// snapshots. Without an interval, no snapshots are taken.
+    pub fn with_interval(self, interval: Option<u64>, keep_recent: u64) -> Self {
+        let filters = match interval {
+            Some(interval) => vec![
+                SnapshotFilter::specific_height(FIRST_SNAPSHOT_HEIGHT, None),
+                SnapshotFilter::interval(interval, keep_recent),
+            ],
+            None => vec![],
+        };
+        Self { interval, ..self }.with_filters(filters)
+    }
+
+    /// Whether snapshots at `height` are served to and accepted from peers:
+    /// multiples of the configured interval (or [SNAPSHOT_INTERVAL] if none
+    /// is set), and [FIRST_SNAPSHOT_HEIGHT].
+    pub fn is_canonical_height(&self, height: u64) -> bool {
+        height == FIRST_SNAPSHOT_HEIGHT || height % self.interval.unwrap_or(SNAPSHOT_INTERVAL) == 0
+    }
+
     pub fn get(&self, height: u64) -> Option<&Snapshot> {
         self.snapshots.get(&height)
     }
@@ -173,6 +249,46 @@ impl Snapshots {
         self.maybe_prune(height)
     }
 
+    /// Like [Snapshots::create], but computes the snapshot's chunks on a
+    /// background thread. The snapshot is only served once it has been added
+    /// by [Snapshots::finish_pending].
+    pub fn create_in_background(&mut self, height: u64, checkpoint: Merk) {
+        if self.snapshots.contains_key(&height) || self.pending.contains_key(&height) {
+            return;
+        }
+
+        let thread = std::thread::spawn(move || Snapshot::prepare(checkpoint));
+        self.pending.insert(height, thread);
+    }
+
+    /// Adds the background snapshots which are ready, or waits for all of
+    /// them if `wait` is set, then prunes the snapshots no longer kept as of
+    /// `cur_height`. Existing snapshots are left alone while snapshots are
+    /// disabled.
+    pub fn finish_pending(&mut self, cur_height: u64, wait: bool) -> Result<()> {
+        if self.filters.is_empty() && self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let heights: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, thread)| wait || thread.is_finished())
+            .map(|(height, _)| *height)
+            .collect();
+
+        for height in heights {
+            let thread = self.pending.remove(&height).unwrap();
+            let prepared = thread
+                .join()
+                .map_err(|_| crate::Error::Store("Snapshot preparation panicked".into()))??;
+            self.snapshots
+                .insert(height, Snapshot::from_prepared(prepared));
+        }
+
+        self.maybe_prune(cur_height)
+    }
+
     pub fn maybe_prune(&mut self, cur_height: u64) -> Result<()> {
         let remove_heights = self
             .snapshots
@@ -198,6 +314,26 @@ impl Snapshots {
         Ok(())
     }
 
+    /// Removes the snapshots taken after `height`, e.g. when the state is
+    /// rolled back to it.
+    pub fn remove_above(&mut self, height: u64) -> Result<()> {
+        let remove_heights: Vec<_> = self
+            .snapshots
+            .range(height + 1..)
+            .map(|(h, _)| *h)
+            .collect();
+        for ss_height in remove_heights {
+            self.snapshots.remove(&ss_height);
+
+            let path = self.path(ss_height);
+            if path.exists() {
+                std::fs::remove_dir_all(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn path(&self, height: u64) -> PathBuf {
         self.path.join(height.to_string())
     }
@@ -205,14 +341,13 @@ impl Snapshots {
     pub fn abci_snapshots(&self) -> Result<Vec<AbciSnapshot>> {
         self.snapshots
             .iter()
-            .filter(|(height, _)| {
-                *height % SNAPSHOT_INTERVAL == 0 || **height == FIRST_SNAPSHOT_HEIGHT
-            })
+            .filter(|(height, _)| self.is_canonical_height(**height))
             .map(|(height, snapshot)| {
                 Ok(AbciSnapshot {
                     chunks: snapshot.length,
                     hash: snapshot.hash.to_vec().into(),
                     height: *height,
+                    metadata: snapshot.chunk_hashes.concat().into(),
                     ..Default::default()
                 })
             })
@@ -1,28 +1,117 @@
 use crate::abci::ABCIStore;
 use crate::error::{Error, Result};
+use crate::store::metrics::record_backing_read;
 use crate::store::*;
-use merk::snapshot::StaticSnapshot;
 use merk::{restore::Restorer, tree::Tree, BatchEntry, Merk, Op};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::BTreeMap, convert::TryInto};
 use tendermint_proto::v0_34::abci::{self, *};
 
+use super::export::{self, StateExport};
+use super::memsnapshot::{self, MemSnapshot, ReadHandle, ReadView, Readers};
 use super::snapshot;
 type Map = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
 
 pub const SNAPSHOT_INTERVAL: u64 = 1000;
 pub const FIRST_SNAPSHOT_HEIGHT: u64 = 2;
+/// The number of interval snapshots kept by default, see
+/// [MerkStore::snapshot_keep_recent].
+pub const DEFAULT_SNAPSHOT_KEEP_RECENT: u64 = 4;
+#[cfg(feature = "state-sync")]
+const DEFAULT_SNAPSHOT_INTERVAL: Option<u64> = Some(SNAPSHOT_INTERVAL);
+#[cfg(not(feature = "state-sync"))]
+const DEFAULT_SNAPSHOT_INTERVAL: Option<u64> = None;
+/// The default number of most recent heights kept in memory for historical
+/// queries, see [MerkStore::retain_heights]. Queries for older heights fail,
+/// unless a [ReadHandle] still pins them.
+pub const MEM_SNAPSHOT_LIMIT: usize = 20;
+/// How long closing or replacing the database waits for outstanding
+/// [ReadHandle]s to be dropped before failing.
+pub const READER_RELEASE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Aux key holding the number of keys in the tree, see
+/// [MerkStore::key_count].
+const KEY_COUNT_KEY: &[u8] = b"key_count";
+/// Aux key holding the bytes stored under each key prefix, see
+/// [MerkStore::state_sizes].
+const STATE_SIZES_KEY: &[u8] = b"state_sizes";
+/// The directory under the store's home holding the state as of the height
+/// before the latest commit, see [MerkStore::rollback].
+const ROLLBACK_DIR: &str = "rollback";
+/// The number of entries written per batch by [MerkStore::import].
+const IMPORT_BATCH_SIZE: usize = 10_000;
+/// Prefix of the aux keys listing the height-scoped aux keys written at each
+/// height, followed by the height, see [MerkStore::put_height_aux].
+const HEIGHT_AUX_PREFIX: &[u8] = b"height_aux/";
+/// Aux key holding the height up to which height-scoped aux data was pruned.
+const PRUNED_HEIGHT_KEY: &[u8] = b"pruned_height";
+/// The most heights pruned by a single commit, bounding the time pruning can
+/// add to a commit when catching up on a large backlog.
+const MAX_PRUNED_HEIGHTS: u64 = 1000;
+
+/// Controls how long a [MerkStore] keeps per-height data.
+///
+/// Merk only stores the latest state, so what accumulates with height is the
+/// auxiliary data written for each block with [MerkStore::put_height_aux],
+/// such as the tx index. Unless `archive` is set, every `interval` heights the
+/// commit deletes the data of heights more than `keep_recent` below it.
+/// Pruning only touches aux data, so it does not affect the app hash and
+/// nodes can choose their own settings.
+///
+/// Only data written with [MerkStore::put_height_aux] is pruned; plain
+/// [MerkStore::put_aux] data, the tree and state snapshots are unaffected.
+/// Pruning runs synchronously as part of the commit, deleting the data of at
+/// most 1000 heights per commit, so a large backlog (e.g. after lowering
+/// `keep_recent`) is worked off over several pruning commits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pruning {
+    pub keep_recent: u64,
+    pub interval: u64,
+    pub archive: bool,
+}
+
+impl Pruning {
+    /// Keeps the data of every height.
+    pub fn archive() -> Self {
+        Self {
+            archive: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Pruning {
+    /// The Cosmos SDK's default: keep the last 362880 heights (about three
+    /// weeks of 5 second blocks), pruning every 10 heights.
+    fn default() -> Self {
+        Self {
+            keep_recent: 362_880,
+            interval: 10,
+            archive: false,
+        }
+    }
+}
 
 /// A [`store::Store`] implementation backed by a [`merk`](https://docs.rs/merk)
 /// Merkle key/value store.
 pub struct MerkStore {
-    merk: Option<Merk>,
+    merk: Option<Box<Merk>>,
     home: PathBuf,
     map: Option<Map>,
     snapshots: snapshot::Snapshots,
     restorer: Option<Restorer>,
     target_snapshot: Option<Snapshot>,
-    mem_snapshots: BTreeMap<u64, StaticSnapshot>,
+    mem_snapshots: BTreeMap<u64, ReadHandle>,
+    readers: Arc<Readers>,
+    snapshot_interval: Option<u64>,
+    snapshot_keep_recent: u64,
+    pending_aux: Map,
+    retained_heights: usize,
+    pending_height_aux: Vec<Vec<u8>>,
+    pruning: Pruning,
+    rollback_checkpoint: bool,
+    state_size_prefix_len: Option<usize>,
 }
 
 impl MerkStore {
@@ -32,19 +121,29 @@ impl MerkStore {
     /// is empty
     pub fn new<P: AsRef<Path>>(home: P) -> Self {
         let home = home.as_ref().to_path_buf();
-        let merk = Merk::open(home.join("db")).unwrap();
+        let mut merk = Merk::open(home.join("db")).unwrap();
 
         // TODO: return result instead of panicking
         maybe_remove_restore(&home).expect("Failed to remove incomplete state sync restore");
+        start_key_count(&mut merk).expect("Failed to initialize key count");
 
         MerkStore {
             map: Some(Map::new()),
-            merk: Some(merk),
+            merk: Some(Box::new(merk)),
             snapshots: Self::load_snapshots(home.join("snapshots")),
             home,
             target_snapshot: None,
             restorer: None,
             mem_snapshots: BTreeMap::new(),
+            readers: Arc::default(),
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+            snapshot_keep_recent: DEFAULT_SNAPSHOT_KEEP_RECENT,
+            pending_aux: Map::new(),
+            retained_heights: MEM_SNAPSHOT_LIMIT,
+            pending_height_aux: vec![],
+            pruning: Pruning::default(),
+            rollback_checkpoint: false,
+            state_size_prefix_len: None,
         }
     }
 
@@ -57,25 +156,110 @@ impl MerkStore {
 
         MerkStore {
             map: Some(Default::default()),
-            merk: Some(merk),
+            merk: Some(Box::new(merk)),
             snapshots: snapshot::Snapshots::default(),
             home,
             target_snapshot: None,
             restorer: None,
             mem_snapshots: BTreeMap::new(),
+            readers: Arc::default(),
+            snapshot_interval: None,
+            snapshot_keep_recent: DEFAULT_SNAPSHOT_KEEP_RECENT,
+            pending_aux: Map::new(),
+            retained_heights: MEM_SNAPSHOT_LIMIT,
+            pending_height_aux: vec![],
+            pruning: Pruning::default(),
+            rollback_checkpoint: false,
+            state_size_prefix_len: None,
         }
     }
 
     fn load_snapshots<P: AsRef<Path>>(path: P) -> snapshot::Snapshots {
         snapshot::Snapshots::load(path.as_ref())
             .expect("Failed to load snapshots")
-            // TODO: make configurable
-            .with_filters(vec![
-                #[cfg(feature = "state-sync")]
-                snapshot::SnapshotFilter::specific_height(2, None),
-                #[cfg(feature = "state-sync")]
-                snapshot::SnapshotFilter::interval(1000, 4),
-            ])
+            .with_interval(DEFAULT_SNAPSHOT_INTERVAL, DEFAULT_SNAPSHOT_KEEP_RECENT)
+    }
+
+    /// Sets how often state sync snapshots are taken: every `interval`
+    /// heights, plus once at [FIRST_SNAPSHOT_HEIGHT]. `None` (or 0) disables
+    /// snapshots. Defaults to [SNAPSHOT_INTERVAL] with the `state-sync`
+    /// feature, and `None` otherwise.
+    ///
+    /// Only nodes using the same interval can state sync from each other,
+    /// since offered snapshots at other heights are rejected.
+    #[must_use]
+    pub fn snapshot_interval(mut self, interval: Option<u64>) -> Self {
+        self.snapshot_interval = interval.filter(|interval| *interval > 0);
+        self.configure_snapshots();
+        self
+    }
+
+    /// Sets how many of the most recent interval snapshots are kept, in
+    /// addition to the one at [FIRST_SNAPSHOT_HEIGHT]. Defaults to
+    /// [DEFAULT_SNAPSHOT_KEEP_RECENT].
+    #[must_use]
+    pub fn snapshot_keep_recent(mut self, count: u64) -> Self {
+        self.snapshot_keep_recent = count;
+        self.configure_snapshots();
+        self
+    }
+
+    /// Sets how long height-scoped aux data (see [MerkStore::put_height_aux])
+    /// is kept. Defaults to `Pruning::default()`. Pruning runs synchronously
+    /// in `commit` and is bounded per commit, see [Pruning].
+    #[must_use]
+    pub fn pruning(mut self, pruning: Pruning) -> Self {
+        self.pruning = pruning;
+        self
+    }
+
+    /// Sets whether each commit first saves a checkpoint of the previous
+    /// height, which [MerkStore::rollback] restores. The checkpoint hard-links
+    /// the database files, so it is cheap to take but keeps files replaced
+    /// by the latest commit on disk. Disabled by default.
+    #[must_use]
+    pub fn rollback_checkpoint(mut self, enabled: bool) -> Self {
+        self.rollback_checkpoint = enabled;
+        self
+    }
+
+    /// Tracks the total bytes of the keys and values stored under each prefix
+    /// of `prefix_len` bytes, see [MerkStore::state_sizes]. Off by default.
+    ///
+    /// Tracking starts automatically for an empty store. A non-empty store
+    /// which didn't track sizes with this prefix length must first be
+    /// scanned with [MerkStore::recount_state_sizes].
+    #[must_use]
+    pub fn track_state_sizes(mut self, prefix_len: usize) -> Self {
+        self.state_size_prefix_len = Some(prefix_len);
+        self
+    }
+
+    /// Sets how many of the most recent heights are retained for historical
+    /// queries (at least 1, the latest height). Each retained height pins a
+    /// RocksDB snapshot, so retaining many heights keeps more superseded data
+    /// on disk. Defaults to [MEM_SNAPSHOT_LIMIT].
+    #[must_use]
+    pub fn retain_heights(mut self, count: usize) -> Self {
+        self.retained_heights = count.max(1);
+        self
+    }
+
+    /// The number of recent heights retained for historical queries.
+    pub fn retained_heights(&self) -> usize {
+        self.retained_heights
+    }
+
+    fn configure_snapshots(&mut self) {
+        let snapshots = std::mem::take(&mut self.snapshots);
+        self.snapshots = snapshots.with_interval(self.snapshot_interval, self.snapshot_keep_recent);
+    }
+
+    /// Blocks until the snapshots being created in the background are ready
+    /// to be served.
+    pub fn wait_for_snapshots(&mut self) -> Result<()> {
+        let height = self.height()?;
+        self.snapshots.finish_pending(height, true)
     }
 
     pub fn init_from(
@@ -106,6 +290,83 @@ impl MerkStore {
         Ok(merk_store)
     }
 
+    /// Creates a store at `home` holding the state of `export`, committed at
+    /// the exported height. Fails unless the store at `home` is empty.
+    ///
+    /// The tree is rebuilt from the exported entries, so its root hash may
+    /// differ from the exported `app_hash`, since the shape of a Merk tree
+    /// depends on the order of its past writes.
+    pub fn import(home: impl AsRef<Path>, export: &StateExport) -> Result<Self> {
+        let mut store = Self::new(home);
+        if !store.is_empty()? || store.height()? != 0 {
+            return Err(Error::Store("Can only import into an empty store".into()));
+        }
+
+        for chunk in export.entries.chunks(IMPORT_BATCH_SIZE) {
+            for (key, value) in chunk {
+                let (key, value) = export::decode_entry(key, value)?;
+                store.put(key, value)?;
+            }
+            store.write(vec![])?;
+        }
+        store.write(vec![(
+            b"height".to_vec(),
+            Some(export.height.to_be_bytes().to_vec()),
+        )])?;
+
+        Ok(store)
+    }
+
+    /// Reverts the latest commit, restoring the state and auxiliary data as of
+    /// the previous height from the checkpoint saved by that commit (see
+    /// [MerkStore::rollback_checkpoint]). Returns the height rolled back to.
+    ///
+    /// This pairs with `cometbft rollback`, which rewinds the consensus state
+    /// by one height, so a node which committed a bad block (e.g. after a
+    /// faulty upgrade) can re-execute it without resyncing. Only one height
+    /// can be rolled back, and state sync snapshots of the reverted height are
+    /// removed.
+    pub fn rollback(&mut self) -> Result<u64> {
+        let height = self.height()?;
+        let rollback_path = self.path(ROLLBACK_DIR);
+        if !rollback_path.exists() {
+            return Err(Error::Store("No rollback checkpoint found".into()));
+        }
+        let prev_height = Merk::open(&rollback_path)?
+            .get_aux(b"height")?
+            .map_or(0, |bytes| read_u64(&bytes));
+        if prev_height + 1 != height {
+            return Err(Error::Store(format!(
+                "Rollback checkpoint is at height {}, but the store is at height {}",
+                prev_height, height
+            )));
+        }
+
+        self.snapshots.finish_pending(height, true)?;
+        self.release_readers()?;
+        (*self.merk.take().unwrap()).destroy()?;
+        let db_path = self.path("db");
+        std::fs::rename(&rollback_path, &db_path)?;
+        self.merk = Some(Box::new(Merk::open(db_path)?));
+        self.map = Some(Map::new());
+        self.pending_aux.clear();
+        self.pending_height_aux.clear();
+        self.snapshots.remove_above(prev_height)?;
+
+        Ok(prev_height)
+    }
+
+    /// Replaces the rollback checkpoint with the currently committed state.
+    fn save_rollback_checkpoint(&self) -> Result<()> {
+        let path = self.path(ROLLBACK_DIR);
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        self.merk().checkpoint(path)?;
+
+        Ok(())
+    }
+
     fn path<T: ToString>(&self, name: T) -> PathBuf {
         self.home.join(name.to_string())
     }
@@ -115,10 +376,50 @@ impl MerkStore {
     /// `aux` may contain auxilary keys and values to be written to the
     /// underlying store, which will not affect the Merkle tree but will still
     /// be persisted in the database.
-    pub fn write(&mut self, aux: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+    pub fn write(&mut self, mut aux: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
         let map = self.map.take().unwrap();
         self.map = Some(Map::new());
 
+        let mut count = self.key_count()?;
+        let mut sizes = self.tracked_state_sizes()?;
+        if count.is_some() || sizes.is_some() {
+            for (key, value) in map.iter() {
+                let prev_len = self.merk().get(key)?.map(|prev| prev.len());
+                if let Some(count) = count.as_mut() {
+                    match (prev_len.is_some(), value.is_some()) {
+                        (false, true) => *count += 1,
+                        (true, false) => *count = count.saturating_sub(1),
+                        _ => {}
+                    }
+                }
+                if let Some((prefix_len, sizes)) = sizes.as_mut() {
+                    let prefix = &key[..key.len().min(*prefix_len)];
+                    let size = sizes.entry(prefix.to_vec()).or_default();
+                    if let Some(prev_len) = prev_len {
+                        *size = size.saturating_sub((key.len() + prev_len) as u64);
+                    }
+                    if let Some(value) = value {
+                        *size += (key.len() + value.len()) as u64;
+                    }
+                    if *size == 0 {
+                        sizes.remove(prefix);
+                    }
+                }
+            }
+        }
+        if let Some(count) = count {
+            aux.retain(|(key, _)| key != KEY_COUNT_KEY);
+            aux.push((KEY_COUNT_KEY.to_vec(), Some(count.to_be_bytes().to_vec())));
+        }
+        if let Some((prefix_len, sizes)) = sizes {
+            aux.retain(|(key, _)| key != STATE_SIZES_KEY);
+            aux.push((
+                STATE_SIZES_KEY.to_vec(),
+                Some(encode_state_sizes(prefix_len, &sizes)),
+            ));
+        }
+        aux.sort_by(|a, b| a.0.cmp(&b.0));
+
         let batch = to_batch(map);
         let aux_batch = to_batch(aux);
 
@@ -129,17 +430,273 @@ impl MerkStore {
             .apply(batch.as_ref(), aux_batch.as_ref())?)
     }
 
+    /// Queues an auxiliary write, persisted atomically with the next commit.
+    /// Auxiliary keys do not affect the Merkle tree, so they can hold
+    /// node-local data such as indexes.
+    pub fn put_aux(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.pending_aux.insert(key, Some(value));
+    }
+
+    /// Like [MerkStore::put_aux], but the write belongs to the height being
+    /// committed and is deleted once that height is pruned (see [Pruning]).
+    pub fn put_height_aux(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.pending_height_aux.push(key.clone());
+        self.put_aux(key, value);
+    }
+
+    /// Reads an auxiliary key as of the last commit.
+    pub fn get_aux(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.merk().get_aux(key)?)
+    }
+
     pub fn merk(&self) -> &Merk {
         self.merk.as_ref().unwrap()
     }
 
-    pub fn into_merk(self) -> Merk {
-        self.merk.unwrap()
+    /// Returns the underlying Merk, once every outstanding [ReadHandle] has
+    /// been dropped. Fails if handles are still held after
+    /// [READER_RELEASE_TIMEOUT].
+    pub fn into_merk(mut self) -> Result<Merk> {
+        self.release_readers()?;
+        Ok(*self.merk.take().unwrap())
+    }
+
+    /// Returns a [ReadHandle] pinned to the given committed height, or to the
+    /// latest committed height if `height` is `None`. Returns `None` if the
+    /// height has not been committed yet or is no longer retained (see
+    /// [MerkStore::retain_heights]).
+    pub fn read_handle(&self, height: Option<u64>) -> Option<ReadHandle> {
+        match height {
+            Some(height) => self.mem_snapshots.get(&height),
+            None => self.mem_snapshots.values().next_back(),
+        }
+        .cloned()
+    }
+
+    /// Returns a [ReadView] of the currently retained heights, which can be
+    /// used from other threads.
+    pub fn read_view(&self) -> ReadView {
+        ReadView::new(self.mem_snapshots.values(), self.retained_heights)
+    }
+
+    /// Returns a read-only [Store] over the state as of the committed
+    /// `height`, which must be one of the retained heights (see
+    /// [MerkStore::retain_heights]). Writing to the returned store panics.
+    pub fn read_at_height(&self, height: u64) -> Result<Store> {
+        let handle = self
+            .read_handle(Some(height))
+            .ok_or_else(|| self.missing_height_error(height))?;
+        let snapshot = Shared::new(MemSnapshot::new(handle));
+
+        Ok(Store::new(BackingStore::MemSnapshot(snapshot)))
+    }
+
+    /// Exports every entry of the tree as of the committed `height`, which
+    /// must be one of the retained heights (see [MerkStore::retain_heights]),
+    /// or the latest height if there are no uncommitted writes, e.g. right
+    /// after opening the store.
+    pub fn export(&self, height: u64) -> Result<StateExport> {
+        if let Some(handle) = self.read_handle(Some(height)) {
+            return handle.use_snapshot(|ss| {
+                let entries = export::read_entries(ss.raw_iter())?;
+                Ok(StateExport::new(height, &ss.root_hash(), entries))
+            });
+        }
+
+        if height != self.height()? {
+            return Err(self.missing_height_error(height));
+        }
+        if !self.map.as_ref().unwrap().is_empty() {
+            return Err(Error::Store(
+                "Cannot export the latest height with uncommitted writes".into(),
+            ));
+        }
+        let entries = export::read_entries(self.merk().raw_iter())?;
+        Ok(StateExport::new(height, &self.merk().root_hash(), entries))
+    }
+
+    /// Returns the aux writes which prune height-scoped aux data at the
+    /// committed `height`, if the [Pruning] settings call for it. Called
+    /// synchronously from the commit, and covers at most
+    /// [MAX_PRUNED_HEIGHTS] heights so it can't stall a single commit.
+    fn prune(&self, height: u64) -> Result<Map> {
+        let mut writes = Map::new();
+        let pruning = &self.pruning;
+        if pruning.archive || pruning.interval == 0 || height % pruning.interval != 0 {
+            return Ok(writes);
+        }
+
+        let pruned = self
+            .get_aux(PRUNED_HEIGHT_KEY)?
+            .map_or(0, |bytes| read_u64(&bytes));
+        let end = height
+            .saturating_sub(pruning.keep_recent)
+            .min(pruned + MAX_PRUNED_HEIGHTS);
+        if end <= pruned {
+            return Ok(writes);
+        }
+
+        for height in pruned + 1..=end {
+            let list_key = height_aux_key(height);
+            if let Some(keys) = self.get_aux(&list_key)? {
+                for key in decode_keys(&keys)? {
+                    writes.insert(key, None);
+                }
+                writes.insert(list_key, None);
+            }
+        }
+        writes.insert(PRUNED_HEIGHT_KEY.to_vec(), Some(end.to_be_bytes().to_vec()));
+
+        Ok(writes)
+    }
+
+    /// Describes why `height` can't be read, distinguishing heights which
+    /// have been pruned from ones which have not been committed yet.
+    pub fn missing_height_error(&self, height: u64) -> Error {
+        let latest = self.read_handle(None).map(|handle| handle.height());
+        memsnapshot::missing_height_error(height, latest, self.retained_heights)
+    }
+
+    /// Releases the retained heights and waits until every outstanding
+    /// [ReadHandle] has been dropped, so the database can be closed. Fails if
+    /// handles are still held after [READER_RELEASE_TIMEOUT].
+    fn release_readers(&mut self) -> Result<()> {
+        self.mem_snapshots.clear();
+        self.readers.wait(READER_RELEASE_TIMEOUT)
+    }
+
+    /// Returns the number of keys in the tree as of the last write.
+    ///
+    /// The count is maintained incrementally on each write (at the cost of a
+    /// read per written key), so it is cheap to query. Stores created before
+    /// the count was introduced, or restored from a state sync snapshot, return
+    /// `None` until [MerkStore::recount_keys] is called.
+    pub fn key_count(&self) -> Result<Option<u64>> {
+        Ok(self
+            .merk()
+            .get_aux(KEY_COUNT_KEY)?
+            .map(|bytes| read_u64(&bytes)))
+    }
+
+    /// Returns the total bytes of the keys and values stored under each key
+    /// prefix as of the last write, along with the prefix length, or `None`
+    /// if the store doesn't track them (see [MerkStore::track_state_sizes]).
+    ///
+    /// For `#[orga]` state types, a prefix is the path of field indexes
+    /// leading to a module, so this shows which modules take up the most
+    /// space. Keys shorter than the prefix length are counted under the whole
+    /// key.
+    pub fn state_sizes(&self) -> Result<Option<(usize, BTreeMap<Vec<u8>, u64>)>> {
+        self.merk()
+            .get_aux(STATE_SIZES_KEY)?
+            .map(|bytes| decode_state_sizes(&bytes))
+            .transpose()
+    }
+
+    /// The sizes to update in the next write: the stored sizes if they use
+    /// the configured prefix length, or empty sizes if the store is empty.
+    fn tracked_state_sizes(&self) -> Result<Option<(usize, BTreeMap<Vec<u8>, u64>)>> {
+        let prefix_len = match self.state_size_prefix_len {
+            Some(prefix_len) => prefix_len,
+            None => return Ok(None),
+        };
+
+        match self.state_sizes()? {
+            Some((stored_len, sizes)) if stored_len == prefix_len => Ok(Some((prefix_len, sizes))),
+            _ if self.is_empty()? => Ok(Some((prefix_len, BTreeMap::new()))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Computes the sizes reported by [MerkStore::state_sizes] with a full
+    /// scan of the tree, and maintains them from there. Fails unless size
+    /// tracking is enabled with [MerkStore::track_state_sizes].
+    pub fn recount_state_sizes(&mut self) -> Result<BTreeMap<Vec<u8>, u64>> {
+        let prefix_len = self
+            .state_size_prefix_len
+            .ok_or_else(|| Error::Store("State size tracking is not enabled".into()))?;
+
+        let mut sizes: BTreeMap<Vec<u8>, u64> = BTreeMap::new();
+        let mut iter = self.merk().raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = iter.key().unwrap();
+            let value_len = Tree::decode(vec![], iter.value().unwrap()).value().len();
+            let prefix = &key[..key.len().min(prefix_len)];
+            *sizes.entry(prefix.to_vec()).or_default() += (key.len() + value_len) as u64;
+            iter.next();
+        }
+        iter.status()?;
+        drop(iter);
+
+        let aux = to_batch(vec![(
+            STATE_SIZES_KEY.to_vec(),
+            Some(encode_state_sizes(prefix_len, &sizes)),
+        )]);
+        self.merk.as_mut().unwrap().apply(&[], aux.as_ref())?;
+
+        Ok(sizes)
+    }
+
+    fn is_empty(&self) -> Result<bool> {
+        let mut iter = self.merk().raw_iter();
+        iter.seek_to_first();
+        let empty = !iter.valid();
+        iter.status()?;
+
+        Ok(empty)
+    }
+
+    /// Counts the keys in the tree with a full scan and starts maintaining the
+    /// count from there.
+    pub fn recount_keys(&mut self) -> Result<u64> {
+        let count = count_keys(self.merk())?;
+        let aux = to_batch(vec![(
+            KEY_COUNT_KEY.to_vec(),
+            Some(count.to_be_bytes().to_vec()),
+        )]);
+        self.merk.as_mut().unwrap().apply(&[], aux.as_ref())?;
+
+        Ok(count)
+    }
+}
+
+/// Starts tracking the key count for empty stores. Non-empty stores without a
+/// count are left untracked, since counting them requires a full scan.
+fn start_key_count(merk: &mut Merk) -> Result<()> {
+    if merk.get_aux(KEY_COUNT_KEY)?.is_some() {
+        return Ok(());
     }
 
-    pub(crate) fn mem_snapshots(&self) -> &BTreeMap<u64, StaticSnapshot> {
-        &self.mem_snapshots
+    let mut iter = merk.raw_iter();
+    iter.seek_to_first();
+    let empty = !iter.valid();
+    iter.status()?;
+    drop(iter);
+
+    if empty {
+        let aux = to_batch(vec![(
+            KEY_COUNT_KEY.to_vec(),
+            Some(0u64.to_be_bytes().to_vec()),
+        )]);
+        merk.apply(&[], aux.as_ref())?;
     }
+
+    Ok(())
+}
+
+fn count_keys(merk: &Merk) -> Result<u64> {
+    let mut iter = merk.raw_iter();
+    iter.seek_to_first();
+
+    let mut count = 0;
+    while iter.valid() {
+        count += 1;
+        iter.next();
+    }
+    iter.status()?;
+
+    Ok(count)
 }
 
 /// Collects an iterator of key/value entries into a `Vec`.
@@ -160,7 +717,10 @@ impl Read for MerkStore {
         match self.map.as_ref().unwrap().get(key) {
             Some(Some(value)) => Ok(Some(value.clone())),
             Some(None) => Ok(None),
-            None => Ok(self.merk.as_ref().unwrap().get(key)?),
+            None => {
+                record_backing_read(key);
+                Ok(self.merk.as_ref().unwrap().get(key)?)
+            }
         }
     }
 
@@ -249,16 +809,17 @@ impl Write for MerkStore {
         self.map.as_mut().unwrap().insert(key.to_vec(), None);
         Ok(())
     }
-}
-
-fn calc_app_hash(merk_root: &[u8]) -> Vec<u8> {
-    use sha2::{Digest, Sha512_256};
-
-    let mut hasher = Sha512_256::new();
-    hasher.update(b"ibc");
-    hasher.update(merk_root);
 
-    hasher.finalize().to_vec()
+    /// Merges a sorted batch into the writes pending for the next commit.
+    fn write_batch(&mut self, mut batch: Map) -> Result<()> {
+        let map = self.map.as_mut().unwrap();
+        if map.is_empty() {
+            *map = batch;
+        } else {
+            map.append(&mut batch);
+        }
+        Ok(())
+    }
 }
 
 impl ABCIStore for MerkStore {
@@ -273,16 +834,27 @@ impl ABCIStore for MerkStore {
     fn root_hash(&self) -> Result<Vec<u8>> {
         let merk_root = self.merk.as_ref().unwrap().root_hash();
 
-        Ok(calc_app_hash(merk_root.as_slice()))
+        Ok(super::app_hash(merk_root.as_slice()))
     }
 
     fn commit(&mut self, header: tendermint_proto::v0_34::types::Header) -> Result<()> {
         let height = header.height as u64;
         let height_bytes = height.to_be_bytes();
 
-        let metadata = vec![(b"height".to_vec(), Some(height_bytes.to_vec()))];
+        if self.rollback_checkpoint && self.height()? > 0 {
+            self.save_rollback_checkpoint()?;
+        }
 
-        self.write(metadata)?;
+        // writes made at this height take precedence over pruned keys
+        let mut metadata = self.prune(height)?;
+        metadata.append(&mut self.pending_aux);
+        if !self.pending_height_aux.is_empty() {
+            let keys = encode_keys(&std::mem::take(&mut self.pending_height_aux));
+            metadata.insert(height_aux_key(height), Some(keys));
+        }
+        metadata.insert(b"height".to_vec(), Some(height_bytes.to_vec()));
+
+        self.write(metadata.into_iter().collect())?;
         self.merk.as_mut().unwrap().flush()?;
 
         let recent = std::time::SystemTime::now()
@@ -292,21 +864,22 @@ impl ABCIStore for MerkStore {
             - header.time.unwrap().seconds
             < 10;
 
-        #[cfg(feature = "state-sync")]
+        self.snapshots.finish_pending(height, false)?;
         if recent && self.snapshots.should_create(height) {
+            // the checkpoint must be taken now, but its chunks are computed in
+            // the background so large states don't delay the commit
             let path = self.snapshots.path(height);
             let checkpoint = self.merk().checkpoint(path)?;
-            self.snapshots.create(height, checkpoint)?;
+            self.snapshots.create_in_background(height, checkpoint);
         }
 
         let snapshot = self.merk().snapshot()?.staticize();
-        self.mem_snapshots.insert(height, snapshot);
+        let handle = ReadHandle::new(height, snapshot, self.merk().db(), &self.readers);
+        self.mem_snapshots.insert(height, handle);
 
-        // TODO: parameterize
-        while self.mem_snapshots.len() > 20 {
-            let ss = self.mem_snapshots.pop_first().unwrap();
-            let db = self.merk().db();
-            unsafe { ss.1.drop(db) };
+        while self.mem_snapshots.len() > self.retained_heights {
+            // the snapshot is released once no handle pins it
+            self.mem_snapshots.pop_first();
         }
 
         Ok(())
@@ -343,16 +916,18 @@ impl ABCIStore for MerkStore {
             self.restorer = Some(restorer);
         }
 
+        snapshot::verify_chunk(target_snapshot, req.index, &req.chunk)?;
         let restorer = self.restorer.as_mut().unwrap();
         let chunks_remaining = restorer.process_chunk(req.chunk.to_vec().as_slice())?;
         if chunks_remaining == 0 {
             let restored = self.restorer.take().unwrap().finalize()?;
-            self.merk.take().unwrap().destroy()?;
+            self.release_readers()?;
+            (*self.merk.take().unwrap()).destroy()?;
             let db_path = self.path("db");
             drop(restored);
 
             std::fs::rename(&restore_path, &db_path)?;
-            self.merk = Some(Merk::open(db_path)?);
+            self.merk = Some(Box::new(Merk::open(db_path)?));
 
             // TODO: write height and flush before renaming db for atomicity
             let height = self.target_snapshot.as_ref().unwrap().height;
@@ -370,10 +945,9 @@ impl ABCIStore for MerkStore {
         res.set_result(abci::response_offer_snapshot::Result::Reject);
 
         if let Some(snapshot) = req.snapshot {
-            let is_canonical_height = snapshot.height % SNAPSHOT_INTERVAL == 0
-                || snapshot.height == FIRST_SNAPSHOT_HEIGHT;
-            if is_canonical_height
-                && calc_app_hash(snapshot.hash.to_vec().as_slice()) == req.app_hash
+            if self.snapshots.is_canonical_height(snapshot.height)
+                && snapshot::has_chunk_hashes(&snapshot)
+                && super::app_hash(snapshot.hash.to_vec().as_slice()) == req.app_hash
             {
                 self.target_snapshot = Some(snapshot);
                 res.set_result(abci::response_offer_snapshot::Result::Accept);
@@ -384,6 +958,15 @@ impl ABCIStore for MerkStore {
     }
 }
 
+impl Drop for MerkStore {
+    fn drop(&mut self) {
+        self.mem_snapshots.clear();
+        if let Some(merk) = self.merk.take() {
+            self.readers.close(merk);
+        }
+    }
+}
+
 fn maybe_remove_restore(home: &Path) -> Result<()> {
     let restore_path = home.join("restore");
     if restore_path.exists() {
@@ -393,8 +976,384 @@ fn maybe_remove_restore(home: &Path) -> Result<()> {
     Ok(())
 }
 
+fn height_aux_key(height: u64) -> Vec<u8> {
+    [HEIGHT_AUX_PREFIX, &height.to_be_bytes()].concat()
+}
+
+/// Encodes a list of keys, each prefixed with its length as a big-endian u32.
+fn encode_keys(keys: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for key in keys {
+        bytes.extend((key.len() as u32).to_be_bytes());
+        bytes.extend(key);
+    }
+    bytes
+}
+
+fn decode_keys(mut bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let invalid = || Error::Store("Invalid height aux key list".into());
+    let mut keys = vec![];
+    while !bytes.is_empty() {
+        let len_bytes: [u8; 4] = bytes.get(..4).ok_or_else(invalid)?.try_into().unwrap();
+        let len = u32::from_be_bytes(len_bytes) as usize;
+        let key = bytes.get(4..4 + len).ok_or_else(invalid)?;
+        keys.push(key.to_vec());
+        bytes = &bytes[4 + len..];
+    }
+    Ok(keys)
+}
+
+/// Encodes state sizes as the prefix length followed by each prefix and its
+/// size, with each prefix preceded by its length.
+fn encode_state_sizes(prefix_len: usize, sizes: &BTreeMap<Vec<u8>, u64>) -> Vec<u8> {
+    let mut bytes = vec![prefix_len as u8];
+    for (prefix, size) in sizes {
+        bytes.push(prefix.len() as u8);
+        bytes.extend(prefix);
+        bytes.extend(size.to_be_bytes());
+    }
+    bytes
+}
+
+fn decode_state_sizes(bytes: &[u8]) -> Result<(usize, BTreeMap<Vec<u8>, u64>)> {
+    let invalid = || Error::Store("Invalid state sizes".into());
+    let (prefix_len, mut bytes) = bytes.split_first().ok_or_else(invalid)?;
+    let mut sizes = BTreeMap::new();
+    while let Some((len, rest)) = bytes.split_first() {
+        let len = *len as usize;
+        let prefix = rest.get(..len).ok_or_else(invalid)?;
+        let size = rest.get(len..len + 8).ok_or_else(invalid)?;
+        sizes.insert(prefix.to_vec(), read_u64(size));
+        bytes = &rest[len + 8..];
+    }
+    Ok((*prefix_len as usize, sizes))
+}
+
 fn read_u64(bytes: &[u8]) -> u64 {
     let mut array = [0; 8];
     array.copy_from_slice(bytes);
     u64::from_be_bytes(array)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tendermint_proto::google::protobuf::Timestamp;
+    use tendermint_proto::v0_34::types::Header;
+
+    fn commit(store: &mut MerkStore, height: u64) -> Result<()> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        store.put(height.to_be_bytes().to_vec(), vec![1])?;
+        store.commit(Header {
+            height: height as i64,
+            time: Some(Timestamp {
+                seconds: now,
+                nanos: 0,
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn snapshot_heights(store: &MerkStore) -> Vec<u64> {
+        store
+            .list_snapshots()
+            .unwrap()
+            .iter()
+            .map(|snapshot| snapshot.height)
+            .collect()
+    }
+
+    #[test]
+    fn snapshot_schedule() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-snapshots").unwrap();
+        let mut store = MerkStore::new(home.path())
+            .snapshot_interval(Some(3))
+            .snapshot_keep_recent(2);
+
+        for height in 1..=9 {
+            commit(&mut store, height)?;
+        }
+        store.wait_for_snapshots()?;
+        assert_eq!(snapshot_heights(&store), vec![2, 6, 9]);
+
+        let snapshot = store.list_snapshots()?.pop().unwrap();
+        assert!(snapshot.chunks > 0);
+        let chunk = store.load_snapshot_chunk(RequestLoadSnapshotChunk {
+            height: 9,
+            chunk: 0,
+            ..Default::default()
+        })?;
+        assert!(!chunk.is_empty());
+
+        let mut res = store.offer_snapshot(RequestOfferSnapshot {
+            app_hash: super::super::app_hash(&snapshot.hash).into(),
+            snapshot: Some(Snapshot {
+                height: 10,
+                ..snapshot.clone()
+            }),
+        })?;
+        assert_eq!(res.result(), abci::response_offer_snapshot::Result::Reject);
+        res = store.offer_snapshot(RequestOfferSnapshot {
+            app_hash: super::super::app_hash(&snapshot.hash).into(),
+            snapshot: Some(snapshot),
+        })?;
+        assert_eq!(res.result(), abci::response_offer_snapshot::Result::Accept);
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_chunk_hashes() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-snapshots").unwrap();
+        let mut store = MerkStore::new(home.path()).snapshot_interval(Some(3));
+        for height in 1..=3 {
+            commit(&mut store, height)?;
+        }
+        store.wait_for_snapshots()?;
+        let snapshot = store.list_snapshots()?.pop().unwrap();
+        assert_eq!(snapshot.metadata.len(), snapshot.chunks as usize * 32);
+
+        let target_home = tempdir::TempDir::new("orga-snapshots").unwrap();
+        let mut target = MerkStore::new(target_home.path()).snapshot_interval(Some(3));
+        let offer = |snapshot: Snapshot| RequestOfferSnapshot {
+            app_hash: super::super::app_hash(&snapshot.hash).into(),
+            snapshot: Some(snapshot),
+        };
+        let res = target.offer_snapshot(offer(Snapshot {
+            metadata: Default::default(),
+            ..snapshot.clone()
+        }))?;
+        assert_eq!(res.result(), abci::response_offer_snapshot::Result::Reject);
+        let res = target.offer_snapshot(offer(snapshot.clone()))?;
+        assert_eq!(res.result(), abci::response_offer_snapshot::Result::Accept);
+
+        for index in 0..snapshot.chunks {
+            let chunk = store.load_snapshot_chunk(RequestLoadSnapshotChunk {
+                height: snapshot.height,
+                chunk: index,
+                ..Default::default()
+            })?;
+            let mut bad_chunk = chunk.clone();
+            bad_chunk[0] ^= 1;
+            let apply = |chunk: Vec<u8>| RequestApplySnapshotChunk {
+                index,
+                chunk: chunk.into(),
+                ..Default::default()
+            };
+            assert!(target.apply_snapshot_chunk(apply(bad_chunk)).is_err());
+            target.apply_snapshot_chunk(apply(chunk))?;
+        }
+        assert_eq!(target.height()?, snapshot.height);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_checkpoint_disabled_by_default() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-rollback").unwrap();
+        let mut store = MerkStore::new(home.path());
+        commit(&mut store, 1)?;
+        commit(&mut store, 2)?;
+
+        assert!(!home.path().join(ROLLBACK_DIR).exists());
+        assert!(store.rollback().is_err());
+        assert_eq!(store.height()?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rollback() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-rollback").unwrap();
+        let mut store = MerkStore::new(home.path()).rollback_checkpoint(true);
+        assert!(store.rollback().is_err());
+
+        commit(&mut store, 1)?;
+        commit(&mut store, 2)?;
+        let root_hash = store.root_hash()?;
+        store.put(vec![0], vec![2])?;
+        commit(&mut store, 3)?;
+        assert_ne!(store.root_hash()?, root_hash);
+
+        assert_eq!(store.rollback()?, 2);
+        assert_eq!(store.height()?, 2);
+        assert_eq!(store.root_hash()?, root_hash);
+        assert_eq!(store.get(&3u64.to_be_bytes())?, None);
+        assert_eq!(store.get(&[0])?, None);
+        assert!(store.rollback().is_err());
+
+        // the chain continues from the restored height
+        commit(&mut store, 3)?;
+        assert_eq!(store.height()?, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn state_sizes() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-state-sizes").unwrap();
+        let mut store = MerkStore::new(home.path()).track_state_sizes(1);
+
+        store.put(vec![1, 0], vec![0; 10])?;
+        store.put(vec![1, 1], vec![0; 10])?;
+        store.put(vec![2, 0], vec![0; 4])?;
+        store.write(vec![])?;
+        let expected = BTreeMap::from([(vec![1], 24), (vec![2], 6)]);
+        assert_eq!(store.state_sizes()?, Some((1, expected)));
+
+        store.put(vec![1, 0], vec![0; 2])?;
+        store.delete(&[2, 0])?;
+        store.write(vec![])?;
+        let expected = BTreeMap::from([(vec![1], 16)]);
+        assert_eq!(store.state_sizes()?, Some((1, expected.clone())));
+        assert_eq!(store.recount_state_sizes()?, expected);
+
+        // sizes by another prefix length need a rescan
+        drop(store);
+        let mut store = MerkStore::new(home.path()).track_state_sizes(2);
+        store.put(vec![3], vec![0])?;
+        store.write(vec![])?;
+        assert_eq!(store.state_sizes()?.unwrap().0, 1);
+        store.recount_state_sizes()?;
+        assert_eq!(store.state_sizes()?.unwrap().1.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshots_disabled() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-snapshots").unwrap();
+        let mut store = MerkStore::new(home.path()).snapshot_interval(None);
+
+        for height in 1..=4 {
+            commit(&mut store, height)?;
+        }
+        store.wait_for_snapshots()?;
+        assert!(snapshot_heights(&store).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn retain_heights() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-retain-heights").unwrap();
+        let mut store = MerkStore::new(home.path()).retain_heights(2);
+
+        for height in 1..=4 {
+            commit(&mut store, height)?;
+        }
+        assert!(store.read_handle(Some(2)).is_none());
+        assert_eq!(store.read_handle(Some(3)).unwrap().height(), 3);
+        assert_eq!(store.read_handle(None).unwrap().height(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_at_height() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-read-at-height").unwrap();
+        let mut store = MerkStore::new(home.path()).retain_heights(2);
+
+        for height in 1..=3 {
+            commit(&mut store, height)?;
+        }
+        let state = store.read_at_height(2)?;
+        assert_eq!(state.get(&2u64.to_be_bytes())?, Some(vec![1]));
+        assert_eq!(state.get(&3u64.to_be_bytes())?, None);
+
+        assert_eq!(
+            store.read_at_height(1).unwrap_err().to_string(),
+            "Query Error: Cannot query for height 1: pruned (only the last 2 heights are retained, latest is 3)"
+        );
+        assert_eq!(
+            store.read_at_height(4).unwrap_err().to_string(),
+            "Query Error: Cannot query for height 4: not yet committed"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pruning() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-pruning").unwrap();
+        let mut store = MerkStore::new(home.path()).pruning(Pruning {
+            keep_recent: 2,
+            interval: 2,
+            archive: false,
+        });
+
+        for height in 1..=6 {
+            store.put_height_aux(vec![height as u8], vec![height as u8]);
+            commit(&mut store, height)?;
+        }
+        let kept: Vec<_> = (1..=6u8)
+            .filter(|n| store.get_aux(&[*n]).unwrap().is_some())
+            .collect();
+        assert_eq!(kept, vec![5, 6]);
+
+        let home = tempdir::TempDir::new("orga-pruning").unwrap();
+        let mut store = MerkStore::new(home.path()).pruning(Pruning::archive());
+        for height in 1..=6 {
+            store.put_height_aux(vec![height as u8], vec![height as u8]);
+            commit(&mut store, height)?;
+        }
+        assert!((1..=6u8).all(|n| store.get_aux(&[n]).unwrap().is_some()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn export() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-export").unwrap();
+        let mut store = MerkStore::new(home.path()).retain_heights(2);
+        for height in 1..=3 {
+            commit(&mut store, height)?;
+        }
+
+        let export = store.export(3)?;
+        assert_eq!(export.height, 3);
+        assert_eq!(export.app_hash, hex::encode(store.root_hash()?));
+        let keys: Vec<_> = export.entries.iter().map(|(key, _)| key.clone()).collect();
+        let expected: Vec<_> = (1..=3u64).map(|h| hex::encode(h.to_be_bytes())).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(store.export(2)?.entries.len(), 2);
+        assert!(store.export(1).is_err());
+
+        store.put(vec![4], vec![4])?;
+        assert!(store.export(3).is_ok());
+        drop(store);
+
+        // the latest height can be exported right after opening the store
+        let store = MerkStore::new(home.path());
+        assert_eq!(store.export(3)?, export);
+
+        Ok(())
+    }
+
+    #[test]
+    fn import() -> Result<()> {
+        let home = tempdir::TempDir::new("orga-import").unwrap();
+        let mut store = MerkStore::new(home.path());
+        for height in 1..=3 {
+            commit(&mut store, height)?;
+        }
+        let export = store.export(3)?;
+
+        let home = tempdir::TempDir::new("orga-import").unwrap();
+        let store = MerkStore::import(home.path(), &export)?;
+        assert_eq!(store.height()?, 3);
+        assert_eq!(store.export(3)?.entries, export.entries);
+        drop(store);
+
+        let err = MerkStore::import(home.path(), &export).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Store Error: Can only import into an empty store"
+        );
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
-use crate::abci::{prost::Adapter, AbciQuery, App};
+use crate::abci::{prost::Adapter, AbciQuery, App, ExtendVote, VerifyVoteExtension};
 use crate::call::Call;
+use crate::coins::{Address, Amount};
 use crate::collections::{Entry, EntryMap, Map};
 use crate::context::Context;
 use crate::describe::Describe;
@@ -9,14 +10,17 @@ use crate::query::Query;
 use crate::state::State;
 use crate::store::Store;
 use crate::{compat_mode, Error, Result};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::cell::{Ref, RefCell};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryInto;
 use std::rc::Rc;
 use tendermint_proto::google::protobuf::Timestamp;
-use tendermint_proto::v0_34::abci::Event;
-use tendermint_proto::v0_34::abci::{Evidence, LastCommitInfo, RequestQuery, ResponseQuery};
+use tendermint_proto::v0_34::abci::{BlockParams, CheckTxType, ConsensusParams};
+use tendermint_proto::v0_34::abci::{Event, EventAttribute};
+use tendermint_proto::v0_34::abci::{
+    Evidence, EvidenceType, LastCommitInfo, RequestQuery, ResponseQuery,
+};
 use tendermint_proto::v0_34::abci::{
     RequestBeginBlock, RequestEndBlock, RequestInitChain, ValidatorUpdate,
 };
@@ -68,6 +72,10 @@ pub struct ABCIPlugin<T> {
     cons_key_by_op_addr: Rc<RefCell<Option<OperatorMap>>>,
     #[serde(skip)]
     pub(crate) logs: Option<Vec<String>>,
+    #[serde(skip)]
+    pub(crate) priority: Option<i64>,
+    #[serde(skip)]
+    pub(crate) consensus_param_updates: Option<ConsensusParams>,
 }
 
 impl<T: Migrate> Migrate for ABCIPlugin<T> {
@@ -84,6 +92,8 @@ impl<T: Migrate> Migrate for ABCIPlugin<T> {
             events: None,
             time: None,
             logs: None,
+            priority: None,
+            consensus_param_updates: None,
         })
     }
 }
@@ -99,6 +109,8 @@ impl<T: Default> Default for ABCIPlugin<T> {
             current_vp: Rc::new(RefCell::new(Some(Default::default()))),
             cons_key_by_op_addr: Rc::new(RefCell::new(Some(Default::default()))),
             logs: None,
+            priority: None,
+            consensus_param_updates: None,
         }
     }
 }
@@ -109,6 +121,44 @@ pub struct InitChainCtx {
     pub validators: Vec<Validator>,
     pub app_state_bytes: Vec<u8>,
     pub initial_height: i64,
+    pub consensus_params: Option<ConsensusParams>,
+}
+
+impl InitChainCtx {
+    /// Parses the [StateImport] in the `import` field of the genesis app
+    /// state, if there is one.
+    pub fn state_import(&self) -> Result<Option<StateImport>> {
+        if self.app_state_bytes.is_empty() {
+            return Ok(None);
+        }
+
+        let app_state: serde_json::Value = serde_json::from_slice(&self.app_state_bytes)?;
+        match app_state.get("import") {
+            Some(import) => Ok(Some(serde_json::from_value(import.clone())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// State carried over from a prior chain when forking or continuing it, read
+/// from the `import` field of the genesis app state.
+///
+/// `NoncePlugin` seeds its nonces from the import, and apps can seed their
+/// `Accounts` with `Accounts::import_balances`.
+///
+/// Importing nonces is what keeps replay protection intact across the fork. A
+/// signed tx from the old chain is valid on the new one unless its signer's
+/// nonce starts at least as high as it was on the old chain, so a fork which
+/// carries over balances but starts nonces at zero lets anyone replay old
+/// transfers.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct StateImport {
+    /// The last nonce used by each signer.
+    #[serde(default)]
+    pub nonces: Vec<(Address, u64)>,
+    /// Account balances, keyed by symbol name (`Symbol::NAME`).
+    #[serde(default)]
+    pub balances: BTreeMap<String, Vec<(Address, Amount)>>,
 }
 
 #[derive(Encode, Decode, Debug)]
@@ -141,6 +191,7 @@ impl From<RequestInitChain> for InitChainCtx {
             validators,
             app_state_bytes: req.app_state_bytes.to_vec(),
             initial_height: req.initial_height,
+            consensus_params: req.consensus_params,
         }
     }
 }
@@ -168,6 +219,72 @@ impl From<RequestBeginBlock> for BeginBlockCtx {
     }
 }
 
+impl BeginBlockCtx {
+    /// The validator misbehavior reported in `byzantine_validators`, skipping
+    /// evidence of unknown types or without a validator.
+    pub fn misbehavior(&self) -> Result<Vec<Misbehavior>> {
+        self.byzantine_validators
+            .iter()
+            .filter_map(|evidence| {
+                let kind = match evidence.r#type() {
+                    EvidenceType::DuplicateVote => MisbehaviorKind::DuplicateVote,
+                    EvidenceType::LightClientAttack => MisbehaviorKind::LightClientAttack,
+                    EvidenceType::Unknown => return None,
+                };
+                let validator = evidence.validator.as_ref()?;
+                let validator_address = validator.address.to_vec().try_into().map_err(|_| {
+                    Error::App("Invalid validator address length from Tendermint".into())
+                });
+
+                Some(validator_address.map(|validator_address| Misbehavior {
+                    kind,
+                    validator_address,
+                    power: validator.power,
+                    height: evidence.height as u64,
+                    time: evidence.time.clone(),
+                }))
+            })
+            .collect()
+    }
+
+    /// Passes each reported [Misbehavior] to `module`, in order.
+    pub fn punish_misbehavior<S: Slashable>(&self, module: &mut S) -> Result<()> {
+        for misbehavior in self.misbehavior()? {
+            module.punish(&misbehavior)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MisbehaviorKind {
+    DuplicateVote,
+    LightClientAttack,
+}
+
+/// Evidence that a validator misbehaved, as reported by Tendermint in
+/// `BeginBlock`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Misbehavior {
+    pub kind: MisbehaviorKind,
+    /// The validator's consensus address (the first 20 bytes of the SHA-256
+    /// hash of its consensus key).
+    pub validator_address: [u8; 20],
+    /// The validator's voting power at `height`.
+    pub power: i64,
+    /// The height at which the misbehavior occurred.
+    pub height: u64,
+    pub time: Option<Timestamp>,
+}
+
+/// Implemented by modules which punish validators (e.g. by jailing and
+/// slashing them) for the misbehavior reported by Tendermint. Modules call
+/// [BeginBlockCtx::punish_misbehavior] from their `begin_block`.
+pub trait Slashable {
+    fn punish(&mut self, misbehavior: &Misbehavior) -> Result<()>;
+}
+
 #[cfg_attr(test, derive(Default))]
 pub struct EndBlockCtx {
     pub height: u64,
@@ -181,6 +298,48 @@ impl From<RequestEndBlock> for EndBlockCtx {
     }
 }
 
+/// The context passed to [crate::abci::ExtendVote], describing the block being
+/// voted on.
+pub struct ExtendVoteCtx {
+    pub hash: Vec<u8>,
+    pub height: u64,
+    pub time: Option<Timestamp>,
+    pub txs: Vec<Vec<u8>>,
+}
+
+#[cfg(feature = "abci-v0_38")]
+impl From<crate::abci::v0_38::messages::RequestExtendVote> for ExtendVoteCtx {
+    fn from(req: crate::abci::v0_38::messages::RequestExtendVote) -> Self {
+        ExtendVoteCtx {
+            hash: req.hash,
+            height: req.height as u64,
+            time: req.time,
+            txs: req.txs,
+        }
+    }
+}
+
+/// The context passed to [crate::abci::VerifyVoteExtension], containing the
+/// extension and the address of the validator which produced it.
+pub struct VerifyVoteExtensionCtx {
+    pub hash: Vec<u8>,
+    pub height: u64,
+    pub validator_address: Vec<u8>,
+    pub vote_extension: Vec<u8>,
+}
+
+#[cfg(feature = "abci-v0_38")]
+impl From<crate::abci::v0_38::messages::RequestVerifyVoteExtension> for VerifyVoteExtensionCtx {
+    fn from(req: crate::abci::v0_38::messages::RequestVerifyVoteExtension) -> Self {
+        VerifyVoteExtensionCtx {
+            hash: req.hash,
+            height: req.height as u64,
+            validator_address: req.validator_address,
+            vote_extension: req.vote_extension,
+        }
+    }
+}
+
 type OperatorMap = Map<[u8; 20], [u8; 32]>;
 
 pub struct Validators {
@@ -286,6 +445,12 @@ impl Validators {
     }
 }
 
+/// Context collecting the ABCI events emitted while handling a block or tx.
+///
+/// Present during `BeginBlock`, `EndBlock`, `DeliverTx` and `CheckTx`. Events
+/// are returned in the corresponding ABCI response (e.g.
+/// `ResponseDeliverTx.events`) so they can be indexed, and are discarded if
+/// the handler returns an error.
 #[derive(Default)]
 pub struct Events {
     pub(crate) events: Vec<Event>,
@@ -296,11 +461,52 @@ impl Events {
         self.events.push(event);
     }
 
+    /// Adds an event of type `kind` with the given indexed attributes.
+    pub fn emit<K, V>(
+        &mut self,
+        kind: impl Into<String>,
+        attributes: impl IntoIterator<Item = (K, V)>,
+    ) where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let attributes = attributes
+            .into_iter()
+            .map(|(key, value)| EventAttribute {
+                key: key.as_ref().to_vec().into(),
+                value: value.as_ref().to_vec().into(),
+                index: true,
+            })
+            .collect();
+
+        self.add(Event {
+            r#type: kind.into(),
+            attributes,
+        });
+    }
+
+    /// Adds a [TypedEvent], indexing all of its attributes.
+    pub fn emit_typed<E: TypedEvent>(&mut self, event: &E) {
+        self.emit(E::KIND, event.attributes());
+    }
+
     pub fn events(&self) -> &[Event] {
         &self.events
     }
 }
 
+/// An event with a fixed type, which modules can define for the events they
+/// emit rather than building attributes by hand at each call site.
+pub trait TypedEvent {
+    /// The ABCI event type, e.g. `"transfer"`.
+    const KIND: &'static str;
+
+    /// The event's key/value attributes, in order.
+    fn attributes(&self) -> Vec<(String, String)>;
+}
+
+/// Context collecting log messages, which are joined into the `log` field of
+/// the ABCI response. Unlike [Events], logs are kept when the handler errors.
 #[derive(Default)]
 pub struct Logs {
     pub(crate) messages: Vec<String>,
@@ -312,6 +518,58 @@ impl Logs {
     }
 }
 
+/// Context collecting changes to Tendermint's consensus parameters, reported
+/// in `ResponseEndBlock.consensus_param_updates`. Only present during
+/// `EndBlock`; parameters which are left unset keep their current values.
+#[derive(Default)]
+pub struct ConsensusParamUpdates {
+    pub(crate) params: Option<ConsensusParams>,
+}
+
+impl ConsensusParamUpdates {
+    /// Sets the maximum block size in bytes and the maximum gas per block
+    /// (`-1` for unlimited).
+    pub fn set_block_params(&mut self, max_bytes: i64, max_gas: i64) {
+        self.params_mut().block = Some(BlockParams { max_bytes, max_gas });
+    }
+
+    /// Returns the pending updates for modification, e.g. to change the
+    /// evidence or validator parameters.
+    pub fn params_mut(&mut self) -> &mut ConsensusParams {
+        self.params.get_or_insert_with(Default::default)
+    }
+
+    pub fn params(&self) -> Option<&ConsensusParams> {
+        self.params.as_ref()
+    }
+}
+
+/// Context describing the tx being checked, only present during `CheckTx`.
+///
+/// After each commit, Tendermint rechecks the txs left in its mempool against
+/// the new state. Those txs already passed a full `CheckTx` on this node, so
+/// checks which don't depend on state can be skipped when `recheck` is set.
+/// Note that some signatures do depend on state, e.g. SDK sign docs include
+/// the signer's sequence and account number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CheckTxCtx {
+    pub recheck: bool,
+}
+
+impl From<CheckTxType> for CheckTxCtx {
+    fn from(kind: CheckTxType) -> Self {
+        Self {
+            recheck: kind == CheckTxType::Recheck,
+        }
+    }
+}
+
+/// Context holding the mempool priority of the tx currently being checked,
+/// reported to Tendermint in `ResponseCheckTx.priority`. Only present during
+/// `CheckTx`; higher values are preferred.
+#[derive(Default)]
+pub struct Priority(pub i64);
+
 #[derive(Debug, Encode, Decode)]
 pub enum ABCICall<C> {
     InitChain(Adapter<RequestInitChain>),
@@ -387,18 +645,25 @@ impl<T: App> Call for ABCIPlugin<T> {
             EndBlock(req) => {
                 Context::add(Events::default());
                 Context::add(Logs::default());
+                Context::add(ConsensusParamUpdates::default());
                 self.events.replace(vec![]);
                 self.logs.replace(vec![]);
+                self.consensus_param_updates = None;
                 let ctx = req.into_inner().into();
                 let res = self.inner.end_block(&ctx);
                 if res.is_ok() {
                     self.events
                         .replace(Context::resolve::<Events>().unwrap().events.clone());
+                    self.consensus_param_updates = Context::resolve::<ConsensusParamUpdates>()
+                        .unwrap()
+                        .params
+                        .take();
                 }
                 self.logs
                     .replace(Context::resolve::<Logs>().unwrap().messages.clone());
                 Context::remove::<Events>();
                 Context::remove::<Logs>();
+                Context::remove::<ConsensusParamUpdates>();
                 res?;
             }
             DeliverTx(inner_call) => {
@@ -420,17 +685,21 @@ impl<T: App> Call for ABCIPlugin<T> {
             CheckTx(inner_call) => {
                 Context::add(Events::default());
                 Context::add(Logs::default());
+                Context::add(Priority::default());
                 self.events.replace(vec![]);
                 self.logs.replace(vec![]);
+                self.priority = None;
                 let res = self.inner.call(inner_call);
                 if res.is_ok() {
                     self.events
                         .replace(Context::resolve::<Events>().unwrap().events.clone());
+                    self.priority = Some(Context::resolve::<Priority>().unwrap().0);
                 }
                 self.logs
                     .replace(Context::resolve::<Logs>().unwrap().messages.clone());
                 Context::remove::<Events>();
                 Context::remove::<Logs>();
+                Context::remove::<Priority>();
                 res?;
             }
         };
@@ -473,6 +742,13 @@ impl Drop for ContextRemover {
     }
 }
 
+impl<T> ABCIPlugin<T> {
+    /// The current validator set, as stored in the state.
+    pub fn validator_set(&self) -> Result<Vec<ValidatorEntry>> {
+        Validators::new(self.current_vp.clone(), self.cons_key_by_op_addr.clone()).entries()
+    }
+}
+
 impl<T: App> ABCIPlugin<T> {
     fn build_updates(&mut self) -> Result<()> {
         let mut update_keys = vec![];
@@ -535,6 +811,8 @@ impl<T: State> State for ABCIPlugin<T> {
             events: None,
             time: None,
             logs: None,
+            priority: None,
+            consensus_param_updates: None,
         })
     }
 
@@ -559,6 +837,24 @@ impl<T: State + Describe> Describe for ABCIPlugin<T> {
     }
 }
 
+impl<T> ExtendVote for ABCIPlugin<T>
+where
+    T: State + ExtendVote,
+{
+    fn extend_vote(&self, ctx: &ExtendVoteCtx) -> Result<Vec<u8>> {
+        self.inner.extend_vote(ctx)
+    }
+}
+
+impl<T> VerifyVoteExtension for ABCIPlugin<T>
+where
+    T: State + VerifyVoteExtension,
+{
+    fn verify_vote_extension(&self, ctx: &VerifyVoteExtensionCtx) -> Result<bool> {
+        self.inner.verify_vote_extension(ctx)
+    }
+}
+
 impl<T> AbciQuery for ABCIPlugin<T>
 where
     T: State + AbciQuery,
@@ -567,3 +863,110 @@ where
         self.inner.abci_query(req)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Transfer {
+        to: Address,
+        amount: Amount,
+    }
+
+    impl TypedEvent for Transfer {
+        const KIND: &'static str = "transfer";
+
+        fn attributes(&self) -> Vec<(String, String)> {
+            vec![
+                ("recipient".to_string(), self.to.to_string()),
+                ("amount".to_string(), self.amount.to_string()),
+            ]
+        }
+    }
+
+    struct Punished(Vec<Misbehavior>);
+
+    impl Slashable for Punished {
+        fn punish(&mut self, misbehavior: &Misbehavior) -> Result<()> {
+            self.0.push(misbehavior.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn punish_misbehavior() -> Result<()> {
+        use tendermint_proto::v0_34::abci::Validator as TmValidator;
+
+        let evidence = |r#type: EvidenceType, address: Vec<u8>| Evidence {
+            r#type: r#type as i32,
+            validator: Some(TmValidator {
+                address: address.into(),
+                power: 10,
+            }),
+            height: 5,
+            ..Default::default()
+        };
+        let mut ctx = BeginBlockCtx {
+            hash: vec![],
+            height: 6,
+            header: Default::default(),
+            last_commit_info: None,
+            byzantine_validators: vec![
+                evidence(EvidenceType::DuplicateVote, vec![1; 20]),
+                evidence(EvidenceType::Unknown, vec![2; 20]),
+                Evidence {
+                    validator: None,
+                    ..evidence(EvidenceType::DuplicateVote, vec![])
+                },
+                evidence(EvidenceType::LightClientAttack, vec![3; 20]),
+            ],
+        };
+
+        let mut punished = Punished(vec![]);
+        ctx.punish_misbehavior(&mut punished)?;
+        assert_eq!(
+            punished.0,
+            vec![
+                Misbehavior {
+                    kind: MisbehaviorKind::DuplicateVote,
+                    validator_address: [1; 20],
+                    power: 10,
+                    height: 5,
+                    time: None,
+                },
+                Misbehavior {
+                    kind: MisbehaviorKind::LightClientAttack,
+                    validator_address: [3; 20],
+                    power: 10,
+                    height: 5,
+                    time: None,
+                },
+            ]
+        );
+
+        ctx.byzantine_validators = vec![evidence(EvidenceType::DuplicateVote, vec![1; 19])];
+        assert!(ctx.misbehavior().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn emit_typed_event() {
+        let mut events = Events::default();
+        events.emit_typed(&Transfer {
+            to: Address::NULL,
+            amount: 100.into(),
+        });
+
+        let event = &events.events()[0];
+        assert_eq!(event.r#type, "transfer");
+        assert_eq!(event.attributes.len(), 2);
+        assert_eq!(event.attributes[0].key.as_ref(), b"recipient");
+        assert_eq!(
+            event.attributes[0].value.as_ref(),
+            Address::NULL.to_string().as_bytes()
+        );
+        assert_eq!(event.attributes[1].value.as_ref(), b"100");
+        assert!(event.attributes.iter().all(|attr| attr.index));
+    }
+}
@@ -0,0 +1,305 @@
+use orga_macros::orga;
+
+use super::sdk_compat::{sdk::Tx as SdkTx, ConvertSdkTx};
+use super::{CheckTxCtx, GasMeter, GetNonce};
+use crate::call::Call;
+use crate::coins::Address;
+use crate::context::Context;
+use crate::state::State;
+use crate::{Error, Result};
+use std::ops::{Deref, DerefMut};
+
+/// Enforces per-block limits on the total gas used by delivered txs and on
+/// their number, like the `max_gas` consensus parameter of the Cosmos SDK.
+///
+/// Usage is tracked in the [BlockGas] context, which is reset in `BeginBlock`.
+/// Txs are metered by the node's [GasMeter] if it sets a tx gas limit,
+/// otherwise the plugin meters each tx itself, limited to the gas remaining in
+/// the block. A tx which exceeds either limit fails, and the gas it used still
+/// counts towards the block, so once the block's gas is exhausted every
+/// further tx fails.
+///
+/// In `CheckTx`, a tx is rejected if its own gas limit exceeds `max_gas`,
+/// since it could never fit in a block.
+#[orga(skip(Call))]
+pub struct BlockGasLimitPlugin<T> {
+    pub max_gas: Option<u64>,
+    pub max_txs: Option<u64>,
+    pub inner: T,
+}
+
+/// Context holding the resources used by the txs delivered so far in the
+/// current block, see [BlockGasLimitPlugin].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockGas {
+    pub gas_used: u64,
+    pub txs: u64,
+}
+
+impl<T: Call + State> BlockGasLimitPlugin<T> {
+    fn check(&mut self, call: T::Call) -> Result<()> {
+        if let (Some(max_gas), Some(meter)) = (self.max_gas, Context::resolve::<GasMeter>()) {
+            if meter.limit() > max_gas {
+                return Err(Error::OutOfGas(format!(
+                    "tx gas limit {} exceeds block gas limit {}",
+                    meter.limit(),
+                    max_gas
+                )));
+            }
+        }
+
+        self.inner.call(call)
+    }
+
+    fn deliver(&mut self, call: T::Call) -> Result<()> {
+        if Context::resolve::<BlockGas>().is_none() {
+            Context::add(BlockGas::default());
+        }
+        let usage = Context::resolve::<BlockGas>().unwrap();
+
+        if let Some(max_txs) = self.max_txs {
+            if usage.txs >= max_txs {
+                return Err(Error::App(format!("Block tx limit of {} reached", max_txs)));
+            }
+        }
+
+        let own_meter = match (self.max_gas, Context::resolve::<GasMeter>()) {
+            (Some(max_gas), None) => {
+                Context::add(GasMeter::new(max_gas.saturating_sub(usage.gas_used)));
+                true
+            }
+            _ => false,
+        };
+        let res = self.inner.call(call);
+        let gas_used = Context::resolve::<GasMeter>().map_or(0, |meter| meter.used());
+        if own_meter {
+            Context::remove::<GasMeter>();
+        }
+
+        let usage = Context::resolve::<BlockGas>().unwrap();
+        usage.txs += 1;
+        usage.gas_used = usage.gas_used.saturating_add(gas_used);
+        if let Some(max_gas) = self.max_gas {
+            if usage.gas_used > max_gas {
+                return Err(Error::OutOfGas(format!(
+                    "block gas limit exceeded, used {} of limit {}",
+                    usage.gas_used, max_gas
+                )));
+            }
+        }
+
+        res
+    }
+}
+
+impl<T: Call + State> Call for BlockGasLimitPlugin<T> {
+    type Call = T::Call;
+
+    fn call(&mut self, call: Self::Call) -> Result<()> {
+        if Context::resolve::<CheckTxCtx>().is_some() {
+            self.check(call)
+        } else {
+            self.deliver(call)
+        }
+    }
+}
+
+impl<T: ConvertSdkTx> ConvertSdkTx for BlockGasLimitPlugin<T> {
+    type Output = T::Output;
+
+    fn convert(&self, sdk_tx: &SdkTx) -> Result<T::Output> {
+        self.inner.convert(sdk_tx)
+    }
+}
+
+impl<T: GetNonce> GetNonce for BlockGasLimitPlugin<T> {
+    fn nonce(&self, address: Address) -> Result<u64> {
+        self.inner.nonce(address)
+    }
+
+    fn account_number(&self, address: Address) -> Result<u64> {
+        self.inner.account_number(address)
+    }
+}
+
+impl<T> Deref for BlockGasLimitPlugin<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for BlockGasLimitPlugin<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+// TODO: Remove dependency on ABCI for this otherwise-pure plugin.
+#[cfg(feature = "abci")]
+mod abci {
+    use super::super::{BeginBlockCtx, EndBlockCtx, InitChainCtx};
+    use super::*;
+    use crate::abci::{BeginBlock, EndBlock, InitChain};
+
+    impl<T> BeginBlock for BlockGasLimitPlugin<T>
+    where
+        T: BeginBlock + State,
+    {
+        fn begin_block(&mut self, ctx: &BeginBlockCtx) -> Result<()> {
+            Context::add(BlockGas::default());
+            self.inner.begin_block(ctx)
+        }
+    }
+
+    impl<T> EndBlock for BlockGasLimitPlugin<T>
+    where
+        T: EndBlock + State,
+    {
+        fn end_block(&mut self, ctx: &EndBlockCtx) -> Result<()> {
+            self.inner.end_block(ctx)
+        }
+    }
+
+    impl<T> InitChain for BlockGasLimitPlugin<T>
+    where
+        T: InitChain + State + Call,
+    {
+        fn init_chain(&mut self, ctx: &InitChainCtx) -> Result<()> {
+            // Tendermint uses -1 for an unlimited block gas limit
+            let max_gas = ctx
+                .consensus_params
+                .as_ref()
+                .and_then(|params| params.block.as_ref())
+                .map(|block| block.max_gas);
+            if let Some(max_gas) = max_gas {
+                self.max_gas = (max_gas >= 0).then_some(max_gas as u64);
+            }
+
+            self.inner.init_chain(ctx)
+        }
+    }
+
+    impl<T> crate::abci::ExtendVote for BlockGasLimitPlugin<T>
+    where
+        T: crate::abci::ExtendVote + State + Call,
+    {
+        fn extend_vote(&self, ctx: &crate::plugins::ExtendVoteCtx) -> Result<Vec<u8>> {
+            self.inner.extend_vote(ctx)
+        }
+    }
+
+    impl<T> crate::abci::VerifyVoteExtension for BlockGasLimitPlugin<T>
+    where
+        T: crate::abci::VerifyVoteExtension + State + Call,
+    {
+        fn verify_vote_extension(
+            &self,
+            ctx: &crate::plugins::VerifyVoteExtensionCtx,
+        ) -> Result<bool> {
+            self.inner.verify_vote_extension(ctx)
+        }
+    }
+
+    impl<T> crate::abci::AbciQuery for BlockGasLimitPlugin<T>
+    where
+        T: crate::abci::AbciQuery + State + Call,
+    {
+        fn abci_query(
+            &self,
+            request: &tendermint_proto::v0_34::abci::RequestQuery,
+        ) -> Result<tendermint_proto::v0_34::abci::ResponseQuery> {
+            self.inner.abci_query(request)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::consume_gas;
+    use super::*;
+    use crate::encoding::{Decode, Encode};
+    use serial_test::serial;
+
+    #[derive(State, Encode, Decode, Default)]
+    struct Burner {
+        count: u64,
+    }
+
+    impl Call for Burner {
+        type Call = u64;
+
+        fn call(&mut self, gas: u64) -> Result<()> {
+            self.count += 1;
+            consume_gas(gas)
+        }
+    }
+
+    fn plugin(max_gas: Option<u64>, max_txs: Option<u64>) -> BlockGasLimitPlugin<Burner> {
+        BlockGasLimitPlugin {
+            max_gas,
+            max_txs,
+            inner: Default::default(),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn block_gas_limit() {
+        let mut state = plugin(Some(100), None);
+        Context::add(BlockGas::default());
+
+        state.call(60).unwrap();
+        assert!(matches!(state.call(60), Err(Error::OutOfGas(_))));
+        // the block's gas is exhausted
+        assert!(state.call(0).is_err());
+        assert_eq!(
+            Context::resolve::<BlockGas>().cloned(),
+            Some(BlockGas {
+                gas_used: 120,
+                txs: 3,
+            })
+        );
+
+        // reset by the next block
+        Context::add(BlockGas::default());
+        state.call(60).unwrap();
+        Context::remove::<BlockGas>();
+    }
+
+    #[test]
+    #[serial]
+    fn block_tx_limit() {
+        let mut state = plugin(None, Some(2));
+        Context::add(BlockGas::default());
+
+        state.call(1_000).unwrap();
+        state.call(1_000).unwrap();
+        let err = state.call(0).unwrap_err();
+        assert_eq!(err.to_string(), "App Error: Block tx limit of 2 reached");
+        assert_eq!(state.inner.count, 2);
+        Context::remove::<BlockGas>();
+    }
+
+    #[test]
+    #[serial]
+    fn check_tx_gas_limit() {
+        let mut state = plugin(Some(100), None);
+        Context::add(BlockGas::default());
+        Context::add(CheckTxCtx::default());
+
+        Context::add(GasMeter::new(200));
+        assert!(matches!(state.call(0), Err(Error::OutOfGas(_))));
+        Context::add(GasMeter::new(100));
+        state.call(50).unwrap();
+        assert_eq!(
+            Context::resolve::<BlockGas>().cloned(),
+            Some(BlockGas::default())
+        );
+
+        Context::remove::<GasMeter>();
+        Context::remove::<CheckTxCtx>();
+        Context::remove::<BlockGas>();
+    }
+}
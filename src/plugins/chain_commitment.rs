@@ -41,8 +41,13 @@ where
     fn nonce(&self, address: crate::coins::Address) -> Result<u64> {
         self.inner.nonce(address)
     }
+
+    fn account_number(&self, address: crate::coins::Address) -> Result<u64> {
+        self.inner.account_number(address)
+    }
 }
 
+#[derive(Clone)]
 pub struct ChainId(pub String);
 
 impl Deref for ChainId {
@@ -159,6 +164,27 @@ mod abci {
         }
     }
 
+    impl<T> crate::abci::ExtendVote for ChainCommitmentPlugin<T>
+    where
+        T: crate::abci::ExtendVote + State,
+    {
+        fn extend_vote(&self, ctx: &crate::plugins::ExtendVoteCtx) -> Result<Vec<u8>> {
+            self.inner.extend_vote(ctx)
+        }
+    }
+
+    impl<T> crate::abci::VerifyVoteExtension for ChainCommitmentPlugin<T>
+    where
+        T: crate::abci::VerifyVoteExtension + State,
+    {
+        fn verify_vote_extension(
+            &self,
+            ctx: &crate::plugins::VerifyVoteExtensionCtx,
+        ) -> Result<bool> {
+            self.inner.verify_vote_extension(ctx)
+        }
+    }
+
     impl<T> crate::abci::AbciQuery for ChainCommitmentPlugin<T>
     where
         T: crate::abci::AbciQuery + State,
@@ -1,7 +1,7 @@
 use orga_macros::orga;
 
 use super::sdk_compat::{sdk::Tx as SdkTx, ConvertSdkTx};
-use super::Paid;
+use super::{GasMeter, Paid, Priority};
 use crate::call::Call;
 use crate::coins::{Coin, Symbol};
 use crate::context::{Context, GetContext};
@@ -38,15 +38,46 @@ impl<S: Symbol, T: Call + State> Call for FeePlugin<S, T> {
             .context::<Paid>()
             .ok_or_else(|| Error::Coins("Minimum fee not paid".into()))?;
 
-        if !paid.running_payer && !paid.fee_disabled {
-            let fee_payment: Coin<S> = paid.take(MIN_FEE)?;
-            fee_payment.burn();
+        if paid.running_payer || paid.fee_disabled {
+            return self.inner.call(call);
         }
 
-        self.inner.call(call)
+        let fee_payment: Coin<S> = paid.take(MIN_FEE)?;
+        fee_payment.burn();
+
+        self.inner.call(call)?;
+
+        // whatever funding in `S` the paid call didn't spend is the rest of
+        // the fee
+        let paid = self
+            .context::<Paid>()
+            .ok_or_else(|| Error::Coins("Minimum fee not paid".into()))?;
+        let leftover = paid.balance::<S>()?;
+        let fee_payment: Coin<S> = paid.take(leftover)?;
+        fee_payment.burn();
+
+        if let Some(priority) = Context::resolve::<Priority>() {
+            let fee: u64 = leftover.into();
+            priority.0 = fee_priority(fee.saturating_add(MIN_FEE));
+        }
+
+        Ok(())
     }
 }
 
+/// Computes a tx's mempool priority as its fee per unit of gas.
+///
+/// The fee is [MIN_FEE] plus the funding in `S` left unspent by the paid call,
+/// all of which [FeePlugin] burns. Gas is the limit of the tx's [GasMeter], or
+/// 1 if txs are not metered, in which case priority is simply the fee.
+fn fee_priority(fee: u64) -> i64 {
+    let gas = Context::resolve::<GasMeter>()
+        .map_or(1, |meter| meter.limit())
+        .max(1);
+
+    (fee / gas).min(i64::MAX as u64) as i64
+}
+
 pub fn disable_fee() {
     if let Some(paid_ctx) = Context::resolve::<Paid>() {
         paid_ctx.fee_disabled = true;
@@ -112,6 +143,29 @@ mod abci {
         }
     }
 
+    impl<S, T> crate::abci::ExtendVote for FeePlugin<S, T>
+    where
+        S: Symbol,
+        T: crate::abci::ExtendVote + State + Call,
+    {
+        fn extend_vote(&self, ctx: &crate::plugins::ExtendVoteCtx) -> Result<Vec<u8>> {
+            self.inner.extend_vote(ctx)
+        }
+    }
+
+    impl<S, T> crate::abci::VerifyVoteExtension for FeePlugin<S, T>
+    where
+        S: Symbol,
+        T: crate::abci::VerifyVoteExtension + State + Call,
+    {
+        fn verify_vote_extension(
+            &self,
+            ctx: &crate::plugins::VerifyVoteExtensionCtx,
+        ) -> Result<bool> {
+            self.inner.verify_vote_extension(ctx)
+        }
+    }
+
     impl<S, T> crate::abci::AbciQuery for FeePlugin<S, T>
     where
         S: Symbol,
@@ -125,3 +179,83 @@ mod abci {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{Decode, Encode};
+    use crate::plugins::{PaidCall, PayableCall, PayablePlugin};
+    use serial_test::serial;
+
+    #[orga]
+    #[derive(Clone, Debug)]
+    struct Simp;
+    impl Symbol for Simp {
+        const INDEX: u8 = 7;
+        const NAME: &'static str = "SIMP";
+    }
+
+    #[derive(State, Encode, Decode, Default)]
+    struct Funder {
+        spent: u64,
+    }
+
+    #[derive(Debug, Encode, Decode)]
+    enum FunderCall {
+        Fund(u64),
+        Spend(u64),
+    }
+
+    impl Call for Funder {
+        type Call = FunderCall;
+
+        fn call(&mut self, call: Self::Call) -> Result<()> {
+            let paid = Context::resolve::<Paid>().unwrap();
+            match call {
+                FunderCall::Fund(amount) => paid.give::<Simp, _>(amount),
+                FunderCall::Spend(amount) => {
+                    paid.take::<Simp, _>(amount)?.burn();
+                    self.spent += amount;
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Runs a paid call through `PayablePlugin` which funds `fee` more than
+    /// the call spends, returning the resulting priority and the funding left
+    /// in the `Paid` context.
+    fn priority_for(fee: u64) -> Result<(i64, u64)> {
+        let mut state: PayablePlugin<FeePlugin<Simp, Funder>> = Default::default();
+        Context::add(Priority::default());
+        Context::add(GasMeter::new(100));
+
+        let res = state.call(PayableCall::Paid(PaidCall {
+            payer: FunderCall::Fund(500 + fee),
+            paid: FunderCall::Spend(500),
+        }));
+        let priority = Context::resolve::<Priority>().unwrap().0;
+        let leftover = Context::resolve::<Paid>().unwrap().balance::<Simp>()?;
+        Context::remove::<Paid>();
+        Context::remove::<Priority>();
+        Context::remove::<GasMeter>();
+        res?;
+        assert_eq!(state.inner.inner.spent, 500);
+
+        Ok((priority, leftover.into()))
+    }
+
+    #[test]
+    #[serial]
+    fn higher_fee_higher_priority() -> Result<()> {
+        let (low, leftover) = priority_for(1_000)?;
+        assert_eq!(leftover, 0);
+        let (high, leftover) = priority_for(5_000)?;
+        assert_eq!(leftover, 0);
+        assert_eq!(low, 10);
+        assert_eq!(high, 50);
+        assert!(high > low);
+
+        Ok(())
+    }
+}
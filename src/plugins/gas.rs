@@ -0,0 +1,129 @@
+use crate::context::Context;
+use crate::{Error, Result};
+use std::time::{Duration, Instant};
+
+/// Gas charged for each store read (`get`, `get_next`, `get_prev`).
+pub const READ_GAS: u64 = 10;
+/// Gas charged for each store write (`put`, `delete`).
+pub const WRITE_GAS: u64 = 20;
+/// Gas charged per byte of key or value read from or written to the store.
+pub const BYTE_GAS: u64 = 1;
+
+/// Context which meters the work done by a single call.
+///
+/// Rust can't preempt a running call, so metering is cooperative: gas is
+/// charged at checkpoints (every store access through
+/// [Store](crate::store::Store), plus any explicit [consume_gas] calls made by
+/// module code), and a call which exceeds its budget fails at the next
+/// checkpoint. A loop which does no store access and never calls
+/// [consume_gas] can't be interrupted.
+///
+/// The gas limit is deterministic and is safe to enforce in `DeliverTx`. The
+/// optional wall-clock deadline is not deterministic (it depends on the
+/// machine's speed), so the node only applies it in `CheckTx`, where it keeps
+/// slow txs out of the mempool without affecting consensus.
+pub struct GasMeter {
+    used: u64,
+    limit: u64,
+    deadline: Option<Instant>,
+}
+
+impl GasMeter {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            used: 0,
+            limit,
+            deadline: None,
+        }
+    }
+
+    /// Also fail the call once `timeout` has elapsed from now. Must only be
+    /// used outside of consensus, see the type-level docs.
+    #[must_use]
+    pub fn with_deadline(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    pub fn consume(&mut self, amount: u64) -> Result<()> {
+        self.used = self.used.saturating_add(amount);
+        if self.used > self.limit {
+            return Err(Error::OutOfGas(format!(
+                "used {} of limit {}",
+                self.used, self.limit
+            )));
+        }
+
+        if let Some(deadline) = self.deadline {
+            if Instant::now() > deadline {
+                return Err(Error::OutOfGas("execution deadline exceeded".into()));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+}
+
+/// Charges `amount` gas to the current [GasMeter] context, if any. Modules with
+/// long-running loops that don't touch the store should call this
+/// periodically so the call can be aborted.
+pub fn consume_gas(amount: u64) -> Result<()> {
+    match Context::resolve::<GasMeter>() {
+        Some(meter) => meter.consume(amount),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{Read, Store, Write};
+    use serial_test::serial;
+
+    #[test]
+    fn meter_limit() {
+        let mut meter = GasMeter::new(100);
+        meter.consume(60).unwrap();
+        assert_eq!(meter.remaining(), 40);
+        meter.consume(40).unwrap();
+        assert!(matches!(meter.consume(1), Err(Error::OutOfGas(_))));
+        assert_eq!(meter.used(), 101);
+    }
+
+    #[test]
+    fn meter_deadline() {
+        let mut meter = GasMeter::new(u64::MAX).with_deadline(Duration::from_millis(0));
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(matches!(meter.consume(0), Err(Error::OutOfGas(_))));
+    }
+
+    #[test]
+    #[serial]
+    fn store_access_charges_gas() -> Result<()> {
+        let mut store = Store::with_map_store();
+        store.put(vec![1], vec![1])?;
+
+        Context::add(GasMeter::new(READ_GAS * 3));
+        for _ in 0..2 {
+            store.get(&[1])?;
+        }
+        let res = store.get(&[1]);
+        Context::remove::<GasMeter>();
+
+        assert!(matches!(res, Err(Error::OutOfGas(_))));
+
+        Ok(())
+    }
+}
@@ -13,6 +13,12 @@ pub use payable::*;
 mod fee;
 pub use fee::*;
 
+pub mod gas;
+pub use gas::{consume_gas, GasMeter};
+
+pub mod block_gas;
+pub use block_gas::{BlockGas, BlockGasLimitPlugin};
+
 pub mod chain_commitment;
 pub use chain_commitment::{ChainCommitmentPlugin, ChainId};
 
@@ -1,6 +1,6 @@
 use orga_macros::orga;
 
-use super::{sdk_compat::sdk::Tx as SdkTx, ConvertSdkTx, Signer};
+use super::{sdk_compat::sdk::Tx as SdkTx, ConvertSdkTx, Signer, Signers};
 use crate::call::Call;
 use crate::coins::Address;
 use crate::collections::Map;
@@ -23,10 +23,38 @@ impl<T: State> NoncePlugin<T> {
     pub fn nonce(&self, address: Address) -> Result<u64> {
         Ok(*self.map.get_or_default(address)?)
     }
+
+    /// Increments the nonce of every other signer of a multi-signer SDK tx
+    /// (see [Signers]), since each of them signed over its own next nonce.
+    fn increment_cosigner_nonces(&mut self, signer: Address) -> Result<()> {
+        let signers = match self.context::<Signers>() {
+            Some(signers) => signers.0.clone(),
+            None => return Ok(()),
+        };
+
+        let mut incremented = vec![signer];
+        for address in signers {
+            if incremented.contains(&address) {
+                continue;
+            }
+            *self.map.entry(address)?.or_default()? += 1;
+            incremented.push(address);
+        }
+
+        Ok(())
+    }
 }
 
 pub trait GetNonce {
     fn nonce(&self, address: Address) -> Result<u64>;
+
+    /// The account number included in SDK sign docs for `address`.
+    ///
+    /// Orga does not assign account numbers, so by default every account uses
+    /// `0`, matching what the auth query service reports to wallets.
+    fn account_number(&self, _address: Address) -> Result<u64> {
+        Ok(0)
+    }
 }
 
 impl<T: State> GetNonce for NoncePlugin<T> {
@@ -96,6 +124,9 @@ where
                 }
 
                 *expected_nonce = nonce;
+                drop(expected_nonce);
+
+                self.increment_cosigner_nonces(pub_key)?;
                 self.inner.call(call.inner_call)
             }
             (None, None) => self.inner.call(call.inner_call),
@@ -139,10 +170,40 @@ mod abci {
         T: InitChain + State + Call,
     {
         fn init_chain(&mut self, ctx: &InitChainCtx) -> Result<()> {
+            // Carry nonces over from a prior chain so its txs can't be
+            // replayed here (see StateImport).
+            if let Some(import) = ctx.state_import()? {
+                for (address, nonce) in import.nonces {
+                    let mut entry = self.map.entry(address)?.or_default()?;
+                    *entry = (*entry).max(nonce);
+                }
+            }
+
             self.inner.init_chain(ctx)
         }
     }
 
+    impl<T> crate::abci::ExtendVote for NoncePlugin<T>
+    where
+        T: crate::abci::ExtendVote + State + Call,
+    {
+        fn extend_vote(&self, ctx: &crate::plugins::ExtendVoteCtx) -> Result<Vec<u8>> {
+            self.inner.extend_vote(ctx)
+        }
+    }
+
+    impl<T> crate::abci::VerifyVoteExtension for NoncePlugin<T>
+    where
+        T: crate::abci::VerifyVoteExtension + State + Call,
+    {
+        fn verify_vote_extension(
+            &self,
+            ctx: &crate::plugins::VerifyVoteExtensionCtx,
+        ) -> Result<bool> {
+            self.inner.verify_vote_extension(ctx)
+        }
+    }
+
     impl<T> crate::abci::AbciQuery for NoncePlugin<T>
     where
         T: crate::abci::AbciQuery + State + Call,
@@ -158,7 +219,7 @@ mod abci {
 
 #[cfg(test)]
 mod tests {
-    use super::super::Signer;
+    use super::super::{Signer, Signers};
     use super::*;
     use crate::context::Context;
 
@@ -237,4 +298,74 @@ mod tests {
         assert!(state.call(unnonced_call()).is_err());
         Context::remove::<Signer>();
     }
+
+    #[serial_test::serial]
+    #[test]
+    fn cosigner_nonces() {
+        let mut state: NoncePlugin<Counter> = Default::default();
+        let signer = Address::from_pubkey([0; 33]);
+        let cosigner = Address::from_pubkey([1; 33]);
+
+        Context::add(Signer {
+            signer: Some(signer),
+        });
+        Context::add(Signers(vec![signer, cosigner, cosigner]));
+        state.call(nonced_call(1)).unwrap();
+        state.call(nonced_call(2)).unwrap();
+        assert_eq!(state.nonce(signer).unwrap(), 2);
+        assert_eq!(state.nonce(cosigner).unwrap(), 2);
+
+        // the signer's nonce is checked before any nonce is incremented
+        assert!(state.call(nonced_call(2)).is_err());
+        assert_eq!(state.nonce(cosigner).unwrap(), 2);
+
+        Context::remove::<Signers>();
+        Context::remove::<Signer>();
+    }
+
+    #[cfg(feature = "abci")]
+    impl crate::abci::InitChain for Counter {
+        fn init_chain(&mut self, _ctx: &super::super::InitChainCtx) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "abci")]
+    #[serial_test::serial]
+    #[test]
+    fn imported_nonces() -> Result<()> {
+        use crate::abci::InitChain;
+
+        let address = Address::from_pubkey([0; 33]);
+        let app_state = serde_json::json!({
+            "import": {
+                "nonces": [[address.to_string(), 5]],
+            }
+        });
+
+        let mut state: NoncePlugin<Counter> = Default::default();
+        state.init_chain(&super::super::InitChainCtx {
+            time: None,
+            chain_id: "test".to_string(),
+            validators: vec![],
+            app_state_bytes: serde_json::to_vec(&app_state)?,
+            initial_height: 1,
+            consensus_params: None,
+        })?;
+        assert_eq!(state.nonce(address)?, 5);
+
+        Context::add(Signer {
+            signer: Some(address),
+        });
+
+        // Replay of a tx from the old chain
+        assert!(state.call(nonced_call(5)).is_err());
+        assert_eq!(state.inner.count, 0);
+
+        state.call(nonced_call(6)).unwrap();
+        assert_eq!(state.inner.count, 1);
+        Context::remove::<Signer>();
+
+        Ok(())
+    }
 }
@@ -34,7 +34,7 @@ impl Paid {
     pub fn give_denom<A: Into<Amount>>(&mut self, amount: A, denom: u8) -> Result<()> {
         let entry = self.map.entry(denom).or_insert_with(|| 0.into());
         let amount = amount.into();
-        *entry = (*entry + amount)?;
+        *entry = entry.checked_add(amount)?;
 
         Ok(())
     }
@@ -53,7 +53,7 @@ impl Paid {
             return Err(Error::Coins("Insufficient funding for paid call".into()));
         }
 
-        *entry = (*entry - amount)?;
+        *entry = entry.checked_sub(amount)?;
 
         Ok(())
     }
@@ -66,6 +66,47 @@ impl Paid {
 
         Ok(entry)
     }
+
+    /// The funds remaining for a single denom.
+    pub fn balance_denom(&self, denom: u8) -> Amount {
+        self.map.get(&denom).copied().unwrap_or_default()
+    }
+
+    /// Every denom with funds remaining and its balance, ordered by denom.
+    pub fn balances(&self) -> Vec<(u8, Amount)> {
+        let mut balances: Vec<_> = self
+            .map
+            .iter()
+            .filter(|(_, amount)| **amount > 0.into())
+            .map(|(denom, amount)| (*denom, *amount))
+            .collect();
+        balances.sort_by_key(|(denom, _)| *denom);
+
+        balances
+    }
+
+    /// Returns an error if any funding provided by the payer was not consumed.
+    ///
+    /// [FeePlugin](super::FeePlugin) burns unspent funding in the fee denom,
+    /// so leftovers of any other denom are dropped when the tx ends.
+    /// [PayablePlugin] only runs this check in debug builds, to surface such
+    /// accounting bugs in tests without changing tx results on release nodes.
+    pub fn check_spent(&self) -> Result<()> {
+        let leftover = self.balances();
+        if leftover.is_empty() {
+            return Ok(());
+        }
+
+        let leftover: Vec<_> = leftover
+            .into_iter()
+            .map(|(denom, amount)| format!("{} of denom {}", amount, denom))
+            .collect();
+
+        Err(Error::Coins(format!(
+            "Paid call left unspent funding: {}",
+            leftover.join(", ")
+        )))
+    }
 }
 
 #[derive(Debug)]
@@ -150,6 +191,11 @@ where
                 let ctx = self.context::<Paid>().unwrap();
                 ctx.running_payer = false;
                 self.inner.call(calls.paid)?;
+
+                if cfg!(debug_assertions) {
+                    self.context::<Paid>().unwrap().check_spent()?;
+                }
+
                 Ok(())
             }
         }
@@ -201,6 +247,27 @@ mod abci {
         }
     }
 
+    impl<T> crate::abci::ExtendVote for PayablePlugin<T>
+    where
+        T: crate::abci::ExtendVote + State + Call,
+    {
+        fn extend_vote(&self, ctx: &crate::plugins::ExtendVoteCtx) -> Result<Vec<u8>> {
+            self.inner.extend_vote(ctx)
+        }
+    }
+
+    impl<T> crate::abci::VerifyVoteExtension for PayablePlugin<T>
+    where
+        T: crate::abci::VerifyVoteExtension + State + Call,
+    {
+        fn verify_vote_extension(
+            &self,
+            ctx: &crate::plugins::VerifyVoteExtensionCtx,
+        ) -> Result<bool> {
+            self.inner.verify_vote_extension(ctx)
+        }
+    }
+
     impl<T> crate::abci::AbciQuery for PayablePlugin<T>
     where
         T: crate::abci::AbciQuery + State + Call,
@@ -213,3 +280,84 @@ mod abci {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::FeePlugin;
+    use serial_test::serial;
+
+    #[orga]
+    #[derive(Clone, Debug)]
+    struct Simp;
+    impl Symbol for Simp {
+        const INDEX: u8 = 7;
+        const NAME: &'static str = "SIMP";
+    }
+
+    #[derive(State, Encode, Decode, Default)]
+    struct Funder {
+        spent: u64,
+    }
+
+    #[derive(Debug, Encode, Decode)]
+    enum FunderCall {
+        Fund(u64),
+        Spend(u64),
+    }
+
+    impl Call for Funder {
+        type Call = FunderCall;
+
+        fn call(&mut self, call: Self::Call) -> Result<()> {
+            let paid = Context::resolve::<Paid>().unwrap();
+            match call {
+                FunderCall::Fund(amount) => paid.give::<Simp, _>(amount),
+                FunderCall::Spend(amount) => {
+                    paid.take::<Simp, _>(amount)?.burn();
+                    self.spent += amount;
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn paid_call(fund: u64, spend: u64) -> PayableCall<FunderCall> {
+        PayableCall::Paid(PaidCall {
+            payer: FunderCall::Fund(fund),
+            paid: FunderCall::Spend(spend),
+        })
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[serial]
+    fn leftover_funding() {
+        let mut state: PayablePlugin<Funder> = Default::default();
+
+        state.call(paid_call(100, 100)).unwrap();
+        assert_eq!(state.inner.spent, 100);
+
+        let err = state.call(paid_call(100, 60)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Coins Error: Paid call left unspent funding: 40 of denom 7"
+        );
+
+        Context::remove::<Paid>();
+    }
+
+    #[test]
+    #[serial]
+    fn fee_plugin_burns_leftover_funding() {
+        let mut state: PayablePlugin<FeePlugin<Simp, Funder>> = Default::default();
+
+        state.call(paid_call(100, 60)).unwrap();
+        assert_eq!(state.inner.inner.spent, 60);
+        let paid = Context::resolve::<Paid>().unwrap();
+        assert!(paid.balances().is_empty());
+        paid.check_spent().unwrap();
+
+        Context::remove::<Paid>();
+    }
+}
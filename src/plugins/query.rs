@@ -140,6 +140,27 @@ mod abci {
         }
     }
 
+    impl<T> crate::abci::ExtendVote for QueryPlugin<T>
+    where
+        T: crate::abci::ExtendVote + State + Call,
+    {
+        fn extend_vote(&self, ctx: &crate::plugins::ExtendVoteCtx) -> Result<Vec<u8>> {
+            self.inner.borrow().deref().extend_vote(ctx)
+        }
+    }
+
+    impl<T> crate::abci::VerifyVoteExtension for QueryPlugin<T>
+    where
+        T: crate::abci::VerifyVoteExtension + State + Call,
+    {
+        fn verify_vote_extension(
+            &self,
+            ctx: &crate::plugins::VerifyVoteExtensionCtx,
+        ) -> Result<bool> {
+            self.inner.borrow().deref().verify_vote_extension(ctx)
+        }
+    }
+
     impl<T> crate::abci::AbciQuery for QueryPlugin<T>
     where
         T: crate::abci::AbciQuery + State + Call,
@@ -11,24 +11,45 @@ use crate::{Error, Result};
 
 use std::marker::PhantomData;
 
+/// The default maximum size of an encoded call, see [SdkCompatPlugin].
 pub const MAX_CALL_SIZE: usize = 65_535;
 pub const NATIVE_CALL_FLAG: u8 = 0xff;
 
+fn check_call_size(len: usize, max: usize) -> ed::Result<()> {
+    if len > max {
+        let err = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("call exceeds max size {}", max),
+        );
+        return Err(ed::Error::IOError(err));
+    }
+
+    Ok(())
+}
+
+/// Accepts Cosmos SDK txs alongside native calls, converting them with
+/// [ConvertSdkTx].
+///
+/// `MAX_SIZE` is the maximum size in bytes of an encoded call, for both native
+/// calls and SDK txs, and defaults to [MAX_CALL_SIZE]. Calls are decoded before
+/// they reach the app, so the limit is part of the app's type, and must be the
+/// same on every node of a network since oversized calls are rejected in
+/// `DeliverTx`.
 #[orga(skip(Call), version = 1)]
-pub struct SdkCompatPlugin<S, T> {
+pub struct SdkCompatPlugin<S, T, const MAX_SIZE: usize = MAX_CALL_SIZE> {
     pub(crate) symbol: PhantomData<S>,
     pub inner: T,
 }
 
 #[derive(Debug)]
-pub enum Call<T> {
+pub enum Call<T, const MAX_SIZE: usize = MAX_CALL_SIZE> {
     Native(T),
     Sdk(sdk::Tx),
 }
 
-unsafe impl<T> Send for Call<T> {}
+unsafe impl<T, const MAX_SIZE: usize> Send for Call<T, MAX_SIZE> {}
 
-impl<T: Encode> Encode for Call<T> {
+impl<T: Encode, const MAX_SIZE: usize> Encode for Call<T, MAX_SIZE> {
     fn encoding_length(&self) -> ed::Result<usize> {
         match self {
             Call::Native(native) => Ok(native.encoding_length()? + 1),
@@ -42,19 +63,21 @@ impl<T: Encode> Encode for Call<T> {
                 NATIVE_CALL_FLAG.encode_into(dest)?;
                 native.encode_into(dest)
             }
-            Call::Sdk(tx) => tx.encode_into(dest),
+            Call::Sdk(tx) => {
+                let bytes = tx.to_bytes()?;
+                check_call_size(bytes.len(), MAX_SIZE)?;
+                dest.write_all(&bytes)?;
+                Ok(())
+            }
         }
     }
 }
 
-impl<T: Decode> Decode for Call<T> {
+impl<T: Decode, const MAX_SIZE: usize> Decode for Call<T, MAX_SIZE> {
     fn decode<R: std::io::Read>(mut reader: R) -> ed::Result<Self> {
         let mut bytes = vec![];
         reader.read_to_end(&mut bytes)?;
-
-        if bytes.len() > MAX_CALL_SIZE {
-            return Err(ed::Error::UnexpectedByte(0));
-        }
+        check_call_size(bytes.len(), MAX_SIZE)?;
 
         match bytes.first() {
             Some(&NATIVE_CALL_FLAG) => {
@@ -62,7 +85,7 @@ impl<T: Decode> Decode for Call<T> {
                 Ok(Call::Native(native))
             }
             Some(_) => {
-                let tx = sdk::Tx::decode(bytes.as_slice())?;
+                let tx = sdk::Tx::from_bytes(bytes.as_slice())?;
                 Ok(Call::Sdk(tx))
             }
             None => {
@@ -74,7 +97,8 @@ impl<T: Decode> Decode for Call<T> {
 }
 
 pub mod sdk {
-    use super::{Address, Decode, Encode, Error, Result, MAX_CALL_SIZE};
+    use super::{Address, Decode, Encode, Error, Result, Symbol};
+    use crate::coins::Amount;
     use cosmrs::proto::cosmos::tx::v1beta1::Tx as ProtoTx;
     use prost::Message;
     use serde::{Deserialize, Serialize};
@@ -84,6 +108,39 @@ pub mod sdk {
     pub enum Tx {
         Amino(AminoTx),
         Protobuf(cosmrs::Tx),
+        Unknown(UnknownTx),
+    }
+
+    /// A tx which is valid protobuf but which `cosmrs` can't interpret, e.g.
+    /// because it uses a field encoding or key type added after the version
+    /// we depend on.
+    ///
+    /// These are kept rather than failing the whole decode so that a tx type we
+    /// don't understand gets a clean rejection (see [UnknownTx::error]) instead
+    /// of an opaque decoding error. Unknown message types and extension
+    /// options in an otherwise-understood tx don't end up here, since they are
+    /// carried as `Any` values in [cosmrs::Tx].
+    #[derive(Debug, Clone)]
+    pub struct UnknownTx {
+        pub raw: ProtoTx,
+        pub reason: String,
+    }
+
+    impl UnknownTx {
+        /// The type URLs of the tx's messages.
+        pub fn msg_type_urls(&self) -> Vec<&str> {
+            self.raw
+                .body
+                .iter()
+                .flat_map(|body| body.messages.iter())
+                .map(|msg| msg.type_url.as_str())
+                .collect()
+        }
+
+        /// The error to reject this tx with.
+        pub fn error(&self) -> Error {
+            Error::App(format!("Unsupported sdk tx: {}", self.reason))
+        }
     }
 
     impl Encode for Tx {
@@ -97,24 +154,12 @@ pub mod sdk {
                     let tx: ProtoTx = tx.clone().into();
                     Ok(tx.encoded_len())
                 }
+                Tx::Unknown(tx) => Ok(tx.raw.encoded_len()),
             }
         }
 
         fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
-            let bytes = match self {
-                Tx::Amino(tx) => {
-                    serde_json::to_vec(tx).map_err(|_| ed::Error::UnexpectedByte(0))?
-                }
-                Tx::Protobuf(tx) => {
-                    let tx: ProtoTx = tx.clone().into();
-                    tx.encode_to_vec()
-                }
-            };
-
-            if bytes.len() > MAX_CALL_SIZE {
-                return Err(ed::Error::UnexpectedByte(0));
-            }
-
+            let bytes = self.to_bytes()?;
             dest.write_all(&bytes)?;
             Ok(())
         }
@@ -122,22 +167,62 @@ pub mod sdk {
 
     impl Decode for Tx {
         fn decode<R: std::io::Read>(mut reader: R) -> ed::Result<Self> {
-            let mut bytes = Vec::with_capacity(MAX_CALL_SIZE);
+            let mut bytes = vec![];
             reader.read_to_end(&mut bytes)?;
 
-            if bytes.len() > MAX_CALL_SIZE || bytes.is_empty() {
+            Self::from_bytes(bytes.as_slice())
+        }
+    }
+
+    impl Tx {
+        /// Encodes the tx. Txs have no size limit of their own, it is enforced
+        /// by [Call](super::Call) with the plugin's maximum size.
+        pub(super) fn to_bytes(&self) -> ed::Result<Vec<u8>> {
+            Ok(match self {
+                Tx::Amino(tx) => {
+                    serde_json::to_vec(tx).map_err(|_| ed::Error::UnexpectedByte(0))?
+                }
+                Tx::Protobuf(tx) => {
+                    let tx: ProtoTx = tx.clone().into();
+                    tx.encode_to_vec()
+                }
+                Tx::Unknown(tx) => tx.raw.encode_to_vec(),
+            })
+        }
+
+        /// Decodes a tx, leaving the size limit to the caller.
+        pub(super) fn from_bytes(bytes: &[u8]) -> ed::Result<Self> {
+            if bytes.is_empty() {
                 return Err(ed::Error::UnexpectedByte(0));
             }
 
-            if b'{' == bytes[0] {
-                let tx = serde_json::from_slice(bytes.as_slice())
-                    .map_err(|_| ed::Error::UnexpectedByte(123))?;
-                return Ok(Tx::Amino(tx));
+            // protobuf txs may also start with `{` (0x7b opens a group for
+            // field 15), so only treat the tx as Amino JSON if it parses as one
+            let mut amino_err = None;
+            if bytes[0] == b'{' {
+                match serde_json::from_slice(bytes) {
+                    Ok(tx) => return Ok(Tx::Amino(tx)),
+                    Err(e) => amino_err = Some(e),
+                }
             }
 
-            let tx = cosmrs::Tx::from_bytes(bytes.as_slice())
-                .map_err(|e| IoError::new(ErrorKind::InvalidData, e))?;
-            Ok(Tx::Protobuf(tx))
+            let raw = ProtoTx::decode(bytes).map_err(|e| {
+                let msg = match &amino_err {
+                    Some(amino_err) => format!(
+                        "Invalid sdk tx: not an Amino JSON tx ({}) or a protobuf tx ({})",
+                        amino_err, e
+                    ),
+                    None => format!("Invalid protobuf tx: {}", e),
+                };
+                IoError::new(ErrorKind::InvalidData, msg)
+            })?;
+            match cosmrs::Tx::try_from(raw.clone()) {
+                Ok(tx) => Ok(Tx::Protobuf(tx)),
+                Err(e) => Ok(Tx::Unknown(UnknownTx {
+                    raw,
+                    reason: e.to_string(),
+                })),
+            }
         }
     }
 
@@ -150,11 +235,16 @@ pub mod sdk {
     }
 
     impl Tx {
-        pub fn sign_bytes(&self, chain_id: String, nonce: u64) -> Result<Vec<u8>> {
+        pub fn sign_bytes(
+            &self,
+            chain_id: String,
+            account_number: u64,
+            nonce: u64,
+        ) -> Result<Vec<u8>> {
             match self {
                 Tx::Amino(tx) => {
                     let sign_tx = SignDoc {
-                        account_number: "0".to_string(),
+                        account_number: account_number.to_string(),
                         chain_id,
                         fee: tx.fee.clone(),
                         memo: tx.memo.clone(),
@@ -176,44 +266,55 @@ pub mod sdk {
                             .into_bytes()
                             .map_err(|e| Error::App(e.to_string()))?,
                         chain_id,
-                        account_number: 0,
+                        account_number,
                     };
                     signdoc.into_bytes().map_err(|e| Error::App(e.to_string()))
                 }
+                Tx::Unknown(tx) => Err(tx.error()),
             }
         }
 
+        /// The public key of the first signer, which pays for and authorizes
+        /// the tx.
         pub fn sender_pubkey(&self) -> Result<[u8; 33]> {
-            let pubkey_vec = match self {
-                Tx::Amino(tx) => {
-                    let pubkey_b64 = &tx
-                        .signatures
-                        .first()
-                        .ok_or_else(|| Error::App("No signatures provided".to_string()))?
-                        .pub_key
-                        .value;
-                    use base64::Engine;
-                    base64::prelude::BASE64_STANDARD
-                        .decode(pubkey_b64)
-                        .map_err(|e| Error::App(e.to_string()))?
+            Ok(self.sender_pubkeys()?[0])
+        }
+
+        /// The public keys of all signers, in the same order as
+        /// [Tx::signatures].
+        pub fn sender_pubkeys(&self) -> Result<Vec<[u8; 33]>> {
+            let pubkeys = match self {
+                Tx::Amino(tx) => tx
+                    .signatures
+                    .iter()
+                    .map(|sig| decode_b64(&sig.pub_key.value))
+                    .collect::<Result<Vec<_>>>()?,
+                Tx::Protobuf(tx) => {
+                    check_signer_count(tx)?;
+                    tx.auth_info
+                        .signer_infos
+                        .iter()
+                        .map(|info| {
+                            Ok(info
+                                .public_key
+                                .as_ref()
+                                .ok_or_else(|| Error::App("No public key provided".to_string()))?
+                                .single()
+                                .ok_or_else(|| Error::App("Invalid public key".to_string()))?
+                                .to_bytes())
+                        })
+                        .collect::<Result<Vec<_>>>()?
                 }
-                Tx::Protobuf(tx) => tx
-                    .auth_info
-                    .signer_infos
-                    .first()
-                    .ok_or_else(|| Error::App("No auth info provided".to_string()))?
-                    .public_key
-                    .as_ref()
-                    .ok_or_else(|| Error::App("No public key provided".to_string()))?
-                    .single()
-                    .ok_or_else(|| Error::App("Invalid public key".to_string()))?
-                    .to_bytes(),
+                Tx::Unknown(tx) => return Err(tx.error()),
             };
 
-            let mut pubkey_arr = [0; 33];
-            pubkey_arr.copy_from_slice(&pubkey_vec);
-
-            Ok(pubkey_arr)
+            if pubkeys.is_empty() {
+                return Err(Error::App("No signatures provided".to_string()));
+            }
+            pubkeys
+                .iter()
+                .map(|pubkey| to_array(pubkey, "public key"))
+                .collect()
         }
 
         pub fn sender_address(&self) -> Result<Address> {
@@ -221,31 +322,31 @@ pub mod sdk {
             signer_call.address()
         }
 
+        /// The signature of the first signer.
         pub fn signature(&self) -> Result<[u8; 64]> {
-            let sig_vec = match self {
-                Tx::Amino(tx) => {
-                    let sig_b64 = &tx
-                        .signatures
-                        .first()
-                        .ok_or_else(|| Error::App("No signatures provided".to_string()))?
-                        .signature;
-
-                    use base64::Engine;
-                    base64::prelude::BASE64_STANDARD
-                        .decode(sig_b64)
-                        .map_err(|e| Error::App(e.to_string()))?
-                }
-                Tx::Protobuf(tx) => tx
+            Ok(self.signatures()?[0])
+        }
+
+        /// The signatures of all signers, in the same order as
+        /// [Tx::sender_pubkeys].
+        pub fn signatures(&self) -> Result<Vec<[u8; 64]>> {
+            let sigs = match self {
+                Tx::Amino(tx) => tx
                     .signatures
-                    .first()
-                    .ok_or_else(|| Error::App("No signatures provided".to_string()))?
-                    .clone(),
+                    .iter()
+                    .map(|sig| decode_b64(&sig.signature))
+                    .collect::<Result<Vec<_>>>()?,
+                Tx::Protobuf(tx) => {
+                    check_signer_count(tx)?;
+                    tx.signatures.clone()
+                }
+                Tx::Unknown(tx) => return Err(tx.error()),
             };
 
-            let mut sig_arr = [0; 64];
-            sig_arr.copy_from_slice(&sig_vec);
-
-            Ok(sig_arr)
+            if sigs.is_empty() {
+                return Err(Error::App("No signatures provided".to_string()));
+            }
+            sigs.iter().map(|sig| to_array(sig, "signature")).collect()
         }
 
         pub fn sig_type(&self) -> Result<Option<&str>> {
@@ -258,10 +359,41 @@ pub mod sdk {
                     .as_deref(),
 
                 Tx::Protobuf(_) => None,
+                Tx::Unknown(tx) => return Err(tx.error()),
             })
         }
     }
 
+    fn check_signer_count(tx: &cosmrs::Tx) -> Result<()> {
+        if tx.auth_info.signer_infos.len() != tx.signatures.len() {
+            return Err(Error::App(format!(
+                "Tx has {} signer infos but {} signatures",
+                tx.auth_info.signer_infos.len(),
+                tx.signatures.len()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn decode_b64(value: &str) -> Result<Vec<u8>> {
+        use base64::Engine;
+        base64::prelude::BASE64_STANDARD
+            .decode(value)
+            .map_err(|e| Error::App(e.to_string()))
+    }
+
+    fn to_array<const N: usize>(bytes: &[u8], name: &str) -> Result<[u8; N]> {
+        bytes.try_into().map_err(|_| {
+            Error::App(format!(
+                "Invalid {} length: expected {}, got {}",
+                name,
+                N,
+                bytes.len()
+            ))
+        })
+    }
+
     #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
     pub struct SignDoc {
         pub account_number: String,
@@ -312,6 +444,56 @@ pub mod sdk {
         pub amount: Vec<Coin>,
     }
 
+    impl From<&cosmrs::bank::MsgSend> for MsgSend {
+        fn from(msg: &cosmrs::bank::MsgSend) -> Self {
+            MsgSend {
+                from_address: msg.from_address.to_string(),
+                to_address: msg.to_address.to_string(),
+                amount: msg
+                    .amount
+                    .iter()
+                    .map(|coin| Coin {
+                        amount: coin.amount.to_string(),
+                        denom: coin.denom.to_string(),
+                    })
+                    .collect(),
+            }
+        }
+    }
+
+    /// Parses a `MsgSend` into the recipient and amount arguments of
+    /// `Accounts::transfer`, e.g. for use in a [ConvertSdkTx](super::ConvertSdkTx)
+    /// implementation.
+    ///
+    /// The amount must be a single coin whose denom is `S::NAME`. The sender is
+    /// not returned since `transfer` sends from the tx signer, so callers
+    /// should check that `from_address` matches `Tx::sender_address`.
+    pub fn msg_send_to_transfer<S: Symbol>(msg: &MsgSend) -> Result<(Address, Amount)> {
+        let to: Address = msg
+            .to_address
+            .parse()
+            .map_err(|e| Error::App(format!("Invalid recipient address: {}", e)))?;
+
+        let coin = match msg.amount.as_slice() {
+            [coin] => coin,
+            [] => return Err(Error::App("MsgSend has no amount".to_string())),
+            _ => return Err(Error::App("MsgSend must send exactly one coin".to_string())),
+        };
+        if coin.denom != S::NAME {
+            return Err(Error::App(format!(
+                "Invalid denom: expected {}, got {}",
+                S::NAME,
+                coin.denom
+            )));
+        }
+        let amount: u64 = coin
+            .amount
+            .parse()
+            .map_err(|e| Error::App(format!("Invalid amount: {}", e)))?;
+
+        Ok((to, amount.into()))
+    }
+
     #[derive(Deserialize, Debug, Clone)]
     pub struct MsgDelegate {
         pub delegator_address: String,
@@ -341,11 +523,11 @@ pub trait ConvertSdkTx {
     fn convert(&self, msg: &sdk::Tx) -> Result<Self::Output>;
 }
 
-impl<S: Symbol, T> CallTrait for SdkCompatPlugin<S, T>
+impl<S: Symbol, T, const MAX_SIZE: usize> CallTrait for SdkCompatPlugin<S, T, MAX_SIZE>
 where
     T: CallTrait + State + ConvertSdkTx<Output = T::Call>,
 {
-    type Call = Call<T::Call>;
+    type Call = Call<T::Call, MAX_SIZE>;
 
     fn call(&mut self, call: Self::Call) -> Result<()> {
         let call = match call {
@@ -357,8 +539,10 @@ where
     }
 }
 
-impl<S: 'static, T: State> MigrateFrom<SdkCompatPluginV0<S, T>> for SdkCompatPluginV1<S, T> {
-    fn migrate_from(_value: SdkCompatPluginV0<S, T>) -> Result<Self> {
+impl<S: 'static, T: State, const MAX_SIZE: usize> MigrateFrom<SdkCompatPluginV0<S, T, MAX_SIZE>>
+    for SdkCompatPluginV1<S, T, MAX_SIZE>
+{
+    fn migrate_from(_value: SdkCompatPluginV0<S, T, MAX_SIZE>) -> Result<Self> {
         unreachable!()
     }
 }
@@ -369,7 +553,7 @@ mod abci {
     use super::*;
     use crate::abci::{BeginBlock, EndBlock, InitChain};
 
-    impl<S, T: State> BeginBlock for SdkCompatPlugin<S, T>
+    impl<S, T: State, const MAX_SIZE: usize> BeginBlock for SdkCompatPlugin<S, T, MAX_SIZE>
     where
         T: BeginBlock + State,
     {
@@ -378,7 +562,7 @@ mod abci {
         }
     }
 
-    impl<S, T: State> EndBlock for SdkCompatPlugin<S, T>
+    impl<S, T: State, const MAX_SIZE: usize> EndBlock for SdkCompatPlugin<S, T, MAX_SIZE>
     where
         T: EndBlock + State,
     {
@@ -387,7 +571,7 @@ mod abci {
         }
     }
 
-    impl<S, T: State> InitChain for SdkCompatPlugin<S, T>
+    impl<S, T: State, const MAX_SIZE: usize> InitChain for SdkCompatPlugin<S, T, MAX_SIZE>
     where
         T: InitChain + State,
     {
@@ -396,7 +580,29 @@ mod abci {
         }
     }
 
-    impl<S, T> crate::abci::AbciQuery for SdkCompatPlugin<S, T>
+    impl<S, T, const MAX_SIZE: usize> crate::abci::ExtendVote for SdkCompatPlugin<S, T, MAX_SIZE>
+    where
+        T: crate::abci::ExtendVote + State + CallTrait,
+    {
+        fn extend_vote(&self, ctx: &crate::plugins::ExtendVoteCtx) -> Result<Vec<u8>> {
+            self.inner.extend_vote(ctx)
+        }
+    }
+
+    impl<S, T, const MAX_SIZE: usize> crate::abci::VerifyVoteExtension
+        for SdkCompatPlugin<S, T, MAX_SIZE>
+    where
+        T: crate::abci::VerifyVoteExtension + State + CallTrait,
+    {
+        fn verify_vote_extension(
+            &self,
+            ctx: &crate::plugins::VerifyVoteExtensionCtx,
+        ) -> Result<bool> {
+            self.inner.verify_vote_extension(ctx)
+        }
+    }
+
+    impl<S, T, const MAX_SIZE: usize> crate::abci::AbciQuery for SdkCompatPlugin<S, T, MAX_SIZE>
     where
         T: crate::abci::AbciQuery + State + CallTrait,
     {
@@ -408,3 +614,258 @@ mod abci {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::sdk::*;
+    use super::*;
+    use cosmrs::proto::cosmos::base::v1beta1::Coin as ProtoCoin;
+    use cosmrs::proto::cosmos::tx::v1beta1::{AuthInfo, Fee as ProtoFee, Tx as ProtoTx, TxBody};
+    use cosmrs::Any;
+    use prost::Message;
+
+    fn any(type_url: &str) -> Any {
+        Any {
+            type_url: type_url.to_string(),
+            value: vec![1, 2, 3],
+        }
+    }
+
+    fn proto_tx(fee_amount: &str) -> ProtoTx {
+        ProtoTx {
+            body: Some(TxBody {
+                messages: vec![any("/foo.v1.MsgFoo")],
+                memo: "memo".to_string(),
+                extension_options: vec![any("/foo.v1.ExtensionOption")],
+                non_critical_extension_options: vec![any("/foo.v1.NonCritical")],
+                ..Default::default()
+            }),
+            auth_info: Some(AuthInfo {
+                fee: Some(ProtoFee {
+                    amount: vec![ProtoCoin {
+                        denom: "uoraii".to_string(),
+                        amount: fee_amount.to_string(),
+                    }],
+                    gas_limit: 100_000,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            signatures: vec![vec![0; 64]],
+        }
+    }
+
+    #[test]
+    fn extension_options() {
+        let bytes = proto_tx("1000").encode_to_vec();
+        let tx = match Tx::decode(bytes.as_slice()).unwrap() {
+            Tx::Protobuf(tx) => tx,
+            other => panic!("Expected protobuf tx, got {:?}", other),
+        };
+
+        assert_eq!(tx.body.messages[0].type_url, "/foo.v1.MsgFoo");
+        assert_eq!(
+            tx.body.extension_options[0].type_url,
+            "/foo.v1.ExtensionOption"
+        );
+        assert_eq!(
+            tx.body.non_critical_extension_options[0].type_url,
+            "/foo.v1.NonCritical"
+        );
+    }
+
+    #[test]
+    fn signer_count_mismatch() {
+        let bytes = proto_tx("1000").encode_to_vec();
+        let tx = Tx::decode(bytes.as_slice()).unwrap();
+        let err = tx.sender_pubkeys().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "App Error: Tx has 0 signer infos but 1 signatures"
+        );
+        assert!(tx.signatures().is_err());
+    }
+
+    #[test]
+    fn unknown_tx() {
+        let bytes = proto_tx("not a number").encode_to_vec();
+        let tx = Tx::decode(bytes.as_slice()).unwrap();
+        let unknown = match &tx {
+            Tx::Unknown(tx) => tx,
+            other => panic!("Expected unknown tx, got {:?}", other),
+        };
+
+        assert_eq!(unknown.msg_type_urls(), vec!["/foo.v1.MsgFoo"]);
+        assert_eq!(tx.encode().unwrap(), bytes);
+
+        let err = tx.sender_address().unwrap_err();
+        assert!(err.to_string().contains("Unsupported sdk tx"));
+    }
+
+    #[test]
+    fn sign_bytes_account_number() {
+        let tx = Tx::Amino(AminoTx {
+            msg: vec![Msg {
+                type_: "cosmos-sdk/MsgSend".to_string(),
+                value: serde_json::json!({
+                    "amount": [{ "amount": "1000", "denom": "uoraibtc" }],
+                    "from_address": "oraibtc19rl4cm2hmr8afy4kldpxz3fka4jguq0a9ets0e",
+                    "to_address": "oraibtc1jrkmdcwgq94uaamx6zax2luewlhf7u4k9pquxw",
+                }),
+            }],
+            fee: Fee {
+                amount: vec![Coin {
+                    amount: "0".to_string(),
+                    denom: "uoraibtc".to_string(),
+                }],
+                gas: "200000".to_string(),
+            },
+            memo: "".to_string(),
+            signatures: vec![],
+        });
+        // the canonical Amino JSON sign doc (sorted keys, no whitespace) for
+        // account number 42, sequence 3, in the form cosmjs' `serializeSignDoc`
+        // produces for Keplr
+        let expected = r#"{"account_number":"42","chain_id":"oraibtc-mainnet-1","fee":{"amount":[{"amount":"0","denom":"uoraibtc"}],"gas":"200000"},"memo":"","msgs":[{"type":"cosmos-sdk/MsgSend","value":{"amount":[{"amount":"1000","denom":"uoraibtc"}],"from_address":"oraibtc19rl4cm2hmr8afy4kldpxz3fka4jguq0a9ets0e","to_address":"oraibtc1jrkmdcwgq94uaamx6zax2luewlhf7u4k9pquxw"}}],"sequence":"3"}"#;
+        let sign_bytes = tx
+            .sign_bytes("oraibtc-mainnet-1".to_string(), 42, 3)
+            .unwrap();
+        assert_eq!(String::from_utf8(sign_bytes).unwrap(), expected);
+
+        let bytes = proto_tx("1000").encode_to_vec();
+        let tx = Tx::decode(bytes.as_slice()).unwrap();
+        let sign_bytes = tx
+            .sign_bytes("oraibtc-mainnet-1".to_string(), 42, 3)
+            .unwrap();
+        let doc =
+            cosmrs::proto::cosmos::tx::v1beta1::SignDoc::decode(sign_bytes.as_slice()).unwrap();
+        assert_eq!(doc.account_number, 42);
+        assert_eq!(doc.chain_id, "oraibtc-mainnet-1");
+        assert!(sign_bytes.ends_with(&[0x20, 42]));
+    }
+
+    fn oversized_calls() -> (Vec<u8>, Vec<u8>) {
+        let mut tx = proto_tx("1000");
+        tx.body.as_mut().unwrap().memo = "x".repeat(MAX_CALL_SIZE);
+        let mut native = vec![NATIVE_CALL_FLAG];
+        native.extend(vec![0; MAX_CALL_SIZE]);
+
+        (tx.encode_to_vec(), native)
+    }
+
+    #[test]
+    fn max_call_size_limit() {
+        let (sdk, native) = oversized_calls();
+
+        // the limit is only applied by `Call`, so it can be configured
+        Tx::decode(sdk.as_slice()).unwrap();
+        let err = super::Call::<Vec<u8>>::decode(sdk.as_slice()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("call exceeds max size {}", MAX_CALL_SIZE)));
+        let err = super::Call::<Vec<u8>>::decode(native.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("call exceeds max size"));
+    }
+
+    #[test]
+    fn custom_max_call_size() {
+        type LargeCall = super::Call<Vec<u8>, { 2 * MAX_CALL_SIZE }>;
+        let (sdk, native) = oversized_calls();
+
+        let call = LargeCall::decode(sdk.as_slice()).unwrap();
+        assert_eq!(call.encode().unwrap(), sdk);
+        LargeCall::decode(native.as_slice()).unwrap();
+
+        let mut sdk = sdk;
+        sdk.extend(vec![0; MAX_CALL_SIZE]);
+        let err = LargeCall::decode(sdk.as_slice()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("call exceeds max size {}", 2 * MAX_CALL_SIZE)));
+    }
+
+    #[test]
+    fn protobuf_tx_starting_with_brace() {
+        // an empty group for unknown field 15 encodes as `{|`, which protobuf
+        // decoders skip
+        let mut bytes = vec![0x7b, 0x7c];
+        bytes.extend(proto_tx("1000").encode_to_vec());
+        match Tx::decode(bytes.as_slice()).unwrap() {
+            Tx::Protobuf(tx) => assert_eq!(tx.body.memo, "memo"),
+            other => panic!("Expected protobuf tx, got {:?}", other),
+        }
+
+        let err = Tx::decode(br#"{"foo":1}"#.as_slice()).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("not an Amino JSON tx"), "{}", msg);
+        assert!(msg.contains("or a protobuf tx"), "{}", msg);
+    }
+
+    #[test]
+    fn invalid_protobuf() {
+        assert!(Tx::decode([0x0a, 0xff].as_slice()).is_err());
+    }
+
+    #[orga]
+    #[derive(Clone, Debug)]
+    struct Simp;
+    impl Symbol for Simp {
+        const INDEX: u8 = 7;
+        const NAME: &'static str = "SIMP";
+    }
+
+    fn msg_send(amount: serde_json::Value) -> MsgSend {
+        serde_json::from_value(serde_json::json!({
+            "from_address": "oraibtc19rl4cm2hmr8afy4kldpxz3fka4jguq0a9ets0e",
+            "to_address": "oraibtc1jrkmdcwgq94uaamx6zax2luewlhf7u4k9pquxw",
+            "amount": amount,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn msg_send_transfer() -> Result<()> {
+        let msg = msg_send(serde_json::json!([{ "denom": "SIMP", "amount": "1000" }]));
+        let (to, amount) = msg_send_to_transfer::<Simp>(&msg)?;
+        assert_eq!(
+            to.to_string(),
+            "oraibtc1jrkmdcwgq94uaamx6zax2luewlhf7u4k9pquxw"
+        );
+        assert_eq!(amount, 1000.into());
+
+        let err = msg_send_to_transfer::<Simp>(&msg_send(serde_json::json!([
+            { "denom": "SIMP", "amount": "1000" },
+            { "denom": "FOO", "amount": "1" },
+        ])))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "App Error: MsgSend must send exactly one coin"
+        );
+
+        let err = msg_send_to_transfer::<Simp>(&msg_send(serde_json::json!([])));
+        assert!(err.is_err());
+
+        let err = msg_send_to_transfer::<Simp>(&msg_send(serde_json::json!([
+            { "denom": "FOO", "amount": "1000" },
+        ])))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "App Error: Invalid denom: expected SIMP, got FOO"
+        );
+
+        let err = msg_send_to_transfer::<Simp>(&msg_send(serde_json::json!([
+            { "denom": "SIMP", "amount": "-5" },
+        ])));
+        assert!(err.is_err());
+
+        let mut msg = msg_send(serde_json::json!([{ "denom": "SIMP", "amount": "1" }]));
+        msg.to_address = "cosmos19rl4cm2hmr8afy4kldpxz3fka4jguq0auqdal4".to_string();
+        assert!(msg_send_to_transfer::<Simp>(&msg).is_err());
+        msg.to_address = "oraibtc1notanaddress".to_string();
+        assert!(msg_send_to_transfer::<Simp>(&msg).is_err());
+
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use super::{
     sdk_compat::{self, sdk::Tx as SdkTx, ConvertSdkTx},
-    ChainId, GetNonce,
+    ChainId, CheckTxCtx, GetNonce,
 };
 use crate::coins::{Address, Symbol};
 use crate::context::{Context, GetContext};
@@ -27,6 +27,24 @@ pub struct Signer {
     pub signer: Option<Address>,
 }
 
+/// The public key which signed the current call, present alongside [Signer]
+/// for signed calls.
+pub struct SignerPubKey(pub crate::coins::PublicKey);
+
+/// Every address which signed the current call: the [Signer] followed by the
+/// other signers of a multi-signer SDK tx. Empty for unsigned calls.
+pub struct Signers(pub Vec<Address>);
+
+/// Chain ids of other networks SDK txs may be replayed from, e.g. a testnet of
+/// the same app. An SDK signature which fails to verify is checked against
+/// these, and rejected as `wrong chain id` if it was made for one of them.
+///
+/// Not present unless configured, e.g. with
+/// [Node::other_chain_ids](crate::abci::Node::other_chain_ids); without it
+/// such txs fail as `Invalid signature`.
+#[derive(Clone)]
+pub struct OtherChainIds(pub Vec<String>);
+
 #[derive(Debug, Encode, Decode)]
 pub struct SignerCall {
     pub signature: Option<[u8; 64]>,
@@ -119,17 +137,132 @@ impl<T: State> SignerPlugin<T>
 where
     T: GetNonce,
 {
-    fn sdk_sign_bytes(&mut self, tx: &SdkTx, address: Address) -> Result<Vec<u8>> {
-        let nonce = self.inner.nonce(address)? + 1;
-        let chain_id = self
+    fn chain_id(&mut self) -> Result<String> {
+        Ok(self
             .context::<ChainId>()
             .ok_or_else(|| Error::App("Chain ID not found".to_string()))?
             .deref()
-            .to_string();
-        tx.sign_bytes(chain_id, nonce)
+            .to_string())
+    }
+
+    fn sdk_sign_bytes(
+        &mut self,
+        tx: &SdkTx,
+        address: Address,
+        chain_id: String,
+    ) -> Result<Vec<u8>> {
+        let nonce = self.inner.nonce(address)? + 1;
+        let account_number = self.inner.account_number(address)?;
+        tx.sign_bytes(chain_id, account_number, nonce)
+    }
+
+    /// Builds the message signed by the holder of `pubkey` for an SDK tx on
+    /// the given chain, and returns it with the signer's address.
+    fn sdk_msg(
+        &mut self,
+        tx: &SdkTx,
+        pubkey: &PublicKey,
+        eth: bool,
+        chain_id: String,
+    ) -> Result<(Message, Address)> {
+        use secp256k1::hashes::sha256;
+
+        if !eth {
+            let addr = Address::from_pubkey(pubkey.serialize());
+            let bytes = self.sdk_sign_bytes(tx, addr, chain_id)?;
+            let msg = Message::from_hashed_data::<sha256::Hash>(bytes.as_slice());
+            return Ok((msg, addr));
+        }
+
+        let pubkey_bytes = pubkey.serialize_uncompressed();
+        let mut eth_pubkey = [0; 64];
+        eth_pubkey.copy_from_slice(&pubkey_bytes[1..]);
+        let addr = Address::from_pubkey_eth(eth_pubkey);
+
+        let prefix = b"\x19Ethereum Signed Message:\n";
+        let mut sdk_bytes = self.sdk_sign_bytes(tx, addr, chain_id)?;
+        let mut len_bytes = sdk_bytes.len().to_string().as_bytes().to_vec();
+
+        let mut bytes = prefix.to_vec();
+        bytes.append(&mut len_bytes);
+        bytes.append(&mut sdk_bytes);
+
+        use sha3::{Digest, Keccak256};
+        let mut hasher = Keccak256::new();
+        hasher.update(&bytes);
+        let hash = hasher.finalize();
+
+        Ok((Message::from_slice(&hash)?, addr))
     }
 
-    fn verify(&mut self, call: &SignerCall) -> Result<Option<Address>> {
+    /// Whether the ECDSA signature check can be skipped, when rechecking a
+    /// mempool tx (see [CheckTxCtx]) which was verified when it entered the
+    /// mempool.
+    ///
+    /// Only native and ADR-36 signatures are skipped, since they only cover
+    /// the call bytes. SDK sign docs also cover the signer's sequence and
+    /// account number, so they are verified again against the new state to
+    /// evict txs whose sequence has since been used.
+    fn skip_signature_check(&mut self, sigtype: &SigType) -> bool {
+        matches!(sigtype, SigType::Native | SigType::Adr36)
+            && self
+                .context::<CheckTxCtx>()
+                .map_or(false, |ctx| ctx.recheck)
+    }
+
+    /// Verifies the signatures of every signer of an SDK tx after the first,
+    /// each over the sign doc built for its own address, and returns their
+    /// addresses. The first signer is the one reported in the [Signer]
+    /// context, and is verified by the caller.
+    fn verify_cosigners(&mut self, tx: &SdkTx, eth: bool) -> Result<Vec<Address>> {
+        let pubkeys = tx.sender_pubkeys()?;
+        let signatures = tx.signatures()?;
+
+        let secp = Secp256k1::verification_only();
+        let mut cosigners = vec![];
+        for (i, (pubkey, signature)) in pubkeys.iter().zip(signatures).enumerate().skip(1) {
+            let pubkey = PublicKey::from_slice(pubkey)?;
+            let chain_id = self.chain_id()?;
+            let (msg, addr) = self.sdk_msg(tx, &pubkey, eth, chain_id)?;
+            let signature = Signature::from_compact(&signature)?;
+            #[cfg(not(fuzzing))]
+            secp.verify_ecdsa(&msg, &signature, &pubkey)
+                .map_err(|_| Error::Signer(format!("Invalid signature for signer {}", i)))?;
+            cosigners.push(addr);
+        }
+
+        Ok(cosigners)
+    }
+
+    /// Whether an SDK signature which failed to verify over our own sign doc
+    /// verifies over the sign doc for one of the [OtherChainIds], i.e. the tx
+    /// was signed for another network.
+    fn signed_for_other_chain(
+        &mut self,
+        tx: &SdkTx,
+        pubkey: &PublicKey,
+        signature: &Signature,
+        eth: bool,
+    ) -> Result<bool> {
+        let chain_ids = match self.context::<OtherChainIds>() {
+            Some(chain_ids) => chain_ids.0.clone(),
+            None => return Ok(false),
+        };
+
+        let secp = Secp256k1::verification_only();
+        for chain_id in chain_ids {
+            let (msg, _) = self.sdk_msg(tx, pubkey, eth, chain_id)?;
+            if secp.verify_ecdsa(&msg, signature, pubkey).is_ok() {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Verifies the call's signatures, returning the addresses of its signers
+    /// (see [Signers]).
+    fn verify(&mut self, call: &SignerCall) -> Result<Vec<Address>> {
         match (call.pubkey.as_ref(), call.signature) {
             (Some(pubkey_bytes), Some(signature)) => {
                 use secp256k1::hashes::sha256;
@@ -149,43 +282,45 @@ where
                         (msg, addr)
                     }
                     SigType::Sdk(tx) => {
-                        let addr = Address::from_pubkey(*pubkey_bytes);
-                        let bytes = self.sdk_sign_bytes(tx, addr)?;
-                        let msg = Message::from_hashed_data::<sha256::Hash>(bytes.as_slice());
-                        (msg, addr)
+                        let chain_id = self.chain_id()?;
+                        self.sdk_msg(tx, &pubkey, false, chain_id)?
                     }
                     SigType::EthPersonalSign(tx) => {
-                        let pubkey_bytes = pubkey.serialize_uncompressed();
-                        let mut eth_pubkey = [0; 64];
-                        eth_pubkey.copy_from_slice(&pubkey_bytes[1..]);
-                        let addr = Address::from_pubkey_eth(eth_pubkey);
-
-                        let prefix = b"\x19Ethereum Signed Message:\n";
-                        let mut sdk_bytes = self.sdk_sign_bytes(tx, addr)?;
-                        let mut len_bytes = sdk_bytes.len().to_string().as_bytes().to_vec();
-
-                        let mut bytes = prefix.to_vec();
-                        bytes.append(&mut len_bytes);
-                        bytes.append(&mut sdk_bytes);
-
-                        use sha3::{Digest, Keccak256};
-                        let mut hasher = Keccak256::new();
-                        hasher.update(&bytes);
-                        let hash = hasher.finalize();
-
-                        let msg = Message::from_slice(&hash)?;
-
-                        (msg, addr)
+                        let chain_id = self.chain_id()?;
+                        self.sdk_msg(tx, &pubkey, true, chain_id)?
                     }
                 };
 
                 let signature = Signature::from_compact(&signature)?;
                 #[cfg(not(fuzzing))]
-                secp.verify_ecdsa(&msg, &signature, &pubkey)?;
+                if !self.skip_signature_check(&call.sigtype) {
+                    let res = secp.verify_ecdsa(&msg, &signature, &pubkey);
+                    // SDK txs don't carry their chain id, it is only committed to
+                    // by the signature over the sign doc, which we rebuilt with our
+                    // own chain id. a failure may mean the tx was signed for
+                    // another network, which we can only tell by checking the
+                    // signature against the other chain ids we know of.
+                    if let (Err(_), SigType::Sdk(tx) | SigType::EthPersonalSign(tx)) =
+                        (&res, &call.sigtype)
+                    {
+                        let eth = matches!(call.sigtype, SigType::EthPersonalSign(_));
+                        if self.signed_for_other_chain(tx, &pubkey, &signature, eth)? {
+                            return Err(Error::App("wrong chain id".to_string()));
+                        }
+                        return Err(Error::Signer("Invalid signature".to_string()));
+                    }
+                    res?;
+                }
 
-                Ok(Some(addr))
+                let mut signers = vec![addr];
+                if let SigType::Sdk(tx) | SigType::EthPersonalSign(tx) = &call.sigtype {
+                    let eth = matches!(call.sigtype, SigType::EthPersonalSign(_));
+                    signers.extend(self.verify_cosigners(tx, eth)?);
+                }
+
+                Ok(signers)
             }
-            (None, None) => Ok(None),
+            (None, None) => Ok(vec![]),
             _ => Err(Error::Signer("Malformed transaction".into())),
         }
     }
@@ -199,10 +334,17 @@ where
 
     fn call(&mut self, call: Self::Call) -> Result<()> {
         Context::remove::<Signer>();
+        Context::remove::<SignerPubKey>();
+        Context::remove::<Signers>();
+        let signers = self.verify(&call)?;
         let signer_ctx = Signer {
-            signer: self.verify(&call)?,
+            signer: signers.first().copied(),
         };
+        if let (Some(_), Some(pubkey)) = (signer_ctx.signer, call.pubkey) {
+            Context::add(SignerPubKey(crate::coins::PublicKey::Secp256k1(pubkey)));
+        }
         Context::add(signer_ctx);
+        Context::add(Signers(signers));
 
         let inner_call = Decode::decode(call.call_bytes.as_slice())?;
         self.inner.call(inner_call)
@@ -477,6 +619,27 @@ mod abci {
         }
     }
 
+    impl<T> crate::abci::ExtendVote for SignerPlugin<T>
+    where
+        T: crate::abci::ExtendVote + State + Call,
+    {
+        fn extend_vote(&self, ctx: &crate::plugins::ExtendVoteCtx) -> Result<Vec<u8>> {
+            self.inner.extend_vote(ctx)
+        }
+    }
+
+    impl<T> crate::abci::VerifyVoteExtension for SignerPlugin<T>
+    where
+        T: crate::abci::VerifyVoteExtension + State + Call,
+    {
+        fn verify_vote_extension(
+            &self,
+            ctx: &crate::plugins::VerifyVoteExtensionCtx,
+        ) -> Result<bool> {
+            self.inner.verify_vote_extension(ctx)
+        }
+    }
+
     impl<T> crate::abci::AbciQuery for SignerPlugin<T>
     where
         T: crate::abci::AbciQuery + State + Call,
@@ -538,6 +701,7 @@ mod tests {
     }
 
     #[test]
+    #[serial_test::serial]
     fn eth_personal_sign() {
         let mut state = SdkCompatPlugin {
             symbol: std::marker::PhantomData::<X>,
@@ -568,4 +732,231 @@ mod tests {
         );
         Context::remove::<ChainId>();
     }
+
+    fn signed_amino_tx(chain_id: &str) -> sdk_compat::sdk::Tx {
+        multisigned_amino_tx(chain_id, &[7])
+    }
+
+    /// Builds an Amino tx signed by the secret key `[n; 32]` for each `n` in
+    /// `signers`, in order.
+    fn multisigned_amino_tx(chain_id: &str, signers: &[u8]) -> sdk_compat::sdk::Tx {
+        use base64::Engine;
+        use sdk_compat::sdk::{AminoTx, Fee, Msg, PubKey, Signature as SdkSignature, Tx};
+        use secp256k1::hashes::sha256;
+
+        let mut tx = AminoTx {
+            msg: vec![Msg {
+                type_: "x".to_string(),
+                value: serde_json::json!({}),
+            }],
+            fee: Fee {
+                amount: vec![],
+                gas: "10000".to_string(),
+            },
+            memo: "".to_string(),
+            signatures: vec![],
+        };
+        let sign_bytes = Tx::Amino(tx.clone())
+            .sign_bytes(chain_id.to_string(), 0, 1)
+            .unwrap();
+        let msg = Message::from_hashed_data::<sha256::Hash>(&sign_bytes);
+
+        let secp = Secp256k1::new();
+        let b64 = base64::prelude::BASE64_STANDARD;
+        for n in signers {
+            let privkey = SecretKey::from_slice(&[*n; 32]).unwrap();
+            let pubkey = PublicKey::from_secret_key(&secp, &privkey);
+            let sig = secp.sign_ecdsa(&msg, &privkey).serialize_compact();
+            tx.signatures.push(SdkSignature {
+                pub_key: PubKey {
+                    type_: "tendermint/PubKeySecp256k1".to_string(),
+                    value: b64.encode(pubkey.serialize()),
+                },
+                signature: b64.encode(sig),
+                r#type: None,
+            });
+        }
+
+        Tx::Amino(tx)
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn wrong_chain_id() {
+        let mut state = SdkCompatPlugin {
+            symbol: std::marker::PhantomData::<X>,
+            inner: SignerPlugin {
+                inner: Counter {
+                    count: 0,
+                    last_signer: Address::NULL,
+                },
+            },
+        };
+
+        Context::add(ChainId("b".to_string()));
+        let err =
+            SdkCompatPlugin::<_, _>::call(&mut state, sdk_compat::Call::Sdk(signed_amino_tx("a")))
+                .unwrap_err();
+        assert_eq!(err.to_string(), "Signer Error: Invalid signature");
+
+        Context::add(OtherChainIds(vec!["c".to_string(), "a".to_string()]));
+        let err =
+            SdkCompatPlugin::<_, _>::call(&mut state, sdk_compat::Call::Sdk(signed_amino_tx("a")))
+                .unwrap_err();
+        assert_eq!(err.to_string(), "App Error: wrong chain id");
+        let err =
+            SdkCompatPlugin::<_, _>::call(&mut state, sdk_compat::Call::Sdk(signed_amino_tx("d")))
+                .unwrap_err();
+        assert_eq!(err.to_string(), "Signer Error: Invalid signature");
+        assert_eq!(state.inner.inner.count, 0);
+        Context::remove::<OtherChainIds>();
+
+        SdkCompatPlugin::<_, _>::call(&mut state, sdk_compat::Call::Sdk(signed_amino_tx("b")))
+            .unwrap();
+        assert_eq!(state.inner.inner.count, 1);
+        Context::remove::<ChainId>();
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn wrong_chain_id_inherited() {
+        let mut state = SdkCompatPlugin {
+            symbol: std::marker::PhantomData::<X>,
+            inner: SignerPlugin {
+                inner: Counter {
+                    count: 0,
+                    last_signer: Address::NULL,
+                },
+            },
+        };
+
+        // added the same way as by `Node`, so isolated contexts see them
+        Context::add_inherited(ChainId("b".to_string()));
+        Context::add_inherited(OtherChainIds(vec!["a".to_string()]));
+        let err = Context::isolated(|| {
+            SdkCompatPlugin::<_, _>::call(&mut state, sdk_compat::Call::Sdk(signed_amino_tx("a")))
+                .unwrap_err()
+        });
+        assert_eq!(err.to_string(), "App Error: wrong chain id");
+        assert_eq!(state.inner.inner.count, 0);
+
+        Context::remove::<OtherChainIds>();
+        Context::remove::<ChainId>();
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn multisig() {
+        let mut state = SdkCompatPlugin {
+            symbol: std::marker::PhantomData::<X>,
+            inner: SignerPlugin {
+                inner: Counter {
+                    count: 0,
+                    last_signer: Address::NULL,
+                },
+            },
+        };
+        Context::add(ChainId("a".to_string()));
+
+        let tx = multisigned_amino_tx("a", &[7, 8]);
+        assert_eq!(tx.sender_pubkeys().unwrap().len(), 2);
+        assert_eq!(tx.signatures().unwrap().len(), 2);
+        assert_eq!(tx.sender_pubkey().unwrap(), tx.sender_pubkeys().unwrap()[0]);
+        SdkCompatPlugin::<_, _>::call(&mut state, sdk_compat::Call::Sdk(tx.clone())).unwrap();
+        assert_eq!(state.inner.inner.count, 1);
+        assert_eq!(state.inner.inner.last_signer, tx.sender_address().unwrap());
+        let signers: Vec<_> = tx
+            .sender_pubkeys()
+            .unwrap()
+            .into_iter()
+            .map(Address::from_pubkey)
+            .collect();
+        assert_eq!(Context::resolve::<Signers>().unwrap().0, signers);
+
+        let mut tx = match multisigned_amino_tx("a", &[7, 8]) {
+            sdk_compat::sdk::Tx::Amino(tx) => tx,
+            _ => unreachable!(),
+        };
+        tx.signatures[1].signature = tx.signatures[0].signature.clone();
+        let err = SdkCompatPlugin::<_, _>::call(
+            &mut state,
+            sdk_compat::Call::Sdk(sdk_compat::sdk::Tx::Amino(tx)),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Signer Error: Invalid signature for signer 1"
+        );
+        assert_eq!(state.inner.inner.count, 1);
+
+        Context::remove::<ChainId>();
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn recheck_skips_native_signature_check() {
+        use secp256k1::hashes::sha256;
+
+        let mut state = SignerPlugin {
+            inner: Counter {
+                count: 0,
+                last_signer: Address::NULL,
+            },
+        };
+
+        let secp = Secp256k1::new();
+        let privkey = SecretKey::from_slice(&[7; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&secp, &privkey);
+        let other_msg = Message::from_hashed_data::<sha256::Hash>(b"other call");
+        let signature = secp.sign_ecdsa(&other_msg, &privkey).serialize_compact();
+        let call = || SignerCall {
+            signature: Some(signature),
+            pubkey: Some(pubkey.serialize()),
+            sigtype: SigType::Native,
+            call_bytes: <Counter as Call>::Call::Method(CounterMethodCall::Increment())
+                .encode()
+                .unwrap(),
+        };
+
+        Context::add(CheckTxCtx { recheck: false });
+        assert!(state.call(call()).is_err());
+        assert_eq!(state.inner.count, 0);
+
+        Context::add(CheckTxCtx { recheck: true });
+        state.call(call()).unwrap();
+        assert_eq!(state.inner.count, 1);
+
+        Context::remove::<CheckTxCtx>();
+        Context::remove::<Signer>();
+        Context::remove::<SignerPubKey>();
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn recheck_verifies_sdk_signatures() {
+        let mut state = SdkCompatPlugin {
+            symbol: std::marker::PhantomData::<X>,
+            inner: SignerPlugin {
+                inner: Counter {
+                    count: 0,
+                    last_signer: Address::NULL,
+                },
+            },
+        };
+        Context::add(ChainId("a".to_string()));
+
+        let mut tx = match multisigned_amino_tx("a", &[7, 8]) {
+            sdk_compat::sdk::Tx::Amino(tx) => tx,
+            _ => unreachable!(),
+        };
+        tx.signatures[1].signature = tx.signatures[0].signature.clone();
+        let call = sdk_compat::Call::Sdk(sdk_compat::sdk::Tx::Amino(tx));
+
+        Context::add(CheckTxCtx { recheck: true });
+        assert!(SdkCompatPlugin::<_, _>::call(&mut state, call).is_err());
+        assert_eq!(state.inner.inner.count, 0);
+
+        Context::remove::<CheckTxCtx>();
+        Context::remove::<ChainId>();
+    }
 }
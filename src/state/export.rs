@@ -0,0 +1,51 @@
+use serde::Serialize;
+
+use super::State;
+use crate::Result;
+
+/// Renders the full contents of a state type as JSON, including the entries
+/// of its collections (loaded from the store as needed), e.g. for the readable
+/// `app_state` of a [`StateExport`](crate::merk::StateExport).
+///
+/// Implemented for every `State` which is `Serialize`, which includes the
+/// types defined with `#[orga]` unless they skip the `Serialize` derive.
+pub trait ExportState {
+    fn export_state(&self) -> Result<serde_json::Value>;
+}
+
+impl<T: State + Serialize> ExportState for T {
+    fn export_state(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::to_value(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::Map;
+    use crate::orga;
+    use crate::store::Store;
+    use serde_json::json;
+
+    #[orga]
+    struct Ledger {
+        count: u32,
+        balances: Map<u32, u64>,
+    }
+
+    #[test]
+    fn export_state() -> Result<()> {
+        let mut ledger = Ledger::default();
+        ledger.attach(Store::with_map_store())?;
+        ledger.count = 2;
+        ledger.balances.insert(1, 10)?;
+        ledger.balances.insert(2, 20)?;
+
+        assert_eq!(
+            ledger.export_state()?,
+            json!({ "count": 2, "balances": [[1, 10], [2, 20]] })
+        );
+
+        Ok(())
+    }
+}
@@ -7,6 +7,8 @@ pub use orga_macros::State;
 
 mod attach;
 pub use attach::Attacher;
+mod export;
+pub use export::ExportState;
 mod flush;
 pub use flush::Flusher;
 mod load;
@@ -0,0 +1,236 @@
+//! Asynchronous counterparts of the [Read] and [Write] store traits, for
+//! stores whose operations are slow enough (remote or disk-backed) that they
+//! should be awaited rather than block an async runtime.
+
+use super::{Read, Write, KV};
+use crate::Result;
+
+/// Async read access to key/value stores, mirroring [Read].
+pub trait AsyncRead {
+    /// Gets a value by key, or `None` if there is no value for the key.
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Gets the key/value entry which comes directly after `key` in ascending
+    /// key order, or `None` if there are no entries which follow.
+    async fn get_next(&self, key: &[u8]) -> Result<Option<KV>>;
+
+    /// Gets the entry at `key` if it exists, otherwise returns the next entry
+    /// by ascending key order, or `None` if there are no entries which follow.
+    async fn get_next_inclusive(&self, key: &[u8]) -> Result<Option<KV>> {
+        match self.get(key).await? {
+            Some(value) => Ok(Some((key.to_vec(), value))),
+            None => self.get_next(key).await,
+        }
+    }
+
+    /// Gets the entry which comes directly before `key` in ascending key
+    /// order, or the last entry if `key` is `None`.
+    async fn get_prev(&self, key: Option<&[u8]>) -> Result<Option<KV>>;
+
+    async fn get_prev_inclusive(&self, key: Option<&[u8]>) -> Result<Option<KV>> {
+        match key {
+            Some(key) => match self.get(key).await? {
+                Some(value) => Ok(Some((key.to_vec(), value))),
+                None => self.get_prev(Some(key)).await,
+            },
+            None => self.get_prev(None).await,
+        }
+    }
+}
+
+/// Async write access to key/value stores, mirroring [Write].
+pub trait AsyncWrite: AsyncRead {
+    /// Writes a key and value to the store, overwriting any existing value.
+    async fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
+
+    /// Deletes the value with the given key, if any.
+    async fn delete(&mut self, key: &[u8]) -> Result<()>;
+}
+
+impl<T: AsyncRead> AsyncRead for &T {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        (**self).get(key).await
+    }
+
+    async fn get_next(&self, key: &[u8]) -> Result<Option<KV>> {
+        (**self).get_next(key).await
+    }
+
+    async fn get_prev(&self, key: Option<&[u8]>) -> Result<Option<KV>> {
+        (**self).get_prev(key).await
+    }
+}
+
+/// Adapts a sync store to the async traits by running its operations
+/// directly on the calling task.
+///
+/// Only suitable for stores whose operations never block, such as
+/// [MapStore](super::MapStore). Use [Blocking] for disk-backed stores.
+#[derive(Default)]
+pub struct Immediate<S>(pub S);
+
+impl<S: Read> AsyncRead for Immediate<S> {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.0.get(key)
+    }
+
+    async fn get_next(&self, key: &[u8]) -> Result<Option<KV>> {
+        self.0.get_next(key)
+    }
+
+    async fn get_prev(&self, key: Option<&[u8]>) -> Result<Option<KV>> {
+        self.0.get_prev(key)
+    }
+}
+
+impl<S: Write> AsyncWrite for Immediate<S> {
+    async fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.0.put(key, value)
+    }
+
+    async fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.0.delete(key)
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use blocking::Blocking;
+
+#[cfg(feature = "tokio")]
+mod blocking {
+    use super::*;
+    use crate::Error;
+    use std::sync::{Arc, Mutex};
+
+    /// Adapts a sync store to the async traits by running each operation on
+    /// tokio's blocking thread pool, so slow reads and writes don't stall the
+    /// runtime's worker threads.
+    ///
+    /// Operations are serialized by a mutex, and must be awaited from within a
+    /// tokio runtime.
+    pub struct Blocking<S>(Arc<Mutex<S>>);
+
+    impl<S> Clone for Blocking<S> {
+        fn clone(&self) -> Self {
+            Blocking(self.0.clone())
+        }
+    }
+
+    impl<S: Send + 'static> Blocking<S> {
+        pub fn new(store: S) -> Self {
+            Blocking(Arc::new(Mutex::new(store)))
+        }
+
+        /// Returns the wrapped store, or `None` if this adapter has been
+        /// cloned and the clones are still alive.
+        pub fn into_inner(self) -> Option<S> {
+            Arc::try_unwrap(self.0)
+                .ok()
+                .and_then(|store| store.into_inner().ok())
+        }
+
+        /// Runs `op` on the store on the blocking thread pool.
+        async fn run<T, F>(&self, op: F) -> Result<T>
+        where
+            T: Send + 'static,
+            F: FnOnce(&mut S) -> Result<T> + Send + 'static,
+        {
+            let store = self.0.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut store = store
+                    .lock()
+                    .map_err(|_| Error::Store("Store mutex is poisoned".into()))?;
+                op(&mut store)
+            })
+            .await
+            .map_err(|err| Error::Store(format!("Store task failed: {}", err)))?
+        }
+    }
+
+    impl<S: Read + Send + 'static> AsyncRead for Blocking<S> {
+        async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            let key = key.to_vec();
+            self.run(move |store| store.get(&key)).await
+        }
+
+        async fn get_next(&self, key: &[u8]) -> Result<Option<KV>> {
+            let key = key.to_vec();
+            self.run(move |store| store.get_next(&key)).await
+        }
+
+        async fn get_next_inclusive(&self, key: &[u8]) -> Result<Option<KV>> {
+            let key = key.to_vec();
+            self.run(move |store| store.get_next_inclusive(&key)).await
+        }
+
+        async fn get_prev(&self, key: Option<&[u8]>) -> Result<Option<KV>> {
+            let key = key.map(<[u8]>::to_vec);
+            self.run(move |store| store.get_prev(key.as_deref())).await
+        }
+
+        async fn get_prev_inclusive(&self, key: Option<&[u8]>) -> Result<Option<KV>> {
+            let key = key.map(<[u8]>::to_vec);
+            self.run(move |store| store.get_prev_inclusive(key.as_deref()))
+                .await
+        }
+    }
+
+    impl<S: Write + Send + 'static> AsyncWrite for Blocking<S> {
+        async fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+            self.run(move |store| store.put(key, value)).await
+        }
+
+        async fn delete(&mut self, key: &[u8]) -> Result<()> {
+            let key = key.to_vec();
+            self.run(move |store| store.delete(&key)).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MapStore;
+    use futures_lite::future::block_on;
+
+    async fn exercise(store: &mut impl AsyncWrite) -> Result<()> {
+        store.put(vec![1], vec![10]).await?;
+        store.put(vec![2], vec![20]).await?;
+        store.put(vec![3], vec![30]).await?;
+        store.delete(&[2]).await?;
+
+        assert_eq!(store.get(&[1]).await?, Some(vec![10]));
+        assert_eq!(store.get(&[2]).await?, None);
+        assert_eq!(store.get_next(&[1]).await?, Some((vec![3], vec![30])));
+        assert_eq!(
+            store.get_next_inclusive(&[1]).await?,
+            Some((vec![1], vec![10]))
+        );
+        assert_eq!(store.get_prev(None).await?, Some((vec![3], vec![30])));
+        assert_eq!(
+            store.get_prev_inclusive(Some(&[2])).await?,
+            Some((vec![1], vec![10]))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn immediate() -> Result<()> {
+        let mut store = Immediate(MapStore::new());
+        block_on(exercise(&mut store))?;
+        assert_eq!(store.0.get(&[3])?, Some(vec![30]));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn blocking() -> Result<()> {
+        let mut store = Blocking::new(MapStore::new());
+        exercise(&mut store).await?;
+        assert_eq!(store.into_inner().unwrap().get(&[3])?, Some(vec![30]));
+
+        Ok(())
+    }
+}
@@ -8,7 +8,7 @@ use crate::merk::ProofStore;
 use crate::merk::{merk::HASH_LENGTH, MerkStore, ProofBuilder};
 #[cfg(feature = "merk-full")]
 use crate::store::BufStore;
-use crate::store::ReadWrite;
+use crate::store::{Checkpoint, ReadWrite};
 use crate::store::{Empty, MapStore, PartialMapStore, Read, Shared, Write, KV};
 use crate::{Error, Result};
 #[cfg(feature = "merk-full")]
@@ -208,6 +208,19 @@ impl Write for BackingStore {
             }
         }
     }
+
+    fn write_batch(&mut self, batch: crate::store::BufStoreMap) -> Result<()> {
+        match self {
+            BackingStore::MapStore(ref mut store) => store.write_batch(batch),
+            BackingStore::Other(ref mut store) => store.borrow_mut().write_batch(batch),
+
+            #[cfg(feature = "merk-full")]
+            BackingStore::WrappedMerk(ref mut store) => store.write_batch(batch),
+            #[cfg(feature = "merk-full")]
+            BackingStore::Merk(ref mut store) => store.write_batch(batch),
+            _ => crate::store::write_each(self, batch),
+        }
+    }
 }
 
 impl BackingStore {
@@ -275,11 +288,72 @@ impl BackingStore {
         }
     }
 
+    /// Wraps any [Read] + [Write] implementation as a backing store, so
+    /// backends other than the built-in ones (e.g. sled, or a custom
+    /// in-memory store) can be plugged in at runtime without adding a variant.
+    pub fn other<T: ReadWrite>(store: T) -> Self {
+        BackingStore::Other(Shared::new(Box::new(store)))
+    }
+
     pub fn into_other(self) -> Result<Shared<Box<dyn ReadWrite>>> {
         match self {
             BackingStore::Other(store) => Ok(store),
             _ => Err(Error::Downcast(
-                "Failed to downcast backing store to map store".into(),
+                "Failed to downcast backing store to other store".into(),
+            )),
+        }
+    }
+
+    /// Unwraps a store created with [BackingStore::other], failing if it is
+    /// not of type `T`. Panics if the store is still shared.
+    pub fn into_other_as<T: ReadWrite>(self) -> Result<T> {
+        let store = self.into_other()?.into_inner();
+        store
+            .into_any()
+            .downcast()
+            .map(|store| *store)
+            .map_err(|_| {
+                Error::Downcast(format!(
+                    "Failed to downcast other store to {}",
+                    std::any::type_name::<T>()
+                ))
+            })
+    }
+
+    /// Creates a [Checkpoint] in the store's write buffer, so a plugin can
+    /// roll back part of a call without discarding the rest of the tx. Only
+    /// the `MapStore` and `WrappedMerk` variants have a write buffer.
+    pub fn checkpoint(&mut self) -> Result<Checkpoint> {
+        match self {
+            BackingStore::MapStore(store) => Ok(store.borrow_mut().checkpoint()),
+            #[cfg(feature = "merk-full")]
+            BackingStore::WrappedMerk(store) => Ok(store.borrow_mut().checkpoint()),
+            _ => Err(Error::Store(
+                "Backing store has no write buffer to checkpoint".into(),
+            )),
+        }
+    }
+
+    /// See [BufStore::revert_to](super::BufStore::revert_to).
+    pub fn revert_to(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        match self {
+            BackingStore::MapStore(store) => store.borrow_mut().revert_to(checkpoint),
+            #[cfg(feature = "merk-full")]
+            BackingStore::WrappedMerk(store) => store.borrow_mut().revert_to(checkpoint),
+            _ => Err(Error::Store(
+                "Backing store has no write buffer to checkpoint".into(),
+            )),
+        }
+    }
+
+    /// See [BufStore::release](super::BufStore::release).
+    pub fn release(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        match self {
+            BackingStore::MapStore(store) => store.borrow_mut().release(checkpoint),
+            #[cfg(feature = "merk-full")]
+            BackingStore::WrappedMerk(store) => store.borrow_mut().release(checkpoint),
+            _ => Err(Error::Store(
+                "Backing store has no write buffer to checkpoint".into(),
             )),
         }
     }
@@ -12,9 +12,31 @@ pub type MapStore = BufStore<Empty>;
 
 /// Wraps a `Store` and records mutations in an in-memory map, so that
 /// modifications do not affect the underlying `Store` until `flush` is called.
+///
+/// Writes made after a [checkpoint](BufStore::checkpoint) can be rolled back
+/// with [revert_to](BufStore::revert_to) without discarding the rest of the
+/// buffer.
+///
+/// The buffer can be limited to a [budget](BufStore::set_budget) of bytes, so
+/// a single call can't buffer unbounded writes before it is flushed.
 pub struct BufStore<S> {
     map: Map,
     store: S,
+    undo: Vec<(Vec<u8>, Option<Option<Vec<u8>>>)>,
+    checkpoints: Vec<usize>,
+    budget: Option<u64>,
+    buffered: u64,
+}
+
+/// A point in the writes to a [BufStore] which can be reverted to, created by
+/// [BufStore::checkpoint].
+///
+/// Checkpoints nest: reverting to or releasing a checkpoint also ends every
+/// checkpoint created after it.
+#[derive(Debug, PartialEq, Eq)]
+#[must_use = "a checkpoint should be reverted to or released"]
+pub struct Checkpoint {
+    depth: usize,
 }
 
 impl<S: Read + Default> BufStore<S> {
@@ -32,6 +54,10 @@ impl<S: Read + Default> Default for BufStore<S> {
         Self {
             map: Default::default(),
             store: Default::default(),
+            undo: vec![],
+            checkpoints: vec![],
+            budget: None,
+            buffered: 0,
         }
     }
 }
@@ -46,6 +72,10 @@ impl<S> BufStore<S> {
         BufStore {
             store,
             map: Default::default(),
+            undo: vec![],
+            checkpoints: vec![],
+            budget: None,
+            buffered: 0,
         }
     }
 
@@ -53,7 +83,14 @@ impl<S> BufStore<S> {
     /// in-memory buffer of key/value entries.
     #[inline]
     pub fn wrap_with_map(store: S, map: Map) -> Self {
-        BufStore { store, map }
+        BufStore {
+            store,
+            map,
+            undo: vec![],
+            checkpoints: vec![],
+            budget: None,
+            buffered: 0,
+        }
     }
 
     /// Consumes the `BufStore` and returns its in-memory buffer of key/value
@@ -78,15 +115,132 @@ impl<S> BufStore<S> {
     where
         S: Write,
     {
-        // TODO: use drain instead of pop?
-        while let Some((key, value)) = self.map.pop_first() {
-            match value {
-                Some(value) => self.store.put(key, value)?,
-                None => self.store.delete(key.as_slice())?,
+        self.undo.clear();
+        self.checkpoints.clear();
+        self.buffered = 0;
+
+        let map = std::mem::take(&mut self.map);
+        self.store.write_batch(map)
+    }
+
+    /// Limits the in-memory buffer to `budget` bytes, counting the key and
+    /// value of each buffered entry (deletes count their key). A write which
+    /// would grow the buffer past the budget fails with
+    /// [BudgetExceeded](super::Error::BudgetExceeded) and leaves the buffer
+    /// unchanged. `None` removes the limit.
+    ///
+    /// The limit depends only on the writes made, so it fails
+    /// deterministically across nodes. Entries already buffered count towards
+    /// the budget, even if they exceed it.
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.buffered = match budget {
+            Some(_) => self
+                .map
+                .iter()
+                .map(|(key, value)| entry_size(key, value.as_deref()))
+                .sum(),
+            None => 0,
+        };
+        self.budget = budget;
+    }
+
+    /// The bytes held in the buffer, counted as for
+    /// [set_budget](BufStore::set_budget). Only tracked while a budget is
+    /// set, otherwise 0.
+    pub fn buffered_bytes(&self) -> u64 {
+        self.buffered
+    }
+
+    /// Marks the current state of the buffer, so the writes made after it can
+    /// be rolled back with [revert_to](BufStore::revert_to), e.g. to undo a
+    /// failed sub-call without discarding the rest of the tx.
+    ///
+    /// Writes are recorded to be undone until the checkpoint is reverted to or
+    /// released, so every checkpoint should be ended with one of them.
+    /// Flushing the buffer ends all checkpoints.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.checkpoints.push(self.undo.len());
+        Checkpoint {
+            depth: self.checkpoints.len() - 1,
+        }
+    }
+
+    /// Undoes the writes made since `checkpoint` was created, ending it and
+    /// any checkpoints created after it.
+    pub fn revert_to(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        let undo_len = self.end_checkpoint(checkpoint)?;
+        for (key, prev) in self.undo.drain(undo_len..).rev() {
+            if self.budget.is_some() {
+                let current = self.map.get(&key).map(|value| value.as_deref());
+                let restored = prev.as_ref().map(|value| value.as_deref());
+                self.buffered = self.buffered + restored.map_or(0, |v| entry_size(&key, v))
+                    - current.map_or(0, |v| entry_size(&key, v));
             }
+            match prev {
+                Some(value) => self.map.insert(key, value),
+                None => self.map.remove(&key),
+            };
+        }
+        if self.checkpoints.is_empty() {
+            self.undo.clear();
         }
+
         Ok(())
     }
+
+    /// Ends `checkpoint` and any checkpoints created after it, keeping the
+    /// writes made since. The writes can still be reverted by an earlier
+    /// checkpoint.
+    pub fn release(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        self.end_checkpoint(checkpoint)?;
+        if self.checkpoints.is_empty() {
+            self.undo.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Removes `checkpoint` and the checkpoints above it, returning the length
+    /// of the undo log when it was created.
+    fn end_checkpoint(&mut self, checkpoint: Checkpoint) -> Result<usize> {
+        if checkpoint.depth >= self.checkpoints.len() {
+            return Err(Error::Store("Checkpoint has already ended".into()));
+        }
+        let undo_len = self.checkpoints[checkpoint.depth];
+        self.checkpoints.truncate(checkpoint.depth);
+
+        Ok(undo_len)
+    }
+
+    /// Accounts for writing `value` at `key`, failing if it would exceed the
+    /// budget.
+    fn reserve(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+
+        let prev = self
+            .map
+            .get(key)
+            .map_or(0, |prev| entry_size(key, prev.as_deref()));
+        let buffered = self.buffered - prev + entry_size(key, value);
+        if buffered > budget {
+            return Err(super::Error::BudgetExceeded(budget).into());
+        }
+        self.buffered = buffered;
+
+        Ok(())
+    }
+
+    /// Records the value `key` had before a write, if any checkpoint may need
+    /// to undo it.
+    fn record_undo(&mut self, key: &[u8]) {
+        if !self.checkpoints.is_empty() {
+            let prev = self.map.get(key).cloned();
+            self.undo.push((key.to_vec(), prev));
+        }
+    }
 }
 
 impl<S: Read> Read for BufStore<S> {
@@ -129,6 +283,11 @@ impl<S: Read> Read for BufStore<S> {
     }
 }
 
+/// The bytes a buffered entry counts towards a [BufStore]'s budget.
+fn entry_size(key: &[u8], value: Option<&[u8]>) -> u64 {
+    (key.len() + value.map_or(0, <[u8]>::len)) as u64
+}
+
 /// Return range bounds which start from the given key (exclusive), with an
 /// unbounded end.
 fn exclusive_range_starting_from(start: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
@@ -211,15 +370,35 @@ where
 impl<S: Read> Write for BufStore<S> {
     #[inline]
     fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.reserve(&key, Some(&value))?;
+        self.record_undo(&key);
         self.map.insert(key, Some(value));
         Ok(())
     }
 
     #[inline]
     fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.reserve(key, None)?;
+        self.record_undo(key);
         self.map.insert(key.to_vec(), None);
         Ok(())
     }
+
+    /// Merges the batch into the in-memory buffer, taking it as the buffer
+    /// when the buffer is empty. Writes made during a checkpoint or under a
+    /// budget are applied one by one so they can be reverted or counted.
+    fn write_batch(&mut self, mut batch: Map) -> Result<()> {
+        if !self.checkpoints.is_empty() || self.budget.is_some() {
+            return write_each(self, batch);
+        }
+
+        if self.map.is_empty() {
+            self.map = batch;
+        } else {
+            self.map.append(&mut batch);
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -328,4 +507,97 @@ mod tests {
 
         assert_eq!(map.remove(&vec![0]), Some(Some(vec![100])));
     }
+
+    #[test]
+    fn checkpoint() {
+        let mut buf = BufStore::wrap(MapStore::new());
+        buf.put(vec![0], vec![0]).unwrap();
+
+        let outer = buf.checkpoint();
+        buf.put(vec![0], vec![1]).unwrap();
+        buf.put(vec![1], vec![1]).unwrap();
+
+        let inner = buf.checkpoint();
+        buf.delete(&[0]).unwrap();
+        buf.put(vec![2], vec![2]).unwrap();
+        buf.revert_to(inner).unwrap();
+        assert_eq!(buf.get(&[0]).unwrap(), Some(vec![1]));
+        assert_eq!(buf.get(&[2]).unwrap(), None);
+
+        let inner = buf.checkpoint();
+        buf.put(vec![3], vec![3]).unwrap();
+        buf.release(inner).unwrap();
+        assert_eq!(buf.get(&[3]).unwrap(), Some(vec![3]));
+
+        // reverting the outer checkpoint also undoes released writes
+        let stale = buf.checkpoint();
+        buf.revert_to(outer).unwrap();
+        assert!(buf.revert_to(stale).is_err());
+        assert_eq!(buf.get(&[0]).unwrap(), Some(vec![0]));
+        assert_eq!(buf.get(&[1]).unwrap(), None);
+        assert_eq!(buf.get(&[3]).unwrap(), None);
+        assert_eq!(buf.into_map().len(), 1);
+    }
+
+    #[test]
+    fn flush_batch() {
+        let mut store = MapStore::new();
+        store.put(vec![0], vec![0]).unwrap();
+        store.put(vec![1], vec![1]).unwrap();
+
+        let mut buf = BufStore::wrap(store);
+        buf.put(vec![1], vec![2]).unwrap();
+        buf.delete(&[0]).unwrap();
+        buf.put(vec![3], vec![3]).unwrap();
+        buf.flush().unwrap();
+
+        let mut store = buf.store;
+        assert!(store.map.contains_key(&vec![0]));
+        assert_eq!(store.get(&[0]).unwrap(), None);
+        assert_eq!(store.get(&[1]).unwrap(), Some(vec![2]));
+        assert_eq!(store.get(&[3]).unwrap(), Some(vec![3]));
+
+        // batches written during a checkpoint can be reverted
+        let checkpoint = store.checkpoint();
+        let mut batch = Map::new();
+        batch.insert(vec![1], None);
+        batch.insert(vec![4], Some(vec![4]));
+        store.write_batch(batch).unwrap();
+        assert_eq!(store.get(&[1]).unwrap(), None);
+        store.revert_to(checkpoint).unwrap();
+        assert_eq!(store.get(&[1]).unwrap(), Some(vec![2]));
+        assert_eq!(store.get(&[4]).unwrap(), None);
+    }
+
+    #[test]
+    fn budget() {
+        let mut buf = BufStore::wrap(MapStore::new());
+        buf.put(vec![0], vec![0; 4]).unwrap();
+        buf.set_budget(Some(10));
+        assert_eq!(buf.buffered_bytes(), 5);
+
+        // overwriting an entry only counts the difference
+        buf.put(vec![0], vec![0; 6]).unwrap();
+        buf.delete(&[1]).unwrap();
+        assert_eq!(buf.buffered_bytes(), 8);
+        let err = buf.put(vec![2], vec![0; 2]).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::StoreErr(super::super::Error::BudgetExceeded(10))
+        ));
+        assert_eq!(buf.get(&[2]).unwrap(), None);
+
+        let checkpoint = buf.checkpoint();
+        buf.put(vec![0], vec![]).unwrap();
+        buf.put(vec![2], vec![0]).unwrap();
+        assert_eq!(buf.buffered_bytes(), 4);
+        buf.revert_to(checkpoint).unwrap();
+        assert_eq!(buf.buffered_bytes(), 8);
+
+        buf.flush().unwrap();
+        assert_eq!(buf.buffered_bytes(), 0);
+        buf.put(vec![3], vec![0; 9]).unwrap();
+        buf.set_budget(None);
+        buf.put(vec![4], vec![0; 9]).unwrap();
+    }
 }
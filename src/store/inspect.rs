@@ -0,0 +1,290 @@
+//! Offline inspection of raw store entries, e.g. to compare the state of two
+//! nodes whose app hashes disagree.
+//!
+//! An [Inspector] walks the entries of a store in key order and labels each
+//! key with its path through the app's state types, using their
+//! [Describe](crate::describe::Describe) metadata.
+
+use std::fmt::{self, Display};
+use std::ops::RangeBounds;
+
+use super::{Read, Store, KV};
+use crate::describe::{Children, Descriptor, KeyOp};
+use crate::Result;
+
+/// Walks the entries of a store, labelling keys using a [Descriptor] of the
+/// state type stored at the root.
+pub struct Inspector<S> {
+    store: S,
+    root: Option<Descriptor>,
+}
+
+#[cfg(feature = "merk-full")]
+impl Inspector<crate::merk::MerkStore> {
+    /// Opens the Merk store of the node with the given home directory as
+    /// read-only, so it can be inspected while the node is running.
+    pub fn open<P: AsRef<std::path::Path>>(home: P) -> Result<Self> {
+        let merk_home = home.as_ref().join("merk");
+        if !merk_home.join("db").exists() {
+            return Err(crate::Error::Store(format!(
+                "No store found at {}",
+                merk_home.display()
+            )));
+        }
+
+        Ok(Self::new(crate::merk::MerkStore::open_readonly(merk_home)))
+    }
+}
+
+impl<S: Read> Inspector<S> {
+    pub fn new(store: S) -> Self {
+        Inspector { store, root: None }
+    }
+
+    /// Labels keys using `root`, the descriptor of the state type stored at
+    /// the empty key (for a node, `ABCIPlugin<App>`). Without it, entries are
+    /// dumped unlabelled.
+    #[must_use]
+    pub fn with_descriptor(mut self, root: Descriptor) -> Self {
+        self.root = Some(root);
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// The entry at `key`, if any.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Entry>> {
+        Ok(self
+            .store
+            .get(key)?
+            .map(|value| self.entry((key.to_vec(), value))))
+    }
+
+    /// Iterates over the entries with keys in `bounds`, in ascending order.
+    pub fn entries<B: RangeBounds<Vec<u8>>>(
+        &self,
+        bounds: B,
+    ) -> impl Iterator<Item = Result<Entry>> + '_ {
+        self.store.range(bounds).map(move |kv| Ok(self.entry(kv?)))
+    }
+
+    /// Writes the entries with keys starting with `prefix` to `out`, one per
+    /// line, returning the number of entries written.
+    pub fn dump<W: std::io::Write>(&self, prefix: &[u8], out: &mut W) -> Result<u64> {
+        let mut count = 0;
+        for entry in self.entries(prefix.to_vec()..) {
+            let entry = entry?;
+            if !entry.key.starts_with(prefix) {
+                break;
+            }
+            writeln!(out, "{}", entry)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    fn entry(&self, (key, value): KV) -> Entry {
+        let label = match self.root.as_ref() {
+            Some(root) => Label::new(root, &key),
+            None => Label::unlabelled(&key),
+        };
+
+        Entry { key, value, label }
+    }
+}
+
+/// A raw store entry, along with its label.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub label: Label,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} = {}",
+            hex::encode(&self.key),
+            self.label,
+            hex::encode(&self.value)
+        )
+    }
+}
+
+/// The path of a key through the state types, as far as it could be decoded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Label {
+    pub path: Vec<Segment>,
+    /// The type of the value stored at the key, if the whole key was decoded.
+    pub type_name: Option<String>,
+    /// The bytes of the key after the decoded path.
+    pub rest: Vec<u8>,
+}
+
+/// A step in a [Label]'s path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Segment {
+    /// A named field of a state type.
+    Field(String),
+    /// An encoded key of a collection, such as a `Map`.
+    Key { type_name: String, bytes: Vec<u8> },
+}
+
+impl Label {
+    /// Decodes as much of `key` as possible, starting from `root`.
+    pub fn new(root: &Descriptor, key: &[u8]) -> Self {
+        let mut desc = root;
+        let mut pos = 0;
+        let mut path = vec![];
+
+        while pos < key.len() {
+            match desc.children() {
+                Children::Named(children) => {
+                    let child = children
+                        .iter()
+                        .filter_map(|child| match &child.store_key {
+                            KeyOp::Append(prefix) if key[pos..].starts_with(prefix) => {
+                                Some((child, pos + prefix.len()))
+                            }
+                            KeyOp::Absolute(prefix) if key.starts_with(prefix) => {
+                                Some((child, prefix.len()))
+                            }
+                            _ => None,
+                        })
+                        .max_by_key(|(_, end)| *end);
+                    let (child, end) = match child {
+                        Some(child) => child,
+                        None => break,
+                    };
+                    path.push(Segment::Field(child.name.clone()));
+                    desc = &child.desc;
+                    pos = end;
+                }
+                Children::Dynamic(child) => {
+                    let key_desc = child.key_desc();
+                    let len = match key_desc.load.map(|load| encoded_len(load, &key[pos..])) {
+                        Some(Some(len)) => len,
+                        _ => break,
+                    };
+                    path.push(Segment::Key {
+                        type_name: key_desc.type_name.clone(),
+                        bytes: key[pos..pos + len].to_vec(),
+                    });
+                    desc = child.value_desc();
+                    pos += len;
+                }
+                Children::None => break,
+            }
+        }
+
+        let type_name = (pos == key.len()).then(|| desc.type_name.clone());
+        Label {
+            path,
+            type_name,
+            rest: key[pos..].to_vec(),
+        }
+    }
+
+    fn unlabelled(key: &[u8]) -> Self {
+        Label {
+            rest: key.to_vec(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The length of the value encoded at the start of `bytes`, found by loading
+/// it, or `None` if it fails to load or is empty.
+fn encoded_len(load: crate::describe::LoadFn, bytes: &[u8]) -> Option<usize> {
+    let mut remaining = bytes;
+    load(Store::with_map_store(), &mut remaining).ok()?;
+    let len = bytes.len() - remaining.len();
+
+    (len > 0).then_some(len)
+}
+
+impl Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() && self.rest.is_empty() {
+            write!(f, "<root>")?;
+        }
+        for (i, segment) in self.path.iter().enumerate() {
+            match segment {
+                Segment::Field(name) if i == 0 => write!(f, "{}", name)?,
+                Segment::Field(name) => write!(f, ".{}", name)?,
+                Segment::Key { bytes, .. } => write!(f, "[{}]", hex::encode(bytes))?,
+            }
+        }
+        if !self.rest.is_empty() {
+            write!(f, "+{}", hex::encode(&self.rest))?;
+        }
+        if let Some(type_name) = self.type_name.as_ref() {
+            write!(f, " ({})", type_name)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::Map;
+    use crate::describe::Describe;
+    use crate::orga;
+    use crate::state::State;
+    use crate::store::Write;
+
+    #[orga]
+    struct Accounts {
+        count: u32,
+        balances: Map<u32, u64>,
+    }
+
+    #[test]
+    fn inspect() -> Result<()> {
+        let mut store = Store::with_map_store();
+        let mut accounts = Accounts::default();
+        accounts.attach(store.clone())?;
+        accounts.balances.insert(5, 10)?;
+        let mut bytes = vec![];
+        accounts.flush(&mut bytes)?;
+        store.put(vec![], bytes)?;
+        store.put(vec![9, 9], vec![1])?;
+
+        let inspector = Inspector::new(store).with_descriptor(Accounts::describe());
+        let entries = inspector.entries(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(
+            entries[0].label.to_string(),
+            format!("<root> ({})", std::any::type_name::<Accounts>())
+        );
+        let balance = &entries[1].label;
+        assert_eq!(
+            balance.path,
+            vec![
+                Segment::Field("balances".into()),
+                Segment::Key {
+                    type_name: "u32".into(),
+                    bytes: vec![0, 0, 0, 5],
+                },
+            ]
+        );
+        assert!(balance.rest.is_empty());
+        assert!(balance.to_string().starts_with("balances[00000005] ("));
+        assert_eq!(entries[2].label.rest, vec![9, 9]);
+        assert_eq!(entries[2].label.type_name, None);
+
+        let mut out = vec![];
+        assert_eq!(inspector.dump(&[9], &mut out)?, 1);
+        assert_eq!(String::from_utf8(out).unwrap(), "0909 +0909 = 01\n");
+
+        Ok(())
+    }
+}
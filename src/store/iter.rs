@@ -264,4 +264,23 @@ mod tests {
         };
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn read_range() {
+        let mut store = test_store();
+
+        let entries: Vec<_> = store.range(vec![1]..).collect::<Result<_>>().unwrap();
+        assert_eq!(entries, vec![(vec![1], vec![1]), (vec![2], vec![2])]);
+
+        let keys: Vec<_> = store
+            .range(..=vec![1])
+            .rev()
+            .map(|entry| entry.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![vec![1], vec![0]]);
+
+        // the store is only borrowed
+        store.put(vec![3], vec![3]).unwrap();
+        assert_eq!(store.range(vec![3]..).count(), 1);
+    }
 }
@@ -48,4 +48,8 @@ impl<T: Write> Write for ReadLog<T> {
     fn delete(&mut self, key: &[u8]) -> Result<()> {
         self.inner.delete(key)
     }
+
+    fn write_batch(&mut self, batch: super::bufstore::Map) -> Result<()> {
+        self.inner.write_batch(batch)
+    }
 }
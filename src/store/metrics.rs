@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+use super::KV;
+use crate::context::Context;
+
+/// Counts of the operations made on a store, see [StoreMetrics].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpCounts {
+    pub gets: u64,
+    pub puts: u64,
+    pub deletes: u64,
+    /// The length of the keys and values returned by reads.
+    pub bytes_read: u64,
+    /// The length of the keys and values of puts, and the keys of deletes.
+    pub bytes_written: u64,
+    /// The gets which were not answered by an in-memory buffer and had to be
+    /// read from the backing database.
+    pub backing_reads: u64,
+}
+
+impl OpCounts {
+    /// The share of gets answered by in-memory buffers (the writes of the
+    /// current block and mempool) rather than the backing database, or `None`
+    /// if there were no gets.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        if self.gets == 0 {
+            return None;
+        }
+        let hits = self.gets.saturating_sub(self.backing_reads);
+        Some(hits as f64 / self.gets as f64)
+    }
+
+    fn add(&mut self, other: &OpCounts) {
+        self.gets += other.gets;
+        self.puts += other.puts;
+        self.deletes += other.deletes;
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+        self.backing_reads += other.backing_reads;
+    }
+}
+
+/// Context collecting the operations made through [Store](super::Store) while
+/// it is present, grouped by module so operators can see which parts of the
+/// state dominate I/O.
+///
+/// A module is identified by the first `prefix_len` bytes of the full store
+/// key, which for `#[orga]` state types is the path of field indexes leading
+/// to it. Iteration counts as a get of each entry visited.
+///
+/// The ABCI state machine replaces this context at the start of each block
+/// when enabled with `store_metrics`, so it holds the operations of the
+/// current block, including `CheckTx` and queries handled during it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreMetrics {
+    prefix_len: usize,
+    modules: BTreeMap<Vec<u8>, OpCounts>,
+}
+
+impl Default for StoreMetrics {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl StoreMetrics {
+    /// Creates empty metrics which group keys by their first `prefix_len`
+    /// bytes.
+    pub fn new(prefix_len: usize) -> Self {
+        Self {
+            prefix_len,
+            modules: BTreeMap::new(),
+        }
+    }
+
+    /// The counts for each module, keyed by the module's key prefix.
+    pub fn modules(&self) -> &BTreeMap<Vec<u8>, OpCounts> {
+        &self.modules
+    }
+
+    /// The counts for all modules combined.
+    pub fn total(&self) -> OpCounts {
+        let mut total = OpCounts::default();
+        for counts in self.modules.values() {
+            total.add(counts);
+        }
+        total
+    }
+
+    /// The modules ordered by the number of bytes they read and wrote, most
+    /// first.
+    pub fn by_bytes(&self) -> Vec<(&[u8], &OpCounts)> {
+        let mut modules: Vec<_> = self
+            .modules
+            .iter()
+            .map(|(prefix, counts)| (prefix.as_slice(), counts))
+            .collect();
+        modules
+            .sort_by_key(|(_, counts)| std::cmp::Reverse(counts.bytes_read + counts.bytes_written));
+        modules
+    }
+
+    fn module(&mut self, key: &[u8]) -> &mut OpCounts {
+        let prefix = &key[..self.prefix_len.min(key.len())];
+        if !self.modules.contains_key(prefix) {
+            self.modules.insert(prefix.to_vec(), OpCounts::default());
+        }
+        self.modules.get_mut(prefix).unwrap()
+    }
+}
+
+/// Applies `op` to the counts of the module `key` belongs to, if the
+/// [StoreMetrics] context is present.
+#[inline]
+fn record(key: &[u8], op: impl FnOnce(&mut OpCounts)) {
+    if let Some(metrics) = Context::resolve::<StoreMetrics>() {
+        op(metrics.module(key));
+    }
+}
+
+/// Records a read of the entry at `key`, whose value has length `value_len`.
+#[inline]
+pub(crate) fn record_get(key: &[u8], value_len: usize) {
+    record(key, |counts| {
+        counts.gets += 1;
+        counts.bytes_read += (key.len() + value_len) as u64;
+    });
+}
+
+#[inline]
+pub(crate) fn record_put(key: &[u8], value_len: usize) {
+    record(key, |counts| {
+        counts.puts += 1;
+        counts.bytes_written += (key.len() + value_len) as u64;
+    });
+}
+
+#[inline]
+pub(crate) fn record_delete(key: &[u8]) {
+    record(key, |counts| {
+        counts.deletes += 1;
+        counts.bytes_written += key.len() as u64;
+    });
+}
+
+/// Records a read of the entry following `key`, whose module `key` is
+/// counted in.
+#[inline]
+pub(crate) fn record_get_kv(key: &[u8], maybe_kv: &Option<KV>) {
+    record(key, |counts| {
+        counts.gets += 1;
+        if let Some((k, v)) = maybe_kv {
+            counts.bytes_read += (k.len() + v.len()) as u64;
+        }
+    });
+}
+
+/// Records a get which was read from the backing database.
+#[cfg(feature = "merk-full")]
+#[inline]
+pub(crate) fn record_backing_read(key: &[u8]) {
+    record(key, |counts| counts.backing_reads += 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{Read, Store, Write};
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn store_metrics() {
+        let mut store = Store::with_map_store();
+        store.put(vec![0, 1], vec![1, 2, 3]).unwrap();
+
+        Context::add(StoreMetrics::default());
+        let mut sub = store.sub(&[1]);
+        sub.put(vec![1], vec![1, 2, 3]).unwrap();
+        sub.delete(&[2]).unwrap();
+        assert_eq!(sub.get(&[1]).unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(store.get(&[0, 1]).unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(store.get(&[0, 2]).unwrap(), None);
+        let metrics = Context::resolve::<StoreMetrics>().unwrap().clone();
+        Context::remove::<StoreMetrics>();
+
+        assert_eq!(
+            metrics.modules()[&vec![1]],
+            OpCounts {
+                gets: 1,
+                puts: 1,
+                deletes: 1,
+                bytes_read: 5,
+                bytes_written: 7,
+                backing_reads: 0,
+            }
+        );
+        assert_eq!(metrics.modules()[&vec![0]].gets, 2);
+        assert_eq!(metrics.modules()[&vec![0]].bytes_read, 7);
+        assert_eq!(metrics.total().gets, 3);
+        assert_eq!(metrics.by_bytes()[0].0, &[1]);
+        assert_eq!(metrics.total().cache_hit_rate(), Some(1.0));
+        assert_eq!(OpCounts::default().cache_hit_rate(), None);
+    }
+}
@@ -5,23 +5,28 @@ use std::{
 };
 use thiserror::Error;
 
+pub mod asyncstore;
 pub mod backingstore;
 pub mod bufstore;
+pub mod inspect;
 pub mod iter;
 pub mod log;
+pub mod metrics;
 pub mod null;
 pub mod partialmap;
 pub mod share;
 #[allow(clippy::module_inception)]
 pub mod store;
 
+pub use asyncstore::{AsyncRead, AsyncWrite};
 pub use backingstore::BackingStore;
-pub use bufstore::{BufStore, Map as BufStoreMap, MapStore};
+pub use bufstore::{BufStore, Checkpoint, Map as BufStoreMap, MapStore};
 pub use iter::Iter;
+pub use metrics::{OpCounts, StoreMetrics};
 pub use null::Empty;
 pub use partialmap::PartialMapStore;
 pub use share::Shared;
-pub use store::{DefaultBackingStore, Store};
+pub use store::{DefaultBackingStore, MoveProgress, Store};
 
 // TODO: Key type (for cheaper concat, enum over ref or owned slice, etc)
 
@@ -33,6 +38,8 @@ pub enum Error {
     GetNextUnknown(Vec<u8>),
     #[error("Tried to read unknown store data before key {0:?}")]
     GetPrevUnknown(Option<Vec<u8>>),
+    #[error("Buffered writes exceeded the budget of {0} bytes")]
+    BudgetExceeded(u64),
 }
 
 /// A key/value entry - the first element is the key and the second element is
@@ -86,6 +93,17 @@ pub trait Read {
             ),
         )
     }
+
+    /// Returns an iterator over the key/value entries in the given range,
+    /// borrowing the store rather than consuming it. The iterator can also be
+    /// reversed to scan the range in descending key order.
+    #[inline]
+    fn range<B: RangeBounds<Vec<u8>>>(&self, bounds: B) -> Iter<&Self>
+    where
+        Self: Sized,
+    {
+        Read::into_iter(self, bounds)
+    }
 }
 
 /// Returns the same variant of bound but with an owned copy of its inner value.
@@ -138,6 +156,26 @@ pub trait Write: Read {
     /// operation as a no-op (but may still issue a call to `delete` to an
     /// underlying store).
     fn delete(&mut self, key: &[u8]) -> Result<()>;
+
+    /// Applies a batch of writes, where a `None` value deletes the key.
+    ///
+    /// The batch is sorted by key, so stores which buffer writes in a sorted
+    /// map can merge it in a single pass rather than inserting each entry.
+    /// The default implementation applies the entries one by one.
+    fn write_batch(&mut self, batch: bufstore::Map) -> Result<()> {
+        write_each(self, batch)
+    }
+}
+
+/// Applies the entries of `batch` to `store` one by one.
+pub(crate) fn write_each<S: Write + ?Sized>(store: &mut S, batch: bufstore::Map) -> Result<()> {
+    for (key, value) in batch {
+        match value {
+            Some(value) => store.put(key, value)?,
+            None => store.delete(key.as_slice())?,
+        }
+    }
+    Ok(())
 }
 
 impl<S: Write, T: DerefMut<Target = S>> Write for T {
@@ -150,6 +188,11 @@ impl<S: Write, T: DerefMut<Target = S>> Write for T {
     fn delete(&mut self, key: &[u8]) -> Result<()> {
         self.deref_mut().delete(key)
     }
+
+    #[inline]
+    fn write_batch(&mut self, batch: bufstore::Map) -> Result<()> {
+        self.deref_mut().write_batch(batch)
+    }
 }
 
 pub trait ReadWrite: Read + Write + Any + 'static {
@@ -80,6 +80,12 @@ impl<W: Write> Write for Shared<W> {
         let mut store = self.0.borrow_mut();
         store.delete(key)
     }
+
+    #[inline]
+    fn write_batch(&mut self, batch: super::bufstore::Map) -> Result<()> {
+        let mut store = self.0.borrow_mut();
+        store.write_batch(batch)
+    }
 }
 
 #[cfg(test)]
@@ -1,20 +1,23 @@
 use serde::{Deserialize, Serialize};
 use std::ops::{Bound, RangeBounds};
 
+use super::metrics::{record_delete, record_get, record_get_kv, record_put};
 use super::{BackingStore, Iter, Read, Shared, Write, KV};
 use crate::describe::Describe;
 use crate::encoding::{Decode, Encode, LengthVec, Terminated};
 use crate::migrate::Migrate;
+use crate::plugins::gas::{consume_gas, BYTE_GAS, READ_GAS, WRITE_GAS};
 use crate::query::FieldQuery;
 use crate::state::State;
 use crate::{orga, Error, Result};
 
-// TODO: figure out how to let users set DefaultBackingStore, similar to setting
-// the global allocator in the standard library
-
 /// The default backing store used as the type parameter given to `Store`. This
 /// is used to prevent generic parameters bubbling up to the application level
 /// for state types when they often all use the same backing store.
+///
+/// Custom backends are plugged in at runtime with
+/// [BackingStore::other](super::BackingStore::other) rather than by changing
+/// this type.
 pub type DefaultBackingStore = BackingStore;
 
 /// Wraps a "backing store" (an implementation of `Read` and possibly `Write`),
@@ -55,6 +58,75 @@ impl Store {
             self.delete(&k)
         })
     }
+
+    /// Moves every entry under the `from` prefix to the same key under the
+    /// `to` prefix (both relative to this store), e.g. to reorganize the state
+    /// layout during an upgrade migration. Returns the number of entries
+    /// moved.
+    ///
+    /// Entries are read and rewritten `chunk_size` at a time, so only one
+    /// chunk is held in memory beyond what the backing store buffers, and
+    /// `progress` is called after each chunk. The destination must be empty,
+    /// and neither prefix may contain the other.
+    pub fn move_prefix(
+        &mut self,
+        from: &[u8],
+        to: &[u8],
+        chunk_size: usize,
+        mut progress: impl FnMut(&MoveProgress),
+    ) -> Result<u64> {
+        if chunk_size == 0 {
+            return Err(Error::Store("Chunk size must be greater than 0".into()));
+        }
+        if from.starts_with(to) || to.starts_with(from) {
+            return Err(Error::Store("Prefixes must not overlap".into()));
+        }
+
+        let mut src = self.sub(from);
+        let mut dest = self.sub(to);
+        if dest.range(..).next().transpose()?.is_some() {
+            return Err(Error::Store("Destination prefix is not empty".into()));
+        }
+
+        let mut status = MoveProgress::default();
+        loop {
+            let start = match &status.last_key {
+                Some(key) => Bound::Excluded(key.clone()),
+                None => Bound::Unbounded,
+            };
+            let chunk = src
+                .range((start, Bound::Unbounded))
+                .take(chunk_size)
+                .collect::<Result<Vec<_>>>()?;
+            let last_key = match chunk.last() {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+
+            for (key, value) in chunk {
+                src.delete(&key)?;
+                status.entries += 1;
+                status.bytes += (key.len() + value.len()) as u64;
+                dest.put(key, value)?;
+            }
+            status.last_key = Some(last_key);
+            progress(&status);
+        }
+
+        Ok(status.entries)
+    }
+}
+
+/// The progress of a [Store::move_prefix] call, reported after each chunk.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MoveProgress {
+    /// The number of entries moved so far.
+    pub entries: u64,
+    /// The total length of the keys (relative to the source prefix) and values
+    /// moved so far.
+    pub bytes: u64,
+    /// The last key moved, relative to the source prefix.
+    pub last_key: Option<Vec<u8>>,
 }
 
 impl Migrate for Store {}
@@ -138,6 +210,9 @@ impl<S: Read> Store<S> {
         self.store
     }
 
+    /// Lazily iterates over the entries within `bounds`, with keys relative
+    /// to this store's prefix. Iteration stops at the end of the prefix, so
+    /// entries of sibling stores are never yielded.
     pub fn range<B: RangeBounds<Vec<u8>>>(&self, bounds: B) -> Iter<Self>
     where
         Self: Read,
@@ -193,7 +268,11 @@ impl<S: Read> Read for Store<S> {
     #[inline]
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let prefixed = concat(self.prefix.as_slice(), key);
-        self.store.get(prefixed.as_slice())
+        let maybe_value = self.store.get(prefixed.as_slice())?;
+        let value_len = maybe_value.as_ref().map_or(0, Vec::len);
+        record_get(&prefixed, value_len);
+        charge_read(prefixed.len(), value_len)?;
+        Ok(maybe_value)
     }
 
     #[inline]
@@ -204,6 +283,8 @@ impl<S: Read> Read for Store<S> {
             .get_next(prefixed.as_slice())?
             .filter(|(k, _)| k.starts_with(self.prefix.as_slice()))
             .map(|(k, v)| (k[self.prefix.len()..].into(), v));
+        record_get_kv(&prefixed, &maybe_kv);
+        charge_kv(&maybe_kv)?;
         Ok(maybe_kv)
     }
 
@@ -216,17 +297,19 @@ impl<S: Read> Read for Store<S> {
                 .filter(|(k, _)| k.starts_with(self.prefix.as_slice()))
                 .map(|(k, v)| (k[self.prefix.len()..].into(), v))
         } else {
-            let incremented = increment_bytes(self.prefix.clone());
-            let end_key = if !self.prefix.is_empty() {
-                Some(incremented.as_slice())
-            } else {
-                None
-            };
+            // scan backwards from the first key after the prefix, so entries of
+            // sibling substores are never returned
+            let end_key = prefix_end(self.prefix.clone());
             self.store
-                .get_prev(end_key)?
+                .get_prev(end_key.as_deref())?
                 .filter(|(k, _)| k.starts_with(self.prefix.as_slice()))
                 .map(|(k, v)| (k[self.prefix.len()..].into(), v))
         };
+        record_get_kv(
+            &concat(self.prefix.as_slice(), key.unwrap_or_default()),
+            &maybe_kv,
+        );
+        charge_kv(&maybe_kv)?;
         Ok(maybe_kv)
     }
 }
@@ -245,16 +328,35 @@ impl<S: Write> Write for Store<S> {
         }
 
         let prefixed = concat(self.prefix.as_slice(), key.as_slice());
+        consume_gas(WRITE_GAS + (prefixed.len() + value.len()) as u64 * BYTE_GAS)?;
+        record_put(&prefixed, value.len());
         self.store.put(prefixed, value)
     }
 
     #[inline]
     fn delete(&mut self, key: &[u8]) -> Result<()> {
         let prefixed = concat(self.prefix.as_slice(), key);
+        consume_gas(WRITE_GAS + prefixed.len() as u64 * BYTE_GAS)?;
+        record_delete(&prefixed);
         self.store.delete(prefixed.as_slice())
     }
 }
 
+/// Charges gas for a read to the current
+/// [GasMeter](crate::plugins::GasMeter), if any.
+#[inline]
+fn charge_read(key_len: usize, value_len: usize) -> Result<()> {
+    consume_gas(READ_GAS + (key_len + value_len) as u64 * BYTE_GAS)
+}
+
+#[inline]
+fn charge_kv(maybe_kv: &Option<KV>) -> Result<()> {
+    match maybe_kv {
+        Some((k, v)) => charge_read(k.len(), v.len()),
+        None => charge_read(0, 0),
+    }
+}
+
 #[inline]
 fn concat(a: &[u8], b: &[u8]) -> Vec<u8> {
     let mut value = Vec::with_capacity(a.len() + b.len());
@@ -263,29 +365,25 @@ fn concat(a: &[u8], b: &[u8]) -> Vec<u8> {
     value
 }
 
+/// Returns the smallest key which is greater than every key starting with
+/// `prefix`, or `None` if there is no such key (when `prefix` is empty or all
+/// `0xff` bytes).
 #[inline]
-fn increment_bytes(mut bytes: Vec<u8>) -> Vec<u8> {
-    for byte in bytes.iter_mut().rev() {
-        if *byte == 255 {
-            *byte = 0;
-        } else {
-            *byte += 1;
-            return bytes;
+fn prefix_end(mut prefix: Vec<u8>) -> Option<Vec<u8>> {
+    while let Some(byte) = prefix.pop() {
+        if byte < 255 {
+            prefix.push(byte + 1);
+            return Some(prefix);
         }
     }
 
-    bytes.push(0);
-    if bytes.len() > 1 {
-        bytes[0] += 1;
-    }
-
-    bytes
+    None
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::store::MapStore;
+    use crate::store::{BufStore, Empty, MapStore};
 
     #[test]
     fn sub() {
@@ -319,6 +417,62 @@ mod test {
         assert_eq!(backing.get(&[1, 3, 2]).unwrap().unwrap(), vec![5, 0]);
     }
 
+    #[test]
+    fn sub_get_prev() {
+        let mut backing = MapStore::new();
+        backing.put(vec![0, 0], vec![0]).unwrap();
+        backing.put(vec![1, 0], vec![1]).unwrap();
+        backing.put(vec![1, 1], vec![2]).unwrap();
+        backing.put(vec![2, 0], vec![3]).unwrap();
+
+        // substore
+        let store = Store::new(&mut backing).sub(&[1]);
+        assert_eq!(store.get_prev(None).unwrap().unwrap(), (vec![1], vec![2]));
+        assert_eq!(
+            store.get_prev(Some(&[1][..])).unwrap().unwrap(),
+            (vec![0], vec![1])
+        );
+        assert!(store.get_prev(Some(&[0][..])).unwrap().is_none());
+
+        backing.put(vec![1, 3, 0], vec![4]).unwrap();
+        backing.put(vec![1, 3, 1], vec![5]).unwrap();
+        backing.put(vec![1, 4], vec![6]).unwrap();
+
+        // recursive substore
+        let store = Store::new(&mut backing).sub(&[1]).sub(&[3]);
+        assert_eq!(store.get_prev(None).unwrap().unwrap(), (vec![1], vec![5]));
+        assert_eq!(
+            store.get_prev(Some(&[1][..])).unwrap().unwrap(),
+            (vec![0], vec![4])
+        );
+        assert!(store.get_prev(Some(&[0][..])).unwrap().is_none());
+    }
+
+    #[test]
+    fn sub_get_prev_max_prefix() {
+        let mut backing = MapStore::new();
+        backing.put(vec![254, 0], vec![0]).unwrap();
+        backing.put(vec![255, 0], vec![1]).unwrap();
+        backing.put(vec![255, 255, 0], vec![2]).unwrap();
+        backing.put(vec![255, 255, 255], vec![3]).unwrap();
+
+        let store = Store::new(&mut backing).sub(&[255]);
+        assert_eq!(
+            store.get_prev(None).unwrap().unwrap(),
+            (vec![255, 255], vec![3])
+        );
+
+        let store = Store::new(&mut backing).sub(&[255, 255]);
+        assert_eq!(store.get_prev(None).unwrap().unwrap(), (vec![255], vec![3]));
+        assert_eq!(
+            store.get_prev(Some(&[255][..])).unwrap().unwrap(),
+            (vec![0], vec![2])
+        );
+
+        let store = Store::new(&mut backing).sub(&[254]);
+        assert_eq!(store.get_prev(None).unwrap().unwrap(), (vec![0], vec![0]));
+    }
+
     #[test]
     fn get_prev_empty_key() {
         let mut backing = MapStore::new();
@@ -353,4 +507,108 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn move_prefix() -> Result<()> {
+        let mut store = Store::with_map_store();
+        for i in 0..5 {
+            store.put(vec![1, i], vec![i])?;
+        }
+        store.put(vec![0, 9], vec![9])?;
+        store.put(vec![2, 0], vec![0])?;
+
+        let mut reports = vec![];
+        let moved =
+            store.move_prefix(&[1], &[3, 1], 2, |progress| reports.push(progress.clone()))?;
+        assert_eq!(moved, 5);
+        assert_eq!(
+            reports.iter().map(|p| p.entries).collect::<Vec<_>>(),
+            vec![2, 4, 5]
+        );
+        assert_eq!(reports[2].last_key, Some(vec![4]));
+        assert_eq!(reports[2].bytes, 10);
+
+        assert_eq!(store.sub(&[1]).range(..).count(), 0);
+        for i in 0..5 {
+            assert_eq!(store.get(&[3, 1, i])?, Some(vec![i]));
+        }
+        assert_eq!(store.get(&[0, 9])?, Some(vec![9]));
+        assert_eq!(store.get(&[2, 0])?, Some(vec![0]));
+
+        assert!(store.move_prefix(&[3], &[3, 1], 2, |_| ()).is_err());
+        assert!(store.move_prefix(&[0], &[2], 2, |_| ()).is_err());
+        assert!(store.move_prefix(&[0], &[4], 0, |_| ()).is_err());
+        assert_eq!(store.move_prefix(&[5], &[6], 2, |_| ())?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sub_range() -> Result<()> {
+        use std::ops::Bound::*;
+
+        let mut store = Store::with_map_store();
+        store.put(vec![0, 9], vec![0])?;
+        for i in 0..4 {
+            store.put(vec![1, i], vec![i])?;
+        }
+        store.put(vec![2, 0], vec![0])?;
+        let sub = store.sub(&[1]);
+
+        let keys = |bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>)| -> Result<Vec<Vec<u8>>> {
+            sub.range(bounds).map(|entry| Ok(entry?.0)).collect()
+        };
+        assert_eq!(
+            keys((Unbounded, Unbounded))?,
+            vec![vec![0], vec![1], vec![2], vec![3]]
+        );
+        assert_eq!(
+            keys((Included(vec![1]), Excluded(vec![3])))?,
+            vec![vec![1], vec![2]]
+        );
+        assert_eq!(
+            keys((Excluded(vec![1]), Included(vec![3])))?,
+            vec![vec![2], vec![3]]
+        );
+        assert_eq!(keys((Excluded(vec![3]), Unbounded))?, Vec::<Vec<u8>>::new());
+        assert_eq!(
+            sub.range(..)
+                .rev()
+                .map(|entry| Ok(entry?.0))
+                .collect::<Result<Vec<_>>>()?,
+            vec![vec![3], vec![2], vec![1], vec![0]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn other_backing_store() {
+        let mut store = Store::new(BackingStore::other(MapStore::new())).sub(&[1]);
+        store.put(vec![2], vec![3]).unwrap();
+        assert_eq!(store.get(&[2]).unwrap(), Some(vec![3]));
+
+        let backing = store.into_backing_store().into_inner();
+        let map = backing.into_other_as::<MapStore>().unwrap();
+        assert_eq!(map.get(&[1, 2]).unwrap(), Some(vec![3]));
+
+        let other = BackingStore::other(MapStore::new());
+        assert!(other.into_other_as::<BufStore<MapStore>>().is_err());
+    }
+
+    #[test]
+    fn backing_store_checkpoint() {
+        let mut store = Store::with_map_store();
+        store.put(vec![1], vec![1]).unwrap();
+
+        let mut backing = store.backing_store();
+        let checkpoint = backing.borrow_mut().checkpoint().unwrap();
+        store.put(vec![1], vec![2]).unwrap();
+        store.put(vec![2], vec![2]).unwrap();
+        backing.borrow_mut().revert_to(checkpoint).unwrap();
+        assert_eq!(store.get(&[1]).unwrap(), Some(vec![1]));
+        assert_eq!(store.get(&[2]).unwrap(), None);
+
+        assert!(BackingStore::Null(Empty).checkpoint().is_err());
+    }
 }
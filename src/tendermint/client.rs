@@ -11,28 +11,75 @@ use crate::{
     Error, Result,
 };
 use futures_lite::future::block_on;
+use std::future::Future;
+use std::time::Duration;
+use tendermint_proto::v0_34::crypto::{ProofOp, ProofOps};
 use tendermint_rpc::{self as tm, Client as _};
 use tokio::sync::Mutex;
 
+/// Timeouts and retries for the RPC requests made by [HttpClient].
+#[derive(Clone, Debug)]
+pub struct HttpClientConfig {
+    /// The maximum time to wait for each RPC request. Note that broadcasting a
+    /// call waits for it to be included in a block.
+    pub timeout: Duration,
+    /// The number of times a failed query request is retried.
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled for each following retry.
+    pub backoff: Duration,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            max_retries: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
 pub struct HttpClient {
     client: tm::HttpClient,
     height: Mutex<Option<u32>>,
+    trusted: Option<tm::HttpClient>,
+    config: HttpClientConfig,
 }
 
 impl HttpClient {
     pub fn new(url: &str) -> Result<Self> {
+        Self::new_with_config(url, HttpClientConfig::default())
+    }
+
+    pub fn new_with_config(url: &str, config: HttpClientConfig) -> Result<Self> {
         Ok(Self {
             client: tm::HttpClient::new(url)?,
             height: Mutex::new(None),
+            trusted: None,
+            config,
         })
     }
 
     pub fn with_height(url: &str, height: u32) -> Result<Self> {
         Ok(Self {
-            client: tm::HttpClient::new(url)?,
             height: Mutex::new(Some(height)),
+            ..Self::new(url)?
         })
     }
+
+    /// Verifies query proofs against the app hash in block headers fetched
+    /// from the RPC node at `url`, rather than the root hash included in the
+    /// query response.
+    ///
+    /// By default the queried node is trusted completely, since it can serve a
+    /// self-consistent proof of fabricated data. With a trusted header source,
+    /// a proof is only accepted if it matches the app hash committed in the
+    /// block following the queried height. Queries without a height are made
+    /// at the height before the trusted source's latest block.
+    pub fn with_trusted_rpc(mut self, url: &str) -> Result<Self> {
+        self.trusted = Some(tm::HttpClient::new(url)?);
+        Ok(self)
+    }
 }
 
 impl<T: App + Call + Query + State + Default> Transport<ABCIPlugin<T>> for HttpClient {
@@ -43,7 +90,16 @@ impl<T: App + Call + Query + State + Default> Transport<ABCIPlugin<T>> for HttpC
             _ => return Err(Error::Client("Unexpected call type".into())),
         };
         let call_bytes = call.encode()?;
-        let res = self.client.broadcast_tx_commit(call_bytes).await?;
+
+        // the broadcast itself is never retried since the tx may have been
+        // received before the request failed, so retry a health check first to
+        // wait out connection failures without risking a double submission
+        self.request(true, || self.client.health()).await?;
+        let res = self
+            .request(false, || {
+                self.client.broadcast_tx_commit(call_bytes.clone())
+            })
+            .await?;
 
         if let tendermint::abci::Code::Err(code) = res.check_tx.code {
             let msg = format!("code {}: {}", code, res.check_tx.log);
@@ -54,22 +110,138 @@ impl<T: App + Call + Query + State + Default> Transport<ABCIPlugin<T>> for HttpC
     }
 
     async fn query(&self, query: T::Query) -> Result<Store> {
-        let query_bytes = query.encode()?;
-        let maybe_height = self.height.lock().await.map(Into::into);
+        let maybe_height = *self.height.lock().await;
+        let (store, height) = self
+            .query_proof(query.encode()?, maybe_height, true)
+            .await?;
+        self.height.lock().await.replace(height);
+
+        Ok(store)
+    }
+
+    async fn query_at(&self, query: T::Query, height: u64) -> Result<Store> {
+        let height: u32 = height
+            .try_into()
+            .map_err(|_| Error::Client(format!("Invalid query height {}", height)))?;
+        let (store, res_height) = self
+            .query_proof(query.encode()?, Some(height), true)
+            .await?;
+        if res_height != height {
+            return Err(Error::Query(format!(
+                "Requested state at height {}, but node responded with height {}",
+                height, res_height
+            )));
+        }
+
+        Ok(store)
+    }
+}
+
+impl HttpClient {
+    /// Queries the node **without verifying the response**, trusting the node
+    /// to return correct data.
+    ///
+    /// The values are read directly out of the returned proof without checking
+    /// any hashes, which avoids the cost of verifying very large proofs. This
+    /// must only be used when the endpoint is already trusted (e.g. a node run
+    /// by the client's operator), since a malicious node can return arbitrary
+    /// values. [Transport::query] always verifies and should be preferred.
+    pub async fn query_untrusted<T: Query>(&self, query: T::Query) -> Result<Store> {
+        let maybe_height = *self.height.lock().await;
+        let (store, _) = self
+            .query_proof(query.encode()?, maybe_height, false)
+            .await?;
+
+        Ok(store)
+    }
+
+    /// Reads the value of a raw store key at `height` (or the height before
+    /// the latest block), verified with an ICS-23 proof from the
+    /// [ICS23_QUERY_PATH](crate::abci::ICS23_QUERY_PATH) query. Returns `None`
+    /// if the proof shows the key is absent.
+    ///
+    /// The proof is checked against the app hash in the header of the block
+    /// following `height`, fetched from the trusted RPC node if one is set
+    /// (see [HttpClient::with_trusted_rpc]) and otherwise from the queried
+    /// node.
+    pub async fn query_ics23(&self, key: &[u8], height: Option<u32>) -> Result<Option<Vec<u8>>> {
+        let header_source = self.trusted.as_ref().unwrap_or(&self.client);
+        let (height, app_hash) = self.trusted_app_hash(header_source, height).await?;
+
         let res = self
-            .client
-            .abci_query(None, query_bytes, maybe_height, true)
+            .request(true, || {
+                self.client.abci_query(
+                    Some(crate::abci::ICS23_QUERY_PATH.to_string()),
+                    key.to_vec(),
+                    Some(height.into()),
+                    true,
+                )
+            })
             .await?;
 
         if let tendermint::abci::Code::Err(code) = res.code {
-            let msg = format!("code {}: {}", code, res.log);
+            let msg = format!("at height {}, code {}: {}", height, code, res.log);
             return Err(Error::Query(msg));
         }
+        if res.height.value() != height as u64 {
+            return Err(Error::Query(format!(
+                "Requested state at height {}, but node responded with height {}",
+                height, res.height
+            )));
+        }
 
-        self.height.lock().await.replace(res.height.value() as u32);
+        let proof = res
+            .proof
+            .ok_or_else(|| Error::Query("Response is missing proof".into()))?;
+        let proof_ops = ProofOps {
+            ops: proof
+                .ops
+                .into_iter()
+                .map(|op| ProofOp {
+                    r#type: op.field_type,
+                    key: op.key,
+                    data: op.data,
+                })
+                .collect(),
+        };
+
+        crate::merk::ics23::verify_proof_ops(&proof_ops, &app_hash, key)
+    }
+
+    /// Sends an ABCI query and reads the returned proof, returning a store of
+    /// the proven data and the height it was read from. The proof is only
+    /// checked against the root hash if `verify` is true.
+    async fn query_proof(
+        &self,
+        query_bytes: Vec<u8>,
+        height: Option<u32>,
+        verify: bool,
+    ) -> Result<(Store, u32)> {
+        let (height, trusted_app_hash) = match self.trusted.as_ref() {
+            Some(trusted) if verify => {
+                let (height, app_hash) = self.trusted_app_hash(trusted, height).await?;
+                (Some(height), Some(app_hash))
+            }
+            _ => (height, None),
+        };
+
+        let res = self
+            .request(true, || {
+                self.client
+                    .abci_query(None, query_bytes.clone(), height.map(Into::into), true)
+            })
+            .await?;
 
-        // TODO: we shouldn't need to include the root hash in the result, it
-        // should come from a trusted source
+        if let tendermint::abci::Code::Err(code) = res.code {
+            let msg = match height {
+                Some(height) => format!("at height {}, code {}: {}", height, code, res.log),
+                None => format!("code {}: {}", code, res.log),
+            };
+            return Err(Error::Query(msg));
+        }
+
+        // the root hash is only trusted if it matches a trusted app hash, see
+        // `with_trusted_rpc`
         let root_hash = match res.value[0..32].try_into() {
             Ok(inner) => inner,
             _ => {
@@ -80,13 +252,113 @@ impl<T: App + Call + Query + State + Default> Transport<ABCIPlugin<T>> for HttpC
         };
         let proof_bytes = &res.value[32..];
 
-        let map = merk::proofs::query::verify(proof_bytes, root_hash)?;
+        if let (Some(expected), Some(height)) = (trusted_app_hash, height) {
+            if res.height.value() != height as u64 {
+                return Err(Error::Query(format!(
+                    "Requested state at height {}, but node responded with height {}",
+                    height, res.height
+                )));
+            }
+            if crate::merk::app_hash(&root_hash) != expected {
+                return Err(Error::Query(format!(
+                    "Proof does not match the trusted app hash at height {}",
+                    height
+                )));
+            }
+        }
+
+        let map = if verify {
+            merk::proofs::query::verify(proof_bytes, root_hash)?
+        } else {
+            read_proof_unverified(proof_bytes)?
+        };
 
         let store: Shared<ProofStore> = Shared::new(ProofStore(map));
         let store = Store::new(BackingStore::ProofMap(store));
 
-        Ok(store)
+        Ok((store, res.height.value() as u32))
+    }
+
+    /// Returns the height to query (`height` if set, otherwise the height
+    /// before the latest block) and the app hash of the state at that height,
+    /// read from the header of the following block.
+    async fn trusted_app_hash(
+        &self,
+        trusted: &tm::HttpClient,
+        height: Option<u32>,
+    ) -> Result<(u32, Vec<u8>)> {
+        let header = match height {
+            Some(height) => {
+                self.request(true, || trusted.commit(height + 1))
+                    .await
+                    .map_err(|e| {
+                        Error::Query(format!(
+                            "No trusted app hash for height {} yet: {}",
+                            height, e
+                        ))
+                    })?
+                    .signed_header
+                    .header
+            }
+            None => {
+                self.request(true, || trusted.latest_commit())
+                    .await?
+                    .signed_header
+                    .header
+            }
+        };
+
+        let height = match header.height.value().checked_sub(1) {
+            Some(height) if height > 0 => height as u32,
+            _ => return Err(Error::Query("No blocks have been committed yet".into())),
+        };
+
+        Ok((height, header.app_hash.as_bytes().to_vec()))
+    }
+
+    /// Sends an RPC request, failing if it takes longer than the configured
+    /// timeout. If `retry` is true, failed requests are retried with backoff,
+    /// so it must only be set for idempotent requests.
+    async fn request<T, F, Fut>(&self, retry: bool, mut send: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, tm::Error>>,
+    {
+        let mut backoff = self.config.backoff;
+        let mut retries = 0;
+        loop {
+            let err = match tokio::time::timeout(self.config.timeout, send()).await {
+                Ok(Ok(res)) => return Ok(res),
+                Ok(Err(err)) => Error::from(err),
+                Err(_) => Error::Client(format!(
+                    "RPC request timed out after {:?}",
+                    self.config.timeout
+                )),
+            };
+
+            if !retry || retries >= self.config.max_retries {
+                return Err(err);
+            }
+            log::debug!("RPC request failed, retrying in {:?}: {}", backoff, err);
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            retries += 1;
+        }
+    }
+}
+
+/// Collects the key/value pairs in a proof without hashing any of its nodes.
+fn read_proof_unverified(proof_bytes: &[u8]) -> Result<merk::proofs::query::Map> {
+    use merk::proofs::{query::MapBuilder, Decoder, Op};
+
+    let mut map_builder = MapBuilder::new();
+    for op in Decoder::new(proof_bytes) {
+        if let Op::Push(node) = op? {
+            map_builder.insert(&node)?;
+        }
     }
+
+    Ok(map_builder.build())
 }
 
 impl<T: App + Call + Query + State + Default> SyncTransport<ABCIPlugin<T>> for HttpClient {
@@ -143,6 +415,7 @@ mod tests {
 
     impl InitChain for App {
         fn init_chain(&mut self, _ctx: &crate::plugins::InitChainCtx) -> Result<()> {
+            self.accounts.allow_transfers(true);
             self.accounts
                 .deposit(DerivedKey::address_for(b"alice").unwrap(), 100_000.into())
         }
@@ -321,4 +594,165 @@ mod tests {
         .await
         .unwrap()
     }
+
+    #[ignore]
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn historical_balances() -> Result<()> {
+        spawn_node();
+        // TODO: node spawn should wait for node to be ready
+        tokio::time::sleep(std::time::Duration::from_secs(15)).await;
+
+        let rpc = tm::HttpClient::new("http://localhost:26657").unwrap();
+        let client = AppClient::<App, App, _, FooCoin, _>::new(
+            HttpClient::new("http://localhost:26657").unwrap(),
+            DerivedKey::new(b"alice").unwrap(),
+        );
+        let bob = DerivedKey::address_for(b"bob").unwrap();
+
+        let mut expected = vec![];
+        for i in 1..=3 {
+            client
+                .call(
+                    |app| build_call!(app.accounts.take_as_funding(0.into())),
+                    |app| build_call!(app.accounts.transfer(bob, 1_000.into())),
+                )
+                .await
+                .unwrap();
+            let height = rpc.status().await.unwrap().sync_info.latest_block_height;
+            expected.push((height.value(), i * 1_000));
+        }
+
+        for (height, balance) in expected.iter() {
+            let res = client
+                .balance_at(bob, *height, |app| &app.accounts)
+                .await
+                .unwrap();
+            assert_eq!(res.value, *balance, "balance at height {}", height);
+        }
+
+        let (latest, _) = expected.last().unwrap();
+        client
+            .balance_at(bob, latest + 1_000, |app| &app.accounts)
+            .await
+            .expect_err("Should not query uncommitted height");
+
+        Ok(())
+    }
+
+    /// Sends queries through [HttpClient::query_untrusted].
+    struct Untrusted(HttpClient);
+
+    impl Transport<ABCIPlugin<DefaultPlugins<FooCoin, App>>> for Untrusted {
+        async fn query(
+            &self,
+            query: <DefaultPlugins<FooCoin, App> as Query>::Query,
+        ) -> Result<Store> {
+            self.0
+                .query_untrusted::<DefaultPlugins<FooCoin, App>>(query)
+                .await
+        }
+
+        async fn call(
+            &self,
+            _call: <ABCIPlugin<DefaultPlugins<FooCoin, App>> as Call>::Call,
+        ) -> Result<()> {
+            Err(Error::Client("Untrusted transport is query-only".into()))
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn untrusted_query() -> Result<()> {
+        spawn_node();
+        // TODO: node spawn should wait for node to be ready
+        tokio::time::sleep(std::time::Duration::from_secs(15)).await;
+
+        let alice = DerivedKey::address_for(b"alice").unwrap();
+        let verified = AppClient::<App, App, _, FooCoin, _>::new(
+            HttpClient::new("http://localhost:26657").unwrap(),
+            DerivedKey::new(b"alice").unwrap(),
+        );
+        let untrusted = AppClient::<App, App, _, FooCoin, _>::new(
+            Untrusted(HttpClient::new("http://localhost:26657").unwrap()),
+            DerivedKey::new(b"alice").unwrap(),
+        );
+
+        let expected = verified
+            .query(|app| app.accounts.balance(alice))
+            .await
+            .unwrap();
+        let res = untrusted
+            .query(|app| app.accounts.balance(alice))
+            .await
+            .unwrap();
+        assert_eq!(res, expected);
+        assert_eq!(res.value, 100_000);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn request_timeout_and_retries() {
+        let config = HttpClientConfig {
+            timeout: std::time::Duration::from_millis(10),
+            max_retries: 2,
+            backoff: std::time::Duration::from_millis(1),
+        };
+        let client = HttpClient::new_with_config("http://localhost:26657", config).unwrap();
+
+        let mut attempts = 0;
+        let err = client
+            .request(true, || {
+                attempts += 1;
+                std::future::pending::<std::result::Result<(), tm::Error>>()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Client Error: RPC request timed out after 10ms"
+        );
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        client
+            .request(false, || {
+                attempts += 1;
+                std::future::pending::<std::result::Result<(), tm::Error>>()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(attempts, 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn trusted_root_query() -> Result<()> {
+        spawn_node();
+        // TODO: node spawn should wait for node to be ready
+        tokio::time::sleep(std::time::Duration::from_secs(15)).await;
+
+        let alice = DerivedKey::address_for(b"alice").unwrap();
+        let client = AppClient::<App, App, _, FooCoin, _>::new(
+            HttpClient::new("http://localhost:26657")?
+                .with_trusted_rpc("http://localhost:26657")?,
+            DerivedKey::new(b"alice").unwrap(),
+        );
+        let res = client.query(|app| app.accounts.balance(alice)).await?;
+        assert_eq!(res.value, 100_000);
+
+        let rpc = tm::HttpClient::new("http://localhost:26657").unwrap();
+        let latest = rpc.status().await.unwrap().sync_info.latest_block_height;
+        client
+            .balance_at(alice, latest.value() + 1_000, |app| &app.accounts)
+            .await
+            .expect_err("Should not trust a height without a committed header");
+
+        Ok(())
+    }
 }